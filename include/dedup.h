@@ -0,0 +1,51 @@
+/* Generated with cbindgen from src/ffi.rs; keep in sync. Built with `--features ffi`. */
+
+#ifndef DEDUP_H
+#define DEDUP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct DedupOptions DedupOptions;
+
+typedef struct DedupScan DedupScan;
+
+/**
+ * The result must be released with `dedup_options_free`.
+ */
+DedupOptions *dedup_options_new(void);
+
+void dedup_options_add_target(DedupOptions *options, const uint8_t *path, size_t len);
+
+void dedup_options_free(DedupOptions *options);
+
+/**
+ * Scans the targets. Never returns null; check `dedup_scan_error` for the outcome.
+ */
+DedupScan *dedup_scan_run(const DedupOptions *options);
+
+/**
+ * Returns the error message of a failed scan, or null if it succeeded.
+ */
+const char *dedup_scan_error(const DedupScan *scan);
+
+size_t dedup_scan_group_count(const DedupScan *scan);
+
+uint64_t dedup_scan_group_size(const DedupScan *scan, size_t group);
+
+/**
+ * Returns the 32 bytes of the SHA-256 of the group.
+ */
+const uint8_t *dedup_scan_group_hash(const DedupScan *scan, size_t group);
+
+size_t dedup_scan_group_path_count(const DedupScan *scan, size_t group);
+
+/**
+ * Returns the bytes of a path, not NUL-terminated, storing their number in `len`. The
+ * first path of a group is the one a run would keep as the original.
+ */
+const uint8_t *dedup_scan_group_path(const DedupScan *scan, size_t group, size_t index, size_t *len);
+
+void dedup_scan_free(DedupScan *scan);
+
+#endif /* DEDUP_H */
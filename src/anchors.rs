@@ -0,0 +1,70 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+
+use crate::models::{Dev, Ino};
+
+/// Files that must be kept as the original of their group, from `--anchors`. They are
+/// identified by inode, since that is what the references held by other systems point to.
+#[derive(Debug, Default)]
+pub struct Anchors {
+    inodes: HashMap<(u64, u64), PathBuf>,
+    /// Anchors that could not be stat'ed, e.g. because of a typo.
+    missing: Vec<PathBuf>,
+    found: HashSet<(u64, u64)>,
+}
+
+impl Anchors {
+    /// Reads one path per line, skipping blank lines and those starting with `#`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the anchors: {}", path.to_string_lossy()))?;
+        let mut anchors = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let anchor = PathBuf::from(line);
+            match fs::metadata(&anchor) {
+                Ok(metadata) => {
+                    anchors
+                        .inodes
+                        .insert((metadata.dev(), metadata.ino()), anchor);
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => anchors.missing.push(anchor),
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("Failed to stat an anchor: {}", anchor.to_string_lossy())
+                    })
+                }
+            }
+        }
+        Ok(anchors)
+    }
+
+    pub fn get(&self, dev: Dev, ino: Ino) -> Option<&Path> {
+        self.inodes.get(&(dev.0, ino.0)).map(PathBuf::as_path)
+    }
+
+    /// Records that the anchor was part of a group, whether or not the group was linked.
+    pub fn mark_found(&mut self, dev: Dev, ino: Ino) {
+        self.found.insert((dev.0, ino.0));
+    }
+
+    /// Anchors that are in no group, sorted.
+    pub fn unmatched(&self) -> Vec<PathBuf> {
+        let mut unmatched: Vec<_> = self
+            .inodes
+            .iter()
+            .filter(|(key, _)| !self.found.contains(key))
+            .map(|(_, path)| path.clone())
+            .chain(self.missing.iter().cloned())
+            .collect();
+        unmatched.sort();
+        unmatched
+    }
+}
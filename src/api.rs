@@ -0,0 +1,218 @@
+//! The library API, in three stages that a caller may inspect in between: [`scan`] finds
+//! the identical files of the targets, [`plan`] picks the original of each group and the
+//! duplicates to link to it, and [`apply`] links them, checking each group against the
+//! files as they are by then, as `apply --plan` does. [`run`](crate::run) goes through the
+//! same scan and plan, and applies the plan straight away against the scan it was made of.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use anyhow::{ensure, Result};
+use clap::Parser as _;
+use serde::{Deserialize, Serialize};
+
+use crate::confine::Roots;
+use crate::digest::{HashAlgorithm, HashHex};
+use crate::glob::Glob;
+use crate::models::{Database, Dev};
+use crate::output::Output;
+use crate::plan_file::{apply_group, raw_path, raw_paths, ApplyReport, PlanGroup};
+use crate::report::Report;
+use crate::temporary::DEFAULT_TMP_PREFIX;
+use crate::{
+    apply_relinks, default_threads, merge_equivalent_targets, plan_relinks, scan_targets, Args,
+    CrossUser, Format,
+};
+
+/// What [`scan`] walks, and how, as the options of the same names do on the command line.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub targets: Vec<PathBuf>,
+    /// Patterns of files and directories left out, as with `--exclude`.
+    pub exclude: Vec<String>,
+    pub min_size: u64,
+    pub max_size: Option<u64>,
+    pub dedup_empty: bool,
+    pub hash: HashAlgorithm,
+    pub follow_symlinks: bool,
+    pub one_file_system: bool,
+    pub threads: u64,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            exclude: Vec::new(),
+            min_size: 0,
+            max_size: None,
+            dedup_empty: false,
+            hash: HashAlgorithm::default(),
+            follow_symlinks: false,
+            one_file_system: false,
+            threads: default_threads(),
+        }
+    }
+}
+
+/// How [`plan`] picks the originals, as the options of the same names do on the command line.
+#[derive(Debug, Clone)]
+pub struct PlanOptions {
+    /// Directories whose files are preferred as originals, the first the most.
+    pub prefer: Vec<PathBuf>,
+    /// Directories among the scanned ones whose files are only linked to, never replaced.
+    pub reference: Vec<PathBuf>,
+    pub same_relative_path: bool,
+    pub ignore_permissions: bool,
+    pub ignore_ownership: bool,
+    pub cross_user: CrossUser,
+    pub max_links: Option<u64>,
+}
+
+impl Default for PlanOptions {
+    fn default() -> Self {
+        Self {
+            prefer: Vec::new(),
+            reference: Vec::new(),
+            same_relative_path: false,
+            ignore_permissions: false,
+            ignore_ownership: false,
+            cross_user: CrossUser::Warn,
+            max_links: None,
+        }
+    }
+}
+
+/// A group of the plan: the duplicates to be replaced with links to the original.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupGroup {
+    pub hash: HashHex,
+    pub size: u64,
+    /// Bytes freed once the group is linked, unless its files change in between.
+    pub gain: u64,
+    #[serde(with = "raw_path")]
+    pub original: PathBuf,
+    #[serde(with = "raw_paths")]
+    pub linked: Vec<PathBuf>,
+}
+
+/// The links that a run would make, for [`apply`] to make them later.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DedupPlan {
+    pub hash: HashAlgorithm,
+    /// The targets scanned, for the record: [`apply`] is confined to the roots of its caller.
+    #[serde(with = "raw_paths")]
+    pub targets: Vec<PathBuf>,
+    pub groups: Vec<DedupGroup>,
+}
+
+impl DedupPlan {
+    /// Bytes freed once the whole plan is applied.
+    pub fn gain(&self) -> u64 {
+        self.groups.iter().map(|group| group.gain).sum()
+    }
+}
+
+/// The arguments of a quiet dry run, otherwise those of a bare command line.
+fn dry_run_args() -> Args {
+    Args::try_parse_from(["dedup", "--dry-run", "--quiet"]).expect("the defaults are valid")
+}
+
+/// Walks the targets and hashes the files whose sizes collide. Nothing is modified.
+pub fn scan(options: &ScanOptions) -> Result<Database> {
+    ensure!(options.threads > 0, "Scanning takes at least one thread");
+    let mut args = dry_run_args();
+    args.targets = options.targets.clone();
+    merge_equivalent_targets(&mut args.targets);
+    args.filters.exclude = options
+        .exclude
+        .iter()
+        .map(|pattern| {
+            let Ok(glob) = pattern.parse::<Glob>();
+            glob
+        })
+        .collect();
+    args.filters.min_size = options.min_size;
+    args.filters.max_size = options.max_size;
+    args.filters.dedup_empty = options.dedup_empty;
+    args.hash = options.hash;
+    args.follow_symlinks = options.follow_symlinks;
+    args.one_file_system = options.one_file_system;
+    args.threads = options.threads;
+    let mut report = Report::new();
+    scan_targets(&args, &HashSet::new(), &mut report)
+}
+
+/// Picks the original of each group of `database` and the duplicates to link to it, as a
+/// dry run would. Nothing is modified.
+pub fn plan(database: &Database, options: &PlanOptions) -> Result<DedupPlan> {
+    let mut args = dry_run_args();
+    args.targets = database.targets.clone();
+    args.hash = database.hash;
+    args.prefer = options.prefer.clone();
+    args.reference = options.reference.clone();
+    args.spell_as_targets();
+    args.same_relative_path = options.same_relative_path;
+    args.ignore_permissions = options.ignore_permissions;
+    args.ignore_ownership = options.ignore_ownership;
+    args.cross_user = options.cross_user;
+    args.max_links = options.max_links;
+    // so that the groups are collected along with their gains
+    args.format = Format::Json;
+    let mut report = Report::new();
+    report.roots = Roots::new(args.targets.iter().map(PathBuf::as_path));
+    let plan = plan_relinks(&args, database, &mut report);
+    // a dry run, for the duplicates each group would link and what it would gain
+    apply_relinks(&args, database, &plan, &mut report, &mut Output::sink())?;
+
+    let groups = report
+        .groups
+        .into_iter()
+        .filter(|group| {
+            !group.linked.is_empty()
+                && database
+                    .devices
+                    .get(&Dev(group.dev))
+                    .is_some_and(|device| !device.report_only)
+        })
+        .map(|group| DedupGroup {
+            hash: group.hash,
+            size: group.size,
+            gain: group.gain,
+            original: group.original,
+            linked: group.linked,
+        })
+        .collect();
+    Ok(DedupPlan {
+        hash: database.hash,
+        targets: database.targets.clone(),
+        groups,
+    })
+}
+
+/// Links the groups of `plan`, skipping the duplicates that changed since it was made, and
+/// the whole group if the original did. Only files under `roots` are linked, whatever the
+/// plan lists: a group outside them aborts with [`OutsideRoots`](crate::OutsideRoots).
+pub fn apply(plan: &DedupPlan, roots: &[PathBuf]) -> Result<ApplyReport> {
+    ensure!(!roots.is_empty(), "Refusing to apply a plan without roots");
+    let roots = Roots::new(roots.iter().map(PathBuf::as_path));
+    let mut report = ApplyReport::default();
+    let mut out = Output::sink();
+    for group in &plan.groups {
+        let group = PlanGroup {
+            hash: group.hash,
+            size: group.size,
+            original: group.original.clone(),
+            linked: group.linked.clone(),
+        };
+        apply_group(
+            &roots,
+            &group,
+            DEFAULT_TMP_PREFIX,
+            false,
+            &mut report,
+            &mut out,
+        )?;
+    }
+    Ok(report)
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+
+use crate::digest::{digest_file, HashValue};
+use crate::report::serialize_path;
+use crate::rng::Rng;
+
+/// Some of the sampled links did not hold up.
+pub const EXIT_AUDIT_FAILED: u8 = 5;
+
+/// A link made during the run, kept for the audit.
+#[derive(Debug)]
+pub struct Performed {
+    pub original: PathBuf,
+    pub duplicate: PathBuf,
+    pub hash: HashValue,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditFailure {
+    #[serde(serialize_with = "serialize_path")]
+    pub original: PathBuf,
+    #[serde(serialize_with = "serialize_path")]
+    pub duplicate: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Audit {
+    pub seed: u64,
+    pub samples: u64,
+    pub passed: u64,
+    pub failures: Vec<AuditFailure>,
+}
+
+fn check(performed: &Performed) -> Result<(), String> {
+    let identity = |path: &Path| {
+        fs::symlink_metadata(path)
+            .map(|metadata| (metadata.dev(), metadata.ino()))
+            .map_err(|err| format!("cannot stat {}: {}", path.display(), err))
+    };
+    if identity(&performed.original)? != identity(&performed.duplicate)? {
+        return Err("not linked to the original".to_string());
+    }
+    match digest_file(&performed.original, performed.hash.algorithm()) {
+        Ok((hash, _)) if hash == performed.hash => Ok(()),
+        Ok(_) => Err("the content of the original changed".to_string()),
+        Err(err) => Err(format!("cannot hash the original: {}", err)),
+    }
+}
+
+/// Checks `samples` links picked at random among those performed: both paths must be the
+/// same inode, whose content must still hash as it did.
+pub fn audit(performed: &mut [Performed], samples: u64, seed: u64) -> Audit {
+    let mut rng = Rng::new(seed);
+    let samples = samples.min(performed.len() as u64) as usize;
+    // a partial Fisher-Yates shuffle brings the samples to the front
+    for i in 0..samples {
+        let j = i + rng.below((performed.len() - i) as u64) as usize;
+        performed.swap(i, j);
+    }
+
+    let mut audit = Audit {
+        seed,
+        samples: samples as u64,
+        passed: 0,
+        failures: Vec::new(),
+    };
+    for sample in &performed[..samples] {
+        match check(sample) {
+            Ok(()) => audit.passed += 1,
+            Err(reason) => audit.failures.push(AuditFailure {
+                original: sample.original.clone(),
+                duplicate: sample.duplicate.clone(),
+                reason,
+            }),
+        }
+    }
+    audit
+}
+
+impl Audit {
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "Audit: {} of {} sampled links passed (seed {})",
+            self.passed.to_formatted_string(&Locale::en),
+            self.samples.to_formatted_string(&Locale::en),
+            self.seed
+        )?;
+        for failure in &self.failures {
+            writeln!(
+                out,
+                "  FAILED {} <- {}: {}",
+                failure.original.display(),
+                failure.duplicate.display(),
+                failure.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Links that were never made, so that every sample fails and is listed.
+    fn performed(count: usize) -> Vec<Performed> {
+        (0..count)
+            .map(|i| Performed {
+                original: PathBuf::from("/nonexistent/original"),
+                duplicate: PathBuf::from(format!("/nonexistent/{}", i)),
+                hash: HashValue::Blake3([0; 32]),
+            })
+            .collect()
+    }
+
+    fn sampled(audit: &Audit) -> Vec<&Path> {
+        audit
+            .failures
+            .iter()
+            .map(|failure| failure.duplicate.as_path())
+            .collect()
+    }
+
+    #[test]
+    fn samples_are_picked_again_from_the_same_seed() {
+        let first = audit(&mut performed(100), 10, 42);
+        let again = audit(&mut performed(100), 10, 42);
+        assert_eq!(first.samples, 10);
+        assert_eq!(first.passed, 0);
+        assert_eq!(sampled(&first), sampled(&again));
+        // without repeats
+        let mut unique = sampled(&first);
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 10);
+        assert_ne!(
+            sampled(&audit(&mut performed(100), 10, 43)),
+            sampled(&first)
+        );
+    }
+
+    #[test]
+    fn samples_are_capped_at_the_links_performed() {
+        let audit = audit(&mut performed(3), 10, 42);
+        assert_eq!(audit.samples, 3);
+        assert_eq!(audit.failures.len(), 3);
+    }
+}
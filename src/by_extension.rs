@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::report::{ExtensionStats, Group};
+
+/// Extensions listed separately; the rest are summed up as "other".
+const TOP_EXTENSIONS: usize = 20;
+
+/// Compound extensions recognized by `--compound-ext` when given without a list.
+pub const DEFAULT_COMPOUND_EXTENSIONS: &str = "tar.gz,tar.bz2,tar.xz,tar.zst";
+
+/// The lowercased extension of a file name: the last one, unless the name ends with one
+/// of `compound`. Hidden files such as `.bashrc` have none.
+fn extension(path: &Path, compound: &[String]) -> String {
+    let Some(name) = path.file_name() else {
+        return "(none)".to_string();
+    };
+    let name = name.to_string_lossy().to_lowercase();
+    let name = name.strip_prefix('.').unwrap_or(&name);
+    for ext in compound {
+        let ext = ext.trim_start_matches('.').to_lowercase();
+        if name.len() > ext.len() + 1 && name.ends_with(&format!(".{}", ext)) {
+            return ext;
+        }
+    }
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => ext.to_string(),
+        _ => "(none)".to_string(),
+    }
+}
+
+/// Sums up the duplicates of the groups, linked or reflinked, by the extension of each
+/// duplicate. Sorted by bytes, largest first, with "other" last.
+pub fn by_extension(groups: &[Group], compound: &[String]) -> Vec<ExtensionStats> {
+    let mut totals: HashMap<String, ExtensionStats> = HashMap::new();
+    for group in groups {
+        for path in group.linked.iter().chain(&group.reflinked) {
+            let extension = extension(path, compound);
+            let stats = totals
+                .entry(extension.clone())
+                .or_insert_with(|| ExtensionStats {
+                    extension,
+                    files: 0,
+                    bytes: 0,
+                });
+            stats.files += 1;
+            stats.bytes += group.size;
+        }
+    }
+
+    let mut stats: Vec<_> = totals.into_values().collect();
+    stats.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then_with(|| a.extension.cmp(&b.extension))
+    });
+    if stats.len() > TOP_EXTENSIONS {
+        let rest = stats.split_off(TOP_EXTENSIONS);
+        stats.push(ExtensionStats {
+            extension: "other".to_string(),
+            files: rest.iter().map(|stats| stats.files).sum(),
+            bytes: rest.iter().map(|stats| stats.bytes).sum(),
+        });
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_are_the_last_unless_compound() {
+        let compound: Vec<String> = DEFAULT_COMPOUND_EXTENSIONS
+            .split(',')
+            .map(String::from)
+            .collect();
+        for (path, simple, with_compound) in [
+            ("dir/photo.JPG", "jpg", "jpg"),
+            ("backup.tar.gz", "gz", "tar.gz"),
+            ("backup.TAR.GZ", "gz", "tar.gz"),
+            ("notes.v2.txt", "txt", "txt"),
+            ("Makefile", "(none)", "(none)"),
+            (".bashrc", "(none)", "(none)"),
+            (".config.toml", "toml", "toml"),
+            ("trailing.", "(none)", "(none)"),
+            // nothing left before the compound extension
+            ("tar.gz", "gz", "gz"),
+        ] {
+            assert_eq!(extension(Path::new(path), &[]), simple, "{}", path);
+            assert_eq!(
+                extension(Path::new(path), &compound),
+                with_compound,
+                "{}",
+                path
+            );
+        }
+    }
+}
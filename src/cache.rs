@@ -0,0 +1,451 @@
+//! `--cache`: hashes kept between runs, for inodes whose size and mtime did not change.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+
+use crate::digest::{HashAlgorithm, HashHex, HashValue};
+use crate::models::{Dev, Device, Ino, Inode};
+use crate::prefilter::Prefilter;
+use crate::report::serialize_paths;
+use crate::rng::Rng;
+
+/// Bumped whenever a field changes meaning; caches of other versions are ignored.
+const CACHE_VERSION: u32 = 2;
+
+/// The [`Prefilter::cache_key`] of the prefixes of entries that have none.
+const LEGACY_PREFILTER: &str = "65536+0";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// The identity of the filesystem, as the device number may change between runs.
+    fs: String,
+    /// The device number of the filesystem in the last run that saw it.
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nanos: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<HashHex>,
+    /// The hash of the prefix, for files sieved on it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prefix: Option<HashHex>,
+    /// The bytes the prefix is of, as in [`Prefilter::cache_key`]; the first 64 KiB if
+    /// missing, as before `--prefilter-region`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prefilter: Option<String>,
+    /// The last run that found no other inode of the same content, if it was the last
+    /// run to hash the inode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unique_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    /// Counts the runs that saved the cache.
+    #[serde(default)]
+    generation: u64,
+    entries: Vec<CacheEntry>,
+}
+
+/// Parses `--cache-verify-rate`, a fraction of the cache hits.
+pub fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("expected a fraction between 0 and 1: {}", s)),
+    }
+}
+
+/// Stale paths listed by [`CacheVerification`]; the rest are only counted.
+const STALE_EXAMPLES: usize = 10;
+
+/// `--cache-verify-rate`: the cache hits hashed again anyway, to catch a corrupt cache or
+/// contents changed without their mtime.
+#[derive(Debug, Serialize)]
+pub struct CacheVerification {
+    pub rate: f64,
+    /// Picks the same inodes again, with `--seed`.
+    pub seed: u64,
+    pub verified: u64,
+    /// Entries whose hash, or hash of the prefix, differed from the contents. They were
+    /// purged, and the inodes hashed again.
+    pub stale: u64,
+    #[serde(serialize_with = "serialize_paths")]
+    pub stale_examples: Vec<PathBuf>,
+}
+
+impl CacheVerification {
+    pub fn new(rate: f64, seed: u64) -> Self {
+        Self {
+            rate,
+            seed,
+            verified: 0,
+            stale: 0,
+            stale_examples: Vec::new(),
+        }
+    }
+
+    /// Whether the cache hits of the inode are to be verified. The pick depends on the seed
+    /// and the inode only, not on the order of the lookups.
+    pub fn picks(&self, dev: Dev, ino: Ino) -> bool {
+        let mut rng = Rng::new(self.seed ^ dev.0.rotate_left(32) ^ ino.0);
+        self.rate >= 1.0 || (rng.next_u64() as f64) < self.rate * u64::MAX as f64
+    }
+
+    /// Counts a stale entry, found through `path`.
+    pub fn record_stale(&mut self, path: &Path) {
+        eprintln!(
+            "Warning: stale hash cache entry, purged and hashed again: {}",
+            path.display()
+        );
+        self.stale += 1;
+        if self.stale_examples.len() < STALE_EXAMPLES {
+            self.stale_examples.push(path.to_path_buf());
+        }
+    }
+
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.stale == 0 {
+            return writeln!(
+                out,
+                "Verified cache hits: {} files, none stale (seed {})",
+                self.verified, self.seed
+            );
+        }
+        writeln!(
+            out,
+            "WARNING: stale hash cache entries: {} of {} cache hits verified (seed {}); purged, and the files hashed again:",
+            self.stale, self.verified, self.seed
+        )?;
+        for path in &self.stale_examples {
+            writeln!(out, "  {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HashCache {
+    /// The entries of the filesystems seen in this run, by their device number in this run.
+    entries: HashMap<(u64, u64), CacheEntry>,
+    /// The entries of the filesystems not seen yet, by filesystem, kept for later runs.
+    unmapped: HashMap<String, Vec<CacheEntry>>,
+    /// The identities of the filesystems seen in this run.
+    filesystems: HashMap<Dev, String>,
+    /// The generation of this run.
+    generation: u64,
+    /// Lookups answered from the cache in this run.
+    pub hits: u64,
+}
+
+impl HashCache {
+    fn new() -> Self {
+        Self {
+            generation: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Reads the cache, starting with an empty one with a warning if it is missing, corrupt
+    /// or of another version.
+    pub fn load(path: &Path) -> Self {
+        let cache: io::Result<CacheFile> = fs::read(path)
+            .and_then(|contents| serde_json::from_slice(&contents).map_err(io::Error::from));
+        match cache {
+            Ok(cache) if cache.version == CACHE_VERSION => {
+                let mut unmapped: HashMap<String, Vec<CacheEntry>> = HashMap::new();
+                for entry in cache.entries {
+                    unmapped.entry(entry.fs.clone()).or_default().push(entry);
+                }
+                Self {
+                    unmapped,
+                    generation: cache.generation + 1,
+                    ..Self::default()
+                }
+            }
+            Ok(cache) => {
+                eprintln!(
+                    "Warning: ignored the hash cache of version {}: {}",
+                    cache.version,
+                    path.display()
+                );
+                Self::new()
+            }
+            Err(err) => {
+                eprintln!(
+                    "Warning: starting without a hash cache: {}: {}",
+                    path.display(),
+                    err
+                );
+                Self::new()
+            }
+        }
+    }
+
+    /// Makes the entries of the filesystem `fs_id` those of `dev`, whichever device number
+    /// it had when they were cached. Called once a device is first seen, before any lookup.
+    pub fn add_device(&mut self, dev: Dev, fs_id: &str) {
+        self.filesystems.insert(dev, fs_id.to_string());
+        for mut entry in self.unmapped.remove(fs_id).unwrap_or_default() {
+            entry.dev = dev.0;
+            self.entries.insert((dev.0, entry.ino), entry);
+        }
+    }
+
+    /// The hash of the inode if it was cached with the same size and mtime, and the same
+    /// algorithm.
+    pub fn get(&mut self, dev: Dev, inode: &Inode, algorithm: HashAlgorithm) -> Option<HashValue> {
+        let hash = self
+            .fresh_entry(dev, inode)?
+            .hash
+            .filter(|hash| hash.0.algorithm() == algorithm)?;
+        self.hits += 1;
+        Some(hash.0)
+    }
+
+    /// The hash of the prefix of the inode, under the same conditions as [`Self::get`].
+    pub fn get_prefix(
+        &self,
+        dev: Dev,
+        inode: &Inode,
+        algorithm: HashAlgorithm,
+        prefilter: Prefilter,
+    ) -> Option<HashValue> {
+        let entry = self.fresh_entry(dev, inode)?;
+        let key = entry.prefilter.as_deref().unwrap_or(LEGACY_PREFILTER);
+        let prefix = entry
+            .prefix
+            .filter(|prefix| prefix.0.algorithm() == algorithm && key == prefilter.cache_key())?;
+        Some(prefix.0)
+    }
+
+    fn fresh_entry(&self, dev: Dev, inode: &Inode) -> Option<&CacheEntry> {
+        self.entries.get(&(dev.0, inode.ino.0)).filter(|entry| {
+            entry.size == inode.size
+                && FileTime::from_unix_time(entry.mtime, entry.mtime_nanos) == inode.mtime
+        })
+    }
+
+    /// The inodes of `pending`, which holds every inode of the sizes it has, whose whole
+    /// size bucket was found unique by the same earlier run: they were compared with each
+    /// other then, and no new or changed inode has joined them since. Buckets with an inode
+    /// `picked` for verification are left out, to be hashed.
+    pub fn known_unique(
+        &self,
+        device: &Device,
+        pending: &[Ino],
+        picked: impl Fn(Ino) -> bool,
+    ) -> HashSet<Ino> {
+        let mut buckets: HashMap<u64, Vec<Ino>> = HashMap::new();
+        for &ino in pending {
+            let inode = device.inodes.get(ino).unwrap();
+            buckets.entry(inode.size).or_default().push(ino);
+        }
+        let mut known_unique = HashSet::new();
+        for inos in buckets.into_values() {
+            let mut generations = inos.iter().map(|&ino| {
+                self.fresh_entry(device.dev, device.inodes.get(ino).unwrap())
+                    .and_then(|entry| entry.unique_in)
+            });
+            let first = generations.next().flatten();
+            if first.is_some()
+                && generations.all(|generation| generation == first)
+                && !inos.iter().any(|&ino| picked(ino))
+            {
+                known_unique.extend(inos);
+            }
+        }
+        known_unique
+    }
+
+    /// Records which inodes this run found unique, before the groups of one inode are
+    /// dropped. The markers of inodes found to have duplicates are cleared.
+    pub fn mark_unique(&mut self, device: &Device) {
+        let generation = self.generation;
+        let mut mark = |ino: Ino, unique: bool| {
+            if let Some(entry) = self.entries.get_mut(&(device.dev.0, ino.0)) {
+                entry.unique_in = unique.then_some(generation);
+            }
+        };
+        for identical in device.identicals.map.values() {
+            let inos = identical.inos.as_slice();
+            for &ino in inos {
+                mark(ino, inos.len() == 1);
+            }
+        }
+        for ino in device.prefix_sieve.unique_inos() {
+            mark(ino, true);
+        }
+        for &ino in &device.known_unique {
+            mark(ino, true);
+        }
+    }
+
+    /// The entry of the inode, emptied first if the inode changed since it was cached.
+    fn entry_mut(&mut self, dev: Dev, inode: &Inode) -> &mut CacheEntry {
+        let fs = &self.filesystems;
+        let entry = self
+            .entries
+            .entry((dev.0, inode.ino.0))
+            .or_insert_with(|| CacheEntry {
+                fs: fs
+                    .get(&dev)
+                    .cloned()
+                    .unwrap_or_else(|| format!("dev:{}", dev.0)),
+                dev: dev.0,
+                ino: inode.ino.0,
+                size: inode.size,
+                mtime: inode.mtime.unix_seconds(),
+                mtime_nanos: inode.mtime.nanoseconds(),
+                hash: None,
+                prefix: None,
+                prefilter: None,
+                unique_in: None,
+            });
+        if entry.size != inode.size
+            || FileTime::from_unix_time(entry.mtime, entry.mtime_nanos) != inode.mtime
+        {
+            entry.size = inode.size;
+            entry.mtime = inode.mtime.unix_seconds();
+            entry.mtime_nanos = inode.mtime.nanoseconds();
+            entry.hash = None;
+            entry.prefix = None;
+            entry.prefilter = None;
+            entry.unique_in = None;
+        }
+        entry
+    }
+
+    /// Drops the entry of the inode, found stale.
+    pub fn purge(&mut self, dev: Dev, inode: &Inode) {
+        self.entries.remove(&(dev.0, inode.ino.0));
+    }
+
+    /// Records a hash just computed.
+    pub fn insert(&mut self, dev: Dev, inode: &Inode, hash: HashValue) {
+        self.entry_mut(dev, inode).hash = Some(HashHex(hash));
+    }
+
+    /// Records the hash of a prefix just computed.
+    pub fn insert_prefix(
+        &mut self,
+        dev: Dev,
+        inode: &Inode,
+        prefix: HashValue,
+        prefilter: Prefilter,
+    ) {
+        let entry = self.entry_mut(dev, inode);
+        entry.prefix = Some(HashHex(prefix));
+        entry.prefilter = Some(prefilter.cache_key());
+    }
+
+    /// Writes the cache next to `path` and renames it over, so that a crash never leaves
+    /// a truncated cache.
+    pub fn save(self, path: &Path) -> Result<()> {
+        let mut entries: Vec<_> = self
+            .entries
+            .into_values()
+            .chain(self.unmapped.into_values().flatten())
+            .collect();
+        entries.sort_by(|a, b| (&a.fs, a.ino).cmp(&(&b.fs, b.ino)));
+        let cache = CacheFile {
+            version: CACHE_VERSION,
+            generation: self.generation,
+            entries,
+        };
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let context = || format!("Failed to write the hash cache: {}", path.to_string_lossy());
+        let file = fs::File::create(&temporary).with_context(context)?;
+        let mut writer = io::BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &cache).with_context(context)?;
+        writer.flush().with_context(context)?;
+        fs::rename(&temporary, path).with_context(context)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TreeBuilder;
+
+    fn inode(ino: u64) -> Inode {
+        Inode {
+            ino: Ino(ino),
+            mtime: FileTime::from_unix_time(1_000_000_000, 0),
+            ctime: FileTime::from_unix_time(1_000_000_000, 0),
+            hashed: false,
+            nlink: 1,
+            size: 100,
+            realsize: 4096,
+            uid: 0,
+            gid: 0,
+            mode: 0o644,
+            files: Vec::new(),
+            extra_paths: 0,
+        }
+    }
+
+    #[test]
+    fn entries_follow_their_filesystem_to_another_device_number() {
+        let tree = TreeBuilder::new().unwrap();
+        let path = tree.path("cache.json");
+        let hash = HashValue::Sha256(Default::default());
+        let mut cache = HashCache::new();
+        cache.add_device(Dev(1), "uuid:a");
+        cache.add_device(Dev(2), "uuid:b");
+        cache.insert(Dev(1), &inode(7), hash);
+        cache.insert(Dev(2), &inode(7), hash);
+        cache.save(&path).unwrap();
+
+        // after a reboot, another filesystem took the number of the first
+        let mut cache = HashCache::load(&path);
+        cache.add_device(Dev(1), "uuid:c");
+        cache.add_device(Dev(3), "uuid:a");
+        assert_eq!(cache.get(Dev(1), &inode(7), HashAlgorithm::Sha256), None);
+        assert_eq!(
+            cache.get(Dev(3), &inode(7), HashAlgorithm::Sha256),
+            Some(hash)
+        );
+        cache.save(&path).unwrap();
+
+        // the filesystem not seen in between kept its entry
+        let mut cache = HashCache::load(&path);
+        cache.add_device(Dev(4), "uuid:b");
+        assert_eq!(
+            cache.get(Dev(4), &inode(7), HashAlgorithm::Sha256),
+            Some(hash)
+        );
+    }
+
+    #[test]
+    fn picks_depend_on_the_seed_only() {
+        let picked = |seed| {
+            let verification = CacheVerification::new(0.25, seed);
+            (0..4000)
+                .filter(|&ino| verification.picks(Dev(1), Ino(ino)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(picked(7), picked(7));
+        assert_ne!(picked(7), picked(8));
+        assert!((800..1200).contains(&picked(7).len()));
+        assert!(!CacheVerification::new(0.0, 7).picks(Dev(1), Ino(1)));
+        assert!(CacheVerification::new(1.0, 7).picks(Dev(1), Ino(1)));
+    }
+
+    #[test]
+    fn rate_is_a_fraction() {
+        assert_eq!(parse_rate("0.01"), Ok(0.01));
+        assert!(parse_rate("1.5").is_err());
+        assert!(parse_rate("-0.1").is_err());
+    }
+}
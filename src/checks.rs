@@ -0,0 +1,72 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+
+/// Why a duplicate path cannot be removed even though the link itself would succeed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unremovable {
+    StickyNotOwner,
+    AppendOnly,
+}
+
+impl fmt::Display for Unremovable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::StickyNotOwner => write!(f, "sticky directory, not owner"),
+            Self::AppendOnly => write!(f, "append-only or immutable directory"),
+        }
+    }
+}
+
+pub fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+// from linux/fs.h
+const FS_IMMUTABLE_FL: libc::c_int = 0x00000010;
+const FS_APPEND_FL: libc::c_int = 0x00000020;
+
+/// Returns the inode flags of a directory, or 0 if the filesystem does not support them.
+/// FS_IOC_GETFLAGS writes an int, despite the long in its definition.
+fn dir_flags(dir: &Path) -> io::Result<libc::c_int> {
+    let file = fs::File::open(dir)?;
+    let mut flags: libc::c_int = 0;
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) };
+    if ret < 0 {
+        return Ok(0);
+    }
+    Ok(flags)
+}
+
+pub fn check_removable(path: &Path) -> Result<Option<Unremovable>> {
+    let dir = parent_dir(path);
+    let dir_metadata = fs::metadata(dir)
+        .with_context(|| format!("Failed to fs::metadata for dir: {}", dir.to_string_lossy()))?;
+
+    if dir_metadata.mode() & libc::S_ISVTX != 0 {
+        let euid = unsafe { libc::geteuid() };
+        if euid != 0 && dir_metadata.uid() != euid {
+            let metadata = fs::symlink_metadata(path).with_context(|| {
+                format!("Failed to fs::symlink_metadata: {}", path.to_string_lossy())
+            })?;
+            if metadata.uid() != euid {
+                return Ok(Some(Unremovable::StickyNotOwner));
+            }
+        }
+    }
+
+    let flags =
+        dir_flags(dir).with_context(|| format!("Failed to open dir: {}", dir.to_string_lossy()))?;
+    if flags & (FS_APPEND_FL | FS_IMMUTABLE_FL) != 0 {
+        return Ok(Some(Unremovable::AppendOnly));
+    }
+    Ok(None)
+}
@@ -0,0 +1,265 @@
+//! The roots that a run may modify anything under: every mutation checks its path here
+//! first, so that the report can attest that nothing outside them was touched.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{Context as _, Result};
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+
+use crate::checks::parent_dir;
+use crate::report::serialize_paths;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mutation {
+    /// Replacing a duplicate with a hard link.
+    Link,
+    /// Sharing the extents of a duplicate with a reflink.
+    Reflink,
+    Remove,
+    /// Moving a duplicate, checked at both ends.
+    Move,
+    SetMtime,
+    /// Creating and removing a scratch file of a probe.
+    Scratch,
+}
+
+impl Mutation {
+    const ALL: [Self; 6] = [
+        Self::Link,
+        Self::Reflink,
+        Self::Remove,
+        Self::Move,
+        Self::SetMtime,
+        Self::Scratch,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Link => "link",
+            Self::Reflink => "reflink",
+            Self::Remove => "remove",
+            Self::Move => "move",
+            Self::SetMtime => "set mtime",
+            Self::Scratch => "create a scratch file",
+        }
+    }
+
+    /// Whether the mutation goes through a final symlink to its destination, which is then
+    /// what must be under the roots.
+    fn follows_symlinks(self) -> bool {
+        matches!(self, Self::Reflink | Self::SetMtime)
+    }
+}
+
+/// A mutation of a path outside the roots, which the run never attempts unless a bug, or
+/// a crafted plan or manifest, leads it to. It is never skipped over, even with
+/// `--keep-going`.
+#[derive(Debug)]
+pub struct OutsideRoots {
+    mutation: Mutation,
+    path: PathBuf,
+    roots: Vec<PathBuf>,
+}
+
+impl fmt::Display for OutsideRoots {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "REFUSED to {} {}: outside of {}; the run is aborted",
+            self.mutation.as_str(),
+            self.path.display(),
+            self.roots
+                .iter()
+                .map(|root| root.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+impl std::error::Error for OutsideRoots {}
+
+/// The canonical form of `path`, which need not exist: its longest existing ancestor is
+/// canonicalized and the rest appended. `None` if the rest climbs up with `..`.
+fn resolve(path: &Path) -> io::Result<Option<PathBuf>> {
+    let path = std::path::absolute(path)?;
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    loop {
+        match fs::canonicalize(existing) {
+            Ok(resolved) => {
+                if rest.iter().any(|name| name == "..") {
+                    return Ok(None);
+                }
+                return Ok(Some(
+                    rest.iter()
+                        .rev()
+                        .fold(resolved, |path, name| path.join(name)),
+                ));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return Ok(None),
+        }
+    }
+}
+
+/// The canonical form of the entry `path`, like [`resolve`] but for its directory only, so
+/// that a symlink is placed where it lies rather than where it leads: replacing or removing
+/// it never touches its destination.
+fn resolve_entry(path: &Path) -> io::Result<Option<PathBuf>> {
+    let path = std::path::absolute(path)?;
+    match path.file_name() {
+        Some(name) => Ok(resolve(parent_dir(&path))?.map(|dir| dir.join(name))),
+        None => resolve(&path),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Roots {
+    roots: Vec<PathBuf>,
+    counts: [AtomicU64; Mutation::ALL.len()],
+}
+
+impl Roots {
+    /// Confines the run to the given directories, canonicalized, which need not exist yet,
+    /// like a quarantine. A file confines it to its directory, whose entry it is.
+    pub fn new<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Self {
+        let mut roots = Self::default();
+        for path in paths {
+            roots.add(path);
+        }
+        roots
+    }
+
+    /// Confines the run to `path` as well, like [`Self::new`].
+    fn add(&mut self, path: &Path) {
+        let Ok(Some(root)) = resolve(path) else {
+            return;
+        };
+        let root = if root.is_file() {
+            parent_dir(&root).to_path_buf()
+        } else {
+            root
+        };
+        if !self.roots.iter().any(|known| root.starts_with(known)) {
+            self.roots.push(root);
+        }
+    }
+
+    /// Whether no path at all may be modified, e.g. for lack of targets.
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Lets `mutation` of `path` go ahead if the path is under one of the roots, and counts
+    /// it. Otherwise returns [`OutsideRoots`], which must abort the run.
+    pub fn check(&self, mutation: Mutation, path: &Path) -> Result<()> {
+        let resolved = if mutation.follows_symlinks() {
+            resolve(path)
+        } else {
+            resolve_entry(path)
+        };
+        let resolved =
+            resolved.with_context(|| format!("Failed to resolve: {}", path.to_string_lossy()))?;
+        let inside = resolved
+            .is_some_and(|resolved| self.roots.iter().any(|root| resolved.starts_with(root)));
+        if !inside {
+            return Err(OutsideRoots {
+                mutation,
+                path: path.to_path_buf(),
+                roots: self.roots.clone(),
+            }
+            .into());
+        }
+        let index = Mutation::ALL.iter().position(|&m| m == mutation).unwrap();
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn attestation(&self) -> Attestation {
+        let by_mutation: BTreeMap<_, _> = Mutation::ALL
+            .iter()
+            .zip(&self.counts)
+            .map(|(&mutation, count)| (mutation, count.load(Ordering::Relaxed)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        Attestation {
+            roots: self.roots.clone(),
+            mutations: by_mutation.values().sum(),
+            by_mutation,
+        }
+    }
+}
+
+/// Every mutation of the run was checked to be under one of the roots before it was made.
+#[derive(Debug, Serialize)]
+pub struct Attestation {
+    #[serde(serialize_with = "serialize_paths")]
+    pub roots: Vec<PathBuf>,
+    pub mutations: u64,
+    pub by_mutation: BTreeMap<Mutation, u64>,
+}
+
+impl Attestation {
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "Modified only under {}: {} operations",
+            self.roots
+                .iter()
+                .map(|root| root.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.mutations.to_formatted_string(&Locale::en)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TreeBuilder;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn symlink_is_placed_where_it_lies() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("inside/file", "x").unwrap();
+        fs::create_dir(tree.path("outside")).unwrap();
+        symlink(tree.path("inside/file"), tree.path("outside/in")).unwrap();
+        symlink(tree.path("outside"), tree.path("inside/out")).unwrap();
+        let roots = Roots::new([tree.path("inside").as_path()]);
+
+        let err = roots
+            .check(Mutation::Remove, &tree.path("outside/in"))
+            .unwrap_err();
+        assert!(err.is::<OutsideRoots>(), "{err:?}");
+        // through a directory symlink, the entry is outside whatever its own name
+        let err = roots
+            .check(Mutation::Link, &tree.path("inside/out/new"))
+            .unwrap_err();
+        assert!(err.is::<OutsideRoots>(), "{err:?}");
+        roots
+            .check(Mutation::Remove, &tree.path("inside/out"))
+            .unwrap();
+        roots
+            .check(Mutation::Link, &tree.path("inside/file"))
+            .unwrap();
+        assert_eq!(roots.attestation().mutations, 2);
+    }
+}
@@ -0,0 +1,160 @@
+//! `--control-socket`: commands to a running instance over a Unix domain socket, one per
+//! connection, which act on the [`ProgressHandle`] of the run; and `dedup ctl`, which
+//! sends them.
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use clap::ValueEnum;
+
+use crate::progress::ProgressHandle;
+
+/// How often the socket thread checks for connections, and whether the run ended.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a client may take to send its command.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Longer than any command, so that a client cannot make the thread read forever.
+const MAX_COMMAND_LEN: u64 = 64;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Finish the file or group at hand, then wait
+    Pause,
+    /// Go on after a pause
+    Resume,
+    /// Print the progress of the run as JSON
+    Status,
+    /// Finish the file or group at hand, then end the run, keeping what was done
+    Stop,
+}
+
+/// The socket of a run, served on its own thread until dropped, which removes it.
+pub struct ControlSocket {
+    path: PathBuf,
+    closed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlSocket {
+    /// Listens on `path`. A socket left there by a run that crashed is replaced, but not
+    /// one that another run still listens on.
+    pub fn bind(path: &Path, progress: &ProgressHandle) -> Result<Self> {
+        if UnixStream::connect(path).is_ok() {
+            bail!(
+                "Another run listens on the control socket: {}",
+                path.to_string_lossy()
+            );
+        }
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path).with_context(|| {
+                format!(
+                    "Failed to remove a stale control socket: {}",
+                    path.to_string_lossy()
+                )
+            })?;
+        }
+        let listener = UnixListener::bind(path).with_context(|| {
+            format!(
+                "Failed to listen on the control socket: {}",
+                path.to_string_lossy()
+            )
+        })?;
+        listener.set_nonblocking(true)?;
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let closed = Arc::clone(&closed);
+            let progress = progress.clone();
+            thread::spawn(move || {
+                while !closed.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(err) = serve(stream, &progress) {
+                                eprintln!("Warning: control socket: {}", err);
+                            }
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL)
+                        }
+                        Err(err) => {
+                            eprintln!("Warning: control socket: {}", err);
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            closed,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Reads a command, one line, and answers it with one line.
+fn serve(stream: UnixStream, progress: &ProgressHandle) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .take(MAX_COMMAND_LEN)
+        .read_line(&mut line)?;
+    let reply = match ControlCommand::from_str(line.trim(), true) {
+        Ok(ControlCommand::Pause) => {
+            progress.pause();
+            "paused".to_string()
+        }
+        Ok(ControlCommand::Resume) => {
+            progress.resume();
+            "resumed".to_string()
+        }
+        Ok(ControlCommand::Status) => serde_json::to_string(&progress.snapshot())?,
+        Ok(ControlCommand::Stop) => {
+            progress.request_stop();
+            "stopping".to_string()
+        }
+        Err(_) => format!("error: unknown command: {}", line.trim()),
+    };
+    writeln!(&stream, "{}", reply)
+}
+
+/// Sends `command` to the run listening on `path` and prints its answer.
+pub fn send_command(path: &Path, command: ControlCommand, out: &mut dyn Write) -> Result<()> {
+    let context = || {
+        format!(
+            "Failed to send a command to the control socket: {}",
+            path.to_string_lossy()
+        )
+    };
+    let mut stream = UnixStream::connect(path).with_context(context)?;
+    let name = command.to_possible_value().unwrap();
+    writeln!(stream, "{}", name.get_name()).with_context(context)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).with_context(context)?;
+    if let Some(err) = reply.trim().strip_prefix("error: ") {
+        bail!("The run refused the command: {}", err);
+    }
+    out.write_all(reply.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! `--cross-device-report`: content found on more than one device, as with an LVM snapshot
+//! mounted next to its origin. Hard links cannot cross devices, so these are only reported.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+
+use crate::digest::{HashHex, HashValue};
+use crate::models::{Database, Dev, Ino};
+use crate::report::serialize_path;
+
+/// The content as found on one device, shown by one of its paths.
+#[derive(Debug, Serialize)]
+pub struct CrossDeviceCopy {
+    pub dev: u64,
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    /// Paths on the device with the content, including those beyond `--max-paths-per-inode`.
+    pub files: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CrossDeviceContent {
+    pub size: u64,
+    pub hash: HashHex,
+    pub copies: Vec<CrossDeviceCopy>,
+}
+
+/// The sizes of inodes on more than one device, the only ones worth hashing for the report.
+pub fn shared_sizes(database: &Database) -> HashSet<u64> {
+    let mut first_dev: HashMap<u64, Dev> = HashMap::new();
+    let mut shared = HashSet::new();
+    for device in database.devices.values() {
+        for inode in device.inodes.map.values() {
+            match first_dev.get(&inode.size) {
+                Some(&dev) if dev != device.dev => {
+                    shared.insert(inode.size);
+                }
+                Some(_) => {}
+                None => {
+                    first_dev.insert(inode.size, device.dev);
+                }
+            }
+        }
+    }
+    shared
+}
+
+/// Joins the identical files of every device on size and hash. Run it before the groups
+/// of one inode are dropped; only one path is taken from each device.
+pub fn cross_device_report(database: &Database) -> Vec<CrossDeviceContent> {
+    let mut joined: HashMap<(u64, HashValue), BTreeMap<Dev, &[Ino]>> = HashMap::new();
+    for device in database.devices.values() {
+        for (hash, identical) in &device.identicals.map {
+            let inos = identical.inos.as_slice();
+            let size = device.inodes.get(inos[0]).unwrap().size;
+            joined
+                .entry((size, *hash))
+                .or_default()
+                .insert(device.dev, inos);
+        }
+    }
+    let mut found: Vec<_> = joined
+        .into_iter()
+        .filter(|(_, devices)| devices.len() > 1)
+        .map(|((size, hash), devices)| CrossDeviceContent {
+            size,
+            hash: HashHex(hash),
+            copies: devices
+                .into_iter()
+                .map(|(dev, inos)| {
+                    let inodes = &database.devices[&dev].inodes;
+                    let path = inos
+                        .iter()
+                        .flat_map(|&ino| &inodes.get(ino).unwrap().files)
+                        .min()
+                        .unwrap()
+                        .clone();
+                    let files = inos
+                        .iter()
+                        .map(|&ino| {
+                            let inode = inodes.get(ino).unwrap();
+                            inode.files.len() as u64 + inode.extra_paths
+                        })
+                        .sum();
+                    CrossDeviceCopy {
+                        dev: dev.0,
+                        path,
+                        files,
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+    found.sort_by(|a, b| {
+        (Reverse(a.size), a.hash.0.as_bytes()).cmp(&(Reverse(b.size), b.hash.0.as_bytes()))
+    });
+    found
+}
+
+pub fn print_cross_device(found: &[CrossDeviceContent], out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "Content on several devices, not linked:")?;
+    for content in found {
+        writeln!(
+            out,
+            "  {} bytes, {:.16}",
+            content.size.to_formatted_string(&Locale::en),
+            content.hash
+        )?;
+        for copy in &content.copies {
+            writeln!(
+                out,
+                "    device {}: {} ({} files)",
+                copy.dev,
+                copy.path.display(),
+                copy.files.to_formatted_string(&Locale::en)
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Two-step deletion: a run in delete mode records the deletions it plans to a manifest,
+//! and `apply-deletions` carries them out once the manifest is old enough and only if
+//! nothing listed in it changed in the meantime.
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context as _, Result};
+use filetime::FileTime;
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+
+use crate::confine::Roots;
+use crate::digest::{digest_file, HashHex};
+use crate::output::Output;
+use crate::remove_duplicate;
+use crate::report::{serialize_path, serialize_paths};
+use crate::timestamp::format_timestamp;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlannedDeletion {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    /// The identical file that is kept, which must still be there when applying.
+    #[serde(serialize_with = "serialize_path")]
+    pub original: PathBuf,
+    pub size: u64,
+    pub hash: HashHex,
+    pub mtime: i64,
+    pub mtime_nanos: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DeletionManifest {
+    /// When the scan was run, in seconds since the epoch.
+    scanned_at: i64,
+    /// The targets of the scan, for the record: the deletions are confined to the targets
+    /// given to `apply-deletions`, not to these.
+    #[serde(default, serialize_with = "serialize_paths")]
+    targets: Vec<PathBuf>,
+    deletions: Vec<PlannedDeletion>,
+}
+
+pub fn write_manifest(
+    path: &Path,
+    scanned_at: FileTime,
+    targets: &[PathBuf],
+    deletions: Vec<PlannedDeletion>,
+) -> Result<()> {
+    let manifest = DeletionManifest {
+        scanned_at: scanned_at.unix_seconds(),
+        targets: targets.to_vec(),
+        deletions,
+    };
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create the manifest: {}", path.to_string_lossy()))?;
+    let mut writer = io::BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, &manifest)
+        .with_context(|| format!("Failed to write the manifest: {}", path.to_string_lossy()))?;
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write the manifest: {}", path.to_string_lossy()))?;
+    Ok(())
+}
+
+/// Why a planned deletion may no longer be carried out, if it may not.
+fn drift(deletion: &PlannedDeletion) -> Result<Option<String>> {
+    let metadata = match fs::symlink_metadata(&deletion.path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some("gone".to_string())),
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!(
+                    "Failed to fs::metadata: {}",
+                    deletion.path.to_string_lossy()
+                )
+            })
+        }
+    };
+    if !metadata.is_file() {
+        return Ok(Some("no longer a regular file".to_string()));
+    }
+    if metadata.len() != deletion.size {
+        return Ok(Some(format!(
+            "size changed from {} to {}",
+            deletion.size,
+            metadata.len()
+        )));
+    }
+    let mtime = FileTime::from_last_modification_time(&metadata);
+    if mtime != FileTime::from_unix_time(deletion.mtime, deletion.mtime_nanos) {
+        return Ok(Some("mtime changed".to_string()));
+    }
+    for (path, role) in [
+        (&deletion.path, "content"),
+        (&deletion.original, "original"),
+    ] {
+        match digest_file(path, deletion.hash.0.algorithm()) {
+            Ok((hash, _)) if hash == deletion.hash.0 => {}
+            Ok(_) => return Ok(Some(format!("{} changed", role))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Some(format!("{} gone", role)))
+            }
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("Failed to calculate a hash: {}", path.to_string_lossy())
+                })
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Deletes the files listed in a manifest written before `written_before`. Nothing is
+/// deleted unless every file, and the original it duplicates, is as it was scanned, and
+/// nothing outside `roots`, given on the command line rather than read from the manifest.
+pub fn apply_deletions(
+    manifest_path: &Path,
+    written_before: FileTime,
+    roots: &Roots,
+    out: &mut Output,
+) -> Result<()> {
+    ensure!(
+        !roots.is_empty(),
+        "Refusing to apply deletions without targets"
+    );
+    let file = fs::File::open(manifest_path).with_context(|| {
+        format!(
+            "Failed to open the manifest: {}",
+            manifest_path.to_string_lossy()
+        )
+    })?;
+    let manifest: DeletionManifest = serde_json::from_reader(io::BufReader::new(file))
+        .with_context(|| {
+            format!(
+                "Failed to read the manifest: {}",
+                manifest_path.to_string_lossy()
+            )
+        })?;
+
+    let scanned_at = FileTime::from_unix_time(manifest.scanned_at, 0);
+    if scanned_at > written_before {
+        bail!(
+            "Refusing to apply {}: scanned at {}, not older than {}",
+            manifest_path.to_string_lossy(),
+            format_timestamp(scanned_at),
+            format_timestamp(written_before)
+        );
+    }
+
+    let mut drifted = Vec::new();
+    for deletion in &manifest.deletions {
+        if let Some(reason) = drift(deletion)? {
+            drifted.push(format!("  {}: {}", deletion.path.display(), reason));
+        }
+    }
+    if !drifted.is_empty() {
+        bail!(
+            "Refusing to apply {}: {} files changed since the scan:\n{}",
+            manifest_path.to_string_lossy(),
+            drifted.len(),
+            drifted.join("\n")
+        );
+    }
+
+    let mut freed: u64 = 0;
+    for deletion in &manifest.deletions {
+        writeln!(out, "rm {}", deletion.path.display())?;
+        remove_duplicate(roots, &deletion.path)?.restore(roots)?;
+        freed += deletion.size;
+    }
+    writeln!(
+        out,
+        "Deleted {} files, {} bytes",
+        (manifest.deletions.len() as u64).to_formatted_string(&Locale::en),
+        freed.to_formatted_string(&Locale::en)
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::confine::OutsideRoots;
+    use crate::test_utils::TreeBuilder;
+
+    #[test]
+    fn manifest_listing_a_path_outside_the_targets_aborts() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("inside/a", "same").unwrap();
+        tree.file("outside/c", "same").unwrap();
+        let victim = tree.path("outside/c");
+        let (hash, _) = digest_file(&victim, crate::digest::HashAlgorithm::Sha256).unwrap();
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&victim).unwrap());
+        let manifest = tree.path("manifest.json");
+        write_manifest(
+            &manifest,
+            FileTime::from_unix_time(0, 0),
+            &[tree.path("inside"), tree.path("outside")],
+            vec![PlannedDeletion {
+                path: victim.clone(),
+                original: tree.path("inside/a"),
+                size: 4,
+                hash: HashHex(hash),
+                mtime: mtime.unix_seconds(),
+                mtime_nanos: mtime.nanoseconds(),
+            }],
+        )
+        .unwrap();
+
+        let roots = Roots::new([tree.path("inside").as_path()]);
+        let err =
+            apply_deletions(&manifest, FileTime::now(), &roots, &mut Output::sink()).unwrap_err();
+        assert!(err.is::<OutsideRoots>(), "{err:?}");
+        assert!(victim.exists());
+    }
+}
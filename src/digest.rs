@@ -1,24 +1,286 @@
+use std::fmt;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::os::unix::fs::{FileExt as _, OpenOptionsExt};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
 use generic_array::typenum::U32;
 use generic_array::GenericArray;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
 
 pub type Sha256Value = GenericArray<u8, U32>;
 
-pub fn sha256file(path: &Path) -> io::Result<Sha256Value> {
-    let mut hasher = Sha256::new();
-    let file = fs::File::open(path)?;
+/// The hash function of `--hash`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    /// BLAKE3, faster than SHA-256 where the CPU has SIMD
+    Blake3,
+    /// SHA-256 of the SHA-256 of each 64 MiB leaf, which threads can hash apart; never
+    /// equal to the plain SHA-256 of a file
+    #[serde(rename = "sha256-tree")]
+    Sha256Tree,
+}
+
+/// A hash along with the algorithm that computed it, so that hashes of different
+/// algorithms are never equal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HashValue {
+    Sha256(Sha256Value),
+    Blake3([u8; 32]),
+    Sha256Tree(Sha256Value),
+}
+
+impl HashValue {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Self::Sha256(_) => HashAlgorithm::Sha256,
+            Self::Blake3(_) => HashAlgorithm::Blake3,
+            Self::Sha256Tree(_) => HashAlgorithm::Sha256Tree,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Sha256(hash) => hash,
+            Self::Blake3(hash) => hash,
+            Self::Sha256Tree(hash) => hash,
+        }
+    }
+}
+
+/// Marks hashes other than SHA-256 in manifests, which predate the choice.
+const BLAKE3_PREFIX: &str = "blake3:";
+const SHA256_TREE_PREFIX: &str = "sha256-tree:";
+
+/// A hash displayed as lowercase hex. The precision of the format, as in `{:.8}`,
+/// truncates it so that `--hash-width` does not need a separate code path.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HashHex(pub HashValue);
+
+impl fmt::Display for HashHex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&hex::encode(self.0.as_bytes()))
+    }
+}
+
+/// Parses a full-length hex hash in either case, as found in external manifests, which
+/// is SHA-256 unless prefixed with `blake3:` or `sha256-tree:`.
+impl FromStr for HashHex {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(s) = s.strip_prefix(BLAKE3_PREFIX) {
+            let mut hash = [0; 32];
+            hex::decode_to_slice(s, &mut hash)?;
+            return Ok(Self(HashValue::Blake3(hash)));
+        }
+        let mut hash = Sha256Value::default();
+        match s.strip_prefix(SHA256_TREE_PREFIX) {
+            Some(s) => {
+                hex::decode_to_slice(s, &mut hash)?;
+                Ok(Self(HashValue::Sha256Tree(hash)))
+            }
+            None => {
+                hex::decode_to_slice(s, &mut hash)?;
+                Ok(Self(HashValue::Sha256(hash)))
+            }
+        }
+    }
+}
+
+/// Serializes as [`FromStr`] parses, so that hashes keep their algorithm.
+impl Serialize for HashHex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            HashValue::Sha256(_) => serializer.collect_str(self),
+            HashValue::Blake3(_) => {
+                serializer.collect_str(&format_args!("{}{}", BLAKE3_PREFIX, self))
+            }
+            HashValue::Sha256Tree(_) => {
+                serializer.collect_str(&format_args!("{}{}", SHA256_TREE_PREFIX, self))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for HashHex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+pub const BUFFER_SIZE: usize = 65536;
+
+// There is no way to use uninitialized read buffer in stable rust 1.65.
+// Nightly rust has std::io::BorrowedBuf for this purpose.
+pub const CHUNK_SIZE: usize = 1024;
+
+/// O_DIRECT transfers must be aligned to the logical block size, which is at most this
+/// on the devices we care about.
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// Called with the number of bytes hashed since the previous call, at most once per buffer.
+/// An error stops the hashing and is returned as is.
+pub type OnProgress<'a> = &'a mut dyn FnMut(u64) -> io::Result<()>;
+
+/// The bytes left to read for hashing with `--io-budget`, shared by all threads.
+#[derive(Debug)]
+pub struct IoBudget {
+    remaining: AtomicU64,
+}
+
+/// The error of a hash stopped by an exhausted [`IoBudget`].
+#[derive(Debug)]
+pub struct BudgetExhausted;
+
+impl fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the --io-budget is spent")
+    }
+}
+
+impl std::error::Error for BudgetExhausted {}
+
+impl BudgetExhausted {
+    pub fn is(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|err| err.is::<Self>())
+    }
+}
+
+impl IoBudget {
+    pub fn new(bytes: u64) -> Self {
+        Self {
+            remaining: AtomicU64::new(bytes),
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining.load(Ordering::Relaxed) == 0
+    }
+
+    /// Takes `n` bytes read from the budget. Fails if nothing was left before them, so
+    /// that the budget is overdrawn by at most a buffer per thread.
+    pub fn charge(&self, n: u64) -> io::Result<()> {
+        let before = self
+            .remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                Some(remaining.saturating_sub(n))
+            })
+            .unwrap();
+        if before == 0 {
+            return Err(io::Error::other(BudgetExhausted));
+        }
+        Ok(())
+    }
+}
+
+/// The size of the leaves of `--hash sha256-tree`, and of the parts of a file hashed on
+/// separate threads.
+pub const LEAF_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The tree hash of `--hash sha256-tree`: the SHA-256 of the concatenated SHA-256 of each
+/// [`LEAF_SIZE`] bytes of the input, the last leaf shorter. An empty input has no leaf.
+/// This is the reference that [`digest_file_parallel`] computes on several threads.
+struct Sha256TreeHasher {
+    leaves: Sha256,
+    leaf: Sha256,
+    leaf_len: u64,
+    /// Always [`LEAF_SIZE`] but in tests, which would rather not hash gigabytes.
+    leaf_size: u64,
+}
+
+impl Default for Sha256TreeHasher {
+    fn default() -> Self {
+        Self::with_leaf_size(LEAF_SIZE)
+    }
+}
+
+impl Sha256TreeHasher {
+    fn with_leaf_size(leaf_size: u64) -> Self {
+        Self {
+            leaves: Sha256::new(),
+            leaf: Sha256::new(),
+            leaf_len: 0,
+            leaf_size,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.leaf_len == self.leaf_size {
+                self.leaves
+                    .update(std::mem::take(&mut self.leaf).finalize());
+                self.leaf_len = 0;
+            }
+            let take = ((self.leaf_size - self.leaf_len) as usize).min(data.len());
+            self.leaf.update(&data[..take]);
+            self.leaf_len += take as u64;
+            data = &data[take..];
+        }
+    }
+
+    fn finalize(mut self) -> Sha256Value {
+        if self.leaf_len > 0 {
+            self.leaves.update(self.leaf.finalize());
+        }
+        self.leaves.finalize()
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    // boxed for its stack of chaining values and its buffer
+    Blake3(Box<blake3::Hasher>),
+    Sha256Tree(Box<Sha256TreeHasher>),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Self::Blake3(Box::default()),
+            HashAlgorithm::Sha256Tree => Self::Sha256Tree(Box::default()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Self::Sha256Tree(hasher) => hasher.update(data),
+        }
+    }
 
-    const BUFFER_SIZE: usize = 65536;
-    let mut reader = io::BufReader::with_capacity(BUFFER_SIZE, file);
+    fn finalize(self) -> HashValue {
+        match self {
+            Self::Sha256(hasher) => HashValue::Sha256(hasher.finalize()),
+            Self::Blake3(hasher) => HashValue::Blake3(*hasher.finalize().as_bytes()),
+            Self::Sha256Tree(hasher) => HashValue::Sha256Tree(hasher.finalize()),
+        }
+    }
+}
 
-    // There is no way to use uninitialized read buffer in stable rust 1.65.
-    // Nightly rust has std::io::BorrowedBuf for this purpose.
-    const CHUNK_SIZE: usize = 1024;
+/// Feeds the rest of `reader` to `hasher`, returning the number of bytes read.
+fn update_from<R: Read>(
+    hasher: &mut Hasher,
+    mut reader: R,
+    on_progress: OnProgress,
+) -> io::Result<u64> {
+    let mut len: u64 = 0;
+    let mut pending: u64 = 0;
     let mut chunk = [0_u8; CHUNK_SIZE];
 
     loop {
@@ -27,6 +289,681 @@ pub fn sha256file(path: &Path) -> io::Result<Sha256Value> {
             break;
         }
         hasher.update(&chunk[..n]);
+        len += n as u64;
+        pending += n as u64;
+        if pending >= BUFFER_SIZE as u64 {
+            on_progress(pending)?;
+            pending = 0;
+        }
+    }
+    if pending > 0 {
+        on_progress(pending)?;
+    }
+    Ok(len)
+}
+
+/// Returns the hash along with the number of bytes read.
+fn digest_reader<R: Read>(
+    reader: R,
+    algorithm: HashAlgorithm,
+    on_progress: OnProgress,
+) -> io::Result<(HashValue, u64)> {
+    let mut hasher = Hasher::new(algorithm);
+    let len = update_from(&mut hasher, reader, on_progress)?;
+    Ok((hasher.finalize(), len))
+}
+
+fn open_buffered(path: &Path) -> io::Result<io::BufReader<fs::File>> {
+    let file = fs::File::open(path)?;
+    Ok(io::BufReader::with_capacity(BUFFER_SIZE, file))
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning the number of bytes read.
+fn fill<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Compares two files byte by byte, stopping at the first buffer that differs.
+pub fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = open_buffered(a)?;
+    let mut b = open_buffered(b)?;
+    let mut a_buf = vec![0_u8; BUFFER_SIZE];
+    let mut b_buf = vec![0_u8; BUFFER_SIZE];
+    loop {
+        let n = fill(&mut a, &mut a_buf)?;
+        let m = fill(&mut b, &mut b_buf)?;
+        if a_buf[..n] != b_buf[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Calculates the hash of a file, returning the number of bytes hashed as well so that
+/// callers can detect files whose size changed since they were examined.
+pub fn digest_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<(HashValue, u64)> {
+    digest_file_with_progress(path, algorithm, &mut |_| Ok(()))
+}
+
+/// Same as [`digest_file`], reporting the bytes hashed as it goes, for files whose hashing
+/// takes long enough to be watched.
+pub fn digest_file_with_progress(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    on_progress: OnProgress,
+) -> io::Result<(HashValue, u64)> {
+    digest_reader(open_buffered(path)?, algorithm, on_progress)
+}
+
+/// Whether the filesystem rejects O_DIRECT, for the file or for a read.
+fn rejects_direct_io(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EINVAL)
+}
+
+/// Opens the file with O_DIRECT, `None` if the filesystem rejects it.
+fn open_direct(path: &Path) -> io::Result<Option<fs::File>> {
+    match fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(err) if rejects_direct_io(&err) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// A buffer of [`BUFFER_SIZE`] bytes within `storage`, aligned for O_DIRECT.
+fn aligned_buffer(storage: &mut Vec<u8>) -> &mut [u8] {
+    storage.resize(BUFFER_SIZE + DIRECT_IO_ALIGNMENT, 0);
+    let offset = storage.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+    &mut storage[offset..offset + BUFFER_SIZE]
+}
+
+/// Same as [`digest_file`], but reads with O_DIRECT so that the page cache is left alone.
+/// Returns `None` if the filesystem rejects O_DIRECT for the file, for the caller to fall
+/// back to [`digest_file`]. A tail that cannot be read directly is read buffered.
+pub fn digest_file_direct(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    on_progress: OnProgress,
+) -> io::Result<Option<(HashValue, u64)>> {
+    let Some(mut file) = open_direct(path)? else {
+        return Ok(None);
+    };
+    let mut storage = Vec::new();
+    let buffer = aligned_buffer(&mut storage);
+    let mut hasher = Hasher::new(algorithm);
+    let mut len: u64 = 0;
+    loop {
+        match file.read(buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buffer[..n]);
+                len += n as u64;
+                on_progress(n as u64)?;
+            }
+            Err(err) if rejects_direct_io(&err) && len == 0 => return Ok(None),
+            Err(err) if rejects_direct_io(&err) => {
+                let mut file = open_buffered(path)?;
+                file.seek(SeekFrom::Start(len))?;
+                len += update_from(&mut hasher, file, on_progress)?;
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Some((hasher.finalize(), len)))
+}
+
+/// Calculates the hash of the first `limit` bytes of a file, returning the number of bytes
+/// hashed as well, which is less than `limit` for a shorter file.
+pub fn digest_prefix(
+    path: &Path,
+    limit: u64,
+    algorithm: HashAlgorithm,
+    on_progress: OnProgress,
+) -> io::Result<(HashValue, u64)> {
+    digest_reader(open_buffered(path)?.take(limit), algorithm, on_progress)
+}
+
+/// Calculates the hash of the first `head` and the last `tail` bytes of a file, which do not
+/// overlap, returning the number of bytes hashed as well, which is less than `head + tail`
+/// for a shorter file.
+pub fn digest_ends(
+    path: &Path,
+    head: u64,
+    tail: u64,
+    algorithm: HashAlgorithm,
+    on_progress: OnProgress,
+) -> io::Result<(HashValue, u64)> {
+    let mut reader = open_buffered(path)?;
+    let mut hasher = Hasher::new(algorithm);
+    let mut len = update_from(&mut hasher, (&mut reader).take(head), on_progress)?;
+    if tail > 0 {
+        let size = reader.get_ref().metadata()?.len();
+        reader.seek(SeekFrom::Start(size.saturating_sub(tail).max(len)))?;
+        len += update_from(&mut hasher, reader.take(tail), on_progress)?;
+    }
+    Ok((hasher.finalize(), len))
+}
+
+/// Same as [`digest_ends`], but reads with O_DIRECT, in aligned blocks of which only the
+/// bytes of either end are hashed. Returns `None` if the filesystem rejects O_DIRECT, for
+/// the caller to fall back to [`digest_ends`].
+pub fn digest_ends_direct(
+    path: &Path,
+    head: u64,
+    tail: u64,
+    algorithm: HashAlgorithm,
+    on_progress: OnProgress,
+) -> io::Result<Option<(HashValue, u64)>> {
+    let Some(file) = open_direct(path)? else {
+        return Ok(None);
+    };
+    let size = file.metadata()?.len();
+    let head = head.min(size);
+    let ends = [0..head, size.saturating_sub(tail).max(head)..size];
+    let mut storage = Vec::new();
+    let buffer = aligned_buffer(&mut storage);
+    let mut hasher = Hasher::new(algorithm);
+    let mut len = 0;
+    for end in ends {
+        let mut offset = end.start - end.start % DIRECT_IO_ALIGNMENT as u64;
+        while offset < end.end {
+            let n = match file.read_at(buffer, offset) {
+                Ok(0) => break,
+                Ok(n) => n as u64,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if rejects_direct_io(&err) => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            let (start, stop) = (end.start.max(offset), end.end.min(offset + n));
+            if start < stop {
+                hasher.update(&buffer[(start - offset) as usize..(stop - offset) as usize]);
+                len += stop - start;
+                on_progress(stop - start)?;
+            }
+            offset += n;
+        }
+    }
+    Ok(Some((hasher.finalize(), len)))
+}
+
+/// When a file is large enough for its parts to be hashed on separate threads, and on how
+/// many of them.
+#[derive(Debug, Clone, Copy)]
+pub struct ParallelHashing {
+    pub threshold: u64,
+    pub workers: usize,
+}
+
+/// The size of the reads of a part, larger than [`BUFFER_SIZE`] for fewer progress messages.
+const PART_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The size of the reads of a file hashed with BLAKE3 on several threads: each read is
+/// shared out among them by the crate, which needs a few chunks per thread.
+const RAYON_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Hashes the rest of `file` with BLAKE3 on `workers` threads, through the crate's own
+/// multithreading.
+fn digest_blake3_rayon(
+    mut file: fs::File,
+    workers: usize,
+    on_progress: OnProgress,
+) -> io::Result<(HashValue, u64)> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()
+        .map_err(io::Error::other)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0_u8; RAYON_BUFFER_SIZE];
+    let mut len = 0;
+    loop {
+        let n = fill(&mut file, &mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        pool.install(|| hasher.update_rayon(&buffer[..n]));
+        len += n as u64;
+        on_progress(n as u64)?;
+    }
+    Ok((HashValue::Blake3(*hasher.finalize().as_bytes()), len))
+}
+
+enum PartMessage {
+    Progress(u64),
+    Done(usize, io::Result<(Sha256Value, u64)>),
+}
+
+/// Hashes the part `index` of the file, a leaf of `--hash sha256-tree` of `part_size`
+/// bytes, through to the end of the file if it is the last, returning its SHA-256 along
+/// with the number of bytes read.
+fn hash_part(
+    file: &fs::File,
+    part_size: u64,
+    index: u64,
+    last: bool,
+    stop: &AtomicBool,
+    progress: &mpsc::Sender<PartMessage>,
+) -> io::Result<(Sha256Value, u64)> {
+    let start = index * part_size;
+    let end = if last { u64::MAX } else { start + part_size };
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0_u8; PART_BUFFER_SIZE];
+    let mut offset = start;
+    while offset < end && !stop.load(Ordering::Relaxed) {
+        let want = (end - offset).min(PART_BUFFER_SIZE as u64) as usize;
+        let n = match file.read_at(&mut buffer[..want], offset) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        hasher.update(&buffer[..n]);
+        offset += n as u64;
+        let _ = progress.send(PartMessage::Progress(n as u64));
+    }
+    Ok((hasher.finalize(), offset - start))
+}
+
+/// Same as [`digest_file_with_progress`], but on several threads, which yields the same
+/// hash: BLAKE3 through the crate's own multithreading, and `--hash sha256-tree` in parts of
+/// [`LEAF_SIZE`] bytes. Returns `None` if the file is smaller than the threshold, or for
+/// plain SHA-256, which has no parts, for the caller to hash it in one go.
+pub fn digest_file_parallel(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    parallel: ParallelHashing,
+    on_progress: OnProgress,
+) -> io::Result<Option<(HashValue, u64)>> {
+    digest_file_in_parts(path, algorithm, parallel, LEAF_SIZE, on_progress)
+}
+
+/// Same as [`digest_file_parallel`], in parts of `part_size` bytes for `--hash
+/// sha256-tree`: the leaf size but in tests.
+fn digest_file_in_parts(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    parallel: ParallelHashing,
+    part_size: u64,
+    on_progress: OnProgress,
+) -> io::Result<Option<(HashValue, u64)>> {
+    if algorithm == HashAlgorithm::Sha256 || parallel.workers < 2 {
+        return Ok(None);
+    }
+    let file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    if size < parallel.threshold.max(1) {
+        return Ok(None);
+    }
+    if algorithm == HashAlgorithm::Blake3 {
+        return digest_blake3_rayon(file, parallel.workers, on_progress).map(Some);
+    }
+    let parts = size.div_ceil(part_size);
+
+    let next = AtomicU64::new(0);
+    let stop = AtomicBool::new(false);
+    let mut done: Vec<Option<(Sha256Value, u64)>> = (0..parts).map(|_| None).collect();
+    let mut error = None;
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..parallel.workers.min(parts as usize) {
+            let sender = sender.clone();
+            let (file, next, stop) = (&file, &next, &stop);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= parts || stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let result = hash_part(file, part_size, index, index + 1 == parts, stop, &sender);
+                let _ = sender.send(PartMessage::Done(index as usize, result));
+            });
+        }
+        drop(sender);
+        for message in receiver {
+            if error.is_some() {
+                continue;
+            }
+            let result = match message {
+                PartMessage::Progress(n) => on_progress(n),
+                PartMessage::Done(index, result) => result.map(|part| done[index] = Some(part)),
+            };
+            if let Err(err) = result {
+                stop.store(true, Ordering::Relaxed);
+                error = Some(err);
+            }
+        }
+    });
+    if let Some(err) = error {
+        return Err(err);
+    }
+
+    let mut len = 0;
+    let mut leaves = Sha256::new();
+    for (leaf, n) in done.into_iter().map(Option::unwrap) {
+        len += n;
+        leaves.update(leaf);
+    }
+    Ok(Some((HashValue::Sha256Tree(leaves.finalize()), len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::progress::ProgressHandle;
+    use crate::test_utils::TreeBuilder;
+
+    /// The official test vectors: the hash of the input of each length, bytes 0 to 250
+    /// repeating.
+    const VECTORS: [(usize, [u8; 32]); 7] = [
+        (
+            0,
+            hex!("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+        ),
+        (
+            1023,
+            hex!("10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11"),
+        ),
+        (
+            1024,
+            hex!("42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+        ),
+        (
+            1025,
+            hex!("d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+        ),
+        (
+            2048,
+            hex!("e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+        ),
+        (
+            2049,
+            hex!("5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030"),
+        ),
+        (
+            1 << 20,
+            hex!("74cb441fd087764ca9c3694da742ebe30cbeb3060a17009ca81825c7a8d10343"),
+        ),
+    ];
+
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn digest_bytes(data: &[u8], algorithm: HashAlgorithm) -> HashValue {
+        digest_reader(data, algorithm, &mut |_| Ok(())).unwrap().0
+    }
+
+    #[test]
+    fn known_digests() {
+        let empty = hex!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        let abc = hex!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(digest_bytes(b"", HashAlgorithm::Sha256).as_bytes(), empty);
+        assert_eq!(digest_bytes(b"abc", HashAlgorithm::Sha256).as_bytes(), abc);
+        // no leaf, then a single one
+        assert_eq!(
+            digest_bytes(b"", HashAlgorithm::Sha256Tree).as_bytes(),
+            empty
+        );
+        assert_eq!(
+            digest_bytes(b"abc", HashAlgorithm::Sha256Tree).as_bytes(),
+            hex!("4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358")
+        );
+        assert_eq!(
+            digest_bytes(b"abc", HashAlgorithm::Blake3).as_bytes(),
+            hex!("6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85")
+        );
+        for (len, expected) in VECTORS {
+            assert_eq!(
+                digest_bytes(&input(len), HashAlgorithm::Blake3).as_bytes(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn official_blake3_vectors_in_pieces() {
+        for (len, expected) in VECTORS {
+            // in updates that straddle the blocks and the chunks
+            let mut hasher = Hasher::new(HashAlgorithm::Blake3);
+            for piece in input(len).chunks(100) {
+                hasher.update(piece);
+            }
+            assert_eq!(hasher.finalize().as_bytes(), expected, "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn parallel_blake3_matches_the_single_threaded_hash() {
+        let mut tree = TreeBuilder::new().unwrap();
+        let parallel = ParallelHashing {
+            threshold: 1,
+            workers: 4,
+        };
+        let larger = [(2 * RAYON_BUFFER_SIZE + 1, None)];
+        for (len, expected) in VECTORS
+            .map(|(len, hash)| (len, Some(hash)))
+            .into_iter()
+            // but the empty vector, which is never hashed on threads
+            .skip(1)
+            .chain(larger)
+        {
+            let data = input(len);
+            tree.file("input", &data).unwrap();
+            let (hash, n) = digest_file_parallel(
+                &tree.path("input"),
+                HashAlgorithm::Blake3,
+                parallel,
+                &mut |_| Ok(()),
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(n, len as u64);
+            assert_eq!(
+                hash,
+                digest_bytes(&data, HashAlgorithm::Blake3),
+                "{len} bytes"
+            );
+            if let Some(expected) = expected {
+                assert_eq!(hash.as_bytes(), expected, "{len} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_hash_matches_the_reference_tree() {
+        let mut tree = TreeBuilder::new().unwrap();
+        let parallel = ParallelHashing {
+            threshold: 1,
+            workers: 4,
+        };
+        let leaf = 64 * 1024;
+        // less than a leaf, a leaf, and a few leaves, short of a byte, exact and beyond
+        for len in [
+            1000,
+            leaf - 1,
+            leaf,
+            leaf + 1,
+            5 * leaf - 1,
+            5 * leaf,
+            5 * leaf + 1,
+        ] {
+            let data = input(len);
+            tree.file("input", &data).unwrap();
+            let in_parts = |algorithm| {
+                digest_file_in_parts(
+                    &tree.path("input"),
+                    algorithm,
+                    parallel,
+                    leaf as u64,
+                    &mut |_| Ok(()),
+                )
+                .unwrap()
+                .unwrap()
+            };
+
+            let mut reference = Sha256TreeHasher::with_leaf_size(leaf as u64);
+            reference.update(&data);
+            let expected = HashValue::Sha256Tree(reference.finalize());
+            assert_eq!(
+                in_parts(HashAlgorithm::Sha256Tree),
+                (expected, len as u64),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn tree_hashes_never_mix_with_plain_ones() {
+        let (plain, tree) = (
+            digest_bytes(b"abc", HashAlgorithm::Sha256),
+            digest_bytes(b"abc", HashAlgorithm::Sha256Tree),
+        );
+        assert_ne!(plain, tree);
+        // even where their bytes are equal, as for the empty input
+        let (plain, tree) = (
+            digest_bytes(b"", HashAlgorithm::Sha256),
+            digest_bytes(b"", HashAlgorithm::Sha256Tree),
+        );
+        assert_eq!(plain.as_bytes(), tree.as_bytes());
+        assert_ne!(plain, tree);
+        let serialized = serde_json::to_string(&HashHex(tree)).unwrap();
+        let parsed: HashHex = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(parsed, HashHex(tree));
+        assert!(serialized.starts_with("\"sha256-tree:"), "{serialized}");
+    }
+
+    #[test]
+    fn hex_hashes_round_trip_in_either_case() {
+        for algorithm in [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Sha256Tree,
+        ] {
+            let hash = HashHex(digest_bytes(b"abc", algorithm));
+            let serialized = serde_json::to_value(hash).unwrap();
+            let serialized = serialized.as_str().unwrap();
+            assert_eq!(serialized.parse(), Ok(hash));
+            // the prefix stays lowercase, the digits need not
+            let (prefix, digits) = serialized.split_at(serialized.len() - 64);
+            let upper = format!("{}{}", prefix, digits.to_uppercase());
+            assert_eq!(upper.parse(), Ok(hash), "{upper}");
+        }
+        let hash = digest_bytes(b"abc", HashAlgorithm::Sha256);
+        assert_eq!(HashHex(hash).to_string().len(), 64);
+        assert_eq!(format!("{:.8}", HashHex(hash)), "ba7816bf");
+    }
+
+    #[test]
+    fn hex_hashes_of_the_wrong_length_or_digits_are_rejected() {
+        let full = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert!(full.parse::<HashHex>().is_ok());
+        for input in [
+            "",
+            &full[..62],
+            &full[..63],
+            &format!("{full}00"),
+            &full.replace('b', "g"),
+            &format!("blake3:{}", &full[..32]),
+            &format!("sha256-tree:{full}0"),
+            &format!("md5:{full}"),
+        ] {
+            assert!(input.parse::<HashHex>().is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn budget_is_overdrawn_by_the_last_charge_only() {
+        let budget = IoBudget::new(100);
+        budget.charge(60).unwrap();
+        assert!(!budget.is_exhausted());
+        // more than is left, but something was
+        budget.charge(60).unwrap();
+        assert!(budget.is_exhausted());
+        let err = budget.charge(1).unwrap_err();
+        assert!(BudgetExhausted::is(&err), "{err}");
+    }
+
+    #[test]
+    fn direct_ends_match_buffered_ones() {
+        let mut tree = TreeBuilder::new().unwrap();
+        let len = 3 * DIRECT_IO_ALIGNMENT + 100;
+        tree.file("input", input(len)).unwrap();
+        let path = tree.path("input");
+        // within a block, across blocks, overlapping, and beyond the file
+        for (head, tail) in [(0, 0), (10, 10), (5000, 3000), (8000, 8000), (0, 1 << 20)] {
+            let buffered = digest_ends(&path, head, tail, HashAlgorithm::Sha256, &mut |_| Ok(()));
+            let direct =
+                digest_ends_direct(&path, head, tail, HashAlgorithm::Sha256, &mut |_| Ok(()));
+            // unless the filesystem of the temporary directory rejects O_DIRECT
+            if let Some(direct) = direct.unwrap() {
+                assert_eq!(direct, buffered.unwrap(), "{head} {tail}");
+            }
+        }
+    }
+
+    #[test]
+    fn progress_adds_up_to_the_file_size() {
+        let mut tree = TreeBuilder::new().unwrap();
+        let len = 5 * BUFFER_SIZE + 123;
+        tree.file("input", input(len)).unwrap();
+        let path = tree.path("input");
+        let parallel = ParallelHashing {
+            threshold: 1,
+            workers: 4,
+        };
+        type Digest<'a> = &'a dyn Fn(OnProgress) -> io::Result<Option<(HashValue, u64)>>;
+        // with the size of their buffers
+        let digests: [(&str, usize, Digest); 4] = [
+            ("buffered", BUFFER_SIZE, &|on_progress| {
+                digest_file_with_progress(&path, HashAlgorithm::Sha256, on_progress).map(Some)
+            }),
+            ("direct", BUFFER_SIZE, &|on_progress| {
+                digest_file_direct(&path, HashAlgorithm::Sha256, on_progress)
+            }),
+            ("blake3", RAYON_BUFFER_SIZE, &|on_progress| {
+                digest_file_parallel(&path, HashAlgorithm::Blake3, parallel, on_progress)
+            }),
+            ("tree", BUFFER_SIZE, &|on_progress| {
+                digest_file_in_parts(
+                    &path,
+                    HashAlgorithm::Sha256Tree,
+                    parallel,
+                    BUFFER_SIZE as u64,
+                    on_progress,
+                )
+            }),
+        ];
+        for (name, buffer, digest) in digests {
+            let progress = ProgressHandle::new();
+            let mut calls = 0;
+            let result = digest(&mut |n| {
+                progress.add_bytes_hashed(n);
+                calls += 1;
+                Ok(())
+            });
+            // unless the filesystem of the temporary directory rejects O_DIRECT
+            let Some((_, hashed)) = result.unwrap() else {
+                continue;
+            };
+            assert_eq!(hashed, len as u64, "{name}");
+            assert_eq!(progress.bytes_hashed(), len as u64, "{name}");
+            // at most once per buffer
+            assert!((1..=len / buffer + 1).contains(&calls), "{name}: {calls}");
+        }
     }
-    Ok(hasher.finalize())
 }
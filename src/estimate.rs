@@ -0,0 +1,168 @@
+//! `--estimate-relink`: how long the relink phase will take, from the number of links to
+//! replace and the latency of linking and unlinking measured on each device.
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result};
+use filetime::FileTime;
+use num_format::{Locale, ToFormattedString};
+
+use crate::checks::parent_dir;
+use crate::confine::{Mutation, OutsideRoots, Roots};
+use crate::models::*;
+
+/// Links created and removed to calibrate a device.
+const CALIBRATION_ROUNDS: usize = 10;
+
+/// Paths that would be replaced on the device, and a directory next to one of them.
+fn planned_operations(device: &Device) -> (u64, Option<&Path>) {
+    let mut operations = 0;
+    let mut dir = None;
+    for identical in device.identicals.map.values() {
+        let mut inodes: Vec<_> = identical
+            .inos
+            .iter()
+            .map(|&ino| device.inodes.get(ino).unwrap())
+            .collect();
+        // the original, which keeps its paths, is the inode with the most links
+        inodes.sort_by_key(|inode| std::cmp::Reverse(inode.nlink));
+        for inode in inodes.iter().skip(1) {
+            operations += inode.files.len() as u64;
+            dir = dir.or_else(|| inode.files.first().map(|path| parent_dir(path)));
+        }
+    }
+    (operations, dir)
+}
+
+/// Times a link and an unlink in `dir`, through a scratch file that is always removed.
+/// The mtime of `dir` is restored afterwards.
+fn calibrate(roots: &Roots, dir: &Path) -> Result<Vec<Duration>> {
+    let dir_mtime = fs::metadata(dir)
+        .map(|metadata| FileTime::from_last_modification_time(&metadata))
+        .with_context(|| format!("Failed to fs::metadata: {}", dir.to_string_lossy()))?;
+    let name = format!(".dedup-calibrate-{}", std::process::id());
+    let scratch = dir.join(&name);
+    let link = dir.join(format!("{}-link", name));
+    roots.check(Mutation::Scratch, &scratch)?;
+    fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(&scratch)
+        .with_context(|| format!("Failed to create: {}", scratch.to_string_lossy()))?;
+    let result = (0..CALIBRATION_ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            fs::hard_link(&scratch, &link)
+                .with_context(|| format!("Failed to fs::hard_link: {}", link.to_string_lossy()))?;
+            fs::remove_file(&link).with_context(|| {
+                format!("Failed to fs::remove_file: {}", link.to_string_lossy())
+            })?;
+            Ok(start.elapsed())
+        })
+        .collect();
+    let _ = fs::remove_file(&link);
+    fs::remove_file(&scratch)
+        .with_context(|| format!("Failed to fs::remove_file: {}", scratch.to_string_lossy()))?;
+    filetime::set_file_mtime(dir, dir_mtime).with_context(|| {
+        format!(
+            "Failed to filetime::set_file_mtime to restore a directory mtime: {}",
+            dir.to_string_lossy()
+        )
+    })?;
+    result
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 1.0 {
+        "under a second".to_string()
+    } else if seconds < 90.0 {
+        format!("{:.0} seconds", seconds.ceil())
+    } else if seconds < 90.0 * 60.0 {
+        format!("{:.0} minutes", (seconds / 60.0).ceil())
+    } else {
+        format!("{:.1} hours", seconds / 3600.0)
+    }
+}
+
+/// Prints to stderr the number of operations ahead and how long they should take, from
+/// the median to the slowest link measured. Report-only devices are not calibrated. Only
+/// a calibration outside the roots fails.
+pub fn estimate_relink(roots: &Roots, database: &Database) -> Result<()> {
+    let mut operations = 0;
+    let mut devices = 0;
+    let mut low = Duration::ZERO;
+    let mut high = Duration::ZERO;
+    let mut uncalibrated = 0;
+    for device in database.devices.values() {
+        let (count, dir) = planned_operations(device);
+        let Some(dir) = dir.filter(|_| count > 0 && !device.report_only) else {
+            continue;
+        };
+        operations += count;
+        devices += 1;
+        match calibrate(roots, dir) {
+            Ok(mut samples) => {
+                samples.sort();
+                low += samples[samples.len() / 2].mul_f64(count as f64);
+                high += samples[samples.len() - 1].mul_f64(count as f64);
+            }
+            Err(err) if err.is::<OutsideRoots>() => return Err(err),
+            Err(err) => {
+                eprintln!("Warning: {:#}", err);
+                uncalibrated += 1;
+            }
+        }
+    }
+    if uncalibrated == 0 {
+        eprintln!(
+            "About {} operations on {} devices, est. {} to {}",
+            operations.to_formatted_string(&Locale::en),
+            devices,
+            format_duration(low),
+            format_duration(high)
+        );
+    } else {
+        eprintln!(
+            "About {} operations on {} devices; {} could not be calibrated, est. at least {} to {}",
+            operations.to_formatted_string(&Locale::en),
+            devices,
+            uncalibrated,
+            format_duration(low),
+            format_duration(high)
+        );
+    }
+    Ok(())
+}
+
+/// Asks whether to go on with the relink, reading the answer from `input`. Only y or yes,
+/// in any case, confirms; the end of the input does not.
+pub fn confirmed(input: impl BufRead) -> Result<bool> {
+    eprint!("Go on with the relink? [y/N] ");
+    io::stderr().flush()?;
+    let answer = match input.lines().next() {
+        Some(line) => line.context("Failed to read the answer")?,
+        None => String::new(),
+    };
+    let answer = answer.trim().to_ascii_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_yes_confirms() {
+        for answer in ["y\n", "Yes\n", " YES "] {
+            assert!(confirmed(answer.as_bytes()).unwrap(), "{answer:?}");
+        }
+        for answer in ["", "\n", "n\n", "no\n", "yes please\n", "\ny\n"] {
+            assert!(!confirmed(answer.as_bytes()).unwrap(), "{answer:?}");
+        }
+    }
+}
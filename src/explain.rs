@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::report::serialize_path;
+
+#[derive(Debug, Serialize)]
+pub struct Explanation {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub decisions: Vec<String>,
+    #[serde(skip)]
+    key: PathBuf,
+    #[serde(skip)]
+    seen: bool,
+    #[serde(skip)]
+    grouped: bool,
+}
+
+/// Records the decision chain for the paths given with `--explain`.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Explain {
+    explanations: Vec<Explanation>,
+    #[serde(skip)]
+    names: HashSet<OsString>,
+}
+
+fn key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl Explain {
+    pub fn new(paths: &[PathBuf]) -> Self {
+        let explanations = paths
+            .iter()
+            .map(|path| Explanation {
+                path: path.clone(),
+                decisions: Vec::new(),
+                key: key(path),
+                seen: false,
+                grouped: false,
+            })
+            .collect();
+        let names = paths
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_os_string())
+            .collect();
+        Self {
+            explanations,
+            names,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.explanations.is_empty()
+    }
+
+    fn find(&mut self, path: &Path) -> Option<&mut Explanation> {
+        if self.names.is_empty() || !self.names.contains(path.file_name()?) {
+            return None;
+        }
+        let key = key(path);
+        self.explanations
+            .iter_mut()
+            .find(|explanation| explanation.key == key)
+    }
+
+    /// Records a decision about `path` if it is watched.
+    pub fn note(&mut self, path: &Path, decision: impl FnOnce() -> String) {
+        if let Some(explanation) = self.find(path) {
+            explanation.seen = true;
+            explanation.decisions.push(decision());
+        }
+    }
+
+    /// Records a decision taken in the relink phase about `path` if it is watched.
+    pub fn note_grouped(&mut self, path: &Path, decision: impl FnOnce() -> String) {
+        if let Some(explanation) = self.find(path) {
+            explanation.seen = true;
+            explanation.grouped = true;
+            explanation.decisions.push(decision());
+        }
+    }
+
+    /// Closes the decision chains of the paths that never reached the relink phase.
+    pub fn finish(&mut self) {
+        for explanation in &mut self.explanations {
+            if !explanation.seen {
+                explanation
+                    .decisions
+                    .push("never found by the walk".to_string());
+            } else if !explanation.grouped {
+                explanation
+                    .decisions
+                    .push("not part of any duplicate group".to_string());
+            }
+        }
+    }
+
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        for explanation in &self.explanations {
+            writeln!(out, "Explain {}:", explanation.path.display())?;
+            for decision in &explanation.decisions {
+                writeln!(out, "  - {}", decision)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+//! `--list`: the groups of identical files in the format of fdupes, for tools that read it:
+//! the paths of each group on consecutive lines, each group followed by an empty line.
+
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use crate::models::Database;
+
+/// Prints the groups sorted by their first path, each sorted by path. With `size`, each
+/// group starts with the size of its files as `fdupes --size` does; with
+/// `hardlinks_as_one`, only the first path of each inode is listed.
+pub fn print_fdupes(
+    database: &Database,
+    size: bool,
+    hardlinks_as_one: bool,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut groups: Vec<(u64, Vec<&PathBuf>)> = Vec::new();
+    for device in database.devices.values() {
+        for identical in device.identicals.map.values() {
+            let mut paths = Vec::new();
+            let mut file_size = 0;
+            for &ino in identical.inos.iter() {
+                let inode = device.inodes.get(ino).unwrap();
+                file_size = inode.size;
+                let mut files: Vec<_> = inode.files.iter().collect();
+                files.sort();
+                if hardlinks_as_one {
+                    files.truncate(1);
+                }
+                paths.extend(files);
+            }
+            paths.sort();
+            groups.push((file_size, paths));
+        }
+    }
+    groups.sort_by(|a, b| a.1.cmp(&b.1));
+    for (file_size, paths) in groups {
+        if size {
+            writeln!(out, "{} bytes each:", file_size)?;
+        }
+        for path in paths {
+            writeln!(out, "{}", path.display())?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
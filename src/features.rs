@@ -0,0 +1,207 @@
+//! Features that depend on the filesystem or on privileges, probed once at startup in the
+//! first target, so that a run where they do nothing says so instead of silently degrading.
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+use filetime::FileTime;
+use serde::Serialize;
+
+use crate::checks::parent_dir;
+use crate::confine::{Mutation, Roots};
+use crate::digest::{digest_file_direct, HashAlgorithm};
+use crate::reflink::reflink;
+
+/// Large enough for a read aligned as O_DIRECT requires.
+const PROBE_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feature {
+    /// Reading with O_DIRECT, for `--direct-io`.
+    DirectIo,
+    /// Sharing extents with FICLONE, for `--conflict-fallback reflink`.
+    Reflink,
+    /// Reading the inode flags with FS_IOC_GETFLAGS, to leave append-only and immutable
+    /// directories alone.
+    InodeFlags,
+}
+
+impl Feature {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::DirectIo => "direct-io",
+            Self::Reflink => "reflink",
+            Self::InodeFlags => "inode-flags",
+        }
+    }
+
+    /// What the run does instead when the feature is not available.
+    fn fallback(self) -> &'static str {
+        match self {
+            Self::DirectIo => "buffered reads",
+            Self::Reflink => "skipping the duplicates",
+            Self::InodeFlags => "append-only directories not detected",
+        }
+    }
+}
+
+/// The outcome of the probe of a requested feature.
+#[derive(Debug, Serialize)]
+pub struct FeatureStatus {
+    pub feature: Feature,
+    pub available: bool,
+    /// What the run does instead, when not available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub degraded_to: Option<&'static str>,
+    /// Why the probe itself failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl FeatureStatus {
+    fn new(feature: Feature, probed: Result<bool>) -> Self {
+        let (available, error) = match probed {
+            Ok(available) => (available, None),
+            Err(err) => (false, Some(format!("{:#}", err))),
+        };
+        Self {
+            feature,
+            available,
+            degraded_to: (!available).then(|| feature.fallback()),
+            error,
+        }
+    }
+}
+
+/// Scratch files of the probes, removed along with the mtime of their directory restored
+/// when dropped, however the probes went.
+struct Scratch<'a> {
+    roots: &'a Roots,
+    dir: PathBuf,
+    dir_mtime: FileTime,
+    files: Vec<PathBuf>,
+}
+
+impl<'a> Scratch<'a> {
+    fn new(roots: &'a Roots, dir: &Path) -> Result<Self> {
+        let dir_mtime = fs::metadata(dir)
+            .map(|metadata| FileTime::from_last_modification_time(&metadata))
+            .with_context(|| format!("Failed to fs::metadata: {}", dir.to_string_lossy()))?;
+        Ok(Self {
+            roots,
+            dir: dir.to_path_buf(),
+            dir_mtime,
+            files: Vec::new(),
+        })
+    }
+
+    fn create(&mut self, name: &str, content: &[u8]) -> Result<PathBuf> {
+        let path = self
+            .dir
+            .join(format!(".dedup-probe-{}-{}", std::process::id(), name));
+        self.roots.check(Mutation::Scratch, &path)?;
+        let mut file = fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create: {}", path.to_string_lossy()))?;
+        self.files.push(path.clone());
+        file.write_all(content)
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("Failed to write: {}", path.to_string_lossy()))?;
+        Ok(path)
+    }
+}
+
+impl Drop for Scratch<'_> {
+    fn drop(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        for file in &self.files {
+            if let Err(err) = fs::remove_file(file) {
+                eprintln!(
+                    "Warning: Failed to remove a probe file: {}: {}",
+                    file.display(),
+                    err
+                );
+            }
+        }
+        let _ = filetime::set_file_mtime(&self.dir, self.dir_mtime);
+    }
+}
+
+fn probe_direct_io(scratch: &mut Scratch) -> Result<bool> {
+    let path = scratch.create("direct", &[0; PROBE_SIZE])?;
+    let result = digest_file_direct(&path, HashAlgorithm::Sha256, &mut |_| Ok(()))
+        .with_context(|| format!("Failed to read with O_DIRECT: {}", path.to_string_lossy()))?;
+    Ok(result.is_some())
+}
+
+fn probe_reflink(scratch: &mut Scratch) -> Result<bool> {
+    let original = scratch.create("original", &[0; PROBE_SIZE])?;
+    let duplicate = scratch.create("duplicate", &[0; PROBE_SIZE])?;
+    reflink(scratch.roots, &original, &duplicate)
+}
+
+fn probe_inode_flags(dir: &Path) -> Result<bool> {
+    let file = fs::File::open(dir)
+        .with_context(|| format!("Failed to open: {}", dir.to_string_lossy()))?;
+    let mut flags: libc::c_long = 0;
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) };
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOTTY | libc::EOPNOTSUPP | libc::EINVAL) => Ok(false),
+            _ => Err(err).with_context(|| {
+                format!("Failed to ioctl FS_IOC_GETFLAGS: {}", dir.to_string_lossy())
+            }),
+        };
+    }
+    Ok(true)
+}
+
+/// Probes each of `features` in `target`, or the directory of it if it is a file. A probe
+/// that fails counts as the feature being unavailable; nothing here fails the run.
+pub fn probe_features(roots: &Roots, target: &Path, features: &[Feature]) -> Vec<FeatureStatus> {
+    let dir = if target.is_dir() {
+        target
+    } else {
+        parent_dir(target)
+    };
+    let mut scratch = Scratch::new(roots, dir);
+    features
+        .iter()
+        .map(|&feature| {
+            let probed = match (feature, &mut scratch) {
+                (Feature::InodeFlags, _) => probe_inode_flags(dir),
+                (_, Err(err)) => Err(anyhow::anyhow!("{:#}", err)),
+                (Feature::DirectIo, Ok(scratch)) => probe_direct_io(scratch),
+                (Feature::Reflink, Ok(scratch)) => probe_reflink(scratch),
+            };
+            FeatureStatus::new(feature, probed)
+        })
+        .collect()
+}
+
+pub fn print_features(features: &[FeatureStatus], out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{:<12} {:<10} Degraded to", "Feature", "Available")?;
+    for status in features {
+        writeln!(
+            out,
+            "{:<12} {:<10} {}",
+            status.feature.as_str(),
+            if status.available { "yes" } else { "no" },
+            status.degraded_to.unwrap_or("-")
+        )?;
+        if let Some(error) = &status.error {
+            writeln!(out, "  {}", error)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! A read-only C ABI over the scan: targets go in, groups of identical files come out.
+//! Nothing is ever linked through it. See `include/dedup.h`.
+
+use std::collections::HashSet;
+use std::ffi::{c_char, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+use std::slice;
+
+use clap::Parser as _;
+
+use crate::digest::HashValue;
+use crate::report::Report;
+use crate::{scan_targets, Args};
+
+pub struct DedupOptions {
+    targets: Vec<PathBuf>,
+}
+
+struct ScanGroup {
+    hash: HashValue,
+    size: u64,
+    /// The original first, as a run would pick it, then the duplicates.
+    paths: Vec<Vec<u8>>,
+}
+
+pub struct DedupScan {
+    groups: Vec<ScanGroup>,
+    error: Option<CString>,
+}
+
+fn run_scan(options: &DedupOptions) -> anyhow::Result<Vec<ScanGroup>> {
+    let mut args = Args::parse_from(["dedup", "--dry-run"]);
+    args.targets = options.targets.clone();
+    let mut report = Report::new();
+    let database = scan_targets(&args, &HashSet::new(), &mut report)?;
+
+    let mut groups = Vec::new();
+    let mut devs: Vec<_> = database.devices.keys().collect();
+    devs.sort();
+    for dev in devs {
+        let device = &database.devices[dev];
+        for (hash, identical) in &device.identicals.map {
+            let mut inodes: Vec<_> = identical
+                .inos
+                .iter()
+                .map(|&ino| device.inodes.get(ino).unwrap())
+                .collect();
+            inodes.sort_by_key(|inode| std::cmp::Reverse(inode.nlink));
+            groups.push(ScanGroup {
+                hash: *hash,
+                size: inodes[0].size,
+                paths: inodes
+                    .iter()
+                    .flat_map(|inode| &inode.files)
+                    .map(|path| path.as_os_str().as_bytes().to_vec())
+                    .collect(),
+            });
+        }
+    }
+    groups.sort_by(|a, b| a.paths[0].cmp(&b.paths[0]));
+    Ok(groups)
+}
+
+/// The result must be released with `dedup_options_free`.
+#[no_mangle]
+pub extern "C" fn dedup_options_new() -> *mut DedupOptions {
+    Box::into_raw(Box::new(DedupOptions {
+        targets: Vec::new(),
+    }))
+}
+
+/// # Safety
+/// `options` must come from `dedup_options_new`, and `path` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_options_add_target(
+    options: *mut DedupOptions,
+    path: *const u8,
+    len: usize,
+) {
+    let options = &mut *options;
+    let path = slice::from_raw_parts(path, len);
+    options.targets.push(PathBuf::from(OsStr::from_bytes(path)));
+}
+
+/// # Safety
+/// `options` must come from `dedup_options_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_options_free(options: *mut DedupOptions) {
+    if !options.is_null() {
+        drop(Box::from_raw(options));
+    }
+}
+
+/// Scans the targets. Never returns null; check `dedup_scan_error` for the outcome.
+///
+/// # Safety
+/// `options` must come from `dedup_options_new`. The result must be released with
+/// `dedup_scan_free`.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_scan_run(options: *const DedupOptions) -> *mut DedupScan {
+    let scan = match run_scan(&*options) {
+        Ok(groups) => DedupScan {
+            groups,
+            error: None,
+        },
+        Err(err) => DedupScan {
+            groups: Vec::new(),
+            error: Some(CString::new(format!("{:#}", err).replace('\0', "?")).unwrap_or_default()),
+        },
+    };
+    Box::into_raw(Box::new(scan))
+}
+
+/// Returns the error message of a failed scan, or null if it succeeded.
+///
+/// # Safety
+/// `scan` must come from `dedup_scan_run`. The message lives as long as `scan`.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_scan_error(scan: *const DedupScan) -> *const c_char {
+    let scan = &*scan;
+    match &scan.error {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// # Safety
+/// `scan` must come from `dedup_scan_run`.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_scan_group_count(scan: *const DedupScan) -> usize {
+    let scan = &*scan;
+    scan.groups.len()
+}
+
+/// # Safety
+/// `scan` must come from `dedup_scan_run` and `group` be less than its group count.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_scan_group_size(scan: *const DedupScan, group: usize) -> u64 {
+    let scan = &*scan;
+    scan.groups[group].size
+}
+
+/// Returns the 32 bytes of the SHA-256 of the group.
+///
+/// # Safety
+/// `scan` must come from `dedup_scan_run` and `group` be less than its group count. The
+/// hash lives as long as `scan`.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_scan_group_hash(scan: *const DedupScan, group: usize) -> *const u8 {
+    let scan = &*scan;
+    scan.groups[group].hash.as_bytes().as_ptr()
+}
+
+/// # Safety
+/// `scan` must come from `dedup_scan_run` and `group` be less than its group count.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_scan_group_path_count(
+    scan: *const DedupScan,
+    group: usize,
+) -> usize {
+    let scan = &*scan;
+    scan.groups[group].paths.len()
+}
+
+/// Returns the bytes of a path, not NUL-terminated, storing their number in `len`. The
+/// first path of a group is the one a run would keep as the original.
+///
+/// # Safety
+/// `scan` must come from `dedup_scan_run`, `group` and `index` be in range, and `len` be
+/// valid for writes. The path lives as long as `scan`.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_scan_group_path(
+    scan: *const DedupScan,
+    group: usize,
+    index: usize,
+    len: *mut usize,
+) -> *const u8 {
+    let scan = &*scan;
+    let path = &scan.groups[group].paths[index];
+    *len = path.len();
+    path.as_ptr()
+}
+
+/// # Safety
+/// `scan` must come from `dedup_scan_run` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn dedup_scan_free(scan: *mut DedupScan) {
+    if !scan.is_null() {
+        drop(Box::from_raw(scan));
+    }
+}
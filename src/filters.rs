@@ -0,0 +1,257 @@
+//! The rules that leave entries of the targets out of the scan, shared by the walk and by
+//! `dedup filters`, which tells for each entry whether, and by which rule, it would be.
+
+use std::collections::HashSet;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::glob::Glob;
+use crate::report::{serialize_path, SkipReason};
+use crate::rng::Rng;
+use crate::size;
+
+#[derive(clap::Args, Debug)]
+pub struct FilterArgs {
+    /// Skip files and directories matching GLOB under a target; may be repeated
+    #[arg(long, value_name = "GLOB", global = true)]
+    pub exclude: Vec<Glob>,
+
+    /// Ignore files smaller than SIZE, e.g. 4k or 1M
+    #[arg(long, value_name = "SIZE", default_value = "0", value_parser = size::parse_size, global = true)]
+    pub min_size: u64,
+
+    /// Link empty files too, which are otherwise left alone, as they are usually markers
+    /// and locks that are meant to stay independent
+    #[arg(long, default_value_t = false, global = true)]
+    pub dedup_empty: bool,
+
+    /// Ignore files larger than SIZE
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size, global = true)]
+    pub max_size: Option<u64>,
+}
+
+impl FilterArgs {
+    /// The first `--exclude` matching `relpath`, the path relative to its target.
+    pub fn excluded_by(&self, relpath: &Path) -> Option<&Glob> {
+        self.exclude.iter().find(|glob| glob.is_match(relpath))
+    }
+
+    /// Whether a file of `size` bytes is left alone for being empty.
+    pub fn skips_empty(&self, size: u64) -> bool {
+        size == 0 && !self.dedup_empty
+    }
+
+    /// Why a file of `size` bytes is ignored by `--min-size` or `--max-size`, if it is.
+    pub fn outside_size_limits(&self, size: u64) -> Option<SkipReason> {
+        if size < self.min_size {
+            Some(SkipReason::BelowMinSize)
+        } else if self.max_size.is_some_and(|max| size > max) {
+            Some(SkipReason::AboveMaxSize)
+        } else {
+            None
+        }
+    }
+
+    /// Prints the rules in the order they apply to an entry.
+    pub fn print_rules(&self, out: &mut dyn Write) -> io::Result<()> {
+        for glob in &self.exclude {
+            writeln!(
+                out,
+                "--exclude {}: prunes matching directories, filters matching files",
+                glob
+            )?;
+        }
+        writeln!(
+            out,
+            "Directories already walked, e.g. through a bind mount: pruned"
+        )?;
+        writeln!(out, "Anything but regular files: filtered")?;
+        if !self.dedup_empty {
+            writeln!(out, "Empty files, without --dedup-empty: filtered")?;
+        }
+        if self.min_size > 0 {
+            writeln!(
+                out,
+                "--min-size {}: filters smaller files",
+                self.min_size.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if let Some(max_size) = self.max_size {
+            writeln!(
+                out,
+                "--max-size {}: filters larger files",
+                max_size.to_formatted_string(&Locale::en)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    /// A file scanned, or a directory walked into.
+    Scanned,
+    /// A directory left out along with everything under it.
+    Pruned,
+    /// A file left out.
+    Filtered,
+}
+
+impl Verdict {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Scanned => "scanned",
+            Self::Pruned => "pruned",
+            Self::Filtered => "filtered",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterTest {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub verdict: Verdict,
+    /// The rule that pruned or filtered the entry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterTests {
+    /// The number of entries walked, of which only a sample may be listed.
+    pub entries: u64,
+    /// The seed of `--sample`, to list the same entries again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    pub tests: Vec<FilterTest>,
+}
+
+/// The verdict on an entry under `target`, as the walk of the scan would give it.
+/// `visited` holds the directories walked into so far.
+fn judge(
+    filters: &FilterArgs,
+    target: &Path,
+    entry: &walkdir::DirEntry,
+    visited: &mut HashSet<(u64, u64)>,
+) -> walkdir::Result<(Verdict, Option<String>)> {
+    let path = entry.path();
+    let is_dir = entry.file_type().is_dir();
+    if entry.depth() > 0 {
+        let relpath = path.strip_prefix(target).unwrap_or(path);
+        if let Some(glob) = filters.excluded_by(relpath) {
+            let verdict = if is_dir {
+                Verdict::Pruned
+            } else {
+                Verdict::Filtered
+            };
+            return Ok((verdict, Some(format!("--exclude {}", glob))));
+        }
+    }
+    let metadata = entry.metadata()?;
+    if metadata.is_dir() {
+        if !visited.insert((metadata.dev(), metadata.ino())) {
+            return Ok((Verdict::Pruned, Some("already walked".to_string())));
+        }
+        return Ok((Verdict::Scanned, None));
+    }
+    if !metadata.is_file() {
+        return Ok((Verdict::Filtered, Some("not a regular file".to_string())));
+    }
+    if filters.skips_empty(metadata.size()) {
+        return Ok((
+            Verdict::Filtered,
+            Some("empty, without --dedup-empty".to_string()),
+        ));
+    }
+    match filters.outside_size_limits(metadata.size()) {
+        Some(SkipReason::BelowMinSize) => Ok((Verdict::Filtered, Some("--min-size".to_string()))),
+        Some(_) => Ok((Verdict::Filtered, Some("--max-size".to_string()))),
+        None => Ok((Verdict::Scanned, None)),
+    }
+}
+
+/// Walks the targets as the scan would, without reading any file, and judges each entry
+/// by the rules of `filters`. With `sample`, only that many entries picked at random are
+/// kept, in the order of the walk.
+pub fn test_filters(
+    filters: &FilterArgs,
+    targets: &[PathBuf],
+    sample: Option<u64>,
+    seed: u64,
+) -> FilterTests {
+    let mut rng = Rng::new(seed);
+    let mut visited = HashSet::new();
+    let mut entries: u64 = 0;
+    let mut kept: Vec<(u64, FilterTest)> = Vec::new();
+    for target in targets {
+        let mut it = WalkDir::new(target).into_iter();
+        while let Some(entry) = it.next() {
+            let judged = entry.and_then(|entry| {
+                let judged = judge(filters, target, &entry, &mut visited)?;
+                Ok((entry, judged))
+            });
+            let (entry, (verdict, rule)) = match judged {
+                Ok(judged) => judged,
+                Err(err) => {
+                    eprintln!("Warning: {}; entry ignored", err);
+                    continue;
+                }
+            };
+            if verdict == Verdict::Pruned {
+                it.skip_current_dir();
+            }
+            let test = FilterTest {
+                path: entry.into_path(),
+                verdict,
+                rule,
+            };
+            entries += 1;
+            match sample {
+                Some(n) if kept.len() as u64 >= n => {
+                    // reservoir sampling: each entry is kept with the same probability
+                    let slot = rng.below(entries);
+                    if slot < n {
+                        kept[slot as usize] = (entries, test);
+                    }
+                }
+                _ => kept.push((entries, test)),
+            }
+        }
+    }
+    kept.sort_by_key(|&(index, _)| index);
+    FilterTests {
+        entries,
+        seed: sample.map(|_| seed),
+        tests: kept.into_iter().map(|(_, test)| test).collect(),
+    }
+}
+
+impl FilterTests {
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        for test in &self.tests {
+            write!(out, "{:<8} {}", test.verdict.as_str(), test.path.display())?;
+            if let Some(rule) = &test.rule {
+                write!(out, "  ({})", rule)?;
+            }
+            writeln!(out)?;
+        }
+        if let Some(seed) = self.seed {
+            writeln!(
+                out,
+                "Sampled {} of {} entries (seed {})",
+                (self.tests.len() as u64).to_formatted_string(&Locale::en),
+                self.entries.to_formatted_string(&Locale::en),
+                seed
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+//! `dedup gen-fixture`: a tree of files generated from a declarative spec and a seed, to
+//! reproduce a scenario without the data it was found on.
+//!
+//! The spec is written in a subset of TOML: `key = value` lines, with integers, floats,
+//! booleans and strings, and the single table `[sizes]`. For example:
+//!
+//! ```toml
+//! seed = 42
+//! budget = "64M"           # bytes written at most; the tree stops short of it
+//! files = 30000
+//! duplicate_ratio = 0.4    # files that copy an earlier file
+//! size_collisions = 0.1    # unique files differing from an earlier one in the last bytes
+//! depth = 6                # directory levels below OUT_DIR
+//! fanout = 8               # subdirectories per directory
+//! weird_names = 0.05       # files named with spaces, newlines, invalid UTF-8 and such
+//! hardlink_farms = 3       # directories of hard links to a single file
+//! farm_links = 20
+//!
+//! [sizes]                  # weights of the ranges the sizes are drawn from
+//! "0-1k" = 50
+//! "1k-64k" = 40
+//! "64k-1M" = 10
+//! ```
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context as _, Result};
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+
+use crate::rng::Rng;
+use crate::size::parse_size;
+
+/// Bytes of the content of a file generated at once.
+const WRITE_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+/// A `key = value` of the spec, with the table it is in, `""` at the top.
+struct Entry {
+    line: usize,
+    table: String,
+    key: String,
+    value: Value,
+}
+
+fn unquote(s: &str) -> Option<String> {
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut unquoted = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        unquoted.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            '"' => '"',
+            '\\' => '\\',
+            _ => return None,
+        });
+    }
+    Some(unquoted)
+}
+
+/// Strips a comment, leaving any `#` inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(s: &str) -> Option<Value> {
+    match s {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    if s.starts_with('"') {
+        return unquote(s).map(Value::String);
+    }
+    let s = s.replace('_', "");
+    if let Ok(n) = s.parse() {
+        return Some(Value::Integer(n));
+    }
+    s.parse().ok().map(Value::Float)
+}
+
+fn parse_toml(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut table = String::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            table = name.trim().to_string();
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected key = value: {}", line_number, line);
+        };
+        let key = key.trim();
+        let key = unquote(key).unwrap_or_else(|| key.to_string());
+        let Some(value) = parse_value(value.trim()) else {
+            bail!("line {}: invalid value for {}", line_number, key);
+        };
+        entries.push(Entry {
+            line: line_number,
+            table: table.clone(),
+            key,
+            value,
+        });
+    }
+    Ok(entries)
+}
+
+/// The distribution of the sizes: ranges, inclusive, with their weights.
+#[derive(Debug, Clone)]
+struct SizeRange {
+    min: u64,
+    max: u64,
+    weight: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FixtureSpec {
+    pub seed: u64,
+    pub budget: u64,
+    pub files: u64,
+    pub duplicate_ratio: f64,
+    pub size_collisions: f64,
+    pub depth: u64,
+    pub fanout: u64,
+    pub weird_names: f64,
+    pub hardlink_farms: u64,
+    pub farm_links: u64,
+    sizes: Vec<SizeRange>,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            budget: 64 << 20,
+            files: 1000,
+            duplicate_ratio: 0.3,
+            size_collisions: 0.0,
+            depth: 3,
+            fanout: 4,
+            weird_names: 0.0,
+            hardlink_farms: 0,
+            farm_links: 10,
+            sizes: vec![SizeRange {
+                min: 1,
+                max: 64 * 1024,
+                weight: 1,
+            }],
+        }
+    }
+}
+
+impl FixtureSpec {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut spec = Self::default();
+        let mut sizes = Vec::new();
+        for entry in parse_toml(text)? {
+            let context = || format!("line {}: {}", entry.line, entry.key);
+            let count = || match entry.value {
+                Value::Integer(n) if n >= 0 => Ok(n as u64),
+                _ => bail!("{}: expected a non-negative integer", context()),
+            };
+            let ratio = || match entry.value {
+                Value::Float(x) if (0.0..=1.0).contains(&x) => Ok(x),
+                Value::Integer(n @ 0..=1) => Ok(n as f64),
+                _ => bail!("{}: expected a ratio between 0 and 1", context()),
+            };
+            let size = |value: &str| parse_size(value).map_err(|err| anyhow::anyhow!(err));
+            match (entry.table.as_str(), entry.key.as_str()) {
+                ("", "seed") => spec.seed = count()?,
+                ("", "budget") => {
+                    spec.budget = match &entry.value {
+                        Value::String(value) => size(value).with_context(context)?,
+                        _ => count()?,
+                    }
+                }
+                ("", "files") => spec.files = count()?,
+                ("", "duplicate_ratio") => spec.duplicate_ratio = ratio()?,
+                ("", "size_collisions") => spec.size_collisions = ratio()?,
+                ("", "depth") => spec.depth = count()?,
+                ("", "fanout") => spec.fanout = count()?.max(1),
+                ("", "weird_names") => spec.weird_names = ratio()?,
+                ("", "hardlink_farms") => spec.hardlink_farms = count()?,
+                ("", "farm_links") => spec.farm_links = count()?,
+                ("sizes", range) => {
+                    let (min, max) = range.split_once('-').unwrap_or((range, range));
+                    let (min, max) = (
+                        size(min.trim()).with_context(context)?,
+                        size(max.trim()).with_context(context)?,
+                    );
+                    ensure!(min <= max, "{}: empty range", context());
+                    sizes.push(SizeRange {
+                        min,
+                        max,
+                        weight: count()?,
+                    });
+                }
+                _ => bail!("{}: unknown key", context()),
+            }
+        }
+        if !sizes.is_empty() {
+            ensure!(
+                sizes.iter().any(|range| range.weight > 0),
+                "[sizes]: every weight is 0"
+            );
+            spec.sizes = sizes;
+        }
+        Ok(spec)
+    }
+
+    fn draw_size(&self, rng: &mut Rng) -> u64 {
+        let total: u64 = self.sizes.iter().map(|range| range.weight).sum();
+        let mut pick = rng.below(total);
+        for range in &self.sizes {
+            if pick < range.weight {
+                return range.min + rng.below(range.max - range.min + 1);
+            }
+            pick -= range.weight;
+        }
+        unreachable!()
+    }
+}
+
+/// Whether an event of probability `ratio` happens.
+fn chance(rng: &mut Rng, ratio: f64) -> bool {
+    ((rng.next_u64() >> 11) as f64 / (1_u64 << 53) as f64) < ratio
+}
+
+/// The content of a file: the stream of its seed, with the last 8 bytes replaced by
+/// `variant` unless it is 0. Size collisions share everything else, so that only the full
+/// hash tells them apart once they are larger than the prefix the scan hashes first.
+#[derive(Debug, Clone, Copy)]
+struct Content {
+    seed: u64,
+    size: u64,
+    variant: u64,
+}
+
+impl Content {
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let mut rng = Rng::new(self.seed);
+        let mut buffer = Vec::with_capacity(WRITE_SIZE);
+        let mut written = 0;
+        while written < self.size {
+            buffer.clear();
+            let n = (self.size - written).min(WRITE_SIZE as u64) as usize;
+            while buffer.len() < n {
+                buffer.extend_from_slice(&rng.next_u64().to_le_bytes());
+            }
+            buffer.truncate(n);
+            let tail = self.size.saturating_sub(8);
+            if self.variant > 0 && written + n as u64 > tail {
+                let variant = self.variant.to_le_bytes();
+                for (offset, byte) in buffer.iter_mut().enumerate() {
+                    let position = written + offset as u64;
+                    if position >= tail {
+                        *byte = variant[(position - tail) as usize];
+                    }
+                }
+            }
+            file.write_all(&buffer)?;
+            written += n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// A file name, unusual with probability `weird_names`. The index keeps names apart.
+fn file_name(rng: &mut Rng, index: u64, weird_names: f64) -> (OsString, bool) {
+    if !chance(rng, weird_names) {
+        return (format!("f{:06}.dat", index).into(), false);
+    }
+    let name = match rng.below(7) {
+        0 => format!("with spaces {}", index).into(),
+        1 => format!("-leading-dash-{}", index).into(),
+        2 => format!("ünïcödé-{}", index).into(),
+        3 => format!("new\nline-{}", index).into(),
+        4 => format!("tab\tand 'quote\" {}", index).into(),
+        5 => {
+            let mut name = b"invalid-\xff\xfe-".to_vec();
+            name.extend_from_slice(index.to_string().as_bytes());
+            OsString::from_vec(name)
+        }
+        _ => format!("{}-{}", "long".repeat(60), index).into(),
+    };
+    (name, true)
+}
+
+/// A directory at a random depth of the tree, created if need be.
+fn directory(rng: &mut Rng, out_dir: &Path, spec: &FixtureSpec) -> io::Result<PathBuf> {
+    let mut dir = out_dir.to_path_buf();
+    for _ in 0..rng.below(spec.depth + 1) {
+        dir.push(format!("d{}", rng.below(spec.fanout)));
+    }
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FixtureSummary {
+    pub files: u64,
+    pub duplicates: u64,
+    pub size_collisions: u64,
+    pub weird_names: u64,
+    pub hard_links: u64,
+    pub bytes: u64,
+    /// Whether the budget ran out before all the files were generated.
+    pub budget_reached: bool,
+}
+
+impl FixtureSummary {
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        let fmt = |n: u64| n.to_formatted_string(&Locale::en);
+        writeln!(
+            out,
+            "Generated {} files ({} duplicates, {} size collisions, {} weird names), {} hard links, {} bytes",
+            fmt(self.files),
+            fmt(self.duplicates),
+            fmt(self.size_collisions),
+            fmt(self.weird_names),
+            fmt(self.hard_links),
+            fmt(self.bytes)
+        )?;
+        if self.budget_reached {
+            writeln!(out, "Stopped short of the budget")?;
+        }
+        Ok(())
+    }
+}
+
+fn generate(spec: &FixtureSpec, out_dir: &Path) -> Result<FixtureSummary> {
+    let mut rng = Rng::new(spec.seed);
+    let mut summary = FixtureSummary::default();
+    let mut unique: Vec<Content> = Vec::new();
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for index in 0..spec.files {
+        let content = if !unique.is_empty() && chance(&mut rng, spec.duplicate_ratio) {
+            summary.duplicates += 1;
+            unique[rng.below(unique.len() as u64) as usize]
+        } else if !unique.is_empty() && chance(&mut rng, spec.size_collisions) {
+            let earlier = unique[rng.below(unique.len() as u64) as usize];
+            summary.size_collisions += 1;
+            let content = Content {
+                variant: index + 1,
+                ..earlier
+            };
+            unique.push(content);
+            content
+        } else {
+            let content = Content {
+                seed: rng.next_u64(),
+                size: spec.draw_size(&mut rng),
+                variant: 0,
+            };
+            unique.push(content);
+            content
+        };
+        if summary.bytes + content.size > spec.budget {
+            summary.budget_reached = true;
+            break;
+        }
+        let (name, weird) = file_name(&mut rng, index, spec.weird_names);
+        let path = directory(&mut rng, out_dir, spec)?.join(name);
+        content
+            .write(&path)
+            .with_context(|| format!("Failed to write: {}", path.to_string_lossy()))?;
+        summary.files += 1;
+        summary.weird_names += u64::from(weird);
+        summary.bytes += content.size;
+        paths.push(path);
+    }
+    if paths.is_empty() {
+        return Ok(summary);
+    }
+    for farm in 0..spec.hardlink_farms {
+        let original = &paths[rng.below(paths.len() as u64) as usize];
+        let dir = out_dir.join(format!("farm{}", farm));
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create: {}", dir.to_string_lossy()))?;
+        for link in 0..spec.farm_links {
+            let path = dir.join(format!("link{}", link));
+            fs::hard_link(original, &path)
+                .with_context(|| format!("Failed to link: {}", path.to_string_lossy()))?;
+            summary.hard_links += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Generates the tree of `spec` in `out_dir`, which must not exist yet, or be empty. On
+/// failure, everything generated is removed.
+pub fn generate_fixture(spec: &FixtureSpec, out_dir: &Path) -> Result<FixtureSummary> {
+    let created = match fs::read_dir(out_dir) {
+        Ok(mut entries) => {
+            ensure!(
+                entries.next().is_none(),
+                "Refusing to generate a fixture in a directory that is not empty: {}",
+                out_dir.to_string_lossy()
+            );
+            false
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            fs::create_dir_all(out_dir)
+                .with_context(|| format!("Failed to create: {}", out_dir.to_string_lossy()))?;
+            true
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to read: {}", out_dir.to_string_lossy()))
+        }
+    };
+    let result = generate(spec, out_dir);
+    if result.is_err() {
+        let cleaned = if created {
+            fs::remove_dir_all(out_dir)
+        } else {
+            fs::read_dir(out_dir).and_then(|entries| {
+                entries.into_iter().try_for_each(|entry| {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        fs::remove_dir_all(entry.path())
+                    } else {
+                        fs::remove_file(entry.path())
+                    }
+                })
+            })
+        };
+        if let Err(err) = cleaned {
+            eprintln!(
+                "Warning: Failed to remove the partial fixture: {}: {}",
+                out_dir.display(),
+                err
+            );
+        }
+    }
+    result
+}
@@ -0,0 +1,106 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+// ext2, ext3 and ext4 share a magic number and are all reported as ext4.
+const MAGICS: &[(u64, &str)] = &[
+    (0xef53, "ext4"),
+    (0x58465342, "xfs"),
+    (0x9123683e, "btrfs"),
+    (0x2fc12fc1, "zfs"),
+    (0xf2f52010, "f2fs"),
+    (0xca451a4e, "bcachefs"),
+    (0x52654973, "reiserfs"),
+    (0x3153464a, "jfs"),
+    (0x3434, "nilfs"),
+    (0x01021994, "tmpfs"),
+    (0x858458f6, "ramfs"),
+    (0x794c7630, "overlay"),
+    (0x4d44, "vfat"),
+    (0x2011bab0, "exfat"),
+    (0x5346544e, "ntfs"),
+    (0x482b, "hfsplus"),
+    (0x65735546, "fuse"),
+    (0x6969, "nfs"),
+    (0xff534d42, "cifs"),
+    (0xfe534d42, "smb2"),
+    (0x00c36400, "ceph"),
+    (0x73717368, "squashfs"),
+    (0x9660, "iso9660"),
+];
+
+pub fn fs_type_name(magic: u64) -> String {
+    MAGICS
+        .iter()
+        .find(|&&(m, _)| m == magic)
+        .map(|&(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("0x{:x}", magic))
+}
+
+/// Returns the name of the filesystem type holding `path`, or its hex magic if unknown.
+pub fn fs_type(path: &Path) -> io::Result<String> {
+    let path = c_path(path)?;
+    let mut buf = MaybeUninit::<libc::statfs>::uninit();
+    let ret = unsafe { libc::statfs(path.as_ptr(), buf.as_mut_ptr()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let buf = unsafe { buf.assume_init() };
+    Ok(fs_type_name(buf.f_type as u64 & 0xffff_ffff))
+}
+
+/// Returns an identity of the filesystem holding `path`, on device `dev`, that survives
+/// the device being numbered differently after a reboot or a remount: the UUID of its block
+/// device, else the fsid of `statfs`. A filesystem with neither, as some report a zero
+/// fsid, is only known by its device number.
+pub fn fs_id(path: &Path, dev: u64) -> io::Result<String> {
+    if let Some(uuid) = block_device_uuid(dev) {
+        return Ok(format!("uuid:{}", uuid));
+    }
+    let c_path = c_path(path)?;
+    let mut buf = MaybeUninit::<libc::statvfs>::uninit();
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), buf.as_mut_ptr()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let buf = unsafe { buf.assume_init() };
+    Ok(match buf.f_fsid {
+        0 => format!("dev:{}", dev),
+        fsid => format!("fsid:{:016x}", fsid),
+    })
+}
+
+/// The UUID that `/dev/disk/by-uuid` lists for the block device `dev`, if any.
+fn block_device_uuid(dev: u64) -> Option<String> {
+    fs::read_dir("/dev/disk/by-uuid")
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|entry| {
+            fs::metadata(entry.path()).is_ok_and(|metadata| {
+                metadata.file_type().is_block_device() && metadata.rdev() == dev
+            })
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_magics_are_named_in_hex() {
+        assert_eq!(fs_type_name(0xef53), "ext4");
+        assert_eq!(fs_type_name(0x58465342), "xfs");
+        assert_eq!(fs_type_name(0x4d44), "vfat");
+        assert_eq!(fs_type_name(0x12345678), "0x12345678");
+    }
+}
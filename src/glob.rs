@@ -0,0 +1,170 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+/// A shell-like pattern for `--exclude`: `*` and `?` match within a path component, `**`
+/// matches any number of components and `[abc]`, `[a-z]` or `[!abc]` one byte. Matching is
+/// case-sensitive. A pattern without `/` matches the name of a file or directory at any
+/// depth, as in `.gitignore`; others match the whole path relative to the target.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    components: Vec<Vec<u8>>,
+}
+
+impl FromStr for Glob {
+    type Err = Infallible;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let mut components: Vec<Vec<u8>> = pattern
+            .trim_matches('/')
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(|component| component.as_bytes().to_vec())
+            .collect();
+        if components.len() == 1 {
+            components.insert(0, b"**".to_vec());
+        }
+        Ok(Self {
+            pattern: pattern.to_string(),
+            components,
+        })
+    }
+}
+
+/// The pattern as given.
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+impl Glob {
+    pub fn is_match(&self, path: &Path) -> bool {
+        let names: Vec<&[u8]> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.as_bytes()),
+                _ => None,
+            })
+            .collect();
+        let pattern: Vec<&[u8]> = self.components.iter().map(Vec::as_slice).collect();
+        match_components(&pattern, &names)
+    }
+}
+
+fn match_components(pattern: &[&[u8]], names: &[&[u8]]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((&b"**", rest)) => {
+            (0..=names.len()).any(|skip| match_components(rest, &names[skip..]))
+        }
+        Some((first, rest)) => match names.split_first() {
+            Some((name, names)) => match_name(first, name) && match_components(rest, names),
+            None => false,
+        },
+    }
+}
+
+fn match_name(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_name(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_name(rest, &name[1..]),
+        Some((b'[', rest)) => match (name.split_first(), match_class(rest)) {
+            (Some((&byte, name)), Some((matches, rest))) => matches(byte) && match_name(rest, name),
+            // an unterminated class is taken literally
+            (Some((&byte, name)), None) => byte == b'[' && match_name(rest, name),
+            (None, _) => false,
+        },
+        Some((&byte, rest)) => name.first() == Some(&byte) && match_name(rest, &name[1..]),
+    }
+}
+
+/// Parses the class after its `[`, returning a predicate on bytes and the rest of the
+/// pattern, or `None` if the class is not terminated.
+fn match_class(pattern: &[u8]) -> Option<(impl Fn(u8) -> bool + '_, &[u8])> {
+    let (negated, pattern) = match pattern.first() {
+        Some(b'!' | b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    // a `]` right after the opening bracket is part of the class
+    let end = pattern
+        .iter()
+        .skip(1)
+        .position(|&byte| byte == b']')
+        .map(|position| position + 1)?;
+    let class = &pattern[..end];
+    let matches = move |byte: u8| {
+        let mut found = false;
+        let mut i = 0;
+        while i < class.len() {
+            if i + 2 < class.len() && class[i + 1] == b'-' {
+                found |= (class[i]..=class[i + 2]).contains(&byte);
+                i += 3;
+            } else {
+                found |= class[i] == byte;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matches, &pattern[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        pattern.parse::<Glob>().unwrap().is_match(Path::new(path))
+    }
+
+    #[test]
+    fn wildcards_stay_within_a_component() {
+        assert!(matches("*.tmp", "a.tmp"));
+        assert!(matches("*.tmp", ".tmp"));
+        assert!(!matches("*.tmp", "a.tmp.bak"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(!matches("a?c", "abbc"));
+        assert!(!matches("a*/c", "a/b/c"));
+        assert!(!matches("a?c", "a/c"));
+    }
+
+    #[test]
+    fn double_stars_span_any_number_of_components() {
+        assert!(matches("a/**/c", "a/c"));
+        assert!(matches("a/**/c", "a/b/c"));
+        assert!(matches("a/**/c", "a/b/b/c"));
+        assert!(!matches("a/**/c", "b/a/c"));
+        assert!(matches("a/**", "a/b/c"));
+        assert!(matches("**/c", "a/b/c"));
+    }
+
+    #[test]
+    fn patterns_without_a_slash_match_names_at_any_depth() {
+        assert!(matches("cache", "cache"));
+        assert!(matches("cache", "a/b/cache"));
+        assert!(!matches("cache", "a/cache/b"));
+        // with a slash, the whole path relative to the target
+        assert!(matches("a/cache", "a/cache"));
+        assert!(!matches("a/cache", "b/a/cache"));
+        assert!(!matches("a/cache", "a/cache/b"));
+    }
+
+    #[test]
+    fn classes_match_one_byte() {
+        assert!(matches("[ab]x", "bx"));
+        assert!(!matches("[ab]x", "cx"));
+        assert!(matches("[a-c]x", "cx"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a", "[a"));
+        assert!(matches("*.TMP", "a.TMP"));
+        assert!(!matches("*.TMP", "a.tmp"));
+    }
+}
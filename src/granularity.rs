@@ -0,0 +1,175 @@
+//! Timestamp granularity of a device, so that mtimes are set as the filesystem stores them.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use filetime::FileTime;
+
+use crate::checks::parent_dir;
+use crate::confine::{Mutation, OutsideRoots, Roots};
+use crate::models::Device;
+
+/// An mtime that every granularity below truncates or rounds differently.
+const PROBE_SECONDS: i64 = 1_000_000_001;
+const PROBE_NANOS: u32 = 987_654_321;
+
+/// Candidate granularities in nanoseconds: exact, µs, ms, 10 ms (exFAT), s and 2 s (FAT).
+const GRANULARITIES: [u64; 6] = [
+    1,
+    1_000,
+    1_000_000,
+    10_000_000,
+    1_000_000_000,
+    2_000_000_000,
+];
+
+fn to_nanos(time: FileTime) -> i128 {
+    i128::from(time.unix_seconds()) * 1_000_000_000 + i128::from(time.nanoseconds())
+}
+
+fn from_nanos(nanos: i128) -> FileTime {
+    FileTime::from_unix_time(
+        nanos.div_euclid(1_000_000_000) as i64,
+        nanos.rem_euclid(1_000_000_000) as u32,
+    )
+}
+
+/// Truncates `time` to a multiple of `granularity` nanoseconds, as the filesystem would.
+pub fn round_down(time: FileTime, granularity: u64) -> FileTime {
+    let nanos = to_nanos(time);
+    from_nanos(nanos - nanos.rem_euclid(i128::from(granularity)))
+}
+
+/// Measures the granularity of mtimes in `dir`, in nanoseconds, by setting an mtime on a
+/// scratch file and reading it back. The scratch file is removed and the mtime of `dir`
+/// restored.
+pub fn mtime_granularity(roots: &Roots, dir: &Path) -> Result<u64> {
+    let dir_mtime = fs::metadata(dir)
+        .map(|metadata| FileTime::from_last_modification_time(&metadata))
+        .with_context(|| format!("Failed to fs::metadata: {}", dir.to_string_lossy()))?;
+    let scratch = dir.join(format!(".dedup-granularity-{}", std::process::id()));
+    roots.check(Mutation::Scratch, &scratch)?;
+    let file = fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(&scratch)
+        .with_context(|| format!("Failed to create: {}", scratch.to_string_lossy()))?;
+
+    let probe = FileTime::from_unix_time(PROBE_SECONDS, PROBE_NANOS);
+    let read_back = filetime::set_file_handle_times(&file, None, Some(probe))
+        .and_then(|()| file.metadata())
+        .map(|metadata| FileTime::from_last_modification_time(&metadata));
+    drop(file);
+    fs::remove_file(&scratch)
+        .with_context(|| format!("Failed to fs::remove_file: {}", scratch.to_string_lossy()))?;
+    filetime::set_file_mtime(dir, dir_mtime).with_context(|| {
+        format!(
+            "Failed to filetime::set_file_mtime to restore a directory mtime: {}",
+            dir.to_string_lossy()
+        )
+    })?;
+    let read_back = read_back
+        .with_context(|| format!("Failed to set an mtime: {}", scratch.to_string_lossy()))?;
+
+    let probe = to_nanos(probe);
+    let read_back = to_nanos(read_back);
+    let granularity = GRANULARITIES
+        .iter()
+        .find(|&&granularity| {
+            let granularity = i128::from(granularity);
+            let down = probe - probe.rem_euclid(granularity);
+            read_back == down || read_back == down + granularity
+        })
+        .copied()
+        .unwrap_or(*GRANULARITIES.last().unwrap());
+    Ok(granularity)
+}
+
+/// Probes the granularity of the device in the directory of the first original that
+/// may get a new mtime. Failures leave it unknown, which keeps mtimes as they are, but for
+/// a probe outside the roots, which aborts the run.
+pub fn probe_device(roots: &Roots, device: &mut Device) -> Result<()> {
+    let dir = device
+        .identicals
+        .map
+        .values()
+        .filter(|identical| identical.inos.len() > 1)
+        .find_map(|identical| device.inodes.get(identical.inos.as_slice()[0]))
+        .and_then(|inode| inode.files.first())
+        .map(|path| parent_dir(path).to_path_buf());
+    let Some(dir) = dir else {
+        return Ok(());
+    };
+    match mtime_granularity(roots, &dir) {
+        Ok(granularity) => device.mtime_granularity = Some(granularity),
+        Err(err) if err.is::<OutsideRoots>() => return Err(err),
+        Err(err) => eprintln!("Warning: {:#}", err),
+    }
+    Ok(())
+}
+
+/// Formats a granularity in nanoseconds with the largest unit that divides it.
+pub fn format_granularity(granularity: u64) -> String {
+    [(1_000_000_000, "s"), (1_000_000, "ms"), (1_000, "µs")]
+        .iter()
+        .find(|(unit, _)| granularity.is_multiple_of(*unit))
+        .map(|(unit, suffix)| format!("{}{}", granularity / unit, suffix))
+        .unwrap_or_else(|| format!("{}ns", granularity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TreeBuilder;
+
+    #[test]
+    fn mtimes_are_truncated_as_a_coarse_filesystem_stores_them() {
+        let time = FileTime::from_unix_time(1_000_000_001, 987_654_321);
+        assert_eq!(round_down(time, 1), time);
+        assert_eq!(
+            round_down(time, 1_000_000),
+            FileTime::from_unix_time(1_000_000_001, 987_000_000)
+        );
+        // FAT stores even seconds
+        assert_eq!(
+            round_down(time, 2_000_000_000),
+            FileTime::from_unix_time(1_000_000_000, 0)
+        );
+        // and before the epoch, still downwards
+        assert_eq!(
+            round_down(FileTime::from_unix_time(-1, 500_000_000), 2_000_000_000),
+            FileTime::from_unix_time(-2, 0)
+        );
+        // so that a rounded mtime rounds to itself, and is not set again on the next run
+        let rounded = round_down(time, 10_000_000);
+        assert_eq!(round_down(rounded, 10_000_000), rounded);
+    }
+
+    #[test]
+    fn granularities_are_formatted_in_their_largest_unit() {
+        assert_eq!(format_granularity(1), "1ns");
+        assert_eq!(format_granularity(1_000), "1µs");
+        assert_eq!(format_granularity(10_000_000), "10ms");
+        assert_eq!(format_granularity(2_000_000_000), "2s");
+    }
+
+    #[test]
+    fn probe_leaves_the_directory_as_it_was() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("d/a", "a")
+            .unwrap()
+            .mtime("d", 1_000_000_000)
+            .unwrap();
+        let dir = tree.path("d");
+        let granularity = mtime_granularity(&Roots::new([tree.root()]), &dir).unwrap();
+        assert!(GRANULARITIES.contains(&granularity));
+        let names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["a"]);
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&dir).unwrap());
+        assert_eq!(mtime, FileTime::from_unix_time(1_000_000_000, 0));
+    }
+}
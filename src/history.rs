@@ -0,0 +1,225 @@
+//! An append-only log of runs, one JSON record per device and run, for charting how much
+//! is deduplicated over time.
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use filetime::FileTime;
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::report::Report;
+use crate::rng::Rng;
+use crate::timestamp::format_timestamp;
+
+/// Bumped whenever a field changes meaning; new fields may be added without bumping it.
+const HISTORY_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub version: u32,
+    /// Shared by the records of a run, which may start in the same second as another.
+    pub run: u64,
+    /// When the run started, in seconds since the epoch.
+    pub timestamp: i64,
+    pub dev: u64,
+    /// The identity of the filesystem, which the device number may not be from one boot to
+    /// the next; missing from the records of older versions.
+    #[serde(default)]
+    pub fs_id: String,
+    pub fs_type: String,
+    pub dry_run: bool,
+    pub gain: u64,
+    pub groups: u64,
+    pub errors: u64,
+    /// The gain left for later runs by `--per-dir-limit`.
+    #[serde(default)]
+    pub deferred_gain: u64,
+    /// Hash of the options of the run, so that runs with different settings can be told apart.
+    pub settings_hash: String,
+}
+
+pub fn settings_hash(settings: &str) -> String {
+    hex::encode(&Sha256::digest(settings.as_bytes())[..8])
+}
+
+/// Appends the records of a run. A record left without its newline by a crash during an
+/// earlier append is closed first, so that it does not swallow the first new one.
+pub fn append_history(
+    path: &Path,
+    started: FileTime,
+    dry_run: bool,
+    settings_hash: &str,
+    report: &Report,
+) -> Result<()> {
+    let context = || {
+        format!(
+            "Failed to append to the history: {}",
+            path.to_string_lossy()
+        )
+    };
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(context)?;
+
+    let mut lines = String::new();
+    if file.metadata().with_context(context)?.len() > 0 {
+        let mut last = [0; 1];
+        file.seek(SeekFrom::End(-1)).with_context(context)?;
+        file.read_exact(&mut last).with_context(context)?;
+        if last[0] != b'\n' {
+            lines.push('\n');
+        }
+    }
+    let run = Rng::new(Rng::time_seed()).next_u64();
+    for device in &report.devices {
+        let record = HistoryRecord {
+            version: HISTORY_VERSION,
+            run,
+            timestamp: started.unix_seconds(),
+            dev: device.dev,
+            fs_id: device.fs_id.clone(),
+            fs_type: device.fs_type.clone(),
+            dry_run: dry_run || device.report_only,
+            gain: device.gain,
+            groups: device.groups_acted,
+            errors: device.errors,
+            deferred_gain: device.deferred_gain,
+            settings_hash: settings_hash.to_string(),
+        };
+        lines.push_str(&serde_json::to_string(&record)?);
+        lines.push('\n');
+    }
+    // a single write, so that concurrent runs do not interleave their records
+    file.write_all(lines.as_bytes()).with_context(context)?;
+    Ok(())
+}
+
+/// Reads the records, skipping with a warning those that do not parse, e.g. the last one
+/// of a run that crashed while appending.
+fn read_history(path: &Path) -> Result<Vec<HistoryRecord>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open the history: {}", path.to_string_lossy()))?;
+    let mut records = Vec::new();
+    for (index, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line
+            .with_context(|| format!("Failed to read the history: {}", path.to_string_lossy()))?;
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(err) => eprintln!(
+                "Warning: skipped a corrupt record at line {} of {}: {}",
+                index + 1,
+                path.display(),
+                err
+            ),
+        }
+    }
+    Ok(records)
+}
+
+/// The records of a run, summed over its devices.
+#[derive(Debug, Default)]
+struct Run {
+    run: u64,
+    timestamp: i64,
+    dry_run: bool,
+    gain: u64,
+    groups: u64,
+    errors: u64,
+    deferred_gain: u64,
+}
+
+fn runs(records: &[HistoryRecord]) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    for record in records {
+        let run = match runs.last_mut() {
+            Some(run) if run.run == record.run => run,
+            _ => {
+                runs.push(Run {
+                    run: record.run,
+                    timestamp: record.timestamp,
+                    dry_run: record.dry_run,
+                    ..Default::default()
+                });
+                runs.last_mut().unwrap()
+            }
+        };
+        run.dry_run &= record.dry_run;
+        run.gain += record.gain;
+        run.groups += record.groups;
+        run.errors += record.errors;
+        run.deferred_gain += record.deferred_gain;
+    }
+    runs
+}
+
+/// Prints a line per run, or with `summary` the totals over the runs that modified files.
+pub fn print_history(path: &Path, summary: bool, out: &mut dyn Write) -> Result<()> {
+    let runs = runs(&read_history(path)?);
+    if !summary {
+        for run in &runs {
+            write!(
+                out,
+                "{}{}  gain: {} bytes, groups: {}, errors: {}",
+                format_timestamp(FileTime::from_unix_time(run.timestamp, 0)),
+                if run.dry_run { " (dry run)" } else { "" },
+                run.gain.to_formatted_string(&Locale::en),
+                run.groups.to_formatted_string(&Locale::en),
+                run.errors.to_formatted_string(&Locale::en)
+            )?;
+            if run.deferred_gain > 0 {
+                write!(
+                    out,
+                    ", deferred: {} bytes",
+                    run.deferred_gain.to_formatted_string(&Locale::en)
+                )?;
+            }
+            writeln!(out)?;
+        }
+        return Ok(());
+    }
+
+    let real: Vec<_> = runs.iter().filter(|run| !run.dry_run).collect();
+    let count = real.len() as u64;
+    let gain: u64 = real.iter().map(|run| run.gain).sum();
+    let groups: u64 = real.iter().map(|run| run.groups).sum();
+    writeln!(
+        out,
+        "Runs: {} ({} dry runs not counted)",
+        count.to_formatted_string(&Locale::en),
+        ((runs.len() - real.len()) as u64).to_formatted_string(&Locale::en)
+    )?;
+    if let (Some(first), Some(last)) = (real.first(), real.last()) {
+        writeln!(
+            out,
+            "From {} to {}",
+            format_timestamp(FileTime::from_unix_time(first.timestamp, 0)),
+            format_timestamp(FileTime::from_unix_time(last.timestamp, 0))
+        )?;
+    }
+    writeln!(
+        out,
+        "Total gain: {} bytes",
+        gain.to_formatted_string(&Locale::en)
+    )?;
+    if let (Some(gain), Some(groups)) = (gain.checked_div(count), groups.checked_div(count)) {
+        writeln!(
+            out,
+            "Average per run: {} bytes, {} groups",
+            gain.to_formatted_string(&Locale::en),
+            groups.to_formatted_string(&Locale::en)
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! `--manifest`: files to scan read from an inventory dump instead of a walk, one per line
+//! as `SIZE<TAB>MTIME<TAB>INODE<TAB>DEVICE<TAB>PATH`. MTIME is in seconds since the epoch,
+//! with an optional fraction. The path comes last so that it may hold tabs, and is taken
+//! as raw bytes. Empty lines and lines starting with `#` are ignored.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+use filetime::FileTime;
+
+use crate::models::{Dev, Ino};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ManifestTrust {
+    /// Go on with the current metadata of files that changed since the manifest
+    #[default]
+    Restat,
+    /// Drop files that changed since the manifest
+    Drop,
+}
+
+#[derive(Debug)]
+pub struct InventoryEntry {
+    pub size: u64,
+    pub mtime: FileTime,
+    pub dev: Dev,
+    pub ino: Ino,
+    pub path: PathBuf,
+}
+
+fn parse_mtime(field: &str) -> Option<FileTime> {
+    let (seconds, fraction) = field.split_once('.').unwrap_or((field, ""));
+    if fraction.len() > 9 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32)
+    };
+    Some(FileTime::from_unix_time(seconds.parse().ok()?, nanos))
+}
+
+fn parse_line(line: &[u8]) -> Result<InventoryEntry, String> {
+    let mut fields = line.splitn(5, |&byte| byte == b'\t');
+    let mut field = |name: &str| {
+        fields
+            .next()
+            .ok_or_else(|| format!("missing {}", name))
+            .and_then(|field| {
+                std::str::from_utf8(field).map_err(|_| format!("{} is not ASCII", name))
+            })
+    };
+    let size = field("size")?;
+    let size = size
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", size))?;
+    let mtime = field("mtime")?;
+    let mtime = parse_mtime(mtime).ok_or_else(|| format!("invalid mtime {:?}", mtime))?;
+    let ino = field("inode")?;
+    let ino = Ino(ino
+        .parse()
+        .map_err(|_| format!("invalid inode {:?}", ino))?);
+    let dev = field("device")?;
+    let dev = Dev(dev
+        .parse()
+        .map_err(|_| format!("invalid device {:?}", dev))?);
+    let path = fields.next().ok_or("missing path")?;
+    if path.first() != Some(&b'/') {
+        return Err("the path is not absolute".to_string());
+    }
+    Ok(InventoryEntry {
+        size,
+        mtime,
+        dev,
+        ino,
+        path: PathBuf::from(OsStr::from_bytes(path)),
+    })
+}
+
+/// Reads the entries of a manifest line by line, in constant memory.
+pub struct Inventory {
+    reader: io::BufReader<fs::File>,
+    path: PathBuf,
+    line: Vec<u8>,
+    line_number: u64,
+}
+
+impl Inventory {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open the manifest: {}", path.to_string_lossy()))?;
+        Ok(Self {
+            reader: io::BufReader::new(file),
+            path: path.to_path_buf(),
+            line: Vec::new(),
+            line_number: 0,
+        })
+    }
+
+    /// The next entry, or why its line is malformed along with the line number.
+    pub fn next_entry(&mut self) -> Result<Option<Result<InventoryEntry, String>>> {
+        loop {
+            self.line.clear();
+            let len = self
+                .reader
+                .read_until(b'\n', &mut self.line)
+                .with_context(|| {
+                    format!(
+                        "Failed to read the manifest: {}",
+                        self.path.to_string_lossy()
+                    )
+                })?;
+            if len == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            let mut line = self.line.as_slice();
+            if let Some(rest) = line.strip_suffix(b"\n") {
+                line = rest.strip_suffix(b"\r").unwrap_or(rest);
+            }
+            if line.is_empty() || line[0] == b'#' {
+                continue;
+            }
+            return Ok(Some(parse_line(line).map_err(|reason| {
+                format!(
+                    "{}:{}: {}",
+                    self.path.to_string_lossy(),
+                    self.line_number,
+                    reason
+                )
+            })));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TreeBuilder;
+
+    #[test]
+    fn lines_are_parsed_with_the_path_last() {
+        let entry = parse_line(b"4096\t1700000000.5\t12\t2049\t/a\tb").unwrap();
+        assert_eq!(entry.size, 4096);
+        assert_eq!(
+            entry.mtime,
+            FileTime::from_unix_time(1_700_000_000, 500_000_000)
+        );
+        assert_eq!((entry.ino, entry.dev), (Ino(12), Dev(2049)));
+        // tabs and bytes that are not UTF-8 are kept in the path
+        assert_eq!(entry.path, Path::new("/a\tb"));
+        let entry = parse_line(b"0\t-1\t1\t1\t/\xff").unwrap();
+        assert_eq!(entry.mtime, FileTime::from_unix_time(-1, 0));
+        assert_eq!(entry.path.as_os_str().as_bytes(), b"/\xff");
+    }
+
+    #[test]
+    fn mtimes_take_up_to_nanoseconds() {
+        assert_eq!(
+            parse_mtime("1.000000001"),
+            Some(FileTime::from_unix_time(1, 1))
+        );
+        assert_eq!(
+            parse_mtime("1.25"),
+            Some(FileTime::from_unix_time(1, 250_000_000))
+        );
+        assert_eq!(parse_mtime("1."), Some(FileTime::from_unix_time(1, 0)));
+        for mtime in ["", "1.0000000001", "1.-5", "1.+5", "x", "1.5.5"] {
+            assert_eq!(parse_mtime(mtime), None, "{mtime}");
+        }
+    }
+
+    #[test]
+    fn malformed_lines_say_why() {
+        for (line, reason) in [
+            (&b"x\t1\t1\t1\t/a"[..], "invalid size \"x\""),
+            (b"1\tnow\t1\t1\t/a", "invalid mtime \"now\""),
+            (b"1\t1\t-1\t1\t/a", "invalid inode \"-1\""),
+            (b"1\t1\t1\t1.5\t/a", "invalid device \"1.5\""),
+            (b"1\t1\t1\t1", "missing path"),
+            (b"1\t1", "missing inode"),
+            (b"1\t1\t1\t1\ta", "the path is not absolute"),
+            (b"\xff\t1\t1\t1\t/a", "size is not ASCII"),
+        ] {
+            assert_eq!(parse_line(line).unwrap_err(), reason);
+        }
+    }
+
+    #[test]
+    fn entries_are_read_skipping_comments_with_line_numbers() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file(
+            "manifest",
+            "# size\tmtime\tinode\tdevice\tpath\n\
+             1\t1\t1\t1\t/a\r\n\
+             \n\
+             bad\n\
+             2\t2\t2\t2\t/b",
+        )
+        .unwrap();
+        let path = tree.path("manifest");
+        let mut inventory = Inventory::open(&path).unwrap();
+        let entry = inventory.next_entry().unwrap().unwrap().unwrap();
+        assert_eq!(entry.path, Path::new("/a"));
+        let err = inventory.next_entry().unwrap().unwrap().unwrap_err();
+        assert_eq!(err, format!("{}:4: invalid size \"bad\"", path.display()));
+        // the last line needs no newline
+        let entry = inventory.next_entry().unwrap().unwrap().unwrap();
+        assert_eq!(entry.path, Path::new("/b"));
+        assert!(inventory.next_entry().unwrap().is_none());
+    }
+}
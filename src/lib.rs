@@ -1,84 +1,1123 @@
+mod anchors;
+mod api;
+mod audit;
+mod by_extension;
+mod cache;
+mod checks;
+mod confine;
+mod control;
+mod cross_device;
+mod deletions;
 mod digest;
+mod estimate;
+mod explain;
+mod fdupes;
+mod features;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod filters;
+mod fixture;
+mod fstype;
+mod glob;
+mod granularity;
+mod history;
+mod inventory;
+mod link_max;
+mod mirror;
 mod models;
+mod near_size;
+mod notify;
+mod output;
+mod pair;
+mod plan;
+mod plan_file;
+mod prefilter;
+mod profile;
+mod progress;
+mod progress_bar;
+mod reflink;
+mod report;
+mod rng;
+mod size;
+mod temporary;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+mod timestamp;
+mod uring;
+mod verify;
 
+use std::collections::{hash_map, BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::ops::Range;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{ensure, Context as _, Result};
 use filetime::FileTime;
 use num_format::{Locale, ToFormattedString};
 use walkdir::WalkDir;
 
-use crate::digest::sha256file;
+use crate::anchors::Anchors;
+pub use crate::api::{apply, plan, scan, DedupGroup, DedupPlan, PlanOptions, ScanOptions};
+use crate::audit::{audit, Performed, EXIT_AUDIT_FAILED};
+use crate::by_extension::{by_extension, DEFAULT_COMPOUND_EXTENSIONS};
+use crate::cache::{parse_rate, CacheVerification, HashCache};
+use crate::checks::{check_removable, parent_dir, Unremovable};
+pub use crate::confine::OutsideRoots;
+use crate::confine::{Mutation, Roots};
+use crate::control::{send_command, ControlCommand, ControlSocket};
+use crate::cross_device::{cross_device_report, shared_sizes};
+use crate::deletions::{apply_deletions, write_manifest, PlannedDeletion};
+use crate::digest::{
+    digest_ends, digest_ends_direct, digest_file_direct, digest_file_parallel,
+    digest_file_with_progress, same_content, BudgetExhausted, HashValue, IoBudget, ParallelHashing,
+};
+pub use crate::digest::{HashAlgorithm, HashHex};
+use crate::estimate::{confirmed, estimate_relink};
+use crate::explain::Explain;
+use crate::fdupes::print_fdupes;
+use crate::features::{print_features, probe_features, Feature};
+use crate::filters::{test_filters, FilterArgs};
+use crate::fixture::{generate_fixture, FixtureSpec};
+use crate::fstype::{fs_id, fs_type};
+use crate::granularity::round_down;
+use crate::history::{append_history, print_history, settings_hash};
+use crate::inventory::{Inventory, ManifestTrust};
+use crate::link_max::split_by_link_max;
+use crate::mirror::expect_mirrored;
+pub use crate::models::Database;
 use crate::models::*;
+use crate::near_size::near_size_report;
+use crate::notify::with_notify;
+use crate::output::{is_report_temporary, Output, EXIT_CLOSED_OUTPUT};
+use crate::pair::link_pair;
+use crate::plan::diff_plan;
+pub use crate::plan_file::ApplyReport;
+use crate::plan_file::{apply_plan, convert_plan, PlanGroup, PlanWriter};
+use crate::prefilter::{Prefilter, PrefilterRegion};
+use crate::profile::Profile;
+use crate::progress::Stopped;
+pub use crate::progress::{Phase, ProgressHandle, ProgressSnapshot};
+use crate::progress_bar::with_progress_bar;
+use crate::reflink::reflink;
+use crate::report::{
+    DeviceSummary, ErrorKind, ErrorRecord, FlatRow, Group, MovedContent, OneSided, Report, Role,
+    SkipReason,
+};
+use crate::rng::Rng;
+pub use crate::temporary::DEFAULT_TMP_PREFIX;
+use crate::temporary::{is_temporary_name, parse_tmp_prefix, temporary_name};
+use crate::timestamp::format_timestamp;
+use crate::uring::StatBatches;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    /// One CSV row per file in a group
+    FlatCsv,
+    /// One JSON object per file in a group
+    FlatJson,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Replace duplicates with hard links to the original
+    Hardlink,
+    /// Remove duplicates, keeping only the original
+    Delete,
+    /// Move duplicates into the directory given by --quarantine-dir
+    Quarantine,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictFallback {
+    /// Share the extents of the duplicate with a reflink, keeping its own inode
+    Reflink,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CtimeChange {
+    /// Leave duplicates whose metadata changed since the scan alone
+    Skip,
+    /// Link duplicates whatever happened to their metadata
+    Ignore,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FutureMtime {
+    /// Treat mtimes in the future as the current time
+    Clamp,
+    /// Use mtimes in the future as they are
+    Ignore,
+    /// Never give an mtime in the future to the original
+    Skip,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MtimePolicy {
+    /// Give the oldest mtime of the group to the original
+    Oldest,
+    /// Give the newest mtime of the group to the original
+    Newest,
+    /// Leave the mtime of the original alone
+    Keep,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MtimeFloorPolicy {
+    /// Give the floor to the original instead of an older mtime
+    Clamp,
+    /// Leave the mtime of the original alone
+    Skip,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrossUser {
+    /// Link files owned by different users
+    Allow,
+    /// Skip groups of files owned by different users
+    Skip,
+    /// Link files owned by different users with a warning
+    Warn,
+}
+
+/// How much is printed along the way: `-q` leaves out the lines per file and per group,
+/// and `-v` adds the files skipped, the decisions of the size sieve and the gain of each
+/// group. Warnings and errors are always printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Link each file under NEW to the identical file at the same relative path under OLD,
+    /// which is always kept as the original, like rsync --link-dest would have done
+    Against { old: PathBuf, new: PathBuf },
+    /// Delete the files listed in a manifest written by --deletion-manifest, unless any of
+    /// them changed since
+    ApplyDeletions {
+        manifest: PathBuf,
+        /// Refuse manifests of scans more recent than this: YYYY-MM-DD, @SECONDS or an age like 7d
+        #[arg(long, value_name = "TIMESTAMP", value_parser = timestamp::parse_timestamp)]
+        older_than: FileTime,
+        /// The targets of the scan; nothing outside them is deleted, whatever the manifest lists
+        #[arg(required = true)]
+        targets: Vec<PathBuf>,
+    },
+    /// Replace B with a hard link to A if both have the same content
+    Pair {
+        a: PathBuf,
+        b: PathBuf,
+        /// Only print what would be done
+        #[arg(short = 'n', long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Link the groups of a plan written by --save-plan, one at a time, skipping the files
+    /// that changed since
+    Apply {
+        #[arg(long, value_name = "FILE")]
+        plan: PathBuf,
+        /// Only print what would be done
+        #[arg(short = 'n', long, default_value_t = false)]
+        dry_run: bool,
+        /// The targets of the run that saved the plan; nothing outside them is linked,
+        /// whatever the plan lists
+        #[arg(required = true)]
+        targets: Vec<PathBuf>,
+    },
+    /// Convert a plan written by --save-plan to JSON, or JSON back to a plan
+    ConvertPlan { input: PathBuf, output: PathBuf },
+    /// Print the rules that leave entries out of the scan, like --exclude and --min-size,
+    /// or with --test what they do to each entry of TARGETS; nothing is hashed or linked
+    Filters {
+        /// Walk TARGETS and print for each entry whether it would be scanned, pruned along
+        /// with its directory, or filtered, and by which rule
+        #[arg(long, default_value_t = false, requires = "targets")]
+        test: bool,
+        /// Only print N entries picked at random, with --seed
+        #[arg(long, value_name = "N", requires = "test")]
+        sample: Option<u64>,
+        targets: Vec<PathBuf>,
+    },
+    /// Send COMMAND to the run listening on the --control-socket SOCKET
+    Ctl {
+        socket: PathBuf,
+        #[arg(value_enum)]
+        command: ControlCommand,
+    },
+    /// Generate a tree of files in OUT_DIR from the spec in SPEC, for reproducing a
+    /// scenario; see src/fixture.rs for the format
+    #[command(hide = true)]
+    GenFixture { spec: PathBuf, out_dir: PathBuf },
+    /// Print the runs recorded by --history
+    History {
+        file: PathBuf,
+        /// Print totals and averages over the runs instead
+        #[arg(long, default_value_t = false)]
+        summary: bool,
+    },
+}
 
 #[derive(clap::Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    #[arg(short = 'n', long, default_value_t = false)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Keep files under the first target as originals, whatever their link counts
+    #[arg(skip)]
+    prefer_first_target: bool,
+
+    /// Print what would be linked and the projected gain, without modifying anything
+    #[arg(short = 'n', long, visible_alias = "no-act", default_value_t = false)]
     dry_run: bool,
 
+    /// Compare duplicates with the original byte by byte before replacing them, ahead of
+    /// the relink on --threads workers
+    #[arg(long, default_value_t = false)]
+    paranoid: bool,
+
+    /// Memory the workers of --paranoid may use for their buffers, 128 KiB each
+    #[arg(long, value_name = "SIZE", default_value = "64M", value_parser = size::parse_size)]
+    verify_buffer_budget: u64,
+
+    /// Modify files; required by the delete and quarantine modes, which are dry runs otherwise
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    execute: bool,
+
+    /// With --mode delete, write the planned deletions to FILE for apply-deletions instead of deleting
+    #[arg(long, value_name = "FILE", conflicts_with = "execute")]
+    deletion_manifest: Option<PathBuf>,
+
+    /// What to do with duplicates
+    #[arg(long, value_enum, default_value_t = Mode::Hardlink)]
+    mode: Mode,
+
+    /// Where --mode quarantine moves duplicates, keeping their absolute paths below it
+    #[arg(long, value_name = "DIR", required_if_eq("mode", "quarantine"))]
+    quarantine_dir: Option<PathBuf>,
+
+    /// Only link files found at the same path relative to their targets
+    #[arg(long, default_value_t = false)]
+    same_relative_path: bool,
+
+    /// Merge identical files whatever their permission bits
+    #[arg(long, default_value_t = false)]
+    ignore_permissions: bool,
+
+    /// Merge identical files whatever their owner and group, subject to --cross-user
+    #[arg(long, default_value_t = false)]
+    ignore_ownership: bool,
+
+    /// What to do with groups of identical files owned by different users, with --ignore-ownership
+    #[arg(long, value_enum, default_value_t = CrossUser::Warn)]
+    cross_user: CrossUser,
+
+    /// What to do with duplicates that may not be linked: skipped by --cross-user skip, or in
+    /// sticky and append-only directories. Without this, they are skipped
+    #[arg(long, value_enum, value_name = "FALLBACK")]
+    conflict_fallback: Option<ConflictFallback>,
+
+    /// Store at most N paths per inode; further paths are counted but never relinked
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_paths_per_inode: Option<u64>,
+
+    #[command(flatten)]
+    filters: FilterArgs,
+
+    /// Follow symlinks to directories and files. Those leading outside the targets are
+    /// scanned but never modified, like --reference: duplicates in the targets may be linked
+    /// to what they lead to. Those leading back inside are left for the walk to reach by
+    /// their own path
+    #[arg(long, default_value_t = false)]
+    follow_symlinks: bool,
+
+    /// Do not walk into other filesystems than that of each target, like mounts under it
+    #[arg(short = 'x', long, default_value_t = false)]
+    one_file_system: bool,
+
+    /// Stat the entries of each directory in batches through io_uring, where the kernel
+    /// supports it, rather than one by one
+    #[arg(long, default_value_t = false)]
+    uring_stat: bool,
+
+    /// Stop hashing once SIZE bytes have been read, leaving later candidates unevaluated;
+    /// the groups already complete are still linked
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    io_budget: Option<u64>,
+
+    /// Link at most K duplicates per directory, the largest first, deferring the others to
+    /// later runs
+    #[arg(long, value_name = "K", value_parser = clap::value_parser!(u64).range(1..))]
+    per_dir_limit: Option<u64>,
+
+    /// Link at most N paths to an inode, instead of the limit the filesystem reports
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_links: Option<u64>,
+
+    /// Once the output is closed by its reader, stop at the next group and exit with 9,
+    /// instead of finishing the run without output
+    #[arg(long, default_value_t = false)]
+    exit_on_epipe: bool,
+
+    /// Keep the files listed in FILE, one per line, as the originals of their groups
+    #[arg(long, value_name = "FILE")]
+    anchors: Option<PathBuf>,
+
+    /// Link duplicates to identical files under DIR, which is scanned too but never modified;
+    /// may be repeated
+    #[arg(long, value_name = "DIR")]
+    reference: Vec<PathBuf>,
+
+    /// Keep files under DIR as originals ahead of files with more links; may be repeated,
+    /// the earlier DIR winning
+    #[arg(long, value_name = "DIR")]
+    prefer: Vec<PathBuf>,
+
+    /// Only modify devices with these filesystem types; others are report-only
+    #[arg(long, value_name = "TYPE,TYPE", value_delimiter = ',')]
+    fs_allow: Vec<String>,
+
+    /// Never modify devices with these filesystem types
+    #[arg(long, value_name = "TYPE,TYPE", value_delimiter = ',')]
+    fs_deny: Vec<String>,
+
+    /// Report files whose sizes differ by at most WINDOW bytes and whose first 1 MiB is identical
+    #[arg(long, value_name = "WINDOW")]
+    near_size_report: Option<u64>,
+
+    /// Report content found on more than one device, which is never linked across
+    #[arg(long, default_value_t = false)]
+    cross_device_report: bool,
+
+    /// Sum up duplicate files and bytes by lowercased file extension
+    #[arg(long, default_value_t = false)]
+    by_extension: bool,
+
+    /// With --by-extension, recognize these compound extensions, e.g. tar.gz, instead of the last one
+    #[arg(long, value_name = "EXT,EXT", value_delimiter = ',', num_args = 0..=1, require_equals = true, default_missing_value = DEFAULT_COMPOUND_EXTENSIONS, requires = "by_extension")]
+    compound_ext: Option<Vec<String>>,
+
+    #[arg(long, value_enum, default_value_t = Format::Text, global = true)]
+    format: Format,
+
+    /// Only list the groups of identical files, in the format of fdupes, without linking anything
+    #[arg(long, default_value_t = false, conflicts_with_all = ["format", "json"])]
+    list: bool,
+
+    /// With --list, start each group with the size of its files
+    #[arg(long, default_value_t = false, requires = "list")]
+    size: bool,
+
+    /// With --list, list only one path of files already linked to each other
+    #[arg(long, default_value_t = false, requires = "list")]
+    hardlinks_as_one: bool,
+
+    /// Same as --format json
+    #[arg(long, default_value_t = false, conflicts_with = "format")]
+    json: bool,
+
+    /// Number of hex digits of the hash shown in group headers of the text output
+    #[arg(long, value_name = "N", default_value_t = 64, value_parser = clap::value_parser!(u64).range(1..=64))]
+    hash_width: u64,
+
+    /// Write the plan to FILE in a compact binary format, compressed with zstd, for
+    /// apply --plan, without linking anything
+    #[arg(long, value_name = "FILE")]
+    save_plan: Option<PathBuf>,
+
+    /// Compare the plan with the one in a JSON report saved earlier, without linking anything
+    #[arg(long, value_name = "OLD_PLAN.json")]
+    diff_plan: Option<PathBuf>,
+
+    /// Check that all content under A_PATH also exists under B_PATH, without linking anything
+    #[arg(long, num_args = 2, value_names = ["A_PATH", "B_PATH"], conflicts_with = "targets")]
+    expect_mirrored: Option<Vec<PathBuf>>,
+
+    /// Trace a file through the pipeline and print why it was or was not linked
+    #[arg(long, value_name = "PATH")]
+    explain: Vec<PathBuf>,
+
+    /// Maximum number of unmatched files listed by --expect-mirrored
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    unmatched_limit: usize,
+
+    /// List a few of the skipped files for each reason
+    #[arg(long, default_value_t = false)]
+    list_skipped: bool,
+
+    /// Print the full chain of causes and OS error codes when failing
+    #[arg(long, default_value_t = false)]
+    verbose_errors: bool,
+
+    /// Print more details; may be repeated
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Print only the gain at the end, and warnings and errors
+    #[arg(short, long, default_value_t = false, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Write the report to FILE instead of stdout; `-` is stdout
+    #[arg(long, value_name = "FILE", visible_alias = "report-file")]
+    output: Option<PathBuf>,
+
+    /// Begin the temporary names of the relink with STRING, followed by the pid and a random
+    /// part; leftovers of a crashed run with this prefix are removed when safe
+    #[arg(long, value_name = "STRING", default_value = DEFAULT_TMP_PREFIX, value_parser = parse_tmp_prefix)]
+    tmp_prefix: String,
+
+    /// Which mtime of the group the original gets
+    #[arg(long, value_enum, default_value_t = MtimePolicy::Oldest)]
+    mtime_policy: MtimePolicy,
+
+    /// How mtimes in the future, usually due to clock skew, take part in the mtime of the original
+    #[arg(long, value_enum, default_value_t = FutureMtime::Ignore)]
+    future_mtime: FutureMtime,
+
+    /// Never give the original an mtime older than this: YYYY-MM-DD, @SECONDS or an age like 300d
+    #[arg(long, value_name = "TIMESTAMP", value_parser = timestamp::parse_timestamp)]
+    mtime_floor: Option<FileTime>,
+
+    /// What to do with a group whose oldest mtime is older than --mtime-floor
+    #[arg(long, value_enum, default_value_t = MtimeFloorPolicy::Clamp)]
+    mtime_floor_policy: MtimeFloorPolicy,
+
+    /// Hash function used to compare files
+    #[arg(long, value_name = "ALGORITHM", value_enum, default_value_t = HashAlgorithm::Sha256)]
+    hash: HashAlgorithm,
+
+    /// Read files with O_DIRECT while hashing, bypassing the page cache where supported
+    #[arg(long, default_value_t = false)]
+    direct_io: bool,
+
+    /// What to do with a duplicate whose ctime advanced since the scan, e.g. by chown or chmod
+    #[arg(long, value_enum, default_value_t = CtimeChange::Ignore)]
+    abort_on_ctime_change: CtimeChange,
+
+    /// After linking, check N links picked at random: same inode, content hashing as before
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    audit_samples: u64,
+
+    /// Seed for the random choices, e.g. of --audit-samples; taken from the clock if not given
+    #[arg(long, value_name = "SEED", global = true)]
+    seed: Option<u64>,
+
+    /// Skip files and groups that fail to be read, hashed or linked instead of aborting,
+    /// and exit with status 8 at the end if any did
+    #[arg(long, default_value_t = false)]
+    keep_going: bool,
+
+    /// Run CMD with sh when the run ends, however it ends, with DEDUP_STATUS (success, failure
+    /// or cancelled), DEDUP_GAIN_BYTES, DEDUP_GROUPS, DEDUP_ERRORS and DEDUP_DURATION_SECS set
+    #[arg(long, value_name = "CMD")]
+    notify_cmd: Option<String>,
+
+    /// Kill the --notify-cmd if it still runs after SECS seconds
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 10,
+        requires = "notify_cmd"
+    )]
+    notify_timeout: u64,
+
+    /// Abort when a feature asked for, like --direct-io, is not available in the first
+    /// target, instead of going on without it
+    #[arg(long, default_value_t = false)]
+    strict_features: bool,
+
+    /// Abort when an mtime cannot be set, instead of warning and going on
+    #[arg(long, default_value_t = false)]
+    strict_mtime: bool,
+
+    /// Number of threads hashing files; 1 hashes them one by one during the walk
+    #[arg(long, value_name = "N", default_value_t = default_threads(), value_parser = clap::value_parser!(u64).range(1..))]
+    threads: u64,
+
+    /// Hash each file of at least SIZE on several threads: with --hash blake3 through its own
+    /// multithreading, with --hash sha256-tree in 64 MiB parts; not with --direct-io
+    #[arg(long, value_name = "SIZE", default_value = "1G", value_parser = size::parse_size)]
+    parallel_hash_threshold: u64,
+
+    /// Which bytes of the larger files of a size are hashed first, to tell them apart
+    /// without reading them in full; the groups are always made on full hashes
+    #[arg(long, value_enum, default_value_t = PrefilterRegion::Head)]
+    prefilter_region: PrefilterRegion,
+
+    /// Bytes hashed first at each end of --prefilter-region
+    #[arg(long, value_name = "SIZE", default_value = "64K", value_parser = parse_prefilter_size)]
+    prefilter_size: u64,
+
+    /// Number of threads hashing the parts of each file of at least --parallel-hash-threshold
+    #[arg(long, value_name = "N", default_value_t = default_threads(), value_parser = clap::value_parser!(u64).range(1..))]
+    hash_workers: u64,
+
+    /// Append a JSON record per device of the run to FILE, for `dedup history`
+    #[arg(long, value_name = "FILE")]
+    history: Option<PathBuf>,
+
+    /// Keep hashes in FILE between runs, for files whose size and mtime did not change
+    #[arg(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+
+    /// Hash again a fraction RATE of the cache hits, picked with --seed, and purge the entries
+    /// found stale, hashing their files again
+    #[arg(long, value_name = "RATE", default_value_t = 0.0, value_parser = parse_rate, requires = "cache")]
+    cache_verify_rate: f64,
+
+    /// Before relinking, time a few links on each device and print how long the relink should
+    /// take; a run that modifies files then needs --confirm
+    #[arg(long, default_value_t = false)]
+    estimate_relink: bool,
+
+    /// Ask on stdin whether to go on once --estimate-relink printed its estimate; anything
+    /// but y or yes makes the rest of the run a dry run
+    #[arg(long, default_value_t = false, requires = "estimate_relink")]
+    confirm: bool,
+
+    /// Listen on a Unix domain socket at PATH for `dedup ctl` to pause, resume, stop or
+    /// query the run; a pause or a stop waits for the file or group at hand
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+
+    /// Show the progress of the walk, the hashing and the relink on stderr; ignored with
+    /// --format json
+    #[arg(long, default_value_t = false)]
+    progress: bool,
+
+    /// Print a per-phase and per-device timing breakdown
+    #[arg(long, default_value_t = false)]
+    profile: bool,
+
+    /// Scan the files listed in FILE instead of walking the targets, which then only filter
+    /// it: one per line as SIZE<TAB>MTIME<TAB>INODE<TAB>DEVICE<TAB>PATH, MTIME in seconds
+    /// since the epoch with an optional fraction, PATH absolute. Without targets, the run
+    /// is a dry run
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// What to do with files listed in the --manifest that changed since
+    #[arg(long, value_enum, default_value_t = ManifestTrust::Restat, requires = "manifest")]
+    manifest_trust: ManifestTrust,
+
     targets: Vec<PathBuf>,
 }
 
-fn insert_identical_file(identicals: &mut IdenticalFiles, path: &Path, ino: Ino) -> Result<()> {
-    let hash = sha256file(path)
-        .with_context(|| format!("Failed to calculate a hash: {}", path.to_string_lossy()))?;
-    let identical = identicals.get_or_insert(hash);
-    identical.inos.push(ino);
-    Ok(())
+fn default_threads() -> u64 {
+    thread::available_parallelism().map_or(1, |n| n.get() as u64)
 }
 
-fn prepare_file(database: &mut Database, path: &Path, metadata: &fs::Metadata) -> Result<()> {
-    let dev = Dev(metadata.dev());
-    let ino = Ino(metadata.ino());
+/// Hashes the file in full, or only the bytes of `prefilter`.
+fn hash_file(
+    args: &Args,
+    path: &Path,
+    progress: &ProgressHandle,
+    budget: Option<&IoBudget>,
+    prefilter: Option<Prefilter>,
+) -> io::Result<(HashValue, u64)> {
+    if budget.is_some_and(IoBudget::is_exhausted) {
+        return Err(io::Error::other(BudgetExhausted));
+    }
+    if progress.checkpoint() {
+        return Err(io::Error::other(Stopped));
+    }
+    #[cfg(any(test, feature = "test-utils"))]
+    test_utils::run_hooks(test_utils::Stage::Hash, path)?;
+    let mut on_progress = |n| {
+        progress.add_bytes_hashed(n);
+        budget.map_or(Ok(()), |budget| budget.charge(n))
+    };
+    if let Some(prefilter) = prefilter {
+        let (head, tail) = (prefilter.head, prefilter.tail);
+        if args.direct_io {
+            if let Some(result) = digest_ends_direct(path, head, tail, args.hash, &mut on_progress)?
+            {
+                return Ok(result);
+            }
+            if args.verbose > 1 {
+                eprintln!(
+                    "Debug: O_DIRECT rejected, reading buffered: {}",
+                    path.display()
+                );
+            }
+        }
+        return digest_ends(path, head, tail, args.hash, &mut on_progress);
+    }
+    if args.direct_io {
+        if let Some(result) = digest_file_direct(path, args.hash, &mut on_progress)? {
+            return Ok(result);
+        }
+        if args.verbose > 1 {
+            eprintln!(
+                "Debug: O_DIRECT rejected, reading buffered: {}",
+                path.display()
+            );
+        }
+    }
+    let parallel = ParallelHashing {
+        threshold: args.parallel_hash_threshold,
+        workers: args.hash_workers as usize,
+    };
+    if !args.direct_io {
+        if let Some(result) = digest_file_parallel(path, args.hash, parallel, &mut on_progress)? {
+            return Ok(result);
+        }
+    }
+    digest_file_with_progress(path, args.hash, &mut on_progress)
+}
 
-    let device = database.get_or_insert(dev);
-    if let Some(inode) = device.inodes.get_mut(ino) {
-        inode.files.push(path.to_path_buf());
+/// Parses `--prefilter-size`, which may not be 0.
+fn parse_prefilter_size(s: &str) -> Result<u64, String> {
+    match size::parse_size(s)? {
+        0 => Err("the prefilter needs at least a byte".to_string()),
+        size => Ok(size),
+    }
+}
+
+/// The bytes hashed first of the larger files of a size, by the prefix sieve.
+fn prefilter(args: &Args) -> Prefilter {
+    Prefilter::new(args.prefilter_region, args.prefilter_size)
+}
+
+type HashAttempt = (io::Result<(HashValue, u64)>, Duration);
+
+/// Hashes through each of `paths` in turn, until one is found that has not vanished.
+fn hash_inode(
+    args: &Args,
+    paths: &[PathBuf],
+    progress: &ProgressHandle,
+    budget: Option<&IoBudget>,
+    prefilter: Option<Prefilter>,
+) -> Vec<HashAttempt> {
+    let mut attempts = Vec::new();
+    for path in paths {
+        let start = Instant::now();
+        let result = hash_file(args, path, progress, budget, prefilter);
+        let vanished = matches!(&result, Err(err) if err.kind() == io::ErrorKind::NotFound);
+        if prefilter.is_none() && result.is_ok() {
+            progress.add_files_hashed(1);
+        }
+        attempts.push((result, start.elapsed()));
+        if !vanished {
+            break;
+        }
+    }
+    attempts
+}
+
+/// Hashes the inode through the first of its paths that still exists and adds it to the
+/// identical files. Paths that vanished since the walk are dropped, and so is the inode
+/// once no path is left or when its size changed, in which case `false` is returned.
+/// An inode already hashed is never hashed again.
+fn insert_identical_file(
+    args: &Args,
+    dev: Dev,
+    device: &mut Device,
+    ino: Ino,
+    report: &mut Report,
+) -> Result<bool> {
+    if device.inodes.get(ino).unwrap().hashed || insert_cached(args, dev, device, ino, report) {
+        return Ok(true);
+    }
+    let inode = device.inodes.get(ino).unwrap();
+    let attempts = hash_inode(
+        args,
+        &inode.files,
+        &report.progress,
+        report.io_budget.as_ref(),
+        None,
+    );
+    Ok(apply_hash_attempts(dev, device, ino, attempts, report)?.is_some())
+}
+
+/// Adds the inode to the identical files with the hash kept by `--cache`, if its size and
+/// mtime are unchanged. Inode numbers of unreliable devices are never looked up.
+fn insert_cached(
+    args: &Args,
+    dev: Dev,
+    device: &mut Device,
+    ino: Ino,
+    report: &mut Report,
+) -> bool {
+    if device.unreliable_inodes {
+        return false;
+    }
+    let Some(cache) = &mut report.hash_cache else {
+        return false;
+    };
+    let inode = device.inodes.get_mut(ino).unwrap();
+    let Some(hash) = cache.get(dev, inode, args.hash) else {
+        return false;
+    };
+    for file in &inode.files {
+        report
+            .explain
+            .note(file, || format!("hash from the cache: {}", HashHex(hash)));
+    }
+    inode.hashed = true;
+    device.identicals.insert(hash, ino);
+    true
+}
+
+/// Does the bookkeeping of [`insert_identical_file`] for hashes computed by [`hash_inode`],
+/// returning the hash unless the inode was dropped.
+fn apply_hash_attempts(
+    dev: Dev,
+    device: &mut Device,
+    ino: Ino,
+    attempts: Vec<HashAttempt>,
+    report: &mut Report,
+) -> Result<Option<HashValue>> {
+    let size = device.inodes.get(ino).unwrap().size;
+    let Some(hash) = settle_hash_attempts(dev, device, ino, attempts, size, report)? else {
+        return Ok(None);
+    };
+    let inode = device.inodes.get_mut(ino).unwrap();
+    report.hashed += 1;
+    for file in &inode.files {
+        report
+            .explain
+            .note(file, || format!("hash computed: {}", HashHex(hash)));
+    }
+    inode.hashed = true;
+    if let Some(cache) = report
+        .hash_cache
+        .as_mut()
+        .filter(|_| !device.unreliable_inodes)
+    {
+        cache.insert(dev, inode, hash);
+    }
+    device.identicals.insert(hash, ino);
+    Ok(Some(hash))
+}
+
+/// Finds the hash among the attempts of [`hash_inode`], which must have read `expected`
+/// bytes. Vanished paths are dropped, and so is the inode once no path is left or when its
+/// size changed, in which case `None` is returned.
+fn settle_hash_attempts(
+    dev: Dev,
+    device: &mut Device,
+    ino: Ino,
+    attempts: Vec<HashAttempt>,
+    expected: u64,
+    report: &mut Report,
+) -> Result<Option<HashValue>> {
+    let inode = device.inodes.get_mut(ino).unwrap();
+    for (result, elapsed) in attempts {
+        let path = &inode.files[0];
+        if let Some(profile) = &mut report.profile {
+            let device_profile = profile.device(dev);
+            device_profile.hash_time += elapsed;
+            if result.is_ok() {
+                device_profile.files_hashed += 1;
+                device_profile.bytes_hashed += expected;
+            }
+        }
+        match result {
+            Ok((_, len)) if len != expected => {
+                eprintln!(
+                    "Warning: {} changed size since the scan ({} -> {} bytes), ignored",
+                    path.display(),
+                    inode.size,
+                    len,
+                );
+                report.changed += 1;
+                report.record_skip(SkipReason::ChangedDuringScan, path);
+                report
+                    .explain
+                    .note(path, || "size changed before hashing: ignored".to_string());
+                break;
+            }
+            Ok((hash, _)) => return Ok(Some(hash)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                report.vanished += 1;
+                report.record_skip(SkipReason::Vanished, path);
+                report
+                    .explain
+                    .note(path, || "vanished before hashing".to_string());
+                inode.files.remove(0);
+            }
+            Err(err) if BudgetExhausted::is(&err) => {
+                report.not_evaluated += 1;
+                report.not_evaluated_bytes += inode.size;
+                report.record_skip(SkipReason::IoBudgetSpent, path);
+                report
+                    .explain
+                    .note(path, || "not hashed, the --io-budget was spent".to_string());
+                break;
+            }
+            Err(err) if Stopped::is(&err) => {
+                report.record_skip(SkipReason::Stopped, path);
+                report
+                    .explain
+                    .note(path, || "not hashed, the run was stopped".to_string());
+                break;
+            }
+            Err(err) => {
+                let err = anyhow::Error::new(err).context(format!(
+                    "Failed to calculate a hash: {}",
+                    path.to_string_lossy()
+                ));
+                report.keep_going(ErrorKind::Hash, path, err)?;
+                break;
+            }
+        }
+    }
+    device.inodes.remove(ino);
+    Ok(None)
+}
+
+/// Sieves an inode larger than its [`prefilter`] on the hash of those bytes, its prefix. Returns the
+/// inodes to hash in full, in order: none while its prefix is unique, the inode itself
+/// when the prefix is already ambiguous, and first the inode it makes the prefix
+/// ambiguous with.
+fn sieve_prefix(device: &mut Device, ino: Ino, prefix: HashValue, report: &mut Report) -> Vec<Ino> {
+    let inode = device.inodes.get(ino).unwrap();
+    let key = (inode.size, prefix);
+    match device.prefix_sieve.get_mut(key) {
+        None => {
+            for file in &inode.files {
+                report.explain.note(file, || {
+                    "unique prefix so far: not hashed in full".to_string()
+                });
+            }
+            device.prefix_sieve.set_unique(key, ino);
+            report.sieve.prefix_unique_set += 1;
+            Vec::new()
+        }
+        Some(entry) => match *entry {
+            FileSizeSieveEntry::Unique(ino0) => {
+                *entry = FileSizeSieveEntry::Ambiguous;
+                report.sieve.prefix_promoted += 1;
+                vec![ino0, ino]
+            }
+            FileSizeSieveEntry::Ambiguous => {
+                report.sieve.prefix_joined += 1;
+                vec![ino]
+            }
+        },
+    }
+}
+
+/// Hashes an inode of an ambiguous size, like [`insert_identical_file`], but only its
+/// prefix first if it is larger than its [`prefilter`]: the full hash waits for another
+/// inode of the same size and prefix.
+fn insert_candidate(
+    args: &Args,
+    dev: Dev,
+    device: &mut Device,
+    ino: Ino,
+    report: &mut Report,
+) -> Result<bool> {
+    let prefilter = prefilter(args);
+    let inode = device.inodes.get(ino).unwrap();
+    if inode.size <= prefilter.bytes() {
+        return insert_identical_file(args, dev, device, ino, report);
+    }
+    let attempts = hash_inode(
+        args,
+        &inode.files,
+        &report.progress,
+        report.io_budget.as_ref(),
+        Some(prefilter),
+    );
+    let Some(prefix) = settle_hash_attempts(dev, device, ino, attempts, prefilter.bytes(), report)?
+    else {
+        return Ok(false);
+    };
+    let size = device.inodes.get(ino).unwrap().size;
+    let inos = sieve_prefix(device, ino, prefix, report);
+    if let [ino0, _] = inos[..] {
+        if !insert_identical_file(args, dev, device, ino0, report)? && !budget_spent(report) {
+            // the previous inode has vanished: this one takes over its place
+            device.prefix_sieve.set_unique((size, prefix), ino);
+            report.sieve.prefix_unique_set += 1;
+            return Ok(true);
+        }
+    }
+    if inos.is_empty() {
+        return Ok(true);
+    }
+    insert_identical_file(args, dev, device, ino, report)
+}
+
+fn prepare_file(
+    args: &Args,
+    database: &mut Database,
+    path: &Path,
+    stat: &Stat,
+    report: &mut Report,
+) -> Result<()> {
+    let size = stat.size;
+    // before any counting, so that empty files do not weigh in the statistics of the scan
+    if args.filters.skips_empty(size) {
+        report.record_skip(SkipReason::Empty, path);
+        report
+            .explain
+            .note(path, || "empty, without --dedup-empty: ignored".to_string());
         return Ok(());
     }
 
-    let mtime = FileTime::from_last_modification_time(metadata);
+    let dev = Dev(stat.dev);
+    let ino = Ino(stat.ino);
+    report.progress.add_files_scanned(1);
 
-    let nlink = metadata.nlink();
-    let realsize = metadata.blocks() * 512;
+    if let Some(reason) = args.filters.outside_size_limits(size) {
+        report.record_skip(reason, path);
+        report.size_filtered += 1;
+        report.explain.note(path, || {
+            format!("{} bytes, outside --min-size/--max-size: ignored", size)
+        });
+        return Ok(());
+    }
+
+    let device = database.get_or_insert(dev);
+    if !device.unreliable_inodes
+        && (ino.0 == 0
+            || device
+                .inodes
+                .get(ino)
+                .is_some_and(|inode| !inode.matches(stat)))
+    {
+        eprintln!(
+            "Warning: device {} reports inconsistent inode numbers ({} as inode {}), it is only reported",
+            dev.0,
+            path.display(),
+            ino.0
+        );
+        device.mark_unreliable_inodes();
+    }
+    let ino = if device.unreliable_inodes {
+        device.pseudo_ino()
+    } else {
+        ino
+    };
+    if let Some(inode) = device.inodes.get_mut(ino) {
+        // the same path may be reached through overlapping targets
+        if inode.files.iter().any(|file| file == path) {
+            return Ok(());
+        }
+        if args
+            .max_paths_per_inode
+            .is_some_and(|max| inode.files.len() as u64 >= max)
+        {
+            inode.extra_paths += 1;
+        } else {
+            inode.files.push(path.to_path_buf());
+            report.explain.note(path, || {
+                format!(
+                    "scanned: another link to inode {}, hashed along with it",
+                    ino.0
+                )
+            });
+        }
+        return Ok(());
+    }
 
-    let inode = device.inodes.get_or_insert(ino, mtime, nlink, realsize);
+    if let Some(profile) = &mut report.profile {
+        let device_profile = profile.device(dev);
+        device_profile.files_scanned += 1;
+        device_profile.bytes_scanned += size;
+    }
+    let inode = device.inodes.get_or_insert(Inode {
+        ino,
+        ..Inode::new(stat)
+    });
     inode.files.push(path.to_path_buf());
+    if inode.mtime > future_limit() {
+        eprintln!("Warning: {} has an mtime in the future", path.display());
+        report.future_mtimes += 1;
+    }
+    report.explain.note(path, || {
+        format!(
+            "scanned: {} bytes, inode {} on device {}",
+            size, ino.0, dev.0
+        )
+    });
 
-    let size = metadata.size();
     match device.sieve.get_mut(size) {
         // first time: mark unique
-        None => device.sieve.set_unique(size, ino),
+        None => {
+            report
+                .explain
+                .note(path, || "unique size so far: not hashed".to_string());
+            report.log(Verbosity::Verbose, || {
+                format!("Sieve: unique size {} so far: {}", size, path.display())
+            });
+            device.sieve.set_unique(size, ino);
+            report.sieve.unique_set += 1;
+        }
         // already seen
         Some(sieve_entry) => {
             if let &mut FileSizeSieveEntry::Unique(ino0) = sieve_entry {
                 // second time: unmark unique and calculate the hash of previous found file
                 *sieve_entry = FileSizeSieveEntry::Ambiguous;
-                let path0 = &device.inodes.get(ino0).unwrap().files[0];
-                insert_identical_file(&mut device.identicals, path0, ino0)?;
+                report.sieve.promoted += 1;
+                report.log(Verbosity::Verbose, || {
+                    format!(
+                        "Sieve: size {} seen again, hashing: {}",
+                        size,
+                        path.display()
+                    )
+                });
+                report.progress.add_queued(size);
+                if defers_hashing(args, report) {
+                    device.pending.push(ino0);
+                } else if !insert_candidate(args, dev, device, ino0, report)?
+                    && !budget_spent(report)
+                {
+                    // the previous file has vanished: the current file takes over its place
+                    device.sieve.set_unique(size, ino);
+                    report.sieve.unique_set += 1;
+                    return Ok(());
+                }
+            } else {
+                report.sieve.joined += 1;
+                report.log(Verbosity::Verbose, || {
+                    format!(
+                        "Sieve: size {} is ambiguous, hashing: {}",
+                        size,
+                        path.display()
+                    )
+                });
             }
             // calculate the hash of current file
-            insert_identical_file(&mut device.identicals, path, ino)?;
+            report.progress.add_queued(size);
+            if defers_hashing(args, report) {
+                device.pending.push(ino);
+            } else {
+                insert_candidate(args, dev, device, ino, report)?;
+            }
         }
     }
     Ok(())
 }
 
-fn update_mtime<P: AsRef<Path>>(filepath: P, mtime: FileTime) -> Result<()> {
+fn update_mtime<P: AsRef<Path>>(roots: &Roots, filepath: P, mtime: FileTime) -> Result<()> {
     let metadata = &fs::metadata(&filepath).with_context(|| {
         format!(
-            "Failed to fs::metadata: {}",
+            "Failed to fs::metadata while updating the mtime of the original: {}",
             filepath.as_ref().to_string_lossy(),
         )
     })?;
     let file_mtime = FileTime::from_last_modification_time(metadata);
     if file_mtime != mtime {
+        roots.check(Mutation::SetMtime, filepath.as_ref())?;
         filetime::set_file_mtime(&filepath, mtime).with_context(|| {
             format!(
-                "Failed to filetime::set_file_mtime: {}",
+                "Failed to filetime::set_file_mtime while updating the mtime of the original: {}",
                 filepath.as_ref().to_string_lossy(),
             )
         })?;
@@ -86,122 +1125,2431 @@ fn update_mtime<P: AsRef<Path>>(filepath: P, mtime: FileTime) -> Result<()> {
     Ok(())
 }
 
-fn relink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> Result<()> {
-    let link_path = link.as_ref();
-    let link_dir_path = link_path.parent().context("Failed to get a parent path")?;
-    let original_metadata = fs::metadata(link_dir_path).with_context(|| {
-        format!(
-            "Failed to fs::metadata for original: {}",
-            link_dir_path.to_string_lossy(),
-        )
-    })?;
-    let link_dir_metadata = fs::metadata(link_dir_path).with_context(|| {
+/// The mtime a directory had before one of its entries was relinked.
+struct DirMtime<'a> {
+    path: &'a Path,
+    mtime: FileTime,
+}
+
+impl DirMtime<'_> {
+    fn restore(&self, roots: &Roots) -> Result<()> {
+        roots.check(Mutation::SetMtime, self.path)?;
+        filetime::set_file_mtime(self.path, self.mtime).with_context(|| {
+            format!(
+                "Failed to filetime::set_file_mtime to restore a directory mtime: {}",
+                self.path.to_string_lossy(),
+            )
+        })
+    }
+}
+
+/// The filesystem refused to link the paths to each other (EXDEV) although they share a
+/// device, as btrfs subvolumes behind bind mounts can, on the link or the rename over the
+/// duplicate. The duplicate was left alone.
+#[derive(Debug)]
+struct CrossLink;
+
+impl std::fmt::Display for CrossLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "cannot link across filesystems")
+    }
+}
+
+impl std::error::Error for CrossLink {}
+
+/// Whether a path involved is longer than the filesystem allows, which no retry can fix.
+/// Every step that can fail this way comes before the duplicate is touched.
+fn name_too_long(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| err.raw_os_error() == Some(libc::ENAMETOOLONG))
+}
+
+/// Whether the original already has as many links as the filesystem allows. The groups are
+/// split by `--max-links` or the probed limit first, so this is for filesystems that
+/// misreport it, and for links made from outside since the scan.
+fn too_many_links(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| err.raw_os_error() == Some(libc::EMLINK))
+}
+
+/// Attempts at a temporary name before giving up, in case of collisions.
+const TEMPORARY_ATTEMPTS: usize = 16;
+
+/// Links `original` under a temporary name in `dir`, beginning with `prefix`.
+fn link_temporary(original: &Path, dir: &Path, prefix: &str) -> io::Result<PathBuf> {
+    let mut rng = Rng::new(Rng::time_seed());
+    let mut attempts = 0;
+    loop {
+        let temporary = dir.join(temporary_name(prefix, &mut rng));
+        match fs::hard_link(original, &temporary) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                attempts += 1;
+                if attempts == TEMPORARY_ATTEMPTS {
+                    return Err(err);
+                }
+            }
+            result => return result.map(|()| temporary),
+        }
+    }
+}
+
+/// Renames the temporary link to an original over a duplicate.
+fn rename_over(temporary: &Path, duplicate: &Path) -> io::Result<()> {
+    #[cfg(any(test, feature = "test-utils"))]
+    test_utils::run_hooks(test_utils::Stage::Rename, duplicate)?;
+    fs::rename(temporary, duplicate)
+}
+
+/// Replaces `link` with a hard link to `original`, atomically: the link is made under a
+/// temporary name and renamed over `link`, which is there with either inode whenever the
+/// run stops. The mtime of the parent directory is left for the caller to restore.
+fn relink<'a>(
+    roots: &Roots,
+    original_path: &Path,
+    link_path: &'a Path,
+    tmp_prefix: &str,
+) -> Result<DirMtime<'a>> {
+    roots.check(Mutation::Link, link_path)?;
+    let link_dir_path = parent_dir(link_path);
+    let stage = |stage: &str| {
         format!(
-            "Failed to fs::metadata or link_dir: {}",
-            link_dir_path.to_string_lossy(),
+            "Failed to {} while relinking {} to {}",
+            stage,
+            link_path.to_string_lossy(),
+            original_path.to_string_lossy(),
         )
-    })?;
+    };
+
+    // not followed: fs::hard_link would link to a symlink swapped in for the original
+    let original_metadata = fs::symlink_metadata(original_path)
+        .with_context(|| stage("fs::symlink_metadata of the original"))?;
+    ensure!(
+        original_metadata.is_file(),
+        "The original is no longer a regular file while relinking {} to {}",
+        link_path.to_string_lossy(),
+        original_path.to_string_lossy(),
+    );
+    let link_dir_metadata = fs::metadata(link_dir_path)
+        .with_context(|| stage("fs::metadata of the parent directory"))?;
     ensure!(
         original_metadata.dev() == link_dir_metadata.dev(),
-        "dev mismatch",
+        "dev mismatch while relinking {} to {}",
+        link_path.to_string_lossy(),
+        original_path.to_string_lossy(),
     );
 
     let link_dir_mtime = FileTime::from_last_modification_time(&link_dir_metadata);
+    let link_metadata =
+        fs::symlink_metadata(link_path).with_context(|| stage("fs::symlink_metadata"))?;
+    let done = Ok(DirMtime {
+        path: link_dir_path,
+        mtime: link_dir_mtime,
+    });
+    // renaming a link over another link to the same inode would leave the temporary behind
+    if link_metadata.dev() == original_metadata.dev()
+        && link_metadata.ino() == original_metadata.ino()
+    {
+        return done;
+    }
 
-    fs::remove_file(&link).with_context(|| {
-        format!(
-            "Failed to fs::remove_file: {}",
-            link.as_ref().to_string_lossy(),
-        )
-    })?;
-    fs::hard_link(&original, &link).with_context(|| {
+    let temporary = match link_temporary(original_path, link_dir_path, tmp_prefix) {
+        Ok(temporary) => temporary,
+        Err(err) if err.raw_os_error() == Some(libc::EXDEV) => return Err(CrossLink.into()),
+        Err(err) => return Err(err).with_context(|| stage("fs::hard_link to a temporary name")),
+    };
+    if let Err(err) = rename_over(&temporary, link_path) {
+        let _ = fs::remove_file(&temporary);
+        if err.raw_os_error() == Some(libc::EXDEV) {
+            return Err(CrossLink.into());
+        }
+        return Err(err).with_context(|| stage("fs::rename over the duplicate"));
+    }
+    done
+}
+
+/// Removes a duplicate of an original that stays. The mtime of the parent directory is
+/// left for the caller to restore.
+fn remove_duplicate<'a>(roots: &Roots, path: &'a Path) -> Result<DirMtime<'a>> {
+    roots.check(Mutation::Remove, path)?;
+    let dir_path = parent_dir(path);
+    let dir_metadata = fs::metadata(dir_path).with_context(|| {
         format!(
-            "Failed to fs::hard_link: {}, {}",
-            original.as_ref().to_string_lossy(),
-            link.as_ref().to_string_lossy(),
+            "Failed to fs::metadata while removing {}: {}",
+            path.to_string_lossy(),
+            dir_path.to_string_lossy(),
         )
     })?;
+    fs::remove_file(path)
+        .with_context(|| format!("Failed to fs::remove_file: {}", path.to_string_lossy()))?;
+    Ok(DirMtime {
+        path: dir_path,
+        mtime: FileTime::from_last_modification_time(&dir_metadata),
+    })
+}
+
+/// Where a duplicate goes in the quarantine: its absolute path, re-rooted below `dir`.
+fn quarantine_path(dir: &Path, path: &Path) -> Result<PathBuf> {
+    let path = std::path::absolute(path)
+        .with_context(|| format!("Failed to make absolute: {}", path.to_string_lossy()))?;
+    Ok(dir.join(path.strip_prefix("/").unwrap_or(&path)))
+}
 
-    filetime::set_file_mtime(link_dir_path, link_dir_mtime).with_context(|| {
+/// Moves a duplicate into the quarantine, which must be on the same device. The mtime of
+/// the parent directory it left is left for the caller to restore.
+fn quarantine<'a>(roots: &Roots, dir: &Path, path: &'a Path) -> Result<DirMtime<'a>> {
+    let destination = quarantine_path(dir, path)?;
+    roots.check(Mutation::Move, path)?;
+    roots.check(Mutation::Move, &destination)?;
+    let stage = |stage: &str| {
         format!(
-            "Failed to filetime::set_file_mtime to restore a directory mtime: {}",
-            link_dir_path.to_string_lossy(),
+            "Failed to {} while moving {} to {}",
+            stage,
+            path.to_string_lossy(),
+            destination.to_string_lossy(),
         )
-    })?;
+    };
 
-    Ok(())
+    let dir_path = parent_dir(path);
+    let dir_metadata =
+        fs::metadata(dir_path).with_context(|| stage("fs::metadata for the parent"))?;
+    fs::create_dir_all(parent_dir(&destination)).with_context(|| stage("fs::create_dir_all"))?;
+    fs::rename(path, &destination).with_context(|| stage("fs::rename"))?;
+    Ok(DirMtime {
+        path: dir_path,
+        mtime: FileTime::from_last_modification_time(&dir_metadata),
+    })
 }
 
-fn walk_and_prepare(args: &Args, database: &mut Database) -> Result<()> {
-    for target in &args.targets {
-        let mut it = WalkDir::new(target).into_iter();
-        while let Some(entry) = it.next() {
-            let entry = entry.context("Failed to get a entry")?;
-            let path = &entry.path();
-            let metadata = entry
-                .metadata()
-                .with_context(|| format!("Failed to get metadata: {}", path.to_string_lossy()))?;
-            if metadata.is_dir() {
-                let dev = Dev(metadata.dev());
-                let ino = Ino(metadata.ino());
-                // If the directory is already visited, do not walk into the directory.
-                // For example:
-                // - duplicated targets
-                // - bind mount
-                if !database.get_or_insert(dev).visited_dirs.visit(ino) {
-                    it.skip_current_dir();
-                }
-            } else if metadata.is_file() {
-                prepare_file(database, path, &metadata)?;
-            }
-        }
-    }
-    Ok(())
+/// The files dedup itself writes to in the run: the report, the cache, the history, the
+/// plan and the deletion manifest, without their temporary names.
+fn own_paths<'a>(args: &'a Args, out: &'a Output) -> Vec<&'a Path> {
+    out.paths()
+        .last()
+        .copied()
+        .into_iter()
+        .chain(
+            [
+                &args.cache,
+                &args.history,
+                &args.save_plan,
+                &args.deletion_manifest,
+            ]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path),
+        )
+        .collect()
 }
 
-fn execute_relink(database: &Database, dry_run: bool) -> Result<u64> {
-    let mut gain: u64 = 0;
-    for device in database.devices.values() {
-        for identical in device.identicals.map.values() {
-            let mut inodes: Vec<_> = identical
-                .inos
-                .iter()
-                .map(|&ino| device.inodes.get(ino).unwrap())
-                .collect();
-            inodes.sort_by_key(|inode| std::cmp::Reverse(inode.nlink));
-
-            if inodes.len() <= 1 {
-                continue;
-            }
+/// Returns the inodes of the files dedup itself writes to, and of the temporaries the
+/// cache and the plan are written to. They are never scanned, so they can neither be
+/// duplicates nor originals.
+fn own_files(args: &Args, out: &Output) -> HashSet<(Dev, Ino)> {
+    let temporaries = [&args.cache, &args.save_plan]
+        .into_iter()
+        .flatten()
+        .map(|path| {
+            let mut temporary = path.as_os_str().to_owned();
+            temporary.push(".tmp");
+            PathBuf::from(temporary)
+        });
+    own_paths(args, out)
+        .into_iter()
+        .map(Path::to_path_buf)
+        .chain(out.paths().into_iter().map(Path::to_path_buf))
+        .chain(temporaries)
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| (Dev(metadata.dev()), Ino(metadata.ino())))
+        .collect()
+}
 
-            let original_path = inodes[0].files[0].as_path();
-            println!("{}", &original_path.display());
+/// Whether the file at `path` is one of `own_files`, or the temporary of a report left
+/// behind by a crashed run.
+fn is_own_file(own_files: &HashSet<(Dev, Ino)>, path: &Path, stat: &Stat) -> bool {
+    own_files.contains(&(Dev(stat.dev), Ino(stat.ino)))
+        || path.file_name().is_some_and(is_report_temporary)
+}
 
-            let mtime = inodes.iter().map(|inode| inode.mtime).min().unwrap();
-            if !dry_run {
-                update_mtime(original_path, mtime)?;
-            }
+fn warn_if_inside_targets(args: &Args, path: &Path) {
+    let Ok(dir) = fs::canonicalize(parent_dir(path)) else {
+        return;
+    };
+    let inside = args
+        .targets
+        .iter()
+        .filter_map(|target| fs::canonicalize(target).ok())
+        .any(|target| dir.starts_with(target));
+    if inside {
+        eprintln!(
+            "Note: {} is written by dedup and excluded from the scan",
+            path.display()
+        );
+    }
+}
 
-            for &inode in &inodes[1..] {
-                for filepath in &inode.files {
-                    println!("<- {}", &filepath.display());
-                    if !dry_run {
-                        relink(original_path, filepath)?;
-                    }
-                }
-                if inode.files.len() as u64 == inode.nlink {
-                    gain += inode.realsize;
-                }
-            }
-        }
+/// Whether the file at `path` is named exactly like a temporary of the relink, left behind
+/// by a crashed run. It is never scanned, but kept for [`remove_leftover_temporaries`].
+fn is_leftover_temporary(args: &Args, path: &Path, stat: &Stat, report: &mut Report) -> bool {
+    let leftover = path
+        .file_name()
+        .is_some_and(|name| is_temporary_name(name, &args.tmp_prefix));
+    if leftover {
+        report.record_skip(SkipReason::LeftoverTemporary, path);
+        report
+            .leftover_temporaries
+            .push((path.to_path_buf(), Dev(stat.dev), Ino(stat.ino)));
     }
-    Ok(gain)
+    leftover
 }
 
-pub fn run(args: Args) -> Result<()> {
-    let mut database = Database::new();
-    walk_and_prepare(&args, &mut database)?;
-    let gain = execute_relink(&database, args.dry_run)?;
-    println!("Gain: {} bytes", gain.to_formatted_string(&Locale::en));
+/// Removes the temporaries of the relink left behind by a crashed run, if they are links to
+/// an inode scanned under another name, so that nothing is lost with them. The others are
+/// left in place with a warning: they are named like ours, but may not be.
+fn remove_leftover_temporaries(
+    args: &Args,
+    database: &mut Database,
+    report: &mut Report,
+) -> Result<()> {
+    for (path, dev, ino) in std::mem::take(&mut report.leftover_temporaries) {
+        let inode = database
+            .devices
+            .get_mut(&dev)
+            .and_then(|device| device.inodes.get_mut(ino))
+            .filter(|inode| !inode.files.is_empty());
+        let Some(inode) = inode else {
+            eprintln!(
+                "Warning: left {} in place: named like a temporary of dedup, but not a link to a scanned file",
+                path.display()
+            );
+            report.temporaries_kept += 1;
+            continue;
+        };
+        if args.dry_run {
+            eprintln!(
+                "Note: would remove {}, a temporary left by a crashed run",
+                path.display()
+            );
+            continue;
+        }
+        remove_duplicate(&report.roots, &path)?.restore(&report.roots)?;
+        // one link less, which advanced the ctime of the inode
+        if let Ok(metadata) = fs::symlink_metadata(&inode.files[0]) {
+            inode.nlink = metadata.nlink();
+            inode.ctime = change_time(&metadata);
+        }
+        report.log(Verbosity::Verbose, || {
+            format!(
+                "Removed a temporary left by a crashed run: {}",
+                path.display()
+            )
+        });
+        report.temporaries_removed += 1;
+    }
+    Ok(())
+}
+
+fn walk_and_prepare(
+    args: &Args,
+    own_files: &HashSet<(Dev, Ino)>,
+    database: &mut Database,
+    report: &mut Report,
+) -> Result<()> {
+    let canonical_targets: Vec<PathBuf> = args
+        .targets
+        .iter()
+        .filter_map(|target| fs::canonicalize(target).ok())
+        .collect();
+    let mut stat_batches = if args.uring_stat {
+        match StatBatches::new(args.follow_symlinks) {
+            Ok(batches) => Some(batches),
+            Err(err) => {
+                eprintln!(
+                    "Note: --uring-stat: io_uring is not available ({}); each entry is stat'ed on its own",
+                    err
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+    for target in &args.targets {
+        let target_dev = fs::metadata(target)
+            .ok()
+            .map(|metadata| Dev(metadata.dev()));
+        let mut it = WalkDir::new(target)
+            .follow_links(args.follow_symlinks)
+            .into_iter();
+        while let Some(entry) = it.next() {
+            if report.progress.checkpoint() {
+                return Ok(());
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) if err.loop_ancestor().is_some() => {
+                    let path = err.path().unwrap_or(target);
+                    report.record_skip(SkipReason::Symlink, path);
+                    report.explain.note(path, || {
+                        format!(
+                            "symlink to {}, which it is under: not followed",
+                            err.loop_ancestor().unwrap().display()
+                        )
+                    });
+                    continue;
+                }
+                Err(err) => {
+                    let path = err.path().unwrap_or(target).to_path_buf();
+                    let err = anyhow::Error::new(err).context(format!(
+                        "Failed to read a directory entry: {} (under target {})",
+                        path.to_string_lossy(),
+                        target.to_string_lossy(),
+                    ));
+                    report.keep_going(ErrorKind::Walk, &path, err)?;
+                    continue;
+                }
+            };
+            let path = &entry.path();
+            if entry.depth() > 0 {
+                let relpath = path.strip_prefix(target).unwrap_or(path);
+                if args.filters.excluded_by(relpath).is_some() {
+                    report.record_skip(SkipReason::ExcludedByPattern, path);
+                    report
+                        .explain
+                        .note(path, || "excluded by --exclude: not scanned".to_string());
+                    if entry.file_type().is_dir() {
+                        it.skip_current_dir();
+                    }
+                    continue;
+                }
+            }
+            let batched = stat_batches
+                .as_mut()
+                .filter(|_| entry.depth() > 0)
+                .and_then(|batches| batches.take(path));
+            let stat = match batched {
+                Some(stat) => stat.map_err(anyhow::Error::from),
+                None => entry
+                    .metadata()
+                    .map(|metadata| Stat::from(&metadata))
+                    .map_err(anyhow::Error::from),
+            };
+            let stat = match stat.with_context(|| {
+                format!(
+                    "Failed to get metadata: {} (in directory {})",
+                    path.to_string_lossy(),
+                    parent_dir(path).to_string_lossy(),
+                )
+            }) {
+                Ok(stat) => stat,
+                Err(err) => {
+                    report.keep_going(ErrorKind::Walk, path, err)?;
+                    continue;
+                }
+            };
+            if on_other_file_system(args, target_dev, &stat) {
+                report.record_skip(SkipReason::OtherFileSystem, path);
+                report.explain.note(path, || {
+                    format!(
+                        "on device {}, not that of its target, with --one-file-system: not scanned",
+                        stat.dev
+                    )
+                });
+                if stat.is_dir() {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+            // the destination of a symlink followed, which the relink must go to instead
+            let mut destination = None;
+            if entry.depth() > 0 && entry.path_is_symlink() {
+                if !args.follow_symlinks {
+                    report.record_skip(SkipReason::Symlink, path);
+                    report.explain.note(path, || {
+                        "symlink, without --follow-symlinks: not followed".to_string()
+                    });
+                    continue;
+                }
+                let canonical = match fs::canonicalize(path) {
+                    Ok(canonical) => canonical,
+                    Err(err) => {
+                        let err = anyhow::Error::new(err).context(format!(
+                            "Failed to resolve a symlink: {}",
+                            path.to_string_lossy()
+                        ));
+                        report.keep_going(ErrorKind::Walk, path, err)?;
+                        continue;
+                    }
+                };
+                if canonical_targets
+                    .iter()
+                    .any(|target| canonical.starts_with(target))
+                {
+                    report.record_skip(SkipReason::Symlink, path);
+                    report.explain.note(path, || {
+                        format!(
+                            "symlink to {}, inside the targets: left for the walk to reach",
+                            canonical.display()
+                        )
+                    });
+                    if stat.is_dir() {
+                        it.skip_current_dir();
+                    }
+                    continue;
+                }
+                // not added to the roots: the run may only modify the targets
+                report.explain.note(path, || {
+                    format!(
+                        "symlink to {}, outside the targets: scanned, never modified",
+                        canonical.display()
+                    )
+                });
+                report.followed_outside.push(path.to_path_buf());
+                report.followed_outside.push(canonical.clone());
+                destination = Some(canonical);
+            }
+            let path = destination.as_deref().unwrap_or(path);
+            classify_device(args, database, path, &stat, report)?;
+            if stat.is_dir() {
+                let dev = Dev(stat.dev);
+                let ino = Ino(stat.ino);
+                // If the directory is already visited, do not walk into the directory.
+                // For example:
+                // - duplicated targets
+                // - bind mount
+                let device = database.get_or_insert(dev);
+                if device.unreliable_inodes {
+                    continue;
+                }
+                if let Some(first_path) = device.visited_dirs.visit(ino, path) {
+                    if first_path != path {
+                        report.skipped_dirs.push(first_path, path);
+                    }
+                    it.skip_current_dir();
+                } else if let Some(batches) = &mut stat_batches {
+                    // by the path of the walk, which the entries under it are reached by
+                    batches.enter(entry.path());
+                }
+            } else if stat.is_file() {
+                if is_own_file(own_files, path, &stat) {
+                    continue;
+                }
+                if is_leftover_temporary(args, path, &stat, report) {
+                    continue;
+                }
+                prepare_file(args, database, path, &stat, report)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `--one-file-system` leaves out an entry, being on another device than its
+/// target, `target_dev`.
+fn on_other_file_system(args: &Args, target_dev: Option<Dev>, stat: &Stat) -> bool {
+    args.one_file_system && target_dev.is_some_and(|dev| dev != Dev(stat.dev))
+}
+
+/// Looks up the filesystem type of the device of `path` when it is first seen, which
+/// decides whether `--fs-allow` and `--fs-deny` leave it report-only, and its identity,
+/// which `--cache` entries are found by.
+fn classify_device(
+    args: &Args,
+    database: &mut Database,
+    path: &Path,
+    stat: &Stat,
+    report: &mut Report,
+) -> Result<()> {
+    let device = database.get_or_insert(Dev(stat.dev));
+    if device.fs_type.is_none() {
+        let context = || format!("Failed to statfs: {}", path.to_string_lossy());
+        let name = fs_type(path).with_context(context)?;
+        let id = fs_id(path, stat.dev).with_context(context)?;
+        device.report_only = (!args.fs_allow.is_empty() && !args.fs_allow.contains(&name))
+            || args.fs_deny.contains(&name);
+        device.fs_type = Some(name);
+        if let Some(cache) = &mut report.hash_cache {
+            cache.add_device(device.dev, &id);
+        }
+        device.fs_id = Some(id);
+    }
+    Ok(())
+}
+
+/// Same as [`walk_and_prepare`] for the files listed in a `--manifest`, which are stat'ed
+/// again, as inodes need their owner, mode and link count, but never read from their
+/// directories. The targets, if any, only filter the manifest.
+fn scan_manifest(
+    args: &Args,
+    manifest: &Path,
+    own_files: &HashSet<(Dev, Ino)>,
+    database: &mut Database,
+    report: &mut Report,
+) -> Result<()> {
+    let mut inventory = Inventory::open(manifest)?;
+    while let Some(entry) = inventory.next_entry()? {
+        if report.progress.checkpoint() {
+            return Ok(());
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(reason) => {
+                eprintln!("Warning: {}; line ignored", reason);
+                report.manifest_malformed += 1;
+                continue;
+            }
+        };
+        let path = &entry.path;
+        if !args.targets.is_empty() && !args.targets.iter().any(|target| path.starts_with(target)) {
+            continue;
+        }
+        let relpath = relative_path(&args.targets, path);
+        if args.filters.excluded_by(relpath).is_some() {
+            report.record_skip(SkipReason::ExcludedByPattern, path);
+            report
+                .explain
+                .note(path, || "excluded by --exclude: not scanned".to_string());
+            continue;
+        }
+        // the listed size saves the stat of files outside the limits, which are skipped
+        // either way
+        if let Some(reason) = args.filters.outside_size_limits(entry.size) {
+            report.record_skip(reason, path);
+            report.size_filtered += 1;
+            continue;
+        }
+
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                report.vanished += 1;
+                report.record_skip(SkipReason::Vanished, path);
+                report
+                    .explain
+                    .note(path, || "listed in the manifest, but gone".to_string());
+                continue;
+            }
+            Err(err) => {
+                let err = anyhow::Error::new(err).context(format!(
+                    "Failed to get metadata: {}",
+                    path.to_string_lossy()
+                ));
+                report.keep_going(ErrorKind::Walk, path, err)?;
+                continue;
+            }
+        };
+        let stale = !metadata.is_file()
+            || Dev(metadata.dev()) != entry.dev
+            || Ino(metadata.ino()) != entry.ino
+            || metadata.size() != entry.size
+            || FileTime::from_last_modification_time(&metadata) != entry.mtime;
+        if stale {
+            if args.manifest_trust == ManifestTrust::Drop || !metadata.is_file() {
+                report.record_skip(SkipReason::ManifestStale, path);
+                report
+                    .explain
+                    .note(path, || "changed since the manifest: dropped".to_string());
+                continue;
+            }
+            report.manifest_restated += 1;
+        }
+        let stat = Stat::from(&metadata);
+        classify_device(args, database, path, &stat, report)?;
+        if is_own_file(own_files, path, &stat) {
+            continue;
+        }
+        if is_leftover_temporary(args, path, &stat, report) {
+            continue;
+        }
+        prepare_file(args, database, path, &stat, report)?;
+    }
+    Ok(())
+}
+
+/// Lists the files found under only one of the targets of `against`, by relative path.
+fn one_sided(args: &Args, database: &Database) -> OneSided {
+    let relative_paths = |target: &Path| -> BTreeSet<&Path> {
+        database
+            .devices
+            .values()
+            .flat_map(|device| device.inodes.map.values())
+            .flat_map(|inode| &inode.files)
+            .filter_map(|file| file.strip_prefix(target).ok())
+            .collect()
+    };
+    let old = relative_paths(&args.targets[0]);
+    let new = relative_paths(&args.targets[1]);
+
+    let mut one_sided = OneSided::default();
+    for path in old.difference(&new) {
+        one_sided.only_in_old_count += 1;
+        if one_sided.only_in_old.len() < args.unmatched_limit {
+            one_sided.only_in_old.push(args.targets[0].join(path));
+        }
+    }
+    for path in new.difference(&old) {
+        one_sided.only_in_new_count += 1;
+        if one_sided.only_in_new.len() < args.unmatched_limit {
+            one_sided.only_in_new.push(args.targets[1].join(path));
+        }
+    }
+    one_sided
+}
+
+/// Whether the `--io-budget` is spent: a candidate left unhashed for it did not vanish, and
+/// the files of its size are not evaluated either.
+fn budget_spent(report: &Report) -> bool {
+    report
+        .io_budget
+        .as_ref()
+        .is_some_and(IoBudget::is_exhausted)
+}
+
+/// Whether hashing waits for the end of the walk: with worker threads, or for `--cache` to
+/// know which size buckets are unchanged.
+fn defers_hashing(args: &Args, report: &Report) -> bool {
+    args.threads > 1 || report.hash_cache.is_some()
+}
+
+/// Hashes the inodes left pending by the walk on `--threads` workers, sieving the larger
+/// ones on their prefixes first. The results are applied in the order the inodes were
+/// queued, so that the outcome does not depend on the scheduling of the workers.
+fn hash_pending(args: &Args, database: &mut Database, report: &mut Report) -> Result<()> {
+    let prefilter = prefilter(args);
+    // the prefixes of the larger inodes first, unless cached
+    let mut candidates = Vec::new();
+    let mut jobs = Vec::new();
+    for device in database.devices.values_mut() {
+        let pending = std::mem::take(&mut device.pending);
+        let known_unique = match &report.hash_cache {
+            Some(cache) if !device.unreliable_inodes => {
+                cache.known_unique(device, &pending, |ino| {
+                    picked_for_verification(device.dev, ino, report)
+                })
+            }
+            _ => HashSet::new(),
+        };
+        for ino in pending {
+            if known_unique.contains(&ino) {
+                report.known_unique += 1;
+                for file in &device.inodes.get(ino).unwrap().files {
+                    report.explain.note(file, || {
+                        "unique in the cache along with its size bucket: not hashed".to_string()
+                    });
+                }
+                device.known_unique.push(ino);
+                continue;
+            }
+            let inode = device.inodes.get(ino).unwrap();
+            let cached = match &report.hash_cache {
+                Some(cache) if !device.unreliable_inodes => {
+                    cache.get_prefix(device.dev, inode, args.hash, prefilter)
+                }
+                _ => None,
+            };
+            // a cached prefix picked by --cache-verify-rate is hashed again to be compared
+            let (cached, expected) = if picked_for_verification(device.dev, ino, report) {
+                (None, cached)
+            } else {
+                (cached, None)
+            };
+            if inode.size > prefilter.bytes() && cached.is_none() {
+                jobs.push(inode.files.clone());
+            }
+            candidates.push((device.dev, ino, cached, expected));
+        }
+    }
+    let mut prefixes = hash_jobs(args, &jobs, Some(prefilter), report).into_iter();
+
+    let mut full = Vec::new();
+    let mut verified = HashSet::new();
+    for (dev, ino, cached, expected) in candidates {
+        let device = database.devices.get_mut(&dev).unwrap();
+        if device.inodes.get(ino).unwrap().size <= prefilter.bytes() {
+            full.push((dev, ino));
+            continue;
+        }
+        let prefix = match cached {
+            Some(prefix) => prefix,
+            None => {
+                let attempts = prefixes.next().unwrap();
+                let Some(prefix) =
+                    settle_hash_attempts(dev, device, ino, attempts, prefilter.bytes(), report)?
+                else {
+                    continue;
+                };
+                if let Some(expected) = expected {
+                    verified.insert((dev, ino));
+                    if expected != prefix {
+                        purge_stale(dev, device, ino, report);
+                    }
+                }
+                if let Some(cache) = report
+                    .hash_cache
+                    .as_mut()
+                    .filter(|_| !device.unreliable_inodes)
+                {
+                    cache.insert_prefix(dev, device.inodes.get(ino).unwrap(), prefix, prefilter);
+                }
+                prefix
+            }
+        };
+        full.extend(
+            sieve_prefix(device, ino, prefix, report)
+                .into_iter()
+                .map(|ino| (dev, ino)),
+        );
+    }
+
+    let mut jobs = Vec::new();
+    let mut hashed = Vec::new();
+    for (dev, ino) in full {
+        let device = database.devices.get_mut(&dev).unwrap();
+        // a cached hash picked by --cache-verify-rate is computed again to be compared
+        let picked = picked_for_verification(dev, ino, report);
+        let expected = match &mut report.hash_cache {
+            Some(cache) if picked && !device.unreliable_inodes => {
+                cache.get(dev, device.inodes.get(ino).unwrap(), args.hash)
+            }
+            _ => None,
+        };
+        if expected.is_none() && insert_cached(args, dev, device, ino, report) {
+            continue;
+        }
+        jobs.push(device.inodes.get(ino).unwrap().files.clone());
+        hashed.push((dev, ino, expected));
+    }
+    let results = hash_jobs(args, &jobs, None, report);
+    for ((dev, ino, expected), attempts) in hashed.into_iter().zip(results) {
+        let device = database.devices.get_mut(&dev).unwrap();
+        let hash = apply_hash_attempts(dev, device, ino, attempts, report)?;
+        if let (Some(expected), Some(hash)) = (expected, hash) {
+            verified.insert((dev, ino));
+            if expected != hash {
+                purge_stale(dev, device, ino, report);
+                if let Some(cache) = &mut report.hash_cache {
+                    cache.insert(dev, device.inodes.get(ino).unwrap(), hash);
+                }
+            }
+        }
+    }
+    if let Some(verification) = &mut report.cache_verification {
+        verification.verified += verified.len() as u64;
+    }
+    Ok(())
+}
+
+/// Whether `--cache-verify-rate` picks the inode, for its cache hits to be checked.
+fn picked_for_verification(dev: Dev, ino: Ino, report: &Report) -> bool {
+    report
+        .cache_verification
+        .as_ref()
+        .is_some_and(|verification| verification.picks(dev, ino))
+}
+
+/// Purges the cache entry of an inode whose contents turned out to differ from its cached
+/// hash, or hash of the prefix, and reports it.
+fn purge_stale(dev: Dev, device: &Device, ino: Ino, report: &mut Report) {
+    let inode = device.inodes.get(ino).unwrap();
+    if let Some(cache) = &mut report.hash_cache {
+        cache.purge(dev, inode);
+    }
+    if let Some(verification) = &mut report.cache_verification {
+        verification.record_stale(&inode.files[0]);
+    }
+}
+
+/// Hashes the paths of each job on `--threads` workers, in full or only the bytes of
+/// `prefilter`, returning the attempts in the order of the jobs.
+fn hash_jobs(
+    args: &Args,
+    jobs: &[Vec<PathBuf>],
+    prefilter: Option<Prefilter>,
+    report: &Report,
+) -> Vec<Vec<HashAttempt>> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Vec<HashAttempt>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..args.threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(files) = jobs.get(index) else {
+                            break;
+                        };
+                        results.push((
+                            index,
+                            hash_inode(
+                                args,
+                                files,
+                                &report.progress,
+                                report.io_budget.as_ref(),
+                                prefilter,
+                            ),
+                        ));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|&(index, _)| index);
+    results.into_iter().map(|(_, attempts)| attempts).collect()
+}
+
+/// Walks the targets and hashes the files whose sizes collide, leaving only the groups of
+/// identical files in the database.
+fn scan_targets(
+    args: &Args,
+    own_files: &HashSet<(Dev, Ino)>,
+    report: &mut Report,
+) -> Result<Database> {
+    let mut database = Database::new();
+    database.targets = args.targets.clone();
+    database.hash = args.hash;
+    match &args.manifest {
+        Some(manifest) => scan_manifest(args, manifest, own_files, &mut database, report)?,
+        None => walk_and_prepare(args, own_files, &mut database, report)?,
+    }
+    if defers_hashing(args, report) {
+        report.progress.set_phase(Phase::Hash);
+        hash_pending(args, &mut database, report)?;
+    }
+    if args.cross_device_report {
+        hash_shared_sizes(args, &mut database, report)?;
+        report.cross_device = Some(cross_device_report(&database));
+    }
+    for device in database.devices.values_mut() {
+        if let Some(cache) = report
+            .hash_cache
+            .as_mut()
+            .filter(|_| !device.unreliable_inodes)
+        {
+            cache.mark_unique(device);
+        }
+        report.sieve.add_outcomes(device);
+        device.identicals.retain_duplicates();
+        device.normalize();
+        report.already_linked += device.already_linked();
+    }
+    Ok(database)
+}
+
+/// Hashes the inodes left unhashed by the sieves whose size is found on another device, so
+/// that the identical files of the devices can be joined.
+fn hash_shared_sizes(args: &Args, database: &mut Database, report: &mut Report) -> Result<()> {
+    if database.devices.len() < 2 {
+        return Ok(());
+    }
+    let shared = shared_sizes(database);
+    let mut devs: Vec<_> = database.devices.keys().copied().collect();
+    devs.sort();
+    for dev in devs {
+        let device = database.devices.get_mut(&dev).unwrap();
+        let mut inos: Vec<_> = device
+            .inodes
+            .map
+            .values()
+            .filter(|inode| !inode.hashed && shared.contains(&inode.size))
+            .map(|inode| inode.ino)
+            .collect();
+        inos.sort();
+        for ino in inos {
+            insert_identical_file(args, dev, device, ino, report)?;
+        }
+    }
+    Ok(())
+}
+
+/// Each of `dirs` as given, followed by its spellings under the targets holding it once both
+/// are canonicalized, as the paths of the files under it are spelled.
+fn spell_under_targets(targets: &[PathBuf], dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let roots: Vec<_> = targets
+        .iter()
+        .filter_map(|target| Some((target, fs::canonicalize(target).ok()?)))
+        .collect();
+    let mut spellings = Vec::new();
+    for dir in dirs {
+        spellings.push(dir.clone());
+        let Ok(canonical) = fs::canonicalize(dir) else {
+            continue;
+        };
+        for (target, root) in &roots {
+            if let Ok(rest) = canonical.strip_prefix(root) {
+                let spelling = target.join(rest);
+                if !spellings.contains(&spelling) {
+                    spellings.push(spelling);
+                }
+            }
+        }
+    }
+    spellings
+}
+
+/// Drops the targets that are the same directory or file as an earlier one, as the same
+/// filesystem mounted twice would give, so that its files are scanned under one spelling.
+fn merge_equivalent_targets(targets: &mut Vec<PathBuf>) {
+    let mut seen: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    targets.retain(|target| {
+        // targets that cannot be read are left for the walk to report
+        let Ok(metadata) = fs::metadata(target) else {
+            return true;
+        };
+        match seen.entry((metadata.dev(), metadata.ino())) {
+            hash_map::Entry::Occupied(first) => {
+                eprintln!(
+                    "Note: {} is the same as {}, scanned only as the latter",
+                    target.display(),
+                    first.get().display()
+                );
+                false
+            }
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(target.clone());
+                true
+            }
+        }
+    });
+}
+
+fn relative_path<'a>(targets: &[PathBuf], path: &'a Path) -> &'a Path {
+    targets
+        .iter()
+        .find_map(|target| path.strip_prefix(target).ok())
+        .unwrap_or(path)
+}
+
+/// Mtimes this far ahead of the clock are not explained by filesystem timestamp granularity.
+const FUTURE_MTIME_TOLERANCE: i64 = 60;
+
+fn future_limit() -> FileTime {
+    let now = FileTime::now();
+    FileTime::from_unix_time(
+        now.unix_seconds() + FUTURE_MTIME_TOLERANCE,
+        now.nanoseconds(),
+    )
+}
+
+/// The mtime given to the original: the oldest or newest of the group as per
+/// `--mtime-policy`, subject to `--future-mtime`. `None` if the original keeps its own,
+/// or no member may donate its mtime.
+fn group_mtime(args: &Args, inodes: &[&Inode]) -> Option<FileTime> {
+    let limit = future_limit();
+    let now = FileTime::now();
+    let mtimes = inodes.iter().map(|inode| inode.mtime);
+    let mtimes: Vec<_> = match args.future_mtime {
+        FutureMtime::Ignore => mtimes.collect(),
+        FutureMtime::Clamp => mtimes
+            .map(|mtime| if mtime > limit { now } else { mtime })
+            .collect(),
+        FutureMtime::Skip => mtimes.filter(|&mtime| mtime <= limit).collect(),
+    };
+    match args.mtime_policy {
+        MtimePolicy::Oldest => mtimes.into_iter().min(),
+        MtimePolicy::Newest => mtimes.into_iter().max(),
+        MtimePolicy::Keep => None,
+    }
+}
+
+/// Whether the ctime of an inode advanced since the scan, as seen through `path`.
+fn ctime_changed(inode: &Inode, path: &Path) -> Result<bool> {
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to re-stat: {}", path.to_string_lossy()))?;
+    Ok(change_time(&metadata) > inode.ctime)
+}
+
+/// Whether `path` is still the inode hashed by the scan: the same device, inode number, size
+/// and mtime. Otherwise the file was modified or replaced since, and may no longer be a
+/// duplicate, or it is gone.
+fn unchanged_since_scan(dev: Dev, inode: &Inode, path: &Path) -> Result<bool> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to re-stat: {}", path.to_string_lossy()))
+        }
+    };
+    Ok(Dev(metadata.dev()) == dev
+        && Ino(metadata.ino()) == inode.ino
+        && inode.matches(&Stat::from(&metadata)))
+}
+
+/// Inodes with a path under a `--reference` directory, or reached through a symlink
+/// followed out of the targets, are never modified, only linked to.
+fn is_reference(args: &Args, followed_outside: &[PathBuf], inode: &Inode) -> bool {
+    inode.files.iter().any(|file| {
+        args.reference
+            .iter()
+            .chain(followed_outside)
+            .any(|dir| file.starts_with(dir))
+    })
+}
+
+/// Setting an mtime is cosmetic: unless `--strict-mtime` is given, a failure is only
+/// reported and the group goes on.
+fn mtime_failure(args: &Args, report: &mut Report, path: &Path, err: anyhow::Error) -> Result<()> {
+    if args.strict_mtime || err.is::<OutsideRoots>() {
+        return Err(err);
+    }
+    eprintln!("Warning: {:#}", err);
+    report.mtime_failures += 1;
+    report
+        .errors
+        .push(ErrorRecord::new(ErrorKind::Relink, &[path], &err));
+    report.progress.add_errors(1);
+    Ok(())
+}
+
+/// A group of identical inodes as planned: in order, the original first, and split in runs
+/// that each fit within the limit on links.
+struct PlannedGroup<'a> {
+    device: &'a Device,
+    hash: &'a HashValue,
+    inodes: Vec<&'a Inode>,
+    runs: Vec<Range<usize>>,
+    reflink_other_users: bool,
+}
+
+/// Picks the original of a group and adds it to `plan`, unless the group is left alone.
+fn plan_group<'a>(
+    args: &Args,
+    device: &'a Device,
+    hash: &'a HashValue,
+    mut inodes: Vec<&'a Inode>,
+    report: &mut Report,
+    plan: &mut Vec<PlannedGroup<'a>>,
+) {
+    let dev = device.dev;
+    let references = inodes
+        .iter()
+        .filter(|inode| is_reference(args, &report.followed_outside, inode))
+        .count();
+    if references == inodes.len() {
+        for inode in &inodes {
+            for file in &inode.files {
+                report.explain.note_grouped(file, || {
+                    "only identical to files under --reference: left alone".to_string()
+                });
+            }
+        }
+        return;
+    }
+    // the sorts are stable: ties keep the order of Device::normalize, by nlink, mtime and
+    // then first path
+    inodes.sort_by_key(|inode| std::cmp::Reverse(inode.nlink));
+    if !args.prefer.is_empty() {
+        inodes.sort_by_key(|inode| {
+            args.prefer
+                .iter()
+                .position(|dir| inode.files[0].starts_with(dir))
+                .unwrap_or(args.prefer.len())
+        });
+    }
+    if args.prefer_first_target {
+        inodes.sort_by_key(|inode| !inode.files[0].starts_with(&args.targets[0]));
+    }
+    report.groups_found += 1;
+    let anchored: Vec<_> = inodes
+        .iter()
+        .filter_map(|inode| report.anchors.get(dev, inode.ino))
+        .map(Path::to_path_buf)
+        .collect();
+    for inode in &inodes {
+        if report.anchors.get(dev, inode.ino).is_some() {
+            report.anchors.mark_found(dev, inode.ino);
+        }
+    }
+    if anchored.len() > 1 {
+        report.log(Verbosity::Normal, || {
+            format!(
+                "Skipped a group with several anchors: {}",
+                anchored
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        });
+        for inode in &inodes {
+            for file in &inode.files {
+                report.record_skip(SkipReason::AnchorConflict, file);
+                report
+                    .explain
+                    .note_grouped(file, || "skipped: group with several anchors".to_string());
+            }
+        }
+        report.anchor_conflicts += 1;
+        return;
+    }
+    inodes.sort_by_key(|inode| report.anchors.get(dev, inode.ino).is_none());
+    if references > 0 {
+        inodes.sort_by_key(|inode| !is_reference(args, &report.followed_outside, inode));
+    }
+
+    let reflink_fallback = args.conflict_fallback == Some(ConflictFallback::Reflink);
+    let mut reflink_other_users = false;
+
+    if inodes.iter().any(|inode| inode.uid != inodes[0].uid) {
+        match args.cross_user {
+            CrossUser::Allow => {}
+            CrossUser::Warn => {
+                eprintln!(
+                    "Warning: linking files owned by different users: {}",
+                    inodes[0].files[0].display()
+                );
+                report.cross_user_warned += 1;
+            }
+            CrossUser::Skip if reflink_fallback => {
+                report.log(Verbosity::Normal, || {
+                    format!(
+                        "Reflinking files owned by different users instead of linking them: {}",
+                        inodes[0].files[0].display()
+                    )
+                });
+                reflink_other_users = true;
+            }
+            CrossUser::Skip => {
+                report.log(Verbosity::Normal, || {
+                    format!(
+                        "Skipped a group of files owned by different users: {}",
+                        inodes[0].files[0].display()
+                    )
+                });
+                for inode in &inodes {
+                    for file in &inode.files {
+                        report.record_skip(SkipReason::OwnerMismatch, file);
+                        report.explain.note_grouped(file, || {
+                            "skipped: group of files owned by different users".to_string()
+                        });
+                    }
+                }
+                report.cross_user_skipped += 1;
+                return;
+            }
+        }
+    }
+
+    let limit = match args.mode {
+        Mode::Hardlink => args.max_links.or(device.link_max),
+        _ => None,
+    };
+    let links: Vec<_> = inodes
+        .iter()
+        .map(|inode| (inode.nlink, inode.files.len() as u64))
+        .collect();
+    let runs = split_by_link_max(&links, limit.unwrap_or(u64::MAX));
+    if runs.len() > 1 {
+        report.log(Verbosity::Normal, || {
+            format!(
+                "Note: split a group between {} originals, as the filesystem allows {} links: {}",
+                runs.len(),
+                limit.unwrap(),
+                inodes[0].files[0].display()
+            )
+        });
+        report.link_max_splits += 1;
+    }
+    plan.push(PlannedGroup {
+        device,
+        hash,
+        inodes,
+        runs,
+        reflink_other_users,
+    });
+}
+
+/// Links, or deletes, the inodes of a group after the first to the first, the original.
+/// Returns whether anything was done.
+fn relink_run(
+    args: &Args,
+    device: &Device,
+    hash: &HashValue,
+    inodes: &[&Inode],
+    reflink_other_users: bool,
+    report: &mut Report,
+    out: &mut Output,
+) -> Result<bool> {
+    let dev = device.dev;
+    let dry_run = args.dry_run || device.report_only;
+    // sorted first by plan_group
+    let references = inodes
+        .iter()
+        .take_while(|inode| is_reference(args, &report.followed_outside, inode))
+        .count();
+    let reflink_fallback = args.conflict_fallback == Some(ConflictFallback::Reflink);
+    if matches!(args.format, Format::FlatCsv | Format::FlatJson) {
+        let group_id = format!("{}-{:.16}", inodes[0].size, HashHex(*hash));
+        for (i, inode) in inodes.iter().enumerate() {
+            for (j, file) in inode.files.iter().enumerate() {
+                report.flat.push(FlatRow {
+                    group_id: group_id.clone(),
+                    role: if i == 0 && j == 0 {
+                        Role::Original
+                    } else {
+                        Role::Duplicate
+                    },
+                    path: file.clone(),
+                    size: inode.size,
+                    hash: HashHex(*hash),
+                    inode: inode.ino.0,
+                    nlink: inode.nlink,
+                });
+            }
+        }
+    }
+    let mut acted = false;
+    let original_path = inodes[0].files[0].as_path();
+    #[cfg(any(test, feature = "test-utils"))]
+    test_utils::run_hooks(test_utils::Stage::Relink, original_path)?;
+    // anything linked to an original that changed since would lose its content
+    if !dry_run && !unchanged_since_scan(dev, inodes[0], original_path)? {
+        eprintln!(
+            "Warning: {} changed since it was hashed, its group is skipped",
+            original_path.display()
+        );
+        for inode in inodes {
+            for file in &inode.files {
+                report.record_skip(SkipReason::ChangedBeforeRelink, file);
+                report.explain.note_grouped(file, || {
+                    "skipped: the original changed since it was hashed".to_string()
+                });
+            }
+        }
+        report.changed_before_relink += 1;
+        return Ok(false);
+    }
+    let hash = HashHex(*hash);
+    if args.lists_groups() {
+        writeln!(
+            out,
+            "{:.width$}  {}",
+            hash,
+            &original_path.display(),
+            width = args.hash_width as usize
+        )?;
+    }
+    let gain_before = report.gain;
+    let mut group = Group {
+        hash,
+        dev: dev.0,
+        size: inodes[0].size,
+        gain: 0,
+        original: original_path.to_path_buf(),
+        linked: Vec::new(),
+        reflinked: Vec::new(),
+        skipped: Vec::new(),
+    };
+    let others = inodes.len() - 1;
+    for inode in inodes {
+        for file in &inode.files {
+            report
+                .explain
+                .note_grouped(file, || format!("grouped with {} other inodes", others));
+        }
+    }
+    report
+        .explain
+        .note_grouped(original_path, || "kept as the original".to_string());
+    for file in &inodes[0].files[1..] {
+        report
+            .explain
+            .note_grouped(file, || "already linked to the original".to_string());
+    }
+
+    // only the time spent in I/O is attributed to the device
+    let mut io_time = Duration::ZERO;
+    let mut mtime = group_mtime(args, inodes);
+    if let (Some(oldest), Some(floor)) = (mtime, args.mtime_floor) {
+        if oldest < floor {
+            mtime = match args.mtime_floor_policy {
+                MtimeFloorPolicy::Clamp => Some(floor),
+                MtimeFloorPolicy::Skip => None,
+            };
+            if args.verbose > 0 && args.lists_groups() {
+                let action = match mtime {
+                    Some(_) => "using the floor",
+                    None => "leaving the mtime alone",
+                };
+                writeln!(
+                    out,
+                    "   mtime {} is older than the floor {}, {}",
+                    format_timestamp(oldest),
+                    format_timestamp(floor),
+                    action
+                )?;
+            }
+        }
+    }
+    // a finer mtime would be stored rounded and then set again on every run
+    let mtime = match device.mtime_granularity {
+        Some(granularity) => mtime.map(|mtime| round_down(mtime, granularity)),
+        None => mtime,
+    };
+    if let Some(mtime) = mtime {
+        if !dry_run && args.mode == Mode::Hardlink && references == 0 {
+            if let Err(err) = update_mtime(&report.roots, original_path, mtime) {
+                mtime_failure(args, report, original_path, err)?;
+            }
+        }
+    }
+
+    for &inode in &inodes[1..references.max(1)] {
+        for file in &inode.files {
+            report
+                .explain
+                .note_grouped(file, || "under --reference: left alone".to_string());
+        }
+    }
+    for &inode in &inodes[references.max(1)..] {
+        let mut linked: u64 = 0;
+        let mut deferred: u64 = 0;
+        let mut reflinked = false;
+        let other_user = reflink_other_users && inode.uid != inodes[0].uid;
+        // unlinking a path changes the ctime of the inode, so only check before the first
+        if args.abort_on_ctime_change == CtimeChange::Skip && ctime_changed(inode, &inode.files[0])?
+        {
+            report.log(Verbosity::Normal, || {
+                format!(
+                    "Skipped {}: metadata changed since the scan",
+                    inode.files[0].display()
+                )
+            });
+            for file in &inode.files {
+                report.record_skip(SkipReason::MetadataChanged, file);
+                report
+                    .explain
+                    .note_grouped(file, || "skipped: ctime changed since the scan".to_string());
+            }
+            report.ctime_skipped += 1;
+            continue;
+        }
+        if args.paranoid {
+            let duplicate = &inode.files[0];
+            let ahead = report
+                .verifications
+                .as_mut()
+                .and_then(|verifications| verifications.same(dev, inodes[0].ino, inode.ino));
+            let same = match ahead {
+                Some(same) => same,
+                None => same_content(original_path, duplicate).with_context(|| {
+                    format!(
+                        "Failed to compare {} with {}",
+                        duplicate.to_string_lossy(),
+                        original_path.to_string_lossy()
+                    )
+                })?,
+            };
+            if !same {
+                eprintln!(
+                    "Warning: {} differs from {} despite the same hash, skipped",
+                    duplicate.display(),
+                    original_path.display()
+                );
+                for file in &inode.files {
+                    report.record_skip(SkipReason::ContentMismatch, file);
+                    report.explain.note_grouped(file, || {
+                        "skipped: differs from the original byte by byte".to_string()
+                    });
+                }
+                report.content_mismatches += 1;
+                continue;
+            }
+        }
+        for filepath in &inode.files {
+            let unremovable = if other_user {
+                None
+            } else {
+                check_removable(filepath)?
+            };
+            if other_user || unremovable.is_some() {
+                if reflinked {
+                    // another link to the inode already shares the extents
+                    continue;
+                }
+                if reflink_fallback && (dry_run || reflink(&report.roots, original_path, filepath)?)
+                {
+                    if args.lists_groups() {
+                        writeln!(out, "<~ {}", &filepath.display())?;
+                    }
+                    report.explain.note_grouped(filepath, || {
+                        format!("reflinked to {}", original_path.display())
+                    });
+                    if args.builds_groups() {
+                        group.reflinked.push(filepath.clone());
+                    }
+                    report.reflinked += 1;
+                    if inode.extra_paths == 0 && report.merged.insert((dev, inode.ino)) {
+                        report.gain += inode.realsize;
+                        report.progress.add_bytes_gained(inode.realsize);
+                    }
+                    reflinked = true;
+                    acted = true;
+                    continue;
+                }
+                let reason = match unremovable {
+                    Some(reason) => reason.to_string(),
+                    None => "owned by a different user, cannot reflink".to_string(),
+                };
+                report.log(Verbosity::Normal, || {
+                    format!("Skipped {}: {}", filepath.display(), reason)
+                });
+                report
+                    .explain
+                    .note_grouped(filepath, || format!("skipped: {}", reason));
+                let skip_reason = match unremovable {
+                    Some(Unremovable::StickyNotOwner) => {
+                        report.sticky_skipped += 1;
+                        SkipReason::StickyDirectory
+                    }
+                    Some(Unremovable::AppendOnly) => {
+                        report.append_only_skipped += 1;
+                        SkipReason::AppendOnlyDirectory
+                    }
+                    None => {
+                        report.cross_user_unsupported += 1;
+                        SkipReason::OwnerMismatch
+                    }
+                };
+                report.record_skip(skip_reason, filepath);
+                if args.collects_groups() {
+                    group.skipped.push(filepath.clone());
+                }
+                continue;
+            }
+            if args.mode == Mode::Hardlink && report.cross_link.known(original_path, filepath) {
+                report.cross_link.skipped += 1;
+                report.record_skip(SkipReason::CrossDevice, filepath);
+                report.explain.note_grouped(filepath, || {
+                    "skipped: cannot be linked to the original (EXDEV)".to_string()
+                });
+                continue;
+            }
+            if let Some(limit) = args.per_dir_limit {
+                if report
+                    .dir_relinks
+                    .get(parent_dir(filepath))
+                    .copied()
+                    .unwrap_or(0)
+                    >= limit
+                {
+                    report.record_skip(SkipReason::PerDirLimit, filepath);
+                    report.explain.note_grouped(filepath, || {
+                        "deferred: --per-dir-limit reached in its directory".to_string()
+                    });
+                    report.deferred += 1;
+                    deferred += 1;
+                    continue;
+                }
+            }
+            if !dry_run && !unchanged_since_scan(dev, inode, filepath)? {
+                eprintln!(
+                    "Warning: {} changed since it was hashed, skipped",
+                    filepath.display()
+                );
+                report.record_skip(SkipReason::ChangedBeforeRelink, filepath);
+                report.explain.note_grouped(filepath, || {
+                    "skipped: changed since it was hashed".to_string()
+                });
+                report.changed_before_relink += 1;
+                continue;
+            }
+            if args.lists_groups() {
+                let action = match args.mode {
+                    Mode::Hardlink => "<-",
+                    Mode::Delete => "rm",
+                    Mode::Quarantine => "mv",
+                };
+                writeln!(out, "{} {}", action, &filepath.display())?;
+            }
+            if args.deletion_manifest.is_some() && !device.report_only {
+                report.planned_deletions.push(PlannedDeletion {
+                    path: filepath.clone(),
+                    original: original_path.to_path_buf(),
+                    size: inode.size,
+                    hash,
+                    mtime: inode.mtime.unix_seconds(),
+                    mtime_nanos: inode.mtime.nanoseconds(),
+                });
+            }
+            if !dry_run {
+                let start = Instant::now();
+                let result = match (args.mode, &args.quarantine_dir) {
+                    (Mode::Quarantine, Some(dir)) => quarantine(&report.roots, dir, filepath),
+                    (Mode::Delete, _) => remove_duplicate(&report.roots, filepath),
+                    _ => relink(&report.roots, original_path, filepath, &args.tmp_prefix),
+                };
+                let dir_mtime = match result {
+                    Err(err) if err.is::<CrossLink>() => {
+                        report.cross_link.record(original_path, filepath);
+                        report.record_skip(SkipReason::CrossDevice, filepath);
+                        continue;
+                    }
+                    Err(err) if name_too_long(&err) => {
+                        report.log(Verbosity::Normal, || {
+                            format!("Skipped {}: {:#}", filepath.display(), err)
+                        });
+                        report.record_skip(SkipReason::NameTooLong, filepath);
+                        report.explain.note_grouped(filepath, || {
+                            "skipped: a path is too long for the filesystem (ENAMETOOLONG)"
+                                .to_string()
+                        });
+                        continue;
+                    }
+                    Err(err) if too_many_links(&err) => {
+                        report.log(Verbosity::Normal, || {
+                            format!("Skipped {}: {:#}", filepath.display(), err)
+                        });
+                        report.record_skip(SkipReason::TooManyLinks, filepath);
+                        report.explain.note_grouped(filepath, || {
+                            "skipped: the original has too many links (EMLINK)".to_string()
+                        });
+                        continue;
+                    }
+                    Err(err) if report.keep_going => {
+                        report.keep_going(ErrorKind::Relink, filepath, err)?;
+                        continue;
+                    }
+                    result => result?,
+                };
+                if args.mode == Mode::Hardlink && args.audit_samples > 0 {
+                    report.performed.push(Performed {
+                        original: original_path.to_path_buf(),
+                        duplicate: filepath.clone(),
+                        hash: hash.0,
+                    });
+                }
+                let restored = dir_mtime.restore(&report.roots);
+                io_time += start.elapsed();
+                if let Err(err) = restored {
+                    mtime_failure(args, report, parent_dir(filepath), err)?;
+                }
+            }
+            report.explain.note_grouped(filepath, || match args.mode {
+                Mode::Hardlink => format!("linked to {}", original_path.display()),
+                Mode::Delete => format!("deleted as a duplicate of {}", original_path.display()),
+                Mode::Quarantine => {
+                    format!("quarantined as a duplicate of {}", original_path.display())
+                }
+            });
+            if args.builds_groups() {
+                group.linked.push(filepath.clone());
+            }
+            if args.per_dir_limit.is_some() {
+                *report
+                    .dir_relinks
+                    .entry(parent_dir(filepath).to_path_buf())
+                    .or_default() += 1;
+            }
+            linked += 1;
+        }
+        acted |= linked > 0;
+        if let Some(profile) = &mut report.profile {
+            profile.device(dev).relinks += linked;
+        }
+        if inode.extra_paths > 0 {
+            // the unstored paths still point to this inode
+            report.capped_inodes += 1;
+        } else if linked == inode.nlink && report.merged.insert((dev, inode.ino)) {
+            report.gain += inode.realsize;
+            report.progress.add_bytes_gained(inode.realsize);
+        } else if deferred > 0 && linked + deferred == inode.nlink {
+            report.deferred_gain += inode.realsize;
+        }
+    }
+    if let Some(profile) = &mut report.profile {
+        profile.device(dev).relink_time += io_time;
+    }
+    if let Some(plan) = &mut report.plan_writer {
+        if !device.report_only && !group.linked.is_empty() {
+            plan.write_group(&PlanGroup {
+                hash: group.hash,
+                size: group.size,
+                original: group.original.clone(),
+                linked: if args.collects_groups() {
+                    group.linked.clone()
+                } else {
+                    std::mem::take(&mut group.linked)
+                },
+            })?;
+        }
+    }
+    if args.lists_groups() && report.verbosity == Verbosity::Verbose {
+        writeln!(
+            out,
+            "   gain: {} bytes",
+            (report.gain - gain_before).to_formatted_string(&Locale::en)
+        )?;
+    }
+    if args.collects_groups() {
+        group.gain = report.gain - gain_before;
+        report.groups.push(group);
+    }
+    Ok(acted)
+}
+
+/// The mode, unless `--ignore-permissions`, and the uid and gid, unless `--ignore-ownership`,
+/// which inodes must share to be merged.
+type MergeKey = (Option<u32>, Option<(u32, u32)>);
+
+/// The devices in a stable order, so that the same trees are linked the same way on every run.
+fn devices_in_order(database: &Database) -> Vec<&Device> {
+    let mut devices: Vec<_> = database.devices.values().collect();
+    devices.sort_by_key(|device| device.dev);
+    devices
+}
+
+/// The groups of identical files of `device` in the order they are relinked.
+fn identicals_in_order<'a>(
+    args: &Args,
+    device: &'a Device,
+) -> Vec<(&'a HashValue, &'a IdenticalFile)> {
+    let mut identicals: Vec<_> = device.identicals.map.iter().collect();
+    identicals.sort_by_key(|(hash, _)| hash.as_bytes());
+    if args.per_dir_limit.is_some() {
+        // the largest gains first
+        identicals.sort_by_key(|(hash, identical)| {
+            let size = device
+                .inodes
+                .get(identical.inos.as_slice()[0])
+                .unwrap()
+                .size;
+            (std::cmp::Reverse(size), hash.as_bytes().to_vec())
+        });
+    }
+    identicals
+}
+
+/// The plan stage: the groups to link, in the order they are linked, each with its original
+/// picked. Nothing is read or modified.
+fn plan_relinks<'a>(
+    args: &Args,
+    database: &'a Database,
+    report: &mut Report,
+) -> Vec<PlannedGroup<'a>> {
+    let mut plan = Vec::new();
+    for device in devices_in_order(database) {
+        for (hash, identical) in identicals_in_order(args, device) {
+            let inodes: Vec<_> = identical
+                .inos
+                .iter()
+                .map(|&ino| device.inodes.get(ino).unwrap())
+                .collect();
+
+            if inodes.len() <= 1 {
+                continue;
+            }
+
+            // merging inodes would change the permissions or owner seen through some paths
+            let mut partitions: BTreeMap<MergeKey, Vec<&Inode>> = BTreeMap::new();
+            for inode in inodes {
+                let mode = (!args.ignore_permissions).then_some(inode.mode);
+                let owner = (!args.ignore_ownership).then_some((inode.uid, inode.gid));
+                partitions.entry((mode, owner)).or_default().push(inode);
+            }
+            if partitions.len() > 1 {
+                report.split_groups += 1;
+                for inode in partitions.values().flatten() {
+                    for file in &inode.files {
+                        report.explain.note_grouped(file, || {
+                            format!(
+                                "grouped only with files of mode {:o} and owner {}:{}",
+                                inode.mode, inode.uid, inode.gid
+                            )
+                        });
+                    }
+                }
+            }
+            for partition in partitions.into_values() {
+                if partition.len() > 1 {
+                    plan_partition(args, device, hash, partition, report, &mut plan);
+                }
+            }
+        }
+    }
+    plan
+}
+
+/// Plans inodes of identical content and metadata, further split by relative path with
+/// `--same-relative-path`.
+fn plan_partition<'a>(
+    args: &Args,
+    device: &'a Device,
+    hash: &'a HashValue,
+    inodes: Vec<&'a Inode>,
+    report: &mut Report,
+    plan: &mut Vec<PlannedGroup<'a>>,
+) {
+    if !args.same_relative_path {
+        return plan_group(args, device, hash, inodes, report, plan);
+    }
+
+    let mut partitions: BTreeMap<&Path, Vec<&Inode>> = BTreeMap::new();
+    for inode in inodes {
+        let relpath = relative_path(&args.targets, &inode.files[0]);
+        partitions.entry(relpath).or_default().push(inode);
+    }
+    if partitions.len() > 1 {
+        // the same content exists at different relative paths
+        let paths = partitions
+            .values()
+            .map(|partition| partition[0].files[0].clone())
+            .collect();
+        report.moved.push(MovedContent { paths });
+    }
+    for partition in partitions.into_values() {
+        if partition.len() > 1 {
+            plan_group(args, device, hash, partition, report, plan);
+        } else {
+            for file in &partition[0].files {
+                report.explain.note_grouped(file, || {
+                    "same content only at other relative paths: not linked".to_string()
+                });
+            }
+        }
+    }
+}
+
+/// The apply stage: links the groups of `plan`, checking each file against the scan first.
+/// With `--paranoid`, the comparisons are made ahead on `--threads` workers.
+fn apply_relinks(
+    args: &Args,
+    database: &Database,
+    plan: &[PlannedGroup],
+    report: &mut Report,
+    out: &mut Output,
+) -> Result<()> {
+    if !args.paranoid || args.threads == 1 {
+        return relink_devices(args, plan, report, out);
+    }
+    let groups: Vec<_> = plan
+        .iter()
+        .flat_map(|group| {
+            group.runs.iter().filter(|run| run.len() > 1).map(|run| {
+                let inos = group.inodes[run.clone()].iter().map(|inode| inode.ino);
+                (group.device.dev, inos.collect())
+            })
+        })
+        .collect();
+    verify::with_verifications(
+        database,
+        &groups,
+        args.threads,
+        args.verify_buffer_budget,
+        |verifications| {
+            report.verifications = Some(verifications);
+            let result = relink_devices(args, plan, report, out);
+            report.verifications = None;
+            result
+        },
+    )
+}
+
+fn relink_devices(
+    args: &Args,
+    plan: &[PlannedGroup],
+    report: &mut Report,
+    out: &mut Output,
+) -> Result<()> {
+    for groups in plan.chunk_by(|a, b| a.device.dev == b.device.dev) {
+        let (gain, groups_acted, errors, deferred_gain) = (
+            report.gain,
+            report.groups_acted,
+            report.errors.len(),
+            report.deferred_gain,
+        );
+        for group in groups {
+            if args.exit_on_epipe && out.closed() || report.progress.checkpoint() {
+                break;
+            }
+            if let Err(err) = relink_group(args, group, report, out) {
+                report.keep_going(ErrorKind::Relink, &group.inodes[0].files[0], err)?;
+            }
+        }
+        let dev = groups[0].device.dev;
+        if let Some(summary) = report
+            .devices
+            .iter_mut()
+            .find(|summary| summary.dev == dev.0)
+        {
+            summary.gain = report.gain - gain;
+            summary.groups_acted = report.groups_acted - groups_acted;
+            summary.errors = (report.errors.len() - errors) as u64;
+            summary.deferred_gain = report.deferred_gain - deferred_gain;
+        }
+    }
+    Ok(())
+}
+
+fn relink_group(
+    args: &Args,
+    group: &PlannedGroup,
+    report: &mut Report,
+    out: &mut Output,
+) -> Result<()> {
+    let mut acted = false;
+    for run in &group.runs {
+        acted |= relink_run(
+            args,
+            group.device,
+            group.hash,
+            &group.inodes[run.clone()],
+            group.reflink_other_users,
+            report,
+            out,
+        )?;
+    }
+    if acted {
+        report.groups_acted += 1;
+    }
+    report.progress.add_groups_done(1);
     Ok(())
 }
+
+impl Args {
+    pub fn verbose_errors(&self) -> bool {
+        self.verbose_errors
+    }
+
+    /// Spells the directories of `--prefer` and `--reference` as the files under them are,
+    /// so that a directory named through a relative path or a symlink still matches them.
+    /// The order of `--prefer` is kept.
+    fn spell_as_targets(&mut self) {
+        self.prefer = spell_under_targets(&self.targets, &self.prefer);
+        self.reference = spell_under_targets(&self.targets, &self.reference);
+    }
+
+    fn verbosity(&self) -> Verbosity {
+        if self.quiet {
+            Verbosity::Quiet
+        } else if self.verbose > 0 {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+
+    /// The text output lists groups as they are linked, unless a plan diff is printed instead
+    /// or with `-q`.
+    fn lists_groups(&self) -> bool {
+        self.format == Format::Text && self.diff_plan.is_none() && !self.quiet
+    }
+
+    /// The features depending on the filesystem or privileges that the run relies on.
+    fn requested_features(&self) -> Vec<Feature> {
+        let mut features = Vec::new();
+        if self.direct_io {
+            features.push(Feature::DirectIo);
+        }
+        if !self.dry_run {
+            if self.conflict_fallback == Some(ConflictFallback::Reflink) {
+                features.push(Feature::Reflink);
+            }
+            features.push(Feature::InodeFlags);
+        }
+        features
+    }
+
+    fn collects_groups(&self) -> bool {
+        self.format == Format::Json || self.diff_plan.is_some() || self.by_extension
+    }
+
+    /// Groups are also filled in for `--save-plan`, which writes them out as they come.
+    fn builds_groups(&self) -> bool {
+        self.collects_groups() || self.save_plan.is_some()
+    }
+}
+
+/// Formats an error with each cause on its own indented line, along with the OS error
+/// code of I/O errors.
+pub fn format_error_chain(err: &anyhow::Error) -> String {
+    let mut message = format!("Error: {}", err);
+    for cause in err.chain().skip(1) {
+        message.push_str(&format!("\n    caused by: {}", cause));
+        if let Some(io_err) = cause.downcast_ref::<io::Error>() {
+            if let Some(code) = io_err.raw_os_error() {
+                message.push_str(&format!(" [errno {}, {:?}]", code, io_err.kind()));
+            }
+        }
+    }
+    message
+}
+
+pub fn run(args: Args) -> Result<ExitCode> {
+    let progress = ProgressHandle::new();
+    let shows_progress = args.progress && !args.json && args.format != Format::Json;
+    let notify = args
+        .notify_cmd
+        .clone()
+        .map(|command| (command, Duration::from_secs(args.notify_timeout)));
+    let run = || {
+        if shows_progress {
+            with_progress_bar(&progress, || run_with_progress(args, &progress))
+        } else {
+            run_with_progress(args, &progress)
+        }
+    };
+    match notify {
+        Some((command, timeout)) => with_notify(command, timeout, &progress, run),
+        None => run(),
+    }
+}
+
+/// Same as [`run`], updating `progress` so that it can be polled from another thread.
+pub fn run_with_progress(mut args: Args, progress: &ProgressHandle) -> Result<ExitCode> {
+    if args.json {
+        args.format = Format::Json;
+    }
+    args.dry_run |= args.diff_plan.is_some() || args.list;
+    if args.save_plan.is_some() {
+        ensure!(
+            args.mode == Mode::Hardlink,
+            "--save-plan requires --mode hardlink"
+        );
+        args.dry_run = true;
+    }
+    let against = matches!(args.command, Some(Command::Against { .. }));
+    if let Some(Command::Against { old, new }) = &args.command {
+        args.targets = vec![old.clone(), new.clone()];
+        args.same_relative_path = true;
+        args.prefer_first_target = true;
+    } else {
+        // the reference trees are scanned first, so that they are the spelling kept
+        for dir in args.reference.iter().rev() {
+            if !args.targets.iter().any(|target| dir.starts_with(target)) {
+                args.targets.insert(0, dir.clone());
+            }
+        }
+        merge_equivalent_targets(&mut args.targets);
+    }
+    args.spell_as_targets();
+    if args.deletion_manifest.is_some() {
+        ensure!(
+            args.mode == Mode::Delete,
+            "--deletion-manifest requires --mode delete"
+        );
+        args.dry_run = true;
+    }
+    if args.manifest.is_some() && args.targets.is_empty() && !args.dry_run {
+        // the manifest may list any path: only the targets say what may be modified
+        eprintln!(
+            "Note: --manifest without targets is a dry run; nothing is changed. Give the \
+             targets that the run may modify"
+        );
+        args.dry_run = true;
+    }
+    if args.mode != Mode::Hardlink && !args.execute && !args.dry_run {
+        eprintln!(
+            "Note: --mode {} without --execute is a dry run; nothing is changed",
+            if args.mode == Mode::Delete {
+                "delete"
+            } else {
+                "quarantine"
+            }
+        );
+        args.dry_run = true;
+    }
+    ensure!(
+        args.dry_run || args.confirm || !args.estimate_relink,
+        "--estimate-relink stops to ask before relinking: add --confirm, or --dry-run for the estimate alone"
+    );
+    let mut out = match &args.output {
+        Some(path) => Output::create(path)?,
+        None => Output::stdout(),
+    };
+
+    if let Some(Command::ApplyDeletions {
+        manifest,
+        older_than,
+        targets,
+    }) = &args.command
+    {
+        let roots = Roots::new(targets.iter().map(PathBuf::as_path));
+        apply_deletions(manifest, *older_than, &roots, &mut out)?;
+        out.finish()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::Pair { a, b, dry_run }) = &args.command {
+        let code = link_pair(a, b, &args.tmp_prefix, *dry_run || args.dry_run, &mut out)?;
+        out.finish()?;
+        return Ok(ExitCode::from(code));
+    }
+    if let Some(Command::Apply {
+        plan,
+        dry_run,
+        targets,
+    }) = &args.command
+    {
+        let roots = Roots::new(targets.iter().map(PathBuf::as_path));
+        apply_plan(
+            plan,
+            &roots,
+            &args.tmp_prefix,
+            *dry_run || args.dry_run,
+            &mut out,
+        )?;
+        out.finish()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::ConvertPlan { input, output }) = &args.command {
+        convert_plan(input, output)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::Filters {
+        test,
+        sample,
+        targets,
+    }) = &args.command
+    {
+        if !test {
+            args.filters.print_rules(&mut out)?;
+        } else {
+            let seed = args.seed.unwrap_or_else(Rng::time_seed);
+            let tests = test_filters(&args.filters, targets, *sample, seed);
+            match args.format {
+                Format::Json | Format::FlatJson => {
+                    writeln!(out, "{}", serde_json::to_string_pretty(&tests)?)?
+                }
+                Format::Text | Format::FlatCsv => tests.print(&mut out)?,
+            }
+        }
+        out.finish()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::Ctl { socket, command }) = &args.command {
+        send_command(socket, *command, &mut out)?;
+        out.finish()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::GenFixture { spec, out_dir }) = &args.command {
+        let text = fs::read_to_string(spec)
+            .with_context(|| format!("Failed to read: {}", spec.to_string_lossy()))?;
+        let spec = FixtureSpec::parse(&text)
+            .with_context(|| format!("Invalid fixture spec: {}", spec.to_string_lossy()))?;
+        generate_fixture(&spec, out_dir)?.print(&mut out)?;
+        out.finish()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::History { file, summary }) = &args.command {
+        print_history(file, *summary, &mut out)?;
+        out.finish()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(paths) = &args.expect_mirrored {
+        let report = expect_mirrored(&paths[0], &paths[1], args.unmatched_limit, args.hash)?;
+        match args.format {
+            Format::Text | Format::FlatCsv => report.print_summary(&mut out)?,
+            Format::Json | Format::FlatJson => {
+                writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?
+            }
+        }
+        out.finish()?;
+        return Ok(ExitCode::from(report.exit_code()));
+    }
+
+    let _control_socket = args
+        .control_socket
+        .as_deref()
+        .map(|path| ControlSocket::bind(path, progress))
+        .transpose()?;
+    let mut report = Report::new();
+    report.progress = progress.clone();
+    report.explain = Explain::new(&args.explain);
+    report.skips.list_examples = args.list_skipped;
+    report.verbosity = args.verbosity();
+    // no targets, as with --manifest alone, is a dry run, which may modify nothing
+    report.roots = if args.targets.is_empty() {
+        Roots::new([])
+    } else {
+        Roots::new(
+            args.targets
+                .iter()
+                .chain(&args.quarantine_dir)
+                .map(PathBuf::as_path),
+        )
+    };
+    report.io_budget = args.io_budget.map(IoBudget::new);
+    report.keep_going = args.keep_going;
+    report.hash_cache = args.cache.as_deref().map(HashCache::load);
+    if args.cache_verify_rate > 0.0 {
+        let seed = args.seed.unwrap_or_else(Rng::time_seed);
+        report.cache_verification = Some(CacheVerification::new(args.cache_verify_rate, seed));
+    }
+    if let Some(path) = &args.anchors {
+        report.anchors = Anchors::load(path)?;
+    }
+    if args.profile {
+        report.profile = Some(Profile::new(args.threads as usize));
+    }
+
+    if let Some(target) = args.targets.first() {
+        let features = args.requested_features();
+        if !features.is_empty() {
+            report.features = probe_features(&report.roots, target, &features);
+            let unavailable: Vec<_> = report
+                .features
+                .iter()
+                .filter(|status| !status.available)
+                .map(|status| status.feature.as_str())
+                .collect();
+            // the inode flags are always relied on, and only worth a mention when missing
+            if features != [Feature::InodeFlags] || !unavailable.is_empty() {
+                print_features(&report.features, &mut io::stderr())?;
+            }
+            ensure!(
+                !args.strict_features || unavailable.is_empty(),
+                "--strict-features: not available in {}: {}",
+                target.display(),
+                unavailable.join(", ")
+            );
+        }
+    }
+
+    progress.set_phase(Phase::Walk);
+    let scanned_at = FileTime::now();
+    let start = Instant::now();
+    for path in own_paths(&args, &out) {
+        warn_if_inside_targets(&args, path);
+    }
+    let own_files = own_files(&args, &out);
+    let mut database = scan_targets(&args, &own_files, &mut report)?;
+    if let Some(profile) = &mut report.profile {
+        profile.walk_time = start.elapsed();
+    }
+
+    if args.list {
+        print_fdupes(&database, args.size, args.hardlinks_as_one, &mut out)?;
+        out.finish()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    remove_leftover_temporaries(&args, &mut database, &mut report)?;
+
+    if !args.dry_run && args.mode == Mode::Hardlink {
+        for device in database.devices.values_mut() {
+            if !device.report_only {
+                granularity::probe_device(&report.roots, device)?;
+            }
+        }
+    }
+    if args.mode == Mode::Hardlink && args.max_links.is_none() {
+        for device in database.devices.values_mut() {
+            link_max::probe_device(device);
+        }
+    }
+
+    report.devices = database
+        .devices
+        .values()
+        .map(|device| DeviceSummary {
+            dev: device.dev.0,
+            fs_type: device.fs_type.clone().unwrap_or_default(),
+            fs_id: device.fs_id.clone().unwrap_or_default(),
+            report_only: device.report_only,
+            unreliable_inodes: device.unreliable_inodes,
+            mtime_granularity: device.mtime_granularity,
+            link_max: device.link_max,
+            ..Default::default()
+        })
+        .collect();
+    report.devices.sort_by_key(|device| device.dev);
+
+    if args.estimate_relink {
+        estimate_relink(&report.roots, &database)?;
+        if !args.dry_run && !confirmed(io::stdin().lock())? {
+            eprintln!("Note: not confirmed, going on as a dry run; nothing is changed");
+            args.dry_run = true;
+        }
+    }
+
+    if let Some(path) = &args.save_plan {
+        report.plan_writer = Some(PlanWriter::create(path, args.hash, &args.targets)?);
+    }
+    progress.set_phase(Phase::Relink);
+    let start = Instant::now();
+    let plan = plan_relinks(&args, &database, &mut report);
+    apply_relinks(&args, &database, &plan, &mut report, &mut out)?;
+    if let Some(profile) = &mut report.profile {
+        profile.relink_time = start.elapsed();
+    }
+    if let (Some(path), Some(plan)) = (&args.save_plan, report.plan_writer.take()) {
+        let groups = plan.finish()?;
+        eprintln!(
+            "Note: wrote {} groups to {}; run apply --plan on it with the same targets to link them",
+            groups,
+            path.display()
+        );
+    }
+
+    if let Some(path) = &args.deletion_manifest {
+        let deletions = std::mem::take(&mut report.planned_deletions);
+        let count = deletions.len();
+        write_manifest(path, scanned_at, &args.targets, deletions)?;
+        eprintln!(
+            "Note: wrote {} planned deletions to {}; run apply-deletions on it with the same targets to delete them",
+            count,
+            path.display()
+        );
+    }
+
+    if let (Some(path), Some(cache)) = (&args.cache, report.hash_cache.take()) {
+        report.cache_hits = cache.hits;
+        cache.save(path)?;
+    }
+
+    if let Some(path) = &args.history {
+        let settings = settings_hash(&format!("{:?}", args));
+        append_history(path, scanned_at, args.dry_run, &settings, &report)?;
+    }
+
+    if args.audit_samples > 0 {
+        let seed = args.seed.unwrap_or_else(Rng::time_seed);
+        report.audit = Some(audit(&mut report.performed, args.audit_samples, seed));
+    }
+
+    progress.set_phase(Phase::Done);
+    report.stopped = progress.is_stop_requested();
+    report.attestation = Some(report.roots.attestation());
+    report.unmatched_anchors = report.anchors.unmatched();
+    report.explain.finish();
+
+    if against {
+        report.one_sided = Some(one_sided(&args, &database));
+    }
+    if let Some(window) = args.near_size_report {
+        report.near_duplicates = Some(near_size_report(&database, window, args.hash)?);
+    }
+    if args.by_extension {
+        let compound = args.compound_ext.as_deref().unwrap_or_default();
+        report.by_extension = Some(by_extension(&report.groups, compound));
+    }
+    if let Some(old_plan) = &args.diff_plan {
+        let diff = diff_plan(old_plan, &report.groups)?;
+        match args.format {
+            Format::Text | Format::FlatCsv => diff.print_summary(&mut out)?,
+            Format::Json | Format::FlatJson => {
+                writeln!(out, "{}", serde_json::to_string_pretty(&diff)?)?
+            }
+        }
+        out.finish()?;
+        return Ok(ExitCode::from(diff.exit_code()));
+    }
+    match args.format {
+        Format::Text => report.print_summary(&mut out, args.verbose)?,
+        Format::Json => report.print_json(&mut out)?,
+        Format::FlatCsv => report.print_flat_csv(&mut out)?,
+        Format::FlatJson => writeln!(out, "{}", serde_json::to_string_pretty(&report.flat)?)?,
+    }
+    let closed = out.closed();
+    out.finish()?;
+    if args.exit_on_epipe && closed {
+        return Ok(ExitCode::from(EXIT_CLOSED_OUTPUT));
+    }
+    if report
+        .audit
+        .as_ref()
+        .is_some_and(|audit| !audit.failures.is_empty())
+    {
+        return Ok(ExitCode::from(EXIT_AUDIT_FAILED));
+    }
+    Ok(ExitCode::from(report.exit_code()))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser as _;
+
+    use super::*;
+
+    fn dir_on(dev: u64) -> Stat {
+        Stat {
+            dev,
+            ino: 2,
+            mode: libc::S_IFDIR | 0o755,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            size: 4096,
+            blocks: 8,
+            mtime: FileTime::zero(),
+            ctime: FileTime::zero(),
+        }
+    }
+
+    #[test]
+    fn one_file_system_keeps_each_target_on_its_device() {
+        let args = Args::parse_from(["dedup", "-x", "a", "b"]);
+        assert!(!on_other_file_system(&args, Some(Dev(1)), &dir_on(1)));
+        assert!(on_other_file_system(&args, Some(Dev(1)), &dir_on(2)));
+        // another target, on the other device
+        assert!(!on_other_file_system(&args, Some(Dev(2)), &dir_on(2)));
+        assert!(on_other_file_system(&args, Some(Dev(2)), &dir_on(1)));
+        // a target that could not be stat'ed
+        assert!(!on_other_file_system(&args, None, &dir_on(2)));
+
+        let args = Args::parse_from(["dedup", "a"]);
+        assert!(!on_other_file_system(&args, Some(Dev(1)), &dir_on(2)));
+    }
+
+    fn file_on(dev: u64, ino: u64, size: u64) -> Stat {
+        Stat {
+            ino,
+            mode: libc::S_IFREG | 0o644,
+            nlink: 1,
+            size,
+            blocks: size.div_ceil(512),
+            ..dir_on(dev)
+        }
+    }
+
+    /// Scans files that do not exist, with the hashing deferred to workers so that none of
+    /// them is read, and returns the database.
+    fn scan(files: &[(&str, Stat)]) -> Database {
+        let args = Args::parse_from(["dedup", "--threads", "2", "t"]);
+        let mut database = Database::new();
+        let mut report = Report::new();
+        for (path, stat) in files {
+            prepare_file(&args, &mut database, Path::new(path), stat, &mut report).unwrap();
+        }
+        database
+    }
+
+    #[test]
+    fn inode_numbers_reused_for_another_file_are_not_trusted() {
+        let database = scan(&[
+            ("t/a", file_on(1, 10, 100)),
+            ("t/b", file_on(1, 11, 200)),
+            // inode 10 again, but not the same file
+            ("t/c", file_on(1, 10, 300)),
+            // no different from an earlier path, yet not trusted either
+            ("t/d", file_on(1, 11, 200)),
+        ]);
+        let device = &database.devices[&Dev(1)];
+        assert!(device.unreliable_inodes);
+        assert!(device.report_only);
+        // from then on, every path is an inode of its own
+        assert_eq!(device.inodes.map.len(), 4);
+
+        // links reported consistently are trusted
+        let database = scan(&[("t/a", file_on(1, 10, 100)), ("t/b", file_on(1, 10, 100))]);
+        let device = &database.devices[&Dev(1)];
+        assert!(!device.unreliable_inodes);
+        assert!(!device.report_only);
+        assert_eq!(device.inodes.map.len(), 1);
+    }
+
+    #[test]
+    fn inode_number_zero_is_not_trusted() {
+        let database = scan(&[("t/a", file_on(1, 0, 100))]);
+        assert!(database.devices[&Dev(1)].unreliable_inodes);
+        // nor held against other devices
+        let database = scan(&[("t/a", file_on(1, 0, 100)), ("t/b", file_on(2, 10, 100))]);
+        assert!(!database.devices[&Dev(2)].unreliable_inodes);
+    }
+}
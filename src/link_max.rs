@@ -0,0 +1,117 @@
+//! Links beyond the limit of the filesystem fail with EMLINK, so that large groups are split
+//! between several originals, each linked up to the limit.
+
+use std::ffi::CString;
+use std::io;
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::checks::parent_dir;
+use crate::models::Device;
+
+/// Assumed when the filesystem does not tell: the limit of ext2 and ext3, the lowest of
+/// the common ones.
+const FALLBACK_LINK_MAX: u64 = 32000;
+
+/// The maximum link count of the filesystem of `dir`, `None` if it has none.
+pub fn link_max(dir: &Path) -> io::Result<Option<u64>> {
+    let dir = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    // pathconf returns -1 both for no limit and on errors, which set errno
+    unsafe { *libc::__errno_location() = 0 };
+    let limit = unsafe { libc::pathconf(dir.as_ptr(), libc::_PC_LINK_MAX) };
+    if limit >= 0 {
+        return Ok(Some(limit as u64));
+    }
+    match io::Error::last_os_error() {
+        err if err.raw_os_error() == Some(0) => Ok(None),
+        err => Err(err),
+    }
+}
+
+/// Queries the limit of the device in the directory of its first original. Failures fall
+/// back to a conservative limit.
+pub fn probe_device(device: &mut Device) {
+    let dir = device
+        .identicals
+        .map
+        .values()
+        .find_map(|identical| device.inodes.get(identical.inos.as_slice()[0]))
+        .and_then(|inode| inode.files.first())
+        .map(|path| parent_dir(path).to_path_buf());
+    let Some(dir) = dir else {
+        return;
+    };
+    device.link_max = match link_max(&dir) {
+        Ok(limit) => limit,
+        Err(err) => {
+            eprintln!(
+                "Warning: assuming at most {} links per inode: {}: {}",
+                FALLBACK_LINK_MAX,
+                dir.display(),
+                err
+            );
+            Some(FALLBACK_LINK_MAX)
+        }
+    };
+}
+
+/// Splits a group, given as the link count of each inode and the number of its paths to
+/// be linked, original first, into runs whose first inode is the original of the rest, so
+/// that no original would exceed `limit` links.
+pub fn split_by_link_max(links: &[(u64, u64)], limit: u64) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut nlink = links[0].0;
+    for (i, &(inode_nlink, paths)) in links.iter().enumerate().skip(1) {
+        if nlink + paths > limit {
+            runs.push(start..i);
+            start = i;
+            nlink = inode_nlink;
+        } else {
+            nlink += paths;
+        }
+    }
+    runs.push(start..links.len());
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The runs of [`split_by_link_max`], as the bounds of each.
+    fn runs(links: &[(u64, u64)], limit: u64) -> Vec<(usize, usize)> {
+        split_by_link_max(links, limit)
+            .into_iter()
+            .map(|run| (run.start, run.end))
+            .collect()
+    }
+
+    #[test]
+    fn groups_are_split_at_a_low_limit() {
+        // single links, then an inode with three paths that no longer fits the first run
+        let links = [(1, 1), (1, 1), (1, 1), (3, 3), (1, 1), (1, 1)];
+        assert_eq!(runs(&links, 4), [(0, 3), (3, 5), (5, 6)]);
+        // an original with links from outside the group already
+        let links = [(3, 1), (1, 1), (1, 1), (1, 1)];
+        assert_eq!(runs(&links, 4), [(0, 2), (2, 4)]);
+        assert_eq!(runs(&links, 100), [(0, 4)]);
+    }
+
+    #[test]
+    fn split_at_limits_of_one_two_and_ext4() {
+        let singles = [(1, 1); 5];
+        // nothing can be linked to anything
+        assert_eq!(runs(&singles, 1), [(0, 1), (1, 2), (2, 3), (3, 4), (4, 5)]);
+        assert_eq!(runs(&singles, 2), [(0, 2), (2, 4), (4, 5)]);
+
+        let singles = vec![(1, 1); 100_000];
+        let split = runs(&singles, 65000);
+        assert_eq!(split, [(0, 65000), (65000, 100_000)]);
+        // the best original keeps the largest run
+        let len = |(start, end): (usize, usize)| end - start;
+        assert!(split.iter().all(|&run| len(run) <= len(split[0])));
+    }
+}
@@ -1,8 +1,16 @@
+use std::process::ExitCode;
+
 use anyhow::Result;
 use clap::Parser as _;
 use dedup::Args;
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
-    dedup::run(args)
+    if !args.verbose_errors() {
+        return dedup::run(args);
+    }
+    dedup::run(args).or_else(|err| {
+        eprintln!("{}", dedup::format_error_chain(&err));
+        Ok(ExitCode::FAILURE)
+    })
 }
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::digest::{digest_file, HashAlgorithm, HashValue};
+use crate::report::serialize_paths;
+
+/// Some content of the source has no counterpart in the mirror.
+pub const EXIT_NOT_MIRRORED: u8 = 4;
+
+#[derive(Debug, Default, Serialize)]
+pub struct MirrorReport {
+    pub scanned: u64,
+    pub unmatched_count: u64,
+    /// The first unmatched files, up to the report limit.
+    #[serde(serialize_with = "serialize_paths")]
+    pub unmatched: Vec<PathBuf>,
+}
+
+impl MirrorReport {
+    pub fn print_summary(&self, out: &mut dyn Write) -> io::Result<()> {
+        for path in &self.unmatched {
+            writeln!(out, "{}", path.display())?;
+        }
+        if self.unmatched_count > self.unmatched.len() as u64 {
+            writeln!(
+                out,
+                "... and {} more",
+                (self.unmatched_count - self.unmatched.len() as u64)
+                    .to_formatted_string(&Locale::en)
+            )?;
+        }
+        writeln!(
+            out,
+            "Unmatched: {} of {} files",
+            self.unmatched_count.to_formatted_string(&Locale::en),
+            self.scanned.to_formatted_string(&Locale::en)
+        )?;
+        Ok(())
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        if self.unmatched_count > 0 {
+            EXIT_NOT_MIRRORED
+        } else {
+            0
+        }
+    }
+}
+
+fn walk_files(root: &Path, mut f: impl FnMut(PathBuf, u64)) -> Result<()> {
+    for entry in WalkDir::new(root) {
+        let entry = entry.context("Failed to get a entry")?;
+        let path = entry.path();
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("Failed to get metadata: {}", path.to_string_lossy()))?;
+        if metadata.is_file() {
+            f(path.to_path_buf(), metadata.len());
+        }
+    }
+    Ok(())
+}
+
+/// Hashes a file, returning `None` if it vanished after the walk.
+fn hash(path: &Path, algorithm: HashAlgorithm) -> Result<Option<HashValue>> {
+    match digest_file(path, algorithm) {
+        Ok((hash, _)) => Ok(Some(hash)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err)
+            .with_context(|| format!("Failed to calculate a hash: {}", path.to_string_lossy())),
+    }
+}
+
+/// Checks that every file under `source` has a file with identical content under `mirror`.
+/// Only files whose size exists on both sides are hashed. Nothing is ever linked.
+pub fn expect_mirrored(
+    source: &Path,
+    mirror: &Path,
+    limit: usize,
+    algorithm: HashAlgorithm,
+) -> Result<MirrorReport> {
+    let mut mirror_files: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    walk_files(mirror, |path, size| {
+        mirror_files.entry(size).or_default().push(path)
+    })?;
+
+    let mut source_files = Vec::new();
+    walk_files(source, |path, size| source_files.push((path, size)))?;
+    source_files.sort();
+
+    let mut mirror_hashes: HashMap<PathBuf, Option<HashValue>> = HashMap::new();
+    let mut report = MirrorReport::default();
+    for (path, size) in source_files {
+        let candidates = mirror_files
+            .get(&size)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let matched = if candidates.is_empty() {
+            false
+        } else if let Some(source_hash) = hash(&path, algorithm)? {
+            let mut matched = false;
+            for candidate in candidates {
+                let candidate_hash = match mirror_hashes.get(candidate) {
+                    Some(&candidate_hash) => candidate_hash,
+                    None => {
+                        let candidate_hash = hash(candidate, algorithm)?;
+                        mirror_hashes.insert(candidate.clone(), candidate_hash);
+                        candidate_hash
+                    }
+                };
+                if candidate_hash == Some(source_hash) {
+                    matched = true;
+                    break;
+                }
+            }
+            matched
+        } else {
+            // vanished since the walk
+            continue;
+        };
+
+        report.scanned += 1;
+        if !matched {
+            report.unmatched_count += 1;
+            if report.unmatched.len() < limit {
+                report.unmatched.push(path);
+            }
+        }
+    }
+    Ok(report)
+}
@@ -1,37 +1,112 @@
 use std::clone::Clone;
-use std::cmp::{Eq, PartialEq};
+use std::cmp::{Eq, PartialEq, Reverse};
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::hash::Hash;
 use std::marker::Copy;
-use std::path::PathBuf;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 
 use filetime::FileTime;
 
-use crate::digest::Sha256Value;
+use crate::digest::{HashAlgorithm, HashValue};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Ino(pub u64);
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Dev(pub u64);
 
 #[derive(Debug)]
 pub struct Inode {
+    pub ino: Ino,
     pub mtime: FileTime,
+    pub ctime: FileTime,
+    pub hashed: bool,
     pub nlink: u64,
+    pub size: u64,
     pub realsize: u64,
+    pub uid: u32,
+    pub gid: u32,
+    /// The permission bits, including setuid, setgid and sticky.
+    pub mode: u32,
     pub files: Vec<PathBuf>,
+    /// Paths found beyond `--max-paths-per-inode`, counted but not stored.
+    pub extra_paths: u64,
+}
+
+pub fn change_time(metadata: &fs::Metadata) -> FileTime {
+    FileTime::from_unix_time(metadata.ctime(), metadata.ctime_nsec() as u32)
+}
+
+/// The metadata of an entry that the scan relies on, from [`fs::Metadata`] or from a
+/// statx of `--uring-stat`.
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    /// The file type and permission bits.
+    pub mode: u32,
+    pub nlink: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// In units of 512 bytes.
+    pub blocks: u64,
+    pub mtime: FileTime,
+    pub ctime: FileTime,
+}
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        self.mode & libc::S_IFMT == libc::S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & libc::S_IFMT == libc::S_IFREG
+    }
+}
+
+impl From<&fs::Metadata> for Stat {
+    fn from(metadata: &fs::Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            mode: metadata.mode(),
+            nlink: metadata.nlink(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size(),
+            blocks: metadata.blocks(),
+            mtime: FileTime::from_last_modification_time(metadata),
+            ctime: change_time(metadata),
+        }
+    }
 }
 
 impl Inode {
-    pub fn new(mtime: FileTime, nlink: u64, realsize: u64) -> Self {
+    pub fn new(stat: &Stat) -> Self {
         Self {
-            mtime,
-            nlink,
-            realsize,
+            ino: Ino(stat.ino),
+            mtime: stat.mtime,
+            ctime: stat.ctime,
+            hashed: false,
+            nlink: stat.nlink,
+            size: stat.size,
+            realsize: stat.blocks * 512,
+            uid: stat.uid,
+            gid: stat.gid,
+            mode: stat.mode & 0o7777,
             files: Vec::new(),
+            extra_paths: 0,
         }
     }
+
+    /// Whether `stat`, of another path to the same inode number, agrees with the inode.
+    pub fn matches(&self, stat: &Stat) -> bool {
+        self.size == stat.size && self.mtime == stat.mtime
+    }
 }
 
 #[derive(Debug)]
@@ -46,16 +121,8 @@ impl Inodes {
         }
     }
 
-    pub fn get_or_insert(
-        &mut self,
-        ino: Ino,
-        mtime: FileTime,
-        nlink: u64,
-        realsize: u64,
-    ) -> &mut Inode {
-        self.map
-            .entry(ino)
-            .or_insert_with(|| Inode::new(mtime, nlink, realsize))
+    pub fn get_or_insert(&mut self, inode: Inode) -> &mut Inode {
+        self.map.entry(inode.ino).or_insert(inode)
     }
 
     pub fn get(&self, ino: Ino) -> Option<&Inode> {
@@ -65,22 +132,66 @@ impl Inodes {
     pub fn get_mut(&mut self, ino: Ino) -> Option<&mut Inode> {
         self.map.get_mut(&ino)
     }
+
+    pub fn remove(&mut self, ino: Ino) -> Option<Inode> {
+        self.map.remove(&ino)
+    }
+}
+
+/// Most hashed files turn out to be unique, so a single inode is stored without allocation.
+#[derive(Debug)]
+pub enum Inos {
+    One(Ino),
+    Many(Vec<Ino>),
+}
+
+impl Inos {
+    pub fn push(&mut self, ino: Ino) {
+        match self {
+            Self::One(ino0) => *self = Self::Many(vec![*ino0, ino]),
+            Self::Many(inos) => inos.push(ino),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[Ino] {
+        match self {
+            Self::One(ino) => std::slice::from_ref(ino),
+            Self::Many(inos) => inos,
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [Ino] {
+        match self {
+            Self::One(ino) => std::slice::from_mut(ino),
+            Self::Many(inos) => inos,
+        }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Ino> {
+        self.as_slice().iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
 }
 
 #[derive(Debug)]
 pub struct IdenticalFile {
-    pub inos: Vec<Ino>,
+    pub inos: Inos,
 }
 
 impl IdenticalFile {
-    pub fn new() -> Self {
-        Self { inos: Vec::new() }
+    pub fn new(ino: Ino) -> Self {
+        Self {
+            inos: Inos::One(ino),
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct IdenticalFiles {
-    pub map: HashMap<Sha256Value, IdenticalFile>,
+    pub map: HashMap<HashValue, IdenticalFile>,
 }
 
 impl IdenticalFiles {
@@ -90,11 +201,22 @@ impl IdenticalFiles {
         }
     }
 
-    pub fn get_or_insert(&mut self, hash: Sha256Value) -> &mut IdenticalFile {
-        self.map.entry(hash).or_insert_with(IdenticalFile::new)
+    pub fn insert(&mut self, hash: HashValue, ino: Ino) {
+        self.map
+            .entry(hash)
+            .and_modify(|identical| identical.inos.push(ino))
+            .or_insert_with(|| IdenticalFile::new(ino));
+    }
+
+    /// Drops the files that turned out to be unique.
+    pub fn retain_duplicates(&mut self) {
+        self.map.retain(|_, identical| identical.inos.len() > 1);
+        self.map.shrink_to_fit();
     }
 }
 
+/// Pending work is keyed by inode, never by path, so that an inode reached through
+/// several paths is hashed at most once.
 #[derive(Debug)]
 pub enum FileSizeSieveEntry {
     Unique(Ino),
@@ -122,55 +244,252 @@ impl FileSizeSieve {
     }
 }
 
+/// Inodes of an ambiguous size larger than the prefix, sieved on the hash of their prefix
+/// before they are hashed in full.
+#[derive(Debug)]
+pub struct PrefixSieve {
+    map: HashMap<(u64, HashValue), FileSizeSieveEntry>,
+}
+
+impl PrefixSieve {
+    pub fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+
+    pub fn get_mut(&mut self, key: (u64, HashValue)) -> Option<&mut FileSizeSieveEntry> {
+        self.map.get_mut(&key)
+    }
+
+    pub fn set_unique(&mut self, key: (u64, HashValue), ino: Ino) {
+        self.map.insert(key, FileSizeSieveEntry::Unique(ino));
+    }
+
+    /// The inodes never hashed in full, since no other inode shares their prefix.
+    pub fn unique_inos(&self) -> impl Iterator<Item = Ino> + '_ {
+        self.map.values().filter_map(|entry| match *entry {
+            FileSizeSieveEntry::Unique(ino) => Some(ino),
+            FileSizeSieveEntry::Ambiguous => None,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct VisitedDirs {
-    pub set: HashSet<Ino>,
+    pub map: HashMap<Ino, PathBuf>,
 }
 
 impl VisitedDirs {
     pub fn new() -> Self {
         Self {
-            set: HashSet::new(),
+            map: HashMap::new(),
         }
     }
 
-    pub fn visit(&mut self, ino: Ino) -> bool {
-        self.set.insert(ino)
+    /// Marks the directory visited. If it already was, returns the path of the first visit.
+    pub fn visit(&mut self, ino: Ino, path: &Path) -> Option<&Path> {
+        match self.map.entry(ino) {
+            Entry::Occupied(entry) => Some(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                entry.insert(path.to_path_buf());
+                None
+            }
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct Device {
+    pub dev: Dev,
+    /// The filesystem type, resolved from the first path seen on the device.
+    pub fs_type: Option<String>,
+    /// The identity of the filesystem, which unlike the device number is the same on every
+    /// run, resolved along with the type.
+    pub fs_id: Option<String>,
+    /// Whether the device is only scanned and reported, never modified.
+    pub report_only: bool,
+    /// Whether the device was caught reusing inode numbers, so that none of them is trusted.
+    pub unreliable_inodes: bool,
+    /// The granularity of mtimes in nanoseconds, when probed before relinking.
+    pub mtime_granularity: Option<u64>,
+    /// The maximum link count of the filesystem, when probed before relinking.
+    pub link_max: Option<u64>,
+    /// Counts down from the top so that pseudo-inodes never collide with real ones.
+    next_pseudo_ino: u64,
     pub inodes: Inodes,
     pub sieve: FileSizeSieve,
+    pub prefix_sieve: PrefixSieve,
     pub identicals: IdenticalFiles,
+    /// Inodes left for the worker threads of `--threads` to hash, in the order they became
+    /// candidates.
+    pub pending: Vec<Ino>,
+    /// Inodes left unhashed because `--cache` knows their whole size bucket to be unique.
+    pub known_unique: Vec<Ino>,
     pub visited_dirs: VisitedDirs,
 }
 
 impl Device {
-    pub fn new() -> Self {
+    pub fn new(dev: Dev) -> Self {
         Self {
+            dev,
+            fs_type: None,
+            fs_id: None,
+            report_only: false,
+            unreliable_inodes: false,
+            mtime_granularity: None,
+            link_max: None,
+            next_pseudo_ino: u64::MAX,
             inodes: Inodes::new(),
             sieve: FileSizeSieve::new(),
+            prefix_sieve: PrefixSieve::new(),
             identicals: IdenticalFiles::new(),
+            pending: Vec::new(),
+            known_unique: Vec::new(),
             visited_dirs: VisitedDirs::new(),
         }
     }
+
+    /// Every path found on the device from now on gets its own pseudo-inode, and nothing
+    /// on it is modified, since paths grouped so far may belong to distinct files.
+    pub fn mark_unreliable_inodes(&mut self) {
+        self.unreliable_inodes = true;
+        self.report_only = true;
+    }
+
+    pub fn pseudo_ino(&mut self) -> Ino {
+        self.next_pseudo_ino -= 1;
+        Ino(self.next_pseudo_ino)
+    }
+
+    /// Counts the inodes found through several paths and with no other inode to link to,
+    /// i.e. those an earlier run has already deduplicated.
+    pub fn already_linked(&self) -> u64 {
+        let grouped: HashSet<Ino> = self
+            .identicals
+            .map
+            .values()
+            .flat_map(|identical| identical.inos.iter().copied())
+            .collect();
+        self.inodes
+            .map
+            .values()
+            .filter(|inode| inode.files.len() as u64 + inode.extra_paths > 1)
+            .filter(|inode| !grouped.contains(&inode.ino))
+            .count() as u64
+    }
+
+    /// Puts the paths of each inode and the inodes of each group in a canonical order, so
+    /// that neither depends on the order in which the scan found them.
+    pub fn normalize(&mut self) {
+        for inode in self.inodes.map.values_mut() {
+            inode.files.sort();
+        }
+        let inodes = &self.inodes;
+        let key = |ino: Ino| {
+            let inode = inodes.get(ino).unwrap();
+            (Reverse(inode.nlink), inode.mtime, inode.files.first())
+        };
+        for identical in self.identicals.map.values_mut() {
+            identical
+                .inos
+                .as_mut_slice()
+                .sort_by(|&a, &b| key(a).cmp(&key(b)));
+        }
+    }
 }
 
-#[derive(Debug)]
+/// The identical files found by a scan, by device.
+#[derive(Debug, Default)]
 pub struct Database {
     pub devices: HashMap<Dev, Device>,
+    /// The targets scanned, which a plan made from the database is confined to.
+    pub targets: Vec<PathBuf>,
+    pub hash: HashAlgorithm,
 }
 
 impl Database {
     pub fn new() -> Self {
-        Self {
-            devices: HashMap::new(),
-        }
+        Self::default()
     }
 
     pub fn get_or_insert(&mut self, dev: Dev) -> &mut Device {
-        self.devices.entry(dev).or_insert_with(Device::new)
+        self.devices.entry(dev).or_insert_with(|| Device::new(dev))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// Counts the allocations of each thread, so that tests running alongside don't add up.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations(f: impl FnOnce()) -> u64 {
+        let before = ALLOCATIONS.with(Cell::get);
+        f();
+        ALLOCATIONS.with(Cell::get) - before
+    }
+
+    /// Unique hashes but for one in a hundred, which is seen twice.
+    fn mostly_unique() -> Vec<(HashValue, Ino)> {
+        (0..10_000u64)
+            .map(|i| {
+                let seen = if i % 100 == 1 { i - 1 } else { i };
+                let mut hash = [0; 32];
+                hash[..8].copy_from_slice(&seen.to_le_bytes());
+                (HashValue::Blake3(hash), Ino(i))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unique_hashes_are_stored_without_allocation() {
+        let hashes = mostly_unique();
+        let mut identicals = IdenticalFiles::new();
+        let compact = allocations(|| {
+            for &(hash, ino) in &hashes {
+                identicals.insert(hash, ino);
+            }
+        });
+        // as stored before, a vector for each hash
+        let mut vectors: HashMap<HashValue, Vec<Ino>> = HashMap::new();
+        let one_vector_each = allocations(|| {
+            for &(hash, ino) in &hashes {
+                vectors.entry(hash).or_default().push(ino);
+            }
+        });
+        assert!(
+            compact * 10 < one_vector_each,
+            "{compact} allocations, against {one_vector_each}"
+        );
+
+        identicals.retain_duplicates();
+        assert_eq!(identicals.map.len(), 100);
+        assert!(identicals
+            .map
+            .values()
+            .all(|identical| identical.inos.len() == 2));
     }
 }
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::io;
+
+use anyhow::{Context as _, Result};
+
+use crate::digest::{digest_prefix, HashAlgorithm, HashValue};
+use crate::models::*;
+use crate::report::{NearDuplicate, NearDuplicateFile};
+
+const PREFIX_SIZE: u64 = 1024 * 1024;
+
+/// `cluster` must be sorted by size. Only the bytes every member has are compared.
+fn push_near_duplicates(
+    cluster: &[&Inode],
+    algorithm: HashAlgorithm,
+    near_duplicates: &mut Vec<NearDuplicate>,
+) -> Result<()> {
+    let limit = cluster[0].size.min(PREFIX_SIZE);
+    let mut groups: HashMap<HashValue, Vec<&Inode>> = HashMap::new();
+    for &inode in cluster {
+        let path = &inode.files[0];
+        match digest_prefix(path, limit, algorithm, &mut |_| Ok(())) {
+            Ok((hash, _)) => groups.entry(hash).or_default().push(inode),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!(
+                        "Failed to calculate a prefix hash: {}",
+                        path.to_string_lossy()
+                    )
+                })
+            }
+        }
+    }
+    let mut found: Vec<_> = groups
+        .into_values()
+        .filter(|inodes| inodes.iter().any(|inode| inode.size != inodes[0].size))
+        .map(|inodes| NearDuplicate {
+            files: inodes
+                .iter()
+                .map(|inode| NearDuplicateFile {
+                    path: inode.files[0].clone(),
+                    size: inode.size,
+                })
+                .collect(),
+        })
+        .collect();
+    found.sort_by(|a, b| a.files[0].path.cmp(&b.files[0].path));
+    near_duplicates.extend(found);
+    Ok(())
+}
+
+/// Finds clusters of files whose sizes differ by at most `window` bytes and whose
+/// prefixes hash identically. Files of exactly one size are left to the normal grouping.
+pub fn near_size_report(
+    database: &Database,
+    window: u64,
+    algorithm: HashAlgorithm,
+) -> Result<Vec<NearDuplicate>> {
+    let mut near_duplicates = Vec::new();
+    let mut devs: Vec<_> = database.devices.keys().collect();
+    devs.sort();
+    for dev in devs {
+        let device = &database.devices[dev];
+        let mut inodes: Vec<&Inode> = device.inodes.map.values().collect();
+        inodes.sort_by(|a, b| (a.size, &a.files[0]).cmp(&(b.size, &b.files[0])));
+
+        let mut start = 0;
+        for end in 1..=inodes.len() {
+            if end < inodes.len() && inodes[end].size - inodes[start].size <= window {
+                continue;
+            }
+            let cluster = &inodes[start..end];
+            if cluster.first().map(|inode| inode.size) != cluster.last().map(|inode| inode.size) {
+                push_near_duplicates(cluster, algorithm, &mut near_duplicates)?;
+            }
+            start = end;
+        }
+    }
+    Ok(near_duplicates)
+}
@@ -0,0 +1,131 @@
+//! `--notify-cmd`: a command run once when the run ends, whether it succeeds, fails or is
+//! interrupted, with the outcome in environment variables.
+
+use std::io;
+use std::os::fd::AsFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::progress::ProgressHandle;
+
+/// How often a running hook is polled until it exits or times out.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Signals that interrupt the run, after which the hook runs with the status `cancelled`.
+const SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+struct Hook {
+    command: String,
+    timeout: Duration,
+    started: Instant,
+    progress: ProgressHandle,
+    done: AtomicBool,
+}
+
+impl Hook {
+    /// Runs the command, once, with the counters as they are. Its failures are warnings.
+    fn notify(&self, status: &str) {
+        if self.done.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Err(err) = self.run(status) {
+            eprintln!("Warning: --notify-cmd failed: {}", err);
+        }
+    }
+
+    fn run(&self, status: &str) -> io::Result<()> {
+        let mut command = Command::new("/bin/sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .env("DEDUP_STATUS", status)
+            .env("DEDUP_GAIN_BYTES", self.progress.bytes_gained().to_string())
+            .env("DEDUP_GROUPS", self.progress.groups_done().to_string())
+            .env("DEDUP_ERRORS", self.progress.errors().to_string())
+            .env(
+                "DEDUP_DURATION_SECS",
+                self.started.elapsed().as_secs().to_string(),
+            )
+            .stdin(Stdio::null())
+            // stdout may carry the report
+            .stdout(Stdio::from(io::stderr().as_fd().try_clone_to_owned()?))
+            // a group of its own, so that what the shell started is killed along with it
+            .process_group(0);
+        let mut child = command.spawn()?;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(exit) = child.try_wait()? {
+                if !exit.success() {
+                    eprintln!("Warning: --notify-cmd exited with {}", exit);
+                }
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                eprintln!(
+                    "Warning: --notify-cmd still running after {}s, killed",
+                    self.timeout.as_secs()
+                );
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                let _ = child.wait();
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Blocks the interrupting signals in the calling thread, and so in every thread spawned
+/// from it later, and waits for them in a thread of its own, which runs the hook and exits
+/// as the signal would have.
+fn watch_signals(hook: Arc<Hook>) {
+    let set = unsafe {
+        let mut set = std::mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut set);
+        for signal in SIGNALS {
+            libc::sigaddset(&mut set, signal);
+        }
+        set
+    };
+    if unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) } != 0 {
+        eprintln!("Warning: --notify-cmd will not run if the run is interrupted");
+        return;
+    }
+    thread::spawn(move || {
+        let mut signal = 0;
+        while unsafe { libc::sigwait(&set, &mut signal) } != 0 {}
+        hook.notify("cancelled");
+        std::process::exit(128 + signal);
+    });
+}
+
+/// Runs `run` with the hook armed, and the hook afterwards: `success` if the run completed,
+/// whatever its exit status, `failure` if it stopped on an error, and `cancelled` if it
+/// was asked to stop through the control socket.
+pub fn with_notify(
+    command: String,
+    timeout: Duration,
+    progress: &ProgressHandle,
+    run: impl FnOnce() -> Result<ExitCode>,
+) -> Result<ExitCode> {
+    let hook = Arc::new(Hook {
+        command,
+        timeout,
+        started: Instant::now(),
+        progress: progress.clone(),
+        done: AtomicBool::new(false),
+    });
+    watch_signals(Arc::clone(&hook));
+    let result = run();
+    hook.notify(match &result {
+        Ok(_) if progress.is_stop_requested() => "cancelled",
+        Ok(_) => "success",
+        Err(_) => "failure",
+    });
+    result
+}
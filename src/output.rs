@@ -0,0 +1,150 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+
+/// Exit status of `--exit-on-epipe` once the reader of the output went away.
+pub const EXIT_CLOSED_OUTPUT: u8 = 9;
+
+/// What the temporary name of an output file ends with, but for the pid of the run.
+const TMP_INFIX: &str = ".dedup-tmp-";
+
+/// Whether `name` is the temporary name of an output file, `.NAME.dedup-tmp-PID`, maybe
+/// left behind by a crashed run.
+pub fn is_report_temporary(name: &OsStr) -> bool {
+    let name = name.as_bytes();
+    let Some(start) = name
+        .windows(TMP_INFIX.len())
+        .rposition(|window| window == TMP_INFIX.as_bytes())
+    else {
+        return false;
+    };
+    let pid = &name[start + TMP_INFIX.len()..];
+    name.starts_with(b".") && start > 1 && !pid.is_empty() && pid.iter().all(u8::is_ascii_digit)
+}
+
+/// The sink of the report. A file is written under a temporary name and renamed into
+/// place by [`Output::finish`], so that it never holds a partial report.
+///
+/// Once the reader of a pipe goes away, as `head` does, the rest of the output is dropped
+/// and the run goes on.
+pub struct Output {
+    writer: Box<dyn Write>,
+    file: Option<(PathBuf, PathBuf)>,
+    closed: bool,
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        Self {
+            writer: Box::new(io::BufWriter::new(io::stdout())),
+            file: None,
+            closed: false,
+        }
+    }
+
+    /// Drops everything written, for the stages of the library API, which report through
+    /// their return values instead.
+    pub fn sink() -> Self {
+        Self {
+            writer: Box::new(io::sink()),
+            file: None,
+            closed: false,
+        }
+    }
+
+    /// Opens `path` for the report; `-` stands for stdout.
+    pub fn create(path: &Path) -> Result<Self> {
+        if path == Path::new("-") {
+            return Ok(Self::stdout());
+        }
+        let file_name = path
+            .file_name()
+            .with_context(|| format!("Invalid output path: {}", path.to_string_lossy()))?;
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(file_name);
+        tmp_name.push(format!("{}{}", TMP_INFIX, std::process::id()));
+        let tmp_path = path.with_file_name(tmp_name);
+        let file = fs::File::create(&tmp_path).with_context(|| {
+            format!(
+                "Failed to create an output file: {}",
+                tmp_path.to_string_lossy()
+            )
+        })?;
+        Ok(Self {
+            writer: Box::new(io::BufWriter::new(file)),
+            file: Some((tmp_path, path.to_path_buf())),
+            closed: false,
+        })
+    }
+
+    /// The files this output writes to, if any.
+    pub fn paths(&self) -> Vec<&Path> {
+        match &self.file {
+            Some((tmp_path, path)) => vec![tmp_path.as_path(), path.as_path()],
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether the reader went away (EPIPE), so that nothing is written anymore.
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Drops the output from now on if `result` says that the reader went away.
+    fn check_closed<T>(&mut self, result: io::Result<T>, closed: T) -> io::Result<T> {
+        match result {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                eprintln!("Note: the output was closed; the rest of it is dropped");
+                self.closed = true;
+                Ok(closed)
+            }
+            result => result,
+        }
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.flush().context("Failed to write the report")?;
+        if let Some((tmp_path, path)) = self.file.take() {
+            fs::rename(&tmp_path, &path).with_context(|| {
+                format!(
+                    "Failed to fs::rename: {}, {}",
+                    tmp_path.to_string_lossy(),
+                    path.to_string_lossy(),
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        // not finished: do not leave a partial report behind
+        if let Some((tmp_path, _)) = &self.file {
+            let _ = fs::remove_file(tmp_path);
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Ok(buf.len());
+        }
+        let result = self.writer.write(buf);
+        self.check_closed(result, buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        let result = self.writer.flush();
+        self.check_closed(result, ())
+    }
+}
@@ -0,0 +1,107 @@
+//! `dedup pair A B`: link B to A if they are identical, as a careful `ln -f A B`.
+
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use anyhow::{bail, Context as _, Result};
+use filetime::FileTime;
+
+use crate::checks::check_removable;
+use crate::confine::Roots;
+use crate::digest::{digest_file, HashAlgorithm, HashHex};
+use crate::output::Output;
+use crate::{relink, update_mtime, CrossLink};
+
+/// The files were already links to the same inode.
+pub const EXIT_ALREADY_LINKED: u8 = 6;
+/// The files differ and were left alone.
+pub const EXIT_DIFFERENT: u8 = 7;
+
+fn regular_file(path: &Path) -> Result<fs::Metadata> {
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to fs::symlink_metadata: {}", path.to_string_lossy()))?;
+    if !metadata.is_file() {
+        bail!("Not a regular file: {}", path.to_string_lossy());
+    }
+    Ok(metadata)
+}
+
+fn hash(path: &Path) -> Result<HashHex> {
+    let (hash, _) = digest_file(path, HashAlgorithm::Sha256)
+        .with_context(|| format!("Failed to calculate a hash: {}", path.to_string_lossy()))?;
+    Ok(HashHex(hash))
+}
+
+/// Replaces `b` with a link to `a` if both have the same content, giving `a` the older
+/// of their mtimes. Returns the exit code.
+pub fn link_pair(
+    a: &Path,
+    b: &Path,
+    tmp_prefix: &str,
+    dry_run: bool,
+    out: &mut Output,
+) -> Result<u8> {
+    let a_metadata = regular_file(a)?;
+    let b_metadata = regular_file(b)?;
+    if a_metadata.dev() != b_metadata.dev() {
+        bail!(
+            "Not on the same device: {} and {}",
+            a.to_string_lossy(),
+            b.to_string_lossy()
+        );
+    }
+    if a_metadata.ino() == b_metadata.ino() {
+        writeln!(out, "Already linked: {} and {}", a.display(), b.display())?;
+        return Ok(EXIT_ALREADY_LINKED);
+    }
+    if a_metadata.len() != b_metadata.len() {
+        writeln!(
+            out,
+            "Different sizes: {} ({} bytes) and {} ({} bytes)",
+            a.display(),
+            a_metadata.len(),
+            b.display(),
+            b_metadata.len()
+        )?;
+        return Ok(EXIT_DIFFERENT);
+    }
+    let a_hash = hash(a)?;
+    let b_hash = hash(b)?;
+    if a_hash != b_hash {
+        writeln!(
+            out,
+            "Different content: {} and {}",
+            a.display(),
+            b.display()
+        )?;
+        return Ok(EXIT_DIFFERENT);
+    }
+    writeln!(out, "{}  {}", a_hash, a.display())?;
+    if let Some(reason) = check_removable(b)? {
+        bail!("Cannot replace {}: {}", b.to_string_lossy(), reason);
+    }
+
+    if dry_run {
+        writeln!(out, "<- {} (dry run)", b.display())?;
+        return Ok(0);
+    }
+    let mtime = FileTime::from_last_modification_time(&a_metadata)
+        .min(FileTime::from_last_modification_time(&b_metadata));
+    let roots = Roots::new([a, b]);
+    update_mtime(&roots, a, mtime)?;
+    match relink(&roots, a, b, tmp_prefix) {
+        Ok(dir_mtime) => dir_mtime.restore(&roots)?,
+        Err(err) if err.is::<CrossLink>() => {
+            bail!(
+                "Cannot link {} to {} across filesystems, left as it was",
+                b.to_string_lossy(),
+                a.to_string_lossy()
+            )
+        }
+        Err(err) => return Err(err),
+    }
+    writeln!(out, "<- {}", b.display())?;
+    Ok(0)
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+
+use crate::report::{serialize_path, Group};
+
+/// The plan changed since the previous one.
+pub const EXIT_PLAN_CHANGED: u8 = 1;
+
+/// The parts of a saved JSON report that make up its plan.
+#[derive(Debug, Deserialize)]
+struct SavedPlan {
+    groups: Vec<SavedGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SavedGroup {
+    original: PathBuf,
+    linked: Vec<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Operation {
+    #[serde(serialize_with = "serialize_path")]
+    pub original: PathBuf,
+    #[serde(serialize_with = "serialize_path")]
+    pub duplicate: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PlanDiff {
+    pub added: Vec<Operation>,
+    pub removed: Vec<Operation>,
+    pub unchanged: u64,
+}
+
+/// Paths of the old plan may be gone by now, in which case only their parent is resolved.
+fn canonicalize(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => canonicalize(parent).join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn operations<'a>(groups: impl Iterator<Item = (&'a Path, &'a [PathBuf])>) -> BTreeSet<Operation> {
+    let mut operations = BTreeSet::new();
+    for (original, linked) in groups {
+        let original = canonicalize(original);
+        for duplicate in linked {
+            operations.insert(Operation {
+                original: original.clone(),
+                duplicate: canonicalize(duplicate),
+            });
+        }
+    }
+    operations
+}
+
+/// Compares the groups of the current run with those of a JSON report saved earlier.
+pub fn diff_plan(old_plan: &Path, groups: &[Group]) -> Result<PlanDiff> {
+    let file = fs::File::open(old_plan)
+        .with_context(|| format!("Failed to open the plan: {}", old_plan.to_string_lossy()))?;
+    let saved: SavedPlan = serde_json::from_reader(io::BufReader::new(file))
+        .with_context(|| format!("Failed to read the plan: {}", old_plan.to_string_lossy()))?;
+
+    let old = operations(
+        saved
+            .groups
+            .iter()
+            .map(|group| (group.original.as_path(), group.linked.as_slice())),
+    );
+    let mut new = operations(
+        groups
+            .iter()
+            .map(|group| (group.original.as_path(), group.linked.as_slice())),
+    );
+
+    let mut diff = PlanDiff::default();
+    for operation in old {
+        if new.remove(&operation) {
+            diff.unchanged += 1;
+        } else {
+            diff.removed.push(operation);
+        }
+    }
+    diff.added = new.into_iter().collect();
+    Ok(diff)
+}
+
+impl PlanDiff {
+    pub fn print_summary(&self, out: &mut dyn Write) -> io::Result<()> {
+        for operation in &self.added {
+            writeln!(
+                out,
+                "+ {} <- {}",
+                operation.original.display(),
+                operation.duplicate.display()
+            )?;
+        }
+        for operation in &self.removed {
+            writeln!(
+                out,
+                "- {} <- {}",
+                operation.original.display(),
+                operation.duplicate.display()
+            )?;
+        }
+        writeln!(
+            out,
+            "Added: {}, removed: {}, unchanged: {}",
+            (self.added.len() as u64).to_formatted_string(&Locale::en),
+            (self.removed.len() as u64).to_formatted_string(&Locale::en),
+            self.unchanged.to_formatted_string(&Locale::en)
+        )?;
+        Ok(())
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        if self.added.is_empty() && self.removed.is_empty() {
+            0
+        } else {
+            EXIT_PLAN_CHANGED
+        }
+    }
+}
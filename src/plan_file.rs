@@ -0,0 +1,846 @@
+//! Binary plans: `--save-plan` writes the groups of a dry run as length-prefixed records,
+//! and `apply --plan` links them one group at a time, checking each against the files as
+//! they are now, without reading the whole plan into memory. `convert-plan` turns a plan
+//! into JSON for inspection, and back.
+//!
+//! After the magic and the version, the records are a single zstd stream, which is read as
+//! it is decompressed. Each record still carries its own checksum, so that a corrupted
+//! plan is rejected at the group where it went wrong.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context as _, Result};
+use filetime::FileTime;
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::checks::check_removable;
+use crate::confine::Roots;
+use crate::digest::{digest_file, HashAlgorithm, HashHex, HashValue, Sha256Value};
+use crate::output::Output;
+use crate::{relink, update_mtime, CrossLink};
+
+const MAGIC: &[u8; 8] = b"DEDUPPLN";
+/// Bumped whenever the layout of a record changes.
+const PLAN_VERSION: u32 = 2;
+/// The zstd level of the records: paths compress well even at low levels.
+const ZSTD_LEVEL: i32 = 3;
+/// Larger records are taken for corruption rather than allocated.
+const MAX_RECORD_LEN: u32 = 256 << 20;
+/// Each record ends with this many bytes of the SHA-256 of its payload.
+const CHECKSUM_LEN: usize = 8;
+
+/// A path of a JSON plan: a string if it is UTF-8, and its bytes otherwise, so that every
+/// path survives the conversion.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPath {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl From<RawPath> for PathBuf {
+    fn from(path: RawPath) -> Self {
+        match path {
+            RawPath::Text(text) => text.into(),
+            RawPath::Bytes(bytes) => OsString::from_vec(bytes).into(),
+        }
+    }
+}
+
+/// (De)serializes a path as a [`RawPath`].
+pub(crate) mod raw_path {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(text) => serializer.serialize_str(text),
+            None => serializer.collect_seq(path.as_os_str().as_bytes()),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        RawPath::deserialize(deserializer).map(PathBuf::from)
+    }
+}
+
+/// (De)serializes paths as [`RawPath`]s.
+pub(crate) mod raw_paths {
+    use super::*;
+
+    struct Raw<'a>(&'a Path);
+
+    impl Serialize for Raw<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            raw_path::serialize(self.0, serializer)
+        }
+    }
+
+    pub fn serialize<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(paths.iter().map(|path| Raw(path)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<PathBuf>, D::Error> {
+        let paths = Vec::<RawPath>::deserialize(deserializer)?;
+        Ok(paths.into_iter().map(PathBuf::from).collect())
+    }
+}
+
+/// A group of the plan: the duplicates to be replaced with links to the original.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanGroup {
+    pub hash: HashHex,
+    pub size: u64,
+    #[serde(with = "raw_path")]
+    pub original: PathBuf,
+    #[serde(with = "raw_paths")]
+    pub linked: Vec<PathBuf>,
+}
+
+/// The JSON form of a plan, for `convert-plan`.
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonPlan {
+    version: u32,
+    hash: HashAlgorithm,
+    #[serde(with = "raw_paths")]
+    targets: Vec<PathBuf>,
+    groups: Vec<PlanGroup>,
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let hash = Sha256::digest(payload);
+    let mut checksum = [0; CHECKSUM_LEN];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LEN]);
+    checksum
+}
+
+fn put_bytes(payload: &mut Vec<u8>, bytes: &[u8]) {
+    payload.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    payload.extend_from_slice(bytes);
+}
+
+/// Reads the fields of a record payload in order.
+struct Fields<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(len <= self.payload.len(), "record shorter than its fields");
+        let (field, rest) = self.payload.split_at(len);
+        self.payload = rest;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn path(&mut self) -> Result<PathBuf> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        Ok(PathBuf::from(OsString::from_vec(bytes.to_vec())))
+    }
+
+    fn finish(&self) -> Result<()> {
+        ensure!(self.payload.is_empty(), "record longer than its fields");
+        Ok(())
+    }
+}
+
+fn algorithm_code(algorithm: HashAlgorithm) -> u8 {
+    match algorithm {
+        HashAlgorithm::Sha256 => 0,
+        HashAlgorithm::Blake3 => 1,
+        HashAlgorithm::Sha256Tree => 2,
+    }
+}
+
+fn algorithm_from_code(code: u8) -> Result<HashAlgorithm> {
+    match code {
+        0 => Ok(HashAlgorithm::Sha256),
+        1 => Ok(HashAlgorithm::Blake3),
+        2 => Ok(HashAlgorithm::Sha256Tree),
+        _ => bail!("unknown hash algorithm {}", code),
+    }
+}
+
+/// Writes a plan under a temporary name, renamed into place by [`PlanWriter::finish`].
+pub struct PlanWriter {
+    writer: io::BufWriter<zstd::Encoder<'static, fs::File>>,
+    path: PathBuf,
+    temporary: PathBuf,
+    algorithm: HashAlgorithm,
+    groups: u64,
+}
+
+impl fmt::Debug for PlanWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlanWriter")
+            .field("path", &self.path)
+            .field("algorithm", &self.algorithm)
+            .field("groups", &self.groups)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PlanWriter {
+    pub fn create(path: &Path, algorithm: HashAlgorithm, targets: &[PathBuf]) -> Result<Self> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let context = || format!("Failed to create the plan: {}", path.to_string_lossy());
+        let mut file = fs::File::create(&temporary).with_context(context)?;
+        file.write_all(MAGIC)
+            .and_then(|()| file.write_all(&PLAN_VERSION.to_le_bytes()))
+            .with_context(context)?;
+        let mut encoder = zstd::Encoder::new(file, ZSTD_LEVEL).with_context(context)?;
+        encoder.include_checksum(true).with_context(context)?;
+        let mut writer = Self {
+            writer: io::BufWriter::new(encoder),
+            path: path.to_path_buf(),
+            temporary,
+            algorithm,
+            groups: 0,
+        };
+        let mut header = vec![algorithm_code(algorithm)];
+        header.extend_from_slice(&(targets.len() as u32).to_le_bytes());
+        for target in targets {
+            put_bytes(&mut header, target.as_os_str().as_bytes());
+        }
+        writer.write_record(&header)?;
+        Ok(writer)
+    }
+
+    fn context(&self) -> String {
+        format!("Failed to write the plan: {}", self.path.to_string_lossy())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes).with_context(|| self.context())
+    }
+
+    fn write_record(&mut self, payload: &[u8]) -> Result<()> {
+        ensure!(
+            !payload.is_empty() && payload.len() <= MAX_RECORD_LEN as usize,
+            "{}: a group of {} bytes does not fit in a record",
+            self.context(),
+            payload.len()
+        );
+        self.write_raw(&(payload.len() as u32).to_le_bytes())?;
+        self.write_raw(payload)?;
+        self.write_raw(&checksum(payload))
+    }
+
+    pub fn write_group(&mut self, group: &PlanGroup) -> Result<()> {
+        ensure!(
+            group.hash.0.algorithm() == self.algorithm,
+            "{}: a hash of another algorithm than the plan",
+            self.context()
+        );
+        let mut payload = group.hash.0.as_bytes().to_vec();
+        payload.extend_from_slice(&group.size.to_le_bytes());
+        put_bytes(&mut payload, group.original.as_os_str().as_bytes());
+        payload.extend_from_slice(&(group.linked.len() as u32).to_le_bytes());
+        for duplicate in &group.linked {
+            put_bytes(&mut payload, duplicate.as_os_str().as_bytes());
+        }
+        self.write_record(&payload)?;
+        self.groups += 1;
+        Ok(())
+    }
+
+    /// Writes the end of the plan, which holds the number of groups so that a truncated
+    /// plan is told from a complete one, and renames the plan into place. Returns the
+    /// number of groups.
+    pub fn finish(mut self) -> Result<u64> {
+        self.write_raw(&0u32.to_le_bytes())?;
+        self.write_raw(&self.groups.to_le_bytes())?;
+        let context = self.context();
+        let encoder = self.writer.into_inner().map_err(|err| err.into_error());
+        encoder
+            .and_then(|encoder| encoder.finish())
+            .and_then(|mut file| file.flush())
+            .with_context(|| context.clone())?;
+        fs::rename(&self.temporary, &self.path).with_context(|| context)?;
+        Ok(self.groups)
+    }
+}
+
+/// Reads a plan written by [`PlanWriter`] one group at a time.
+pub struct PlanReader {
+    reader: zstd::Decoder<'static, io::BufReader<fs::File>>,
+    path: PathBuf,
+    pub algorithm: HashAlgorithm,
+    pub targets: Vec<PathBuf>,
+    groups: u64,
+    done: bool,
+}
+
+/// Fills `buf`, taking the end of the plan before it for truncation.
+fn read_exact(reader: &mut impl Read, buf: &mut [u8], context: &str) -> Result<()> {
+    match reader.read_exact(buf) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => bail!("{}: truncated", context),
+        result => result.with_context(|| context.to_string()),
+    }
+}
+
+impl PlanReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open the plan: {}", path.to_string_lossy()))?;
+        let context = format!("Failed to read the plan: {}", path.to_string_lossy());
+        let mut file = io::BufReader::new(file);
+        let mut magic = [0; 8];
+        read_exact(&mut file, &mut magic, &context)?;
+        ensure!(&magic == MAGIC, "{}: not a binary plan", context);
+        let mut version = [0; 4];
+        read_exact(&mut file, &mut version, &context)?;
+        let version = u32::from_le_bytes(version);
+        ensure!(
+            version == PLAN_VERSION,
+            "{}: unsupported version {}",
+            context,
+            version
+        );
+        let mut reader = Self {
+            reader: zstd::Decoder::with_buffer(file).with_context(|| context.clone())?,
+            path: path.to_path_buf(),
+            algorithm: HashAlgorithm::default(),
+            targets: Vec::new(),
+            groups: 0,
+            done: false,
+        };
+        let header = reader
+            .read_record()?
+            .with_context(|| format!("{}: no header", context))?;
+        let mut fields = Fields { payload: &header };
+        let parsed: Result<()> = (|| {
+            reader.algorithm = algorithm_from_code(fields.u8()?)?;
+            for _ in 0..fields.u32()? {
+                reader.targets.push(fields.path()?);
+            }
+            fields.finish()
+        })();
+        parsed.with_context(|| context)?;
+        Ok(reader)
+    }
+
+    fn context(&self) -> String {
+        format!("Failed to read the plan: {}", self.path.to_string_lossy())
+    }
+
+    fn read_raw(&mut self, buf: &mut [u8]) -> Result<()> {
+        let context = self.context();
+        read_exact(&mut self.reader, buf, &context)
+    }
+
+    /// The payload of the next record, or `None` at the end of the plan, which is checked
+    /// to hold the number of records read.
+    fn read_record(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut len = [0; 4];
+        self.read_raw(&mut len)?;
+        let len = u32::from_le_bytes(len);
+        if len == 0 {
+            let mut groups = [0; 8];
+            self.read_raw(&mut groups)?;
+            let groups = u64::from_le_bytes(groups);
+            ensure!(
+                groups == self.groups,
+                "{}: ends after {} groups instead of {}",
+                self.context(),
+                self.groups,
+                groups
+            );
+            let mut rest = Vec::new();
+            self.reader
+                .read_to_end(&mut rest)
+                .with_context(|| self.context())?;
+            ensure!(rest.is_empty(), "{}: data after the end", self.context());
+            return Ok(None);
+        }
+        ensure!(
+            len <= MAX_RECORD_LEN,
+            "{}: record of {} bytes",
+            self.context(),
+            len
+        );
+        let mut payload = vec![0; len as usize];
+        self.read_raw(&mut payload)?;
+        let mut stored = [0; CHECKSUM_LEN];
+        self.read_raw(&mut stored)?;
+        ensure!(
+            stored == checksum(&payload),
+            "{}: checksum mismatch in record {}",
+            self.context(),
+            self.groups + 1
+        );
+        Ok(Some(payload))
+    }
+
+    pub fn next_group(&mut self) -> Result<Option<PlanGroup>> {
+        if self.done {
+            return Ok(None);
+        }
+        let Some(payload) = self.read_record()? else {
+            self.done = true;
+            return Ok(None);
+        };
+        self.groups += 1;
+        let mut fields = Fields { payload: &payload };
+        let algorithm = self.algorithm;
+        let group: Result<PlanGroup> = (|| {
+            let hash = match algorithm {
+                HashAlgorithm::Sha256 => {
+                    HashValue::Sha256(Sha256Value::clone_from_slice(fields.take(32)?))
+                }
+                HashAlgorithm::Blake3 => HashValue::Blake3(fields.take(32)?.try_into().unwrap()),
+                HashAlgorithm::Sha256Tree => {
+                    HashValue::Sha256Tree(Sha256Value::clone_from_slice(fields.take(32)?))
+                }
+            };
+            let size = fields.u64()?;
+            let original = fields.path()?;
+            let mut linked = Vec::new();
+            for _ in 0..fields.u32()? {
+                linked.push(fields.path()?);
+            }
+            fields.finish()?;
+            Ok(PlanGroup {
+                hash: HashHex(hash),
+                size,
+                original,
+                linked,
+            })
+        })();
+        group
+            .map(Some)
+            .with_context(|| format!("{}: record {}", self.context(), self.groups))
+    }
+}
+
+/// Converts a binary plan to JSON, or JSON back to a binary plan, whichever `input` is.
+pub fn convert_plan(input: &Path, output: &Path) -> Result<()> {
+    let mut magic = [0; 8];
+    let is_binary = fs::File::open(input)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == MAGIC;
+    if is_binary {
+        let mut reader = PlanReader::open(input)?;
+        let mut groups = Vec::new();
+        while let Some(group) = reader.next_group()? {
+            groups.push(group);
+        }
+        let plan = JsonPlan {
+            version: PLAN_VERSION,
+            hash: reader.algorithm,
+            targets: reader.targets,
+            groups,
+        };
+        let context = || format!("Failed to write the plan: {}", output.to_string_lossy());
+        let file = fs::File::create(output).with_context(context)?;
+        let mut writer = io::BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &plan).with_context(context)?;
+        writer.flush().with_context(context)?;
+    } else {
+        let file = fs::File::open(input)
+            .with_context(|| format!("Failed to open the plan: {}", input.to_string_lossy()))?;
+        let plan: JsonPlan = serde_json::from_reader(io::BufReader::new(file))
+            .with_context(|| format!("Failed to read the plan: {}", input.to_string_lossy()))?;
+        ensure!(
+            plan.version == PLAN_VERSION,
+            "Unsupported plan version {}: {}",
+            plan.version,
+            input.to_string_lossy()
+        );
+        let mut writer = PlanWriter::create(output, plan.hash, &plan.targets)?;
+        for group in &plan.groups {
+            writer.write_group(group)?;
+        }
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+/// Why a planned link may no longer be made, if it may not.
+fn drift(path: &Path, group: &PlanGroup, original: &fs::Metadata) -> Result<Option<String>> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some("gone".to_string())),
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!("Failed to fs::symlink_metadata: {}", path.to_string_lossy())
+            })
+        }
+    };
+    if !metadata.is_file() {
+        return Ok(Some("no longer a regular file".to_string()));
+    }
+    if metadata.dev() != original.dev() {
+        return Ok(Some("on another device than the original".to_string()));
+    }
+    if metadata.ino() == original.ino() {
+        return Ok(Some("already linked".to_string()));
+    }
+    if metadata.len() != group.size {
+        return Ok(Some(format!(
+            "size changed from {} to {}",
+            group.size,
+            metadata.len()
+        )));
+    }
+    match digest_file(path, group.hash.0.algorithm()) {
+        Ok((hash, _)) if hash == group.hash.0 => {}
+        Ok(_) => return Ok(Some("content changed".to_string())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Some("gone".to_string())),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to calculate a hash: {}", path.to_string_lossy()))
+        }
+    }
+    if let Some(reason) = check_removable(path)? {
+        return Ok(Some(reason.to_string()));
+    }
+    Ok(None)
+}
+
+/// What applying a plan did.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ApplyReport {
+    /// The groups whose original was unchanged.
+    pub groups: u64,
+    pub linked: u64,
+    /// The duplicates that changed since the plan, or could not be linked.
+    pub skipped: u64,
+}
+
+/// Links one group of the plan, skipping the duplicates that changed since, and the whole
+/// group if the original did.
+pub(crate) fn apply_group(
+    roots: &Roots,
+    group: &PlanGroup,
+    tmp_prefix: &str,
+    dry_run: bool,
+    totals: &mut ApplyReport,
+    out: &mut Output,
+) -> Result<()> {
+    writeln!(out, "{}  {}", group.hash, group.original.display())?;
+    // not followed: an original swapped for a symlink would get the duplicates linked to it
+    let original = match fs::symlink_metadata(&group.original) {
+        Ok(original) if original.is_file() && original.len() == group.size => original,
+        Ok(_) => {
+            eprintln!(
+                "Skipped the group of {}: the original changed",
+                group.original.display()
+            );
+            totals.skipped += group.linked.len() as u64;
+            return Ok(());
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            eprintln!(
+                "Skipped the group of {}: the original is gone",
+                group.original.display()
+            );
+            totals.skipped += group.linked.len() as u64;
+            return Ok(());
+        }
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!(
+                    "Failed to fs::symlink_metadata: {}",
+                    group.original.to_string_lossy()
+                )
+            })
+        }
+    };
+    let (hash, _) = digest_file(&group.original, group.hash.0.algorithm()).with_context(|| {
+        format!(
+            "Failed to calculate a hash: {}",
+            group.original.to_string_lossy()
+        )
+    })?;
+    if hash != group.hash.0 {
+        eprintln!(
+            "Skipped the group of {}: the original changed",
+            group.original.display()
+        );
+        totals.skipped += group.linked.len() as u64;
+        return Ok(());
+    }
+
+    let mut duplicates = Vec::new();
+    let mut mtime = FileTime::from_last_modification_time(&original);
+    for duplicate in &group.linked {
+        if let Some(reason) = drift(duplicate, group, &original)? {
+            eprintln!("Skipped {}: {}", duplicate.display(), reason);
+            totals.skipped += 1;
+            continue;
+        }
+        if let Ok(metadata) = fs::symlink_metadata(duplicate) {
+            mtime = mtime.min(FileTime::from_last_modification_time(&metadata));
+        }
+        duplicates.push(duplicate);
+    }
+    totals.groups += 1;
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+    if !dry_run {
+        update_mtime(roots, &group.original, mtime)?;
+    }
+    for duplicate in duplicates {
+        if !dry_run {
+            match relink(roots, &group.original, duplicate, tmp_prefix) {
+                Ok(dir_mtime) => dir_mtime.restore(roots)?,
+                Err(err) if err.is::<CrossLink>() => {
+                    eprintln!(
+                        "Skipped {}: cannot be linked to the original (EXDEV)",
+                        duplicate.display()
+                    );
+                    totals.skipped += 1;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        writeln!(out, "<- {}", duplicate.display())?;
+        totals.linked += 1;
+    }
+    Ok(())
+}
+
+/// Carries out a plan written by `--save-plan`, one group at a time, confined to `roots`
+/// given on the command line rather than to the targets the plan claims, which a crafted
+/// plan could widen.
+pub fn apply_plan(
+    path: &Path,
+    roots: &Roots,
+    tmp_prefix: &str,
+    dry_run: bool,
+    out: &mut Output,
+) -> Result<()> {
+    ensure!(
+        !roots.is_empty(),
+        "Refusing to apply a plan without targets"
+    );
+    let mut reader = PlanReader::open(path)?;
+    let mut totals = ApplyReport::default();
+    while let Some(group) = reader.next_group()? {
+        apply_group(roots, &group, tmp_prefix, dry_run, &mut totals, out)?;
+    }
+    writeln!(
+        out,
+        "{} {} files in {} groups; skipped {} files that changed since the plan",
+        if dry_run { "Would link" } else { "Linked" },
+        totals.linked.to_formatted_string(&Locale::en),
+        totals.groups.to_formatted_string(&Locale::en),
+        totals.skipped.to_formatted_string(&Locale::en)
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::confine::OutsideRoots;
+    use crate::temporary::DEFAULT_TMP_PREFIX;
+    use crate::test_utils::{assert_not_linked, TreeBuilder};
+
+    #[test]
+    fn plan_listing_a_path_outside_the_targets_aborts() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("inside/a", "same").unwrap();
+        tree.file("outside/c", "same").unwrap();
+        let plan_path = tree.path("plan");
+        let (hash, _) = digest_file(&tree.path("inside/a"), HashAlgorithm::Sha256).unwrap();
+        // the header claims both directories, which must not widen the roots
+        let mut writer = PlanWriter::create(
+            &plan_path,
+            HashAlgorithm::Sha256,
+            &[tree.path("inside"), tree.path("outside")],
+        )
+        .unwrap();
+        writer
+            .write_group(&PlanGroup {
+                hash: HashHex(hash),
+                size: 4,
+                original: tree.path("inside/a"),
+                linked: vec![tree.path("outside/c")],
+            })
+            .unwrap();
+        writer.finish().unwrap();
+
+        let roots = Roots::new([tree.path("inside").as_path()]);
+        let err = apply_plan(
+            &plan_path,
+            &roots,
+            DEFAULT_TMP_PREFIX,
+            false,
+            &mut Output::sink(),
+        )
+        .unwrap_err();
+        assert!(err.is::<OutsideRoots>(), "{err:?}");
+        assert_not_linked(tree.path("inside/a"), tree.path("outside/c"));
+    }
+
+    #[test]
+    fn refuses_to_apply_without_targets() {
+        let tree = TreeBuilder::new().unwrap();
+        let roots = Roots::new([]);
+        assert!(apply_plan(
+            &tree.path("plan"),
+            &roots,
+            DEFAULT_TMP_PREFIX,
+            false,
+            &mut Output::sink(),
+        )
+        .is_err());
+    }
+
+    /// Groups of long, alike paths, one of them not UTF-8.
+    fn groups(tree: &TreeBuilder, n: u64) -> Vec<PlanGroup> {
+        let (hash, _) = digest_file(Path::new("/dev/null"), HashAlgorithm::Sha256).unwrap();
+        (0..n)
+            .map(|i| PlanGroup {
+                hash: HashHex(hash),
+                size: i,
+                original: tree.path(format!("some/deep/directory/original-{i}")),
+                linked: vec![
+                    tree.path(format!("some/deep/directory/copy-{i}")),
+                    tree.path(std::ffi::OsStr::from_bytes(b"not-utf-8-\xff")),
+                ],
+            })
+            .collect()
+    }
+
+    fn write_plan(path: &Path, groups: &[PlanGroup]) {
+        let mut writer = PlanWriter::create(path, HashAlgorithm::Sha256, &[]).unwrap();
+        for group in groups {
+            writer.write_group(group).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), groups.len() as u64);
+    }
+
+    fn read_plan(path: &Path) -> Result<Vec<PlanGroup>> {
+        let mut reader = PlanReader::open(path)?;
+        let mut groups = Vec::new();
+        while let Some(group) = reader.next_group()? {
+            groups.push(group);
+        }
+        Ok(groups)
+    }
+
+    #[test]
+    fn plans_read_back_as_written() {
+        let tree = TreeBuilder::new().unwrap();
+        let groups = groups(&tree, 500);
+        let path = tree.path("plan");
+        write_plan(&path, &groups);
+        assert_eq!(read_plan(&path).unwrap(), groups);
+        // compressed: far smaller than the paths alone
+        let paths: usize = groups
+            .iter()
+            .flat_map(|group| group.linked.iter().chain([&group.original]))
+            .map(|path| path.as_os_str().len())
+            .sum();
+        assert!(fs::metadata(&path).unwrap().len() < paths as u64 / 4);
+    }
+
+    #[test]
+    fn corrupted_plans_are_rejected() {
+        let tree = TreeBuilder::new().unwrap();
+        let path = tree.path("plan");
+        let groups = groups(&tree, 20);
+        write_plan(&path, &groups);
+        let plan = fs::read(&path).unwrap();
+        let corrupted = tree.path("corrupted");
+        let mut rejected = 0;
+        for i in 0..plan.len() {
+            let mut flipped = plan.clone();
+            flipped[i] ^= 0x10;
+            fs::write(&corrupted, flipped).unwrap();
+            // a few bits of the zstd frame do not bear on the records, which come out intact
+            match read_plan(&corrupted) {
+                Ok(read) => assert_eq!(read, groups, "byte {i} flipped"),
+                Err(_) => rejected += 1,
+            }
+        }
+        assert!(
+            rejected > plan.len() * 9 / 10,
+            "{rejected} of {}",
+            plan.len()
+        );
+        // the trailer, holding the number of groups, cut short or missing
+        for cut in [1, 4, 16] {
+            fs::write(&corrupted, &plan[..plan.len() - cut]).unwrap();
+            assert!(read_plan(&corrupted).is_err(), "{cut} bytes cut");
+        }
+    }
+
+    #[test]
+    fn plans_survive_conversion_to_json_and_back() {
+        let tree = TreeBuilder::new().unwrap();
+        let groups = groups(&tree, 3);
+        let (binary, json, back) = (tree.path("plan"), tree.path("json"), tree.path("back"));
+        write_plan(&binary, &groups);
+        convert_plan(&binary, &json).unwrap();
+        let text = fs::read_to_string(&json).unwrap();
+        assert!(text.contains("some/deep/directory/copy-2"), "{text}");
+        convert_plan(&json, &back).unwrap();
+        assert_eq!(read_plan(&back).unwrap(), groups);
+    }
+
+    #[test]
+    fn original_swapped_for_a_symlink_is_skipped() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("t/a", "same")
+            .unwrap()
+            .file("t/b", "same")
+            .unwrap()
+            .file("elsewhere", "same")
+            .unwrap();
+        let (hash, _) = digest_file(&tree.path("t/a"), HashAlgorithm::Sha256).unwrap();
+        let group = PlanGroup {
+            hash: HashHex(hash),
+            size: 4,
+            original: tree.path("t/a"),
+            linked: vec![tree.path("t/b")],
+        };
+        fs::remove_file(tree.path("t/a")).unwrap();
+        std::os::unix::fs::symlink(tree.path("elsewhere"), tree.path("t/a")).unwrap();
+
+        let roots = Roots::new([tree.path("t").as_path()]);
+        let mut totals = ApplyReport::default();
+        apply_group(
+            &roots,
+            &group,
+            DEFAULT_TMP_PREFIX,
+            false,
+            &mut totals,
+            &mut Output::sink(),
+        )
+        .unwrap();
+        assert_eq!((totals.linked, totals.skipped), (0, 1));
+        assert!(fs::symlink_metadata(tree.path("t/b")).unwrap().is_file());
+        assert_not_linked(tree.path("elsewhere"), tree.path("t/b"));
+    }
+}
@@ -0,0 +1,51 @@
+//! `--prefilter-region`: the bytes the prefix sieve hashes of the larger files of an
+//! ambiguous size, to tell most of them apart without reading them in full. Only the
+//! pruning depends on it; the groups are always made on full hashes.
+
+/// Which end of a file the prefix sieve hashes.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PrefilterRegion {
+    #[default]
+    Head,
+    /// For formats with trailing metadata, or with a common header
+    Tail,
+    #[value(name = "head+tail")]
+    HeadTail,
+}
+
+/// The bytes the prefix sieve hashes at either end of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefilter {
+    pub head: u64,
+    pub tail: u64,
+}
+
+impl Prefilter {
+    /// `size` bytes of `region`, at both ends for `head+tail`.
+    pub fn new(region: PrefilterRegion, size: u64) -> Self {
+        match region {
+            PrefilterRegion::Head => Self {
+                head: size,
+                tail: 0,
+            },
+            PrefilterRegion::Tail => Self {
+                head: 0,
+                tail: size,
+            },
+            PrefilterRegion::HeadTail => Self {
+                head: size,
+                tail: size,
+            },
+        }
+    }
+
+    /// The bytes hashed. Files no larger are hashed in full right away.
+    pub fn bytes(&self) -> u64 {
+        self.head + self.tail
+    }
+
+    /// Tells the hashes of the prefix sieve in the cache apart by the bytes they are of.
+    pub fn cache_key(&self) -> String {
+        format!("{}+{}", self.head, self.tail)
+    }
+}
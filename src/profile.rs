@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+use std::time::Duration;
+
+use num_format::{Locale, ToFormattedString};
+use serde::{Serialize, Serializer};
+
+use crate::digest::{BUFFER_SIZE, CHUNK_SIZE};
+use crate::models::Dev;
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn format_throughput(bytes: u64, duration: Duration) -> String {
+    if duration.is_zero() {
+        return "-".to_string();
+    }
+    format!(
+        "{:.1} MiB/s",
+        bytes as f64 / duration.as_secs_f64() / (1024.0 * 1024.0)
+    )
+}
+
+/// Times are measured around the I/O calls only, so that a slow disk stands out.
+#[derive(Debug, Default, Serialize)]
+pub struct DeviceProfile {
+    pub files_scanned: u64,
+    pub bytes_scanned: u64,
+    pub files_hashed: u64,
+    pub bytes_hashed: u64,
+    /// Summed over the worker threads, so it may exceed the wall time of the walk.
+    #[serde(serialize_with = "serialize_secs")]
+    pub hash_time: Duration,
+    pub relinks: u64,
+    #[serde(serialize_with = "serialize_secs")]
+    pub relink_time: Duration,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    /// Wall time of the walk, including the hashing, whether done while walking or by the
+    /// worker threads afterwards.
+    #[serde(serialize_with = "serialize_secs")]
+    pub walk_time: Duration,
+    #[serde(serialize_with = "serialize_secs")]
+    pub relink_time: Duration,
+    pub threads: usize,
+    pub buffer_size: usize,
+    pub chunk_size: usize,
+    pub devices: BTreeMap<u64, DeviceProfile>,
+}
+
+impl Profile {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            walk_time: Duration::ZERO,
+            relink_time: Duration::ZERO,
+            threads,
+            buffer_size: BUFFER_SIZE,
+            chunk_size: CHUNK_SIZE,
+            devices: BTreeMap::new(),
+        }
+    }
+
+    pub fn device(&mut self, dev: Dev) -> &mut DeviceProfile {
+        self.devices.entry(dev.0).or_default()
+    }
+
+    fn hash_time(&self) -> Duration {
+        self.devices.values().map(|device| device.hash_time).sum()
+    }
+
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        let fmt = |n: u64| n.to_formatted_string(&Locale::en);
+        writeln!(out, "Profile:")?;
+        writeln!(
+            out,
+            "  walk: {:.3}s (hashing: {:.3}s)",
+            self.walk_time.as_secs_f64(),
+            self.hash_time().as_secs_f64(),
+        )?;
+        writeln!(out, "  relink: {:.3}s", self.relink_time.as_secs_f64())?;
+        writeln!(
+            out,
+            "  threads: {}, buffer: {} bytes, chunk: {} bytes",
+            self.threads,
+            fmt(self.buffer_size as u64),
+            fmt(self.chunk_size as u64),
+        )?;
+        if self.devices.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "  {:>10} {:>10} {:>16} {:>10} {:>16} {:>10} {:>12} {:>10} {:>10}",
+            "device",
+            "scanned",
+            "bytes",
+            "hashed",
+            "bytes",
+            "hash time",
+            "throughput",
+            "relinked",
+            "time",
+        )?;
+        for (dev, device) in &self.devices {
+            writeln!(
+                out,
+                "  {:>10} {:>10} {:>16} {:>10} {:>16} {:>9.3}s {:>12} {:>10} {:>9.3}s",
+                dev,
+                fmt(device.files_scanned),
+                fmt(device.bytes_scanned),
+                fmt(device.files_hashed),
+                fmt(device.bytes_hashed),
+                device.hash_time.as_secs_f64(),
+                format_throughput(device.bytes_hashed, device.hash_time),
+                fmt(device.relinks),
+                device.relink_time.as_secs_f64(),
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// How often a paused run checks whether it may go on.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    Idle,
+    Walk,
+    /// Hashing the candidates left by the walk, on `--threads` workers or for `--cache`.
+    Hash,
+    Relink,
+    Done,
+}
+
+impl Phase {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Walk,
+            2 => Self::Hash,
+            3 => Self::Relink,
+            4 => Self::Done,
+            _ => Self::Idle,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    files_scanned: AtomicU64,
+    files_queued: AtomicU64,
+    bytes_queued: AtomicU64,
+    files_hashed: AtomicU64,
+    bytes_hashed: AtomicU64,
+    groups_done: AtomicU64,
+    bytes_gained: AtomicU64,
+    errors: AtomicU64,
+    phase: AtomicU8,
+    paused: AtomicBool,
+    stop_requested: AtomicBool,
+}
+
+/// The error of a hash not started because the run was asked to stop.
+#[derive(Debug)]
+pub struct Stopped;
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the run was asked to stop")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+impl Stopped {
+    pub fn is(err: &io::Error) -> bool {
+        err.get_ref().is_some_and(|err| err.is::<Self>())
+    }
+}
+
+/// The counters of a run at one point, e.g. for `--control-socket status`.
+#[derive(Debug, Serialize)]
+pub struct ProgressSnapshot {
+    pub phase: Phase,
+    pub paused: bool,
+    pub stop_requested: bool,
+    pub files_scanned: u64,
+    pub files_queued: u64,
+    pub bytes_queued: u64,
+    pub files_hashed: u64,
+    pub bytes_hashed: u64,
+    pub groups_done: u64,
+    pub bytes_gained: u64,
+    pub errors: u64,
+}
+
+/// A cheaply cloneable handle for polling the progress of a run from another thread.
+///
+/// Counters only ever increase and are updated with relaxed ordering: a snapshot may be
+/// slightly stale and the counters are not guaranteed to be consistent with each other.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressHandle {
+    counters: Arc<Counters>,
+}
+
+impl ProgressHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn files_scanned(&self) -> u64 {
+        self.counters.files_scanned.load(Ordering::Relaxed)
+    }
+
+    /// Files whose size turned out not to be unique, so that they are to be hashed.
+    pub fn files_queued(&self) -> u64 {
+        self.counters.files_queued.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_queued(&self) -> u64 {
+        self.counters.bytes_queued.load(Ordering::Relaxed)
+    }
+
+    /// Files hashed in full, which the prefix sieve spares some of the queued files.
+    pub fn files_hashed(&self) -> u64 {
+        self.counters.files_hashed.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_hashed(&self) -> u64 {
+        self.counters.bytes_hashed.load(Ordering::Relaxed)
+    }
+
+    pub fn groups_done(&self) -> u64 {
+        self.counters.groups_done.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_gained(&self) -> u64 {
+        self.counters.bytes_gained.load(Ordering::Relaxed)
+    }
+
+    /// Errors the run went on after.
+    pub fn errors(&self) -> u64 {
+        self.counters.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn phase(&self) -> Phase {
+        Phase::from_u8(self.counters.phase.load(Ordering::Relaxed))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.counters.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stop_requested(&self) -> bool {
+        self.counters.stop_requested.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            phase: self.phase(),
+            paused: self.is_paused(),
+            stop_requested: self.is_stop_requested(),
+            files_scanned: self.files_scanned(),
+            files_queued: self.files_queued(),
+            bytes_queued: self.bytes_queued(),
+            files_hashed: self.files_hashed(),
+            bytes_hashed: self.bytes_hashed(),
+            groups_done: self.groups_done(),
+            bytes_gained: self.bytes_gained(),
+            errors: self.errors(),
+        }
+    }
+
+    /// Makes the run wait once the file or group at hand is done, until [`Self::resume`].
+    pub fn pause(&self) {
+        self.counters.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.counters.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Makes the run end once the file or group at hand is done, as if nothing was left
+    /// to do, paused or not.
+    pub fn request_stop(&self) {
+        self.counters.stop_requested.store(true, Ordering::Relaxed);
+        self.resume();
+    }
+
+    /// Waits while the run is paused, and returns whether it was asked to stop. Called
+    /// between files and groups, never in the middle of one.
+    pub(crate) fn checkpoint(&self) -> bool {
+        while self.is_paused() && !self.is_stop_requested() {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+        self.is_stop_requested()
+    }
+
+    pub(crate) fn add_files_scanned(&self, n: u64) {
+        self.counters.files_scanned.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_queued(&self, bytes: u64) {
+        self.counters.files_queued.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .bytes_queued
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_files_hashed(&self, n: u64) {
+        self.counters.files_hashed.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_hashed(&self, n: u64) {
+        self.counters.bytes_hashed.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_groups_done(&self, n: u64) {
+        self.counters.groups_done.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_gained(&self, n: u64) {
+        self.counters.bytes_gained.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_errors(&self, n: u64) {
+        self.counters.errors.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_phase(&self, phase: Phase) {
+        let value = match phase {
+            Phase::Idle => 0,
+            Phase::Walk => 1,
+            Phase::Hash => 2,
+            Phase::Relink => 3,
+            Phase::Done => 4,
+        };
+        self.counters.phase.store(value, Ordering::Relaxed);
+    }
+}
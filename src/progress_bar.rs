@@ -0,0 +1,154 @@
+//! `--progress`: a line on stderr showing how far the run got, redrawn in place on a
+//! terminal and printed every few seconds otherwise, so that logs do not fill up.
+
+use std::io::{self, IsTerminal as _, Write as _};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use num_format::{Locale, ToFormattedString};
+
+use crate::progress::{Phase, ProgressHandle};
+
+/// How often the line is redrawn on a terminal.
+const TERMINAL_INTERVAL: Duration = Duration::from_millis(250);
+/// How often a line is printed when stderr is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+/// How often the display thread checks whether the run ended.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn fmt(n: u64) -> String {
+    n.to_formatted_string(&Locale::en)
+}
+
+/// The line for the current phase, or `None` when there is nothing to show. `rate` is the
+/// number of bytes hashed per second since the previous line.
+fn progress_line(progress: &ProgressHandle, rate: u64) -> Option<String> {
+    match progress.phase() {
+        Phase::Idle | Phase::Done => None,
+        Phase::Walk => Some(format!(
+            "Walking: {} files, {} queued for hashing ({} bytes), {} hashed, {} bytes/s",
+            fmt(progress.files_scanned()),
+            fmt(progress.files_queued()),
+            fmt(progress.bytes_queued()),
+            fmt(progress.files_hashed()),
+            fmt(rate),
+        )),
+        Phase::Hash => Some(format!(
+            "Hashing: {} of {} files, {} of {} bytes, {} bytes/s",
+            fmt(progress.files_hashed()),
+            fmt(progress.files_queued()),
+            fmt(progress.bytes_hashed()),
+            fmt(progress.bytes_queued()),
+            fmt(rate),
+        )),
+        Phase::Relink => Some(format!(
+            "Relinking: {} groups, {} bytes gained",
+            fmt(progress.groups_done()),
+            fmt(progress.bytes_gained()),
+        )),
+    }
+}
+
+/// Prints the progress line until `done` is set. On a terminal, the line is cleared once
+/// the run is done, so that the summary that follows is not mixed with it.
+fn display(progress: &ProgressHandle, done: &AtomicBool) {
+    let terminal = io::stderr().is_terminal();
+    let interval = if terminal {
+        TERMINAL_INTERVAL
+    } else {
+        LOG_INTERVAL
+    };
+    let clear = || {
+        let _ = write!(io::stderr(), "\r\x1b[K");
+    };
+    let mut shown = false;
+    let mut last = Instant::now();
+    let mut last_bytes = progress.bytes_hashed();
+    while !done.load(Ordering::Relaxed) {
+        thread::sleep(POLL_INTERVAL);
+        if terminal && shown && progress.phase() == Phase::Done {
+            clear();
+            shown = false;
+        }
+        let elapsed = last.elapsed();
+        if elapsed < interval {
+            continue;
+        }
+        let bytes = progress.bytes_hashed();
+        let rate = ((bytes - last_bytes) as f64 / elapsed.as_secs_f64()) as u64;
+        (last, last_bytes) = (Instant::now(), bytes);
+        let Some(line) = progress_line(progress, rate) else {
+            continue;
+        };
+        let mut stderr = io::stderr().lock();
+        let _ = if terminal {
+            write!(stderr, "\r\x1b[K{}", line)
+        } else {
+            writeln!(stderr, "{}", line)
+        };
+        let _ = stderr.flush();
+        shown = true;
+    }
+    if terminal && shown {
+        clear();
+    }
+}
+
+/// Runs `run` with the progress line shown on stderr until it returns.
+pub fn with_progress_bar(
+    progress: &ProgressHandle,
+    run: impl FnOnce() -> Result<ExitCode>,
+) -> Result<ExitCode> {
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| display(progress, &done));
+        let result = run();
+        done.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_is_shown_before_the_walk_or_once_done() {
+        let progress = ProgressHandle::new();
+        assert_eq!(progress_line(&progress, 0), None);
+        progress.set_phase(Phase::Done);
+        assert_eq!(progress_line(&progress, 0), None);
+    }
+
+    #[test]
+    fn each_phase_has_its_line() {
+        let progress = ProgressHandle::new();
+        progress.add_files_scanned(12_345);
+        progress.add_queued(4_096);
+        progress.add_queued(1_000_000);
+        progress.add_files_hashed(1);
+        progress.add_bytes_hashed(4_096);
+
+        progress.set_phase(Phase::Walk);
+        assert_eq!(
+            progress_line(&progress, 2_048).unwrap(),
+            "Walking: 12,345 files, 2 queued for hashing (1,004,096 bytes), 1 hashed, \
+             2,048 bytes/s"
+        );
+        progress.set_phase(Phase::Hash);
+        assert_eq!(
+            progress_line(&progress, 2_048).unwrap(),
+            "Hashing: 1 of 2 files, 4,096 of 1,004,096 bytes, 2,048 bytes/s"
+        );
+        progress.add_groups_done(1);
+        progress.add_bytes_gained(4_096);
+        progress.set_phase(Phase::Relink);
+        assert_eq!(
+            progress_line(&progress, 0).unwrap(),
+            "Relinking: 1 groups, 4,096 bytes gained"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use filetime::FileTime;
+
+use crate::confine::{Mutation, Roots};
+
+/// Errors of FICLONE meaning that the filesystem cannot share extents between the files.
+fn unsupported(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EOPNOTSUPP | libc::EXDEV | libc::EINVAL | libc::ENOTTY)
+    )
+}
+
+/// Makes `path` share the extents of `original` while keeping its own inode, and thus its
+/// owner, mode and mtime. Returns `false` if the filesystem does not support reflinks or
+/// the duplicate may not be written.
+pub fn reflink(roots: &Roots, original: &Path, path: &Path) -> Result<bool> {
+    roots.check(Mutation::Reflink, path)?;
+    let stage = |stage: &str| {
+        format!(
+            "Failed to {} while reflinking {} to {}",
+            stage,
+            path.to_string_lossy(),
+            original.to_string_lossy(),
+        )
+    };
+
+    let source = fs::File::open(original).with_context(|| stage("open the original"))?;
+    let destination = match fs::OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => return Ok(false),
+        Err(err) => return Err(err).with_context(|| stage("open the duplicate")),
+    };
+    let metadata = destination
+        .metadata()
+        .with_context(|| stage("fs::metadata"))?;
+
+    let ret = unsafe { libc::ioctl(destination.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        if unsupported(&err) {
+            return Ok(false);
+        }
+        return Err(err).with_context(|| stage("ioctl FICLONE"));
+    }
+
+    filetime::set_file_handle_times(
+        &destination,
+        None,
+        Some(FileTime::from_last_modification_time(&metadata)),
+    )
+    .with_context(|| stage("restore the mtime"))?;
+    Ok(true)
+}
@@ -0,0 +1,1201 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use num_format::{Locale, ToFormattedString};
+use serde::{Serialize, Serializer};
+
+use crate::anchors::Anchors;
+use crate::audit::{Audit, Performed};
+use crate::cache::{CacheVerification, HashCache};
+use crate::checks::parent_dir;
+use crate::confine::{Attestation, OutsideRoots, Roots};
+use crate::cross_device::{print_cross_device, CrossDeviceContent};
+use crate::deletions::PlannedDeletion;
+use crate::digest::{HashHex, IoBudget};
+use crate::explain::Explain;
+use crate::features::FeatureStatus;
+use crate::granularity::format_granularity;
+use crate::models::{Dev, Device, Ino};
+use crate::plan_file::PlanWriter;
+use crate::profile::Profile;
+use crate::progress::ProgressHandle;
+use crate::verify::Verifications;
+use crate::Verbosity;
+
+pub fn serialize_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+pub fn serialize_paths<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(paths.iter().map(|path| path.to_string_lossy()))
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedDir {
+    #[serde(serialize_with = "serialize_path")]
+    pub first_path: PathBuf,
+    #[serde(serialize_with = "serialize_path")]
+    pub skipped_path: PathBuf,
+}
+
+/// Directories not walked because they had already been visited through another path,
+/// e.g. bind mounts. Only the first few are kept as examples.
+#[derive(Debug, Default, Serialize)]
+pub struct SkippedDirs {
+    pub count: u64,
+    pub examples: Vec<SkippedDir>,
+}
+
+impl SkippedDirs {
+    const MAX_EXAMPLES: usize = 10;
+
+    pub fn push(&mut self, first_path: &Path, skipped_path: &Path) {
+        self.count += 1;
+        if self.examples.len() < Self::MAX_EXAMPLES {
+            self.examples.push(SkippedDir {
+                first_path: first_path.to_path_buf(),
+                skipped_path: skipped_path.to_path_buf(),
+            });
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DeviceSummary {
+    pub dev: u64,
+    pub fs_type: String,
+    /// The identity of the filesystem, the same on every run unlike `dev`.
+    pub fs_id: String,
+    pub report_only: bool,
+    pub unreliable_inodes: bool,
+    /// In nanoseconds, when probed.
+    pub mtime_granularity: Option<u64>,
+    /// The maximum link count, when probed.
+    pub link_max: Option<u64>,
+    pub gain: u64,
+    pub groups_acted: u64,
+    pub errors: u64,
+    /// The gain left for later runs by `--per-dir-limit`.
+    pub deferred_gain: u64,
+}
+
+/// A group as acted upon: the paths linked, or that would be with `--dry-run`, to the original.
+#[derive(Debug, Serialize)]
+pub struct Group {
+    pub hash: HashHex,
+    pub dev: u64,
+    pub size: u64,
+    /// Bytes freed by the group, or that would be with `--dry-run`.
+    pub gain: u64,
+    #[serde(serialize_with = "serialize_path")]
+    pub original: PathBuf,
+    #[serde(serialize_with = "serialize_paths")]
+    pub linked: Vec<PathBuf>,
+    /// Duplicates that may not be linked, but share the extents of the original instead.
+    #[serde(serialize_with = "serialize_paths")]
+    pub reflinked: Vec<PathBuf>,
+    #[serde(serialize_with = "serialize_paths")]
+    pub skipped: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Original,
+    Duplicate,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Original => "original",
+            Self::Duplicate => "duplicate",
+        }
+    }
+}
+
+impl Serialize for Role {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// A file of a group, for the flat formats. The group id is derived from the size and the
+/// hash, so that exports of the same scan can be joined.
+#[derive(Debug, Serialize)]
+pub struct FlatRow {
+    pub group_id: String,
+    pub role: Role,
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub size: u64,
+    pub hash: HashHex,
+    pub inode: u64,
+    pub nlink: u64,
+}
+
+/// Quotes a CSV field as RFC 4180 has it, where needed.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct CrossLinkPair {
+    #[serde(serialize_with = "serialize_path")]
+    pub original_dir: PathBuf,
+    #[serde(serialize_with = "serialize_path")]
+    pub duplicate_dir: PathBuf,
+}
+
+/// Directories found not to be linkable to each other (EXDEV) despite sharing a device.
+/// Later duplicates in and below them are skipped without trying. Only the first few pairs
+/// are printed.
+#[derive(Debug, Default, Serialize)]
+pub struct CrossLinks {
+    pub pairs: BTreeSet<CrossLinkPair>,
+    pub skipped: u64,
+}
+
+impl CrossLinks {
+    const MAX_EXAMPLES: usize = 10;
+
+    pub fn record(&mut self, original: &Path, duplicate: &Path) {
+        self.pairs.insert(CrossLinkPair {
+            original_dir: parent_dir(original).to_path_buf(),
+            duplicate_dir: parent_dir(duplicate).to_path_buf(),
+        });
+        self.skipped += 1;
+    }
+
+    pub fn known(&self, original: &Path, duplicate: &Path) -> bool {
+        self.pairs.iter().any(|pair| {
+            original.starts_with(&pair.original_dir) && duplicate.starts_with(&pair.duplicate_dir)
+        })
+    }
+
+    fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "Warning: {} files could not be linked across filesystems (EXDEV) between:",
+            self.skipped.to_formatted_string(&Locale::en)
+        )?;
+        for pair in self.pairs.iter().take(Self::MAX_EXAMPLES) {
+            writeln!(
+                out,
+                "  {} and {}",
+                pair.original_dir.display(),
+                pair.duplicate_dir.display()
+            )?;
+        }
+        if self.pairs.len() > Self::MAX_EXAMPLES {
+            writeln!(
+                out,
+                "  ... and {} more",
+                (self.pairs.len() - Self::MAX_EXAMPLES).to_formatted_string(&Locale::en)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a file was left alone. The names, serialized as in the text report, are stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    /// A file, or a whole directory, matching `--exclude`.
+    ExcludedByPattern,
+    BelowMinSize,
+    AboveMaxSize,
+    /// An empty file, without `--dedup-empty`.
+    Empty,
+    Vanished,
+    ChangedDuringScan,
+    MetadataChanged,
+    StickyDirectory,
+    AppendOnlyDirectory,
+    OwnerMismatch,
+    CrossDevice,
+    AnchorConflict,
+    ContentMismatch,
+    IoBudgetSpent,
+    NameTooLong,
+    /// A file listed in `--manifest` that changed since, with `--manifest-trust drop`.
+    ManifestStale,
+    /// A duplicate left for a later run by `--per-dir-limit`.
+    PerDirLimit,
+    /// The original reached the link limit (EMLINK) before the filesystem said it would.
+    TooManyLinks,
+    /// A file modified or replaced between its hashing and its relink, or its original.
+    ChangedBeforeRelink,
+    /// A symlink, not followed without `--follow-symlinks`, or leading back into the
+    /// targets or into a loop with it.
+    Symlink,
+    /// A candidate left unhashed once the run was asked to stop.
+    Stopped,
+    /// A file or directory on another device than its target, with `--one-file-system`.
+    OtherFileSystem,
+    /// A temporary of the relink, left behind by a crashed run.
+    LeftoverTemporary,
+}
+
+impl SkipReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::ExcludedByPattern => "excluded-by-pattern",
+            Self::BelowMinSize => "below-min-size",
+            Self::AboveMaxSize => "above-max-size",
+            Self::Empty => "empty",
+            Self::Vanished => "vanished",
+            Self::ChangedDuringScan => "changed-during-scan",
+            Self::MetadataChanged => "metadata-changed",
+            Self::StickyDirectory => "sticky-directory",
+            Self::AppendOnlyDirectory => "append-only-directory",
+            Self::OwnerMismatch => "owner-mismatch",
+            Self::CrossDevice => "cross-device",
+            Self::AnchorConflict => "anchor-conflict",
+            Self::ContentMismatch => "content-mismatch",
+            Self::IoBudgetSpent => "io-budget-spent",
+            Self::NameTooLong => "name-too-long",
+            Self::ManifestStale => "manifest-stale",
+            Self::PerDirLimit => "per-dir-limit",
+            Self::TooManyLinks => "too-many-links",
+            Self::ChangedBeforeRelink => "changed-before-relink",
+            Self::Symlink => "symlink",
+            Self::Stopped => "stopped",
+            Self::OtherFileSystem => "other-file-system",
+            Self::LeftoverTemporary => "leftover-temporary",
+        }
+    }
+}
+
+impl Serialize for SkipReason {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Example paths kept per reason with `--list-skipped`.
+const SKIP_EXAMPLES: usize = 10;
+
+/// Skipped files by reason, with a few examples of each if asked for.
+#[derive(Debug, Default, Serialize)]
+pub struct Skips {
+    pub counts: BTreeMap<SkipReason, u64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub examples: BTreeMap<SkipReason, Vec<String>>,
+    #[serde(skip)]
+    pub list_examples: bool,
+}
+
+impl Skips {
+    fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Skipped files by reason:")?;
+        for (reason, count) in &self.counts {
+            writeln!(
+                out,
+                "  {}: {}",
+                reason.as_str(),
+                count.to_formatted_string(&Locale::en)
+            )?;
+            for path in self.examples.get(reason).into_iter().flatten() {
+                writeln!(out, "    {}", path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MovedContent {
+    #[serde(serialize_with = "serialize_paths")]
+    pub paths: Vec<PathBuf>,
+}
+
+/// Files found under only one of the targets of `against`. Only the first are listed,
+/// up to the report limit.
+#[derive(Debug, Default, Serialize)]
+pub struct OneSided {
+    pub only_in_old_count: u64,
+    #[serde(serialize_with = "serialize_paths")]
+    pub only_in_old: Vec<PathBuf>,
+    pub only_in_new_count: u64,
+    #[serde(serialize_with = "serialize_paths")]
+    pub only_in_new: Vec<PathBuf>,
+}
+
+impl OneSided {
+    fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        for (label, paths, count) in [
+            ("Only in OLD", &self.only_in_old, self.only_in_old_count),
+            ("Only in NEW", &self.only_in_new, self.only_in_new_count),
+        ] {
+            writeln!(
+                out,
+                "{}: {} files",
+                label,
+                count.to_formatted_string(&Locale::en)
+            )?;
+            for path in paths {
+                writeln!(out, "  {}", path.display())?;
+            }
+            if count > paths.len() as u64 {
+                writeln!(
+                    out,
+                    "  ... and {} more",
+                    (count - paths.len() as u64).to_formatted_string(&Locale::en)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NearDuplicateFile {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NearDuplicate {
+    pub files: Vec<NearDuplicateFile>,
+}
+
+/// Duplicates of one extension, counting the apparent size of each duplicate path.
+#[derive(Debug, Serialize)]
+pub struct ExtensionStats {
+    pub extension: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+fn print_by_extension(stats: &[ExtensionStats], out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "Duplicates by extension:")?;
+    for stats in stats {
+        writeln!(
+            out,
+            "  {:<12} {:>10} files {:>20} bytes",
+            stats.extension,
+            stats.files.to_formatted_string(&Locale::en),
+            stats.bytes.to_formatted_string(&Locale::en)
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Reading a directory or the metadata of an entry, with `--keep-going`.
+    Walk,
+    /// Hashing a file, with `--keep-going`.
+    Hash,
+    Relink,
+}
+
+impl ErrorKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Walk => "walk",
+            Self::Hash => "hash",
+            Self::Relink => "relink",
+        }
+    }
+}
+
+impl Serialize for ErrorKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// An error the run went on after, kept structured so that it can be matched to its paths.
+#[derive(Debug, Serialize)]
+pub struct ErrorRecord {
+    pub kind: ErrorKind,
+    #[serde(serialize_with = "serialize_paths")]
+    pub paths: Vec<PathBuf>,
+    /// The OS error code of the first I/O error in the chain of causes.
+    pub errno: Option<i32>,
+    pub message: String,
+}
+
+impl ErrorRecord {
+    pub fn new(kind: ErrorKind, paths: &[&Path], err: &anyhow::Error) -> Self {
+        Self {
+            kind,
+            paths: paths.iter().map(|path| path.to_path_buf()).collect(),
+            errno: err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<io::Error>())
+                .and_then(io::Error::raw_os_error),
+            message: format!("{:#}", err),
+        }
+    }
+}
+
+/// How far the size sieve got each file: the transitions of its entries during the scan,
+/// and where the inodes ended up.
+#[derive(Debug, Default, Serialize)]
+pub struct SieveStats {
+    /// Sizes seen for the first time, or again after the only file of that size vanished.
+    pub unique_set: u64,
+    /// Sizes seen a second time, when the first file of that size is hashed too.
+    pub promoted: u64,
+    /// Files of a size already seen at least twice.
+    pub joined: u64,
+    /// The same for the hashes of the prefixes of larger files of an ambiguous size.
+    pub prefix_unique_set: u64,
+    pub prefix_promoted: u64,
+    pub prefix_joined: u64,
+    pub never_hashed: u64,
+    pub hashed_unique: u64,
+    pub grouped: u64,
+}
+
+impl SieveStats {
+    /// Must be called before the unique hashes are dropped from the device.
+    pub fn add_outcomes(&mut self, device: &Device) {
+        let hashed = device
+            .inodes
+            .map
+            .values()
+            .filter(|inode| inode.hashed)
+            .count() as u64;
+        self.never_hashed += device.inodes.map.len() as u64 - hashed;
+        for identical in device.identicals.map.values() {
+            match identical.inos.len() {
+                1 => self.hashed_unique += 1,
+                n => self.grouped += n as u64,
+            }
+        }
+    }
+
+    fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        let fmt = |n: u64| n.to_formatted_string(&Locale::en);
+        writeln!(
+            out,
+            "Size sieve: {} unique sizes set, {} promoted to ambiguous, {} joined ambiguous",
+            fmt(self.unique_set),
+            fmt(self.promoted),
+            fmt(self.joined)
+        )?;
+        if self.prefix_unique_set + self.prefix_promoted + self.prefix_joined > 0 {
+            writeln!(
+                out,
+                "Prefix sieve: {} unique prefixes set, {} promoted to ambiguous, {} joined ambiguous",
+                fmt(self.prefix_unique_set),
+                fmt(self.prefix_promoted),
+                fmt(self.prefix_joined)
+            )?;
+        }
+        writeln!(
+            out,
+            "Inodes: {} never hashed, {} hashed but unique, {} in groups",
+            fmt(self.never_hashed),
+            fmt(self.hashed_unique),
+            fmt(self.grouped)
+        )?;
+        Ok(())
+    }
+}
+
+/// Duplicates were found, but every group was skipped by policy or safety checks.
+pub const EXIT_NOTHING_LINKABLE: u8 = 3;
+/// Files or groups were skipped over errors with `--keep-going`.
+pub const EXIT_ERRORS: u8 = 8;
+/// The run was asked to stop through `--control-socket` before its end.
+pub const EXIT_STOPPED: u8 = 10;
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub devices: Vec<DeviceSummary>,
+    /// The features the run relies on, as probed at startup.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<FeatureStatus>,
+    pub groups_found: u64,
+    pub groups_acted: u64,
+    /// Inodes found through several paths with no other content to link to.
+    pub already_linked: u64,
+    pub gain: u64,
+    /// Inodes whose every link has been replaced, so their blocks count towards the gain once.
+    #[serde(skip)]
+    pub merged: HashSet<(Dev, Ino)>,
+    pub hashed: u64,
+    pub sieve: SieveStats,
+    pub vanished: u64,
+    pub changed: u64,
+    /// Files found modified or replaced when about to be relinked, or linked to.
+    pub changed_before_relink: u64,
+    pub mtime_failures: u64,
+    pub errors: Vec<ErrorRecord>,
+    #[serde(skip)]
+    pub keep_going: bool,
+    /// Files and groups skipped over an error with `--keep-going`.
+    pub failures: u64,
+    /// Files left out by `--min-size` or `--max-size`.
+    pub size_filtered: u64,
+    #[serde(skip)]
+    pub io_budget: Option<IoBudget>,
+    #[serde(skip)]
+    pub hash_cache: Option<HashCache>,
+    /// Where `--save-plan` writes the groups as they are planned.
+    #[serde(skip)]
+    pub plan_writer: Option<PlanWriter>,
+    /// The `--paranoid` comparisons made ahead on worker threads, during the relink.
+    #[serde(skip)]
+    pub verifications: Option<Verifications>,
+    /// Inodes whose hash was taken from `--cache` instead of being computed.
+    pub cache_hits: u64,
+    /// Inodes not even looked up, since `--cache` knows their size bucket to be unique.
+    pub known_unique: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_verification: Option<CacheVerification>,
+    /// Candidates left unhashed once the `--io-budget` was spent, and their total size: the
+    /// potential beyond the gain of the groups evaluated.
+    pub not_evaluated: u64,
+    pub not_evaluated_bytes: u64,
+    /// Whether the run was asked to stop through `--control-socket` before its end.
+    pub stopped: bool,
+    /// Relinks per directory, for `--per-dir-limit`.
+    #[serde(skip)]
+    pub dir_relinks: HashMap<PathBuf, u64>,
+    /// Duplicates deferred by `--per-dir-limit`, and the gain of the inodes they alone keep.
+    pub deferred: u64,
+    pub deferred_gain: u64,
+    /// Lines of the `--manifest` that could not be parsed.
+    pub manifest_malformed: u64,
+    /// Files listed in the `--manifest` that changed since, scanned as they are now.
+    pub manifest_restated: u64,
+    /// Files whose mtime is ahead of the clock.
+    pub future_mtimes: u64,
+    /// Inodes with more paths than `--max-paths-per-inode`, never counted in the gain.
+    pub capped_inodes: u64,
+    pub sticky_skipped: u64,
+    /// Inodes whose ctime advanced between the scan and the relink.
+    pub ctime_skipped: u64,
+    /// Groups of files owned by different users.
+    pub cross_user_skipped: u64,
+    pub cross_user_warned: u64,
+    /// Files owned by different users that could not be reflinked either.
+    pub cross_user_unsupported: u64,
+    pub reflinked: u64,
+    pub skipped_dirs: SkippedDirs,
+    pub append_only_skipped: u64,
+    /// Temporaries of the relink left behind by a crashed run, found by the walk, with the
+    /// inode they are links to.
+    #[serde(skip)]
+    pub leftover_temporaries: Vec<(PathBuf, Dev, Ino)>,
+    /// Leftover temporaries removed at startup, being links to a file scanned under
+    /// another name.
+    pub temporaries_removed: u64,
+    /// Leftover temporaries left in place, with a warning.
+    pub temporaries_kept: u64,
+    pub cross_link: CrossLinks,
+    /// Only collected for the JSON output and `--by-extension`; the text output lists groups
+    /// as they are linked.
+    pub groups: Vec<Group>,
+    /// Only collected for the flat formats, which print nothing else.
+    #[serde(skip)]
+    pub flat: Vec<FlatRow>,
+    pub skips: Skips,
+    #[serde(skip)]
+    pub anchors: Anchors,
+    /// Inodes found by `--paranoid` to differ from their original despite the same hash.
+    pub content_mismatches: u64,
+    /// Groups of identical files split by permissions or ownership.
+    pub split_groups: u64,
+    /// Groups split between several originals to stay within the link limit.
+    pub link_max_splits: u64,
+    /// Groups skipped because more than one of their files is anchored.
+    pub anchor_conflicts: u64,
+    /// Anchors missing or in no group, which may be typos.
+    #[serde(serialize_with = "serialize_paths")]
+    pub unmatched_anchors: Vec<PathBuf>,
+    /// Groups of paths with identical content but different relative paths.
+    pub moved: Vec<MovedContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_sided: Option<OneSided>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub near_duplicates: Option<Vec<NearDuplicate>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cross_device: Option<Vec<CrossDeviceContent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by_extension: Option<Vec<ExtensionStats>>,
+    #[serde(skip)]
+    pub performed: Vec<Performed>,
+    #[serde(skip)]
+    pub planned_deletions: Vec<PlannedDeletion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit: Option<Audit>,
+    /// What the run was confined to, and how much it modified there.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Attestation>,
+    #[serde(skip)]
+    pub roots: Roots,
+    /// Symlinks followed out of the targets, and their destinations: what is found through
+    /// them is scanned but never modified.
+    #[serde(skip)]
+    pub followed_outside: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<Profile>,
+    #[serde(skip_serializing_if = "Explain::is_empty")]
+    pub explain: Explain,
+    #[serde(skip)]
+    pub progress: ProgressHandle,
+    #[serde(skip)]
+    pub verbosity: Verbosity,
+}
+
+/// The totals a dashboard needs, ahead of the details of the JSON report.
+#[derive(Debug, Serialize)]
+struct JsonSummary {
+    gain: u64,
+    files_scanned: u64,
+    files_hashed: u64,
+    errors: u64,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    summary: JsonSummary,
+    #[serde(flatten)]
+    report: &'a Report,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prints a line about a file or a group on stderr, if the verbosity is at least `level`.
+    pub fn log(&self, level: Verbosity, message: impl FnOnce() -> String) {
+        if self.verbosity >= level {
+            eprintln!("{}", message());
+        }
+    }
+
+    /// Every file left alone is counted here, on top of any counter specific to its reason,
+    /// and listed with `-v`.
+    pub fn record_skip(&mut self, reason: SkipReason, path: &Path) {
+        self.log(Verbosity::Verbose, || {
+            format!("Skipped ({}): {}", reason.as_str(), path.display())
+        });
+        *self.skips.counts.entry(reason).or_default() += 1;
+        if self.skips.list_examples {
+            let examples = self.skips.examples.entry(reason).or_default();
+            if examples.len() < SKIP_EXAMPLES {
+                examples.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    pub fn print_summary(&self, out: &mut dyn Write, verbose: u8) -> io::Result<()> {
+        // a stale cache puts every hash from it in doubt, quiet or not
+        if let Some(verification) = self.cache_verification.as_ref().filter(|v| v.stale > 0) {
+            verification.print(out)?;
+        }
+        if self.verbosity == Verbosity::Quiet {
+            return writeln!(
+                out,
+                "Gain: {} bytes",
+                self.gain.to_formatted_string(&Locale::en)
+            );
+        }
+        if verbose > 0 && self.skipped_dirs.count > 0 {
+            writeln!(
+                out,
+                "Skipped {} directories already visited via other paths:",
+                self.skipped_dirs.count.to_formatted_string(&Locale::en)
+            )?;
+            for skipped in &self.skipped_dirs.examples {
+                writeln!(
+                    out,
+                    "  {} (visited as {})",
+                    skipped.skipped_path.display(),
+                    skipped.first_path.display()
+                )?;
+            }
+        }
+        if !self.moved.is_empty() {
+            writeln!(out, "Moved or renamed content:")?;
+            for moved in &self.moved {
+                for path in &moved.paths {
+                    writeln!(out, "  {}", path.display())?;
+                }
+                writeln!(out)?;
+            }
+        }
+        if let Some(one_sided) = &self.one_sided {
+            one_sided.print(out)?;
+        }
+        if let Some(near_duplicates) = &self.near_duplicates {
+            writeln!(out, "Near-duplicates:")?;
+            for near_duplicate in near_duplicates {
+                for file in &near_duplicate.files {
+                    writeln!(
+                        out,
+                        "  {:>15} {}",
+                        file.size.to_formatted_string(&Locale::en),
+                        file.path.display(),
+                    )?;
+                }
+                writeln!(out)?;
+            }
+        }
+        if let Some(cross_device) = &self.cross_device {
+            print_cross_device(cross_device, out)?;
+        }
+        if let Some(by_extension) = &self.by_extension {
+            print_by_extension(by_extension, out)?;
+        }
+        if let Some(audit) = &self.audit {
+            audit.print(out)?;
+        }
+        if let Some(attestation) = self.attestation.as_ref().filter(|_| verbose > 0) {
+            attestation.print(out)?;
+        }
+        if let Some(profile) = &self.profile {
+            profile.print(out)?;
+        }
+        if verbose > 0 {
+            self.sieve.print(out)?;
+        }
+        if (verbose > 0 || self.skips.list_examples) && !self.skips.counts.is_empty() {
+            self.skips.print(out)?;
+        }
+        self.explain.print(out)?;
+        for device in &self.devices {
+            if verbose > 0 || device.report_only {
+                writeln!(
+                    out,
+                    "Device {} ({}){}{}{}{}",
+                    device.dev,
+                    device.fs_type,
+                    if device.report_only {
+                        ": report-only"
+                    } else {
+                        ""
+                    },
+                    if device.unreliable_inodes {
+                        ", unreliable inode numbers"
+                    } else {
+                        ""
+                    },
+                    match device.mtime_granularity {
+                        Some(granularity) if verbose > 0 => {
+                            format!(", mtime granularity {}", format_granularity(granularity))
+                        }
+                        _ => String::new(),
+                    },
+                    match device.link_max {
+                        Some(link_max) if verbose > 0 => {
+                            format!(", {} links", link_max.to_formatted_string(&Locale::en))
+                        }
+                        _ => String::new(),
+                    },
+                )?;
+            }
+        }
+        if self.groups_found == 0 {
+            writeln!(out, "No duplicates found")?;
+        } else if self.groups_acted == 0 {
+            writeln!(
+                out,
+                "Found {} duplicate groups, but none could be linked",
+                self.groups_found.to_formatted_string(&Locale::en)
+            )?;
+        } else {
+            writeln!(
+                out,
+                "Deduplicated {} of {} duplicate groups",
+                self.groups_acted.to_formatted_string(&Locale::en),
+                self.groups_found.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.already_linked > 0 {
+            writeln!(
+                out,
+                "Already deduplicated: {} files",
+                self.already_linked.to_formatted_string(&Locale::en)
+            )?;
+        }
+        writeln!(
+            out,
+            "Gain: {} bytes",
+            self.gain.to_formatted_string(&Locale::en)
+        )?;
+        writeln!(
+            out,
+            "Hashed: {} files",
+            self.hashed.to_formatted_string(&Locale::en)
+        )?;
+        if self.cache_hits > 0 {
+            writeln!(
+                out,
+                "Hashes from the cache: {} files",
+                self.cache_hits.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.known_unique > 0 {
+            writeln!(
+                out,
+                "Known unique from the cache, not hashed: {} files",
+                self.known_unique.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if let Some(verification) = self.cache_verification.as_ref().filter(|v| v.stale == 0) {
+            verification.print(out)?;
+        }
+        if self.vanished > 0 {
+            writeln!(
+                out,
+                "Vanished: {} files",
+                self.vanished.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.capped_inodes > 0 {
+            writeln!(
+                out,
+                "Inodes with more paths than stored: {} (not counted in the gain)",
+                self.capped_inodes.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.mtime_failures > 0 {
+            writeln!(
+                out,
+                "Failed to set mtimes: {}",
+                self.mtime_failures.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.failures > 0 {
+            writeln!(
+                out,
+                "Skipped over errors: {}",
+                self.failures.to_formatted_string(&Locale::en)
+            )?;
+            for error in &self.errors {
+                writeln!(out, "  {}: {}", error.kind.as_str(), error.message)?;
+            }
+        }
+        if self.deferred > 0 {
+            writeln!(
+                out,
+                "Deferred by --per-dir-limit: {} files, {} bytes",
+                self.deferred.to_formatted_string(&Locale::en),
+                self.deferred_gain.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.manifest_malformed > 0 || self.manifest_restated > 0 {
+            writeln!(
+                out,
+                "Manifest: {} malformed lines, {} files changed since",
+                self.manifest_malformed.to_formatted_string(&Locale::en),
+                self.manifest_restated.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.size_filtered > 0 {
+            writeln!(
+                out,
+                "Outside the size limits: {} files",
+                self.size_filtered.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.not_evaluated > 0 {
+            writeln!(
+                out,
+                "Not evaluated once the --io-budget was spent: {} files, {} bytes",
+                self.not_evaluated.to_formatted_string(&Locale::en),
+                self.not_evaluated_bytes.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.stopped {
+            writeln!(
+                out,
+                "Stopped through the control socket: the run is incomplete"
+            )?;
+        }
+        if self.future_mtimes > 0 {
+            writeln!(
+                out,
+                "Mtimes in the future: {} files",
+                self.future_mtimes.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.changed > 0 {
+            writeln!(
+                out,
+                "Changed during the scan: {} files",
+                self.changed.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.changed_before_relink > 0 {
+            writeln!(
+                out,
+                "Changed between hashing and relinking: {} files",
+                self.changed_before_relink.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.cross_user_skipped > 0 {
+            writeln!(
+                out,
+                "Skipped groups owned by different users: {}",
+                self.cross_user_skipped.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.cross_user_warned > 0 {
+            writeln!(
+                out,
+                "Linked groups owned by different users: {}",
+                self.cross_user_warned.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.reflinked > 0 {
+            writeln!(
+                out,
+                "Reflinked: {} files",
+                self.reflinked.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.cross_user_unsupported > 0 {
+            writeln!(
+                out,
+                "Skipped files owned by different users, not reflinkable: {}",
+                self.cross_user_unsupported.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.ctime_skipped > 0 {
+            writeln!(
+                out,
+                "Skipped with metadata changed since the scan: {} inodes",
+                self.ctime_skipped.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.cross_link.skipped > 0 {
+            self.cross_link.print(out)?;
+        }
+        if self.sticky_skipped > 0 {
+            writeln!(
+                out,
+                "Skipped in sticky directories: {} files",
+                self.sticky_skipped.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.append_only_skipped > 0 {
+            writeln!(
+                out,
+                "Skipped in append-only directories: {} files",
+                self.append_only_skipped.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.temporaries_removed > 0 {
+            writeln!(
+                out,
+                "Removed temporaries left by a crashed run: {} files",
+                self.temporaries_removed.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.temporaries_kept > 0 {
+            writeln!(
+                out,
+                "Warning: left in place files named like temporaries of a crashed run: {} files",
+                self.temporaries_kept.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.content_mismatches > 0 {
+            writeln!(
+                out,
+                "Warning: differing content despite the same hash: {} inodes",
+                self.content_mismatches.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.split_groups > 0 {
+            writeln!(
+                out,
+                "Split by permissions or ownership: {} groups",
+                self.split_groups.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.link_max_splits > 0 {
+            writeln!(
+                out,
+                "Split between several originals to stay within the link limit: {} groups",
+                self.link_max_splits.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if self.anchor_conflicts > 0 {
+            writeln!(
+                out,
+                "Skipped groups with several anchors: {}",
+                self.anchor_conflicts.to_formatted_string(&Locale::en)
+            )?;
+        }
+        if !self.unmatched_anchors.is_empty() {
+            writeln!(out, "Anchors in no group:")?;
+            for path in &self.unmatched_anchors {
+                writeln!(out, "  {}", path.display())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records an error to go on after with `--keep-going`, which skips the file or group
+    /// at `path`, or returns it otherwise.
+    pub fn keep_going(
+        &mut self,
+        kind: ErrorKind,
+        path: &Path,
+        err: anyhow::Error,
+    ) -> anyhow::Result<()> {
+        if !self.keep_going || err.is::<OutsideRoots>() {
+            return Err(err);
+        }
+        eprintln!("Error: {:#}; skipped", err);
+        self.errors.push(ErrorRecord::new(kind, &[path], &err));
+        self.progress.add_errors(1);
+        self.failures += 1;
+        Ok(())
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        if self.failures > 0 {
+            EXIT_ERRORS
+        } else if self.stopped {
+            EXIT_STOPPED
+        } else if self.groups_found > 0 && self.groups_acted == 0 {
+            EXIT_NOTHING_LINKABLE
+        } else {
+            0
+        }
+    }
+
+    /// Paths that are not valid UTF-8 are converted lossily.
+    pub fn print_flat_csv(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "group_id,role,path,size,hash,inode,nlink")?;
+        for row in &self.flat {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                csv_field(&row.group_id),
+                row.role.as_str(),
+                csv_field(&row.path.to_string_lossy()),
+                row.size,
+                row.hash,
+                row.inode,
+                row.nlink
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn print_json(&self, out: &mut dyn Write) -> anyhow::Result<()> {
+        let report = JsonReport {
+            summary: JsonSummary {
+                gain: self.gain,
+                files_scanned: self.progress.files_scanned(),
+                files_hashed: self.hashed,
+                errors: self.errors.len() as u64,
+            },
+            report: self,
+        };
+        writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser as _;
+
+    use super::*;
+    use crate::test_utils::{before_hash, TreeBuilder};
+    use crate::{scan_targets, Args};
+
+    /// The sieve counters of a single-threaded scan of `tree`.
+    fn sieve(tree: &TreeBuilder) -> SieveStats {
+        let args = Args::parse_from([
+            "dedup".as_ref(),
+            "--threads".as_ref(),
+            "1".as_ref(),
+            tree.root().as_os_str(),
+        ]);
+        let mut report = Report::new();
+        scan_targets(&args, &HashSet::new(), &mut report).unwrap();
+        report.sieve
+    }
+
+    #[test]
+    fn sieve_counts_each_transition_of_a_size() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.sized("one", 1, b'a')
+            .unwrap()
+            .sized("two/a", 2, b'a')
+            .unwrap()
+            .sized("two/b", 2, b'a')
+            .unwrap()
+            .sized("two/c", 2, b'b')
+            .unwrap()
+            .sized("two/d", 2, b'a')
+            .unwrap()
+            .sized("three", 3, b'a')
+            .unwrap()
+            // another path to a scanned inode is no new file for the sieve
+            .link("two/a", "link")
+            .unwrap();
+        let stats = sieve(&tree);
+        assert_eq!(
+            (stats.unique_set, stats.promoted, stats.joined),
+            (3, 1, 2),
+            "{stats:?}"
+        );
+        assert_eq!(
+            (stats.never_hashed, stats.hashed_unique, stats.grouped),
+            (2, 1, 3),
+            "{stats:?}"
+        );
+        assert_eq!(
+            (
+                stats.prefix_unique_set,
+                stats.prefix_promoted,
+                stats.prefix_joined
+            ),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn a_vanished_first_file_hands_the_unique_size_over() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.sized("a", 4, b'a')
+            .unwrap()
+            .sized("b", 4, b'a')
+            .unwrap();
+        let root = tree.root().to_path_buf();
+        // the first file of the size is hashed once the second is found, and is gone by then:
+        // the second is left unhashed as the only file of its size
+        let _hook = before_hash(move |path| {
+            if path.starts_with(&root) {
+                fs::remove_file(path)?;
+            }
+            Ok(())
+        });
+        let stats = sieve(&tree);
+        assert_eq!(
+            (stats.unique_set, stats.promoted, stats.joined),
+            (2, 1, 0),
+            "{stats:?}"
+        );
+        assert_eq!(
+            (stats.never_hashed, stats.hashed_unique, stats.grouped),
+            (1, 0, 0),
+            "{stats:?}"
+        );
+    }
+}
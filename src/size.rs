@@ -0,0 +1,46 @@
+/// Parses a number of bytes with an optional binary suffix: `4k`, `1M`, `2GiB` or `512`.
+/// Suffixes are case-insensitive and `k` stands for 1024 bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = s[digits.len()..].to_ascii_lowercase();
+    let number: u64 = digits.parse().map_err(|_| format!("invalid size: {}", s))?;
+    let shift = match suffix.trim_end_matches('b').trim_end_matches('i') {
+        "" => 0,
+        "k" => 10,
+        "m" => 20,
+        "g" => 30,
+        "t" => 40,
+        _ => return Err(format!("unknown unit in {}, expected k, M, G or T", s)),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size too large: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffixes_are_binary_and_case_insensitive() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4k"), Ok(4 << 10));
+        assert_eq!(parse_size("4K"), Ok(4 << 10));
+        assert_eq!(parse_size("1M"), Ok(1 << 20));
+        assert_eq!(parse_size("1mb"), Ok(1 << 20));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("3t"), Ok(3 << 40));
+    }
+
+    #[test]
+    fn overflows_and_bad_suffixes_are_rejected() {
+        assert_eq!(parse_size("16777215T"), Ok(16777215 << 40));
+        assert!(parse_size("16777216T").unwrap_err().contains("too large"));
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("4x").unwrap_err().contains("unknown unit"));
+        assert!(parse_size("4kk").unwrap_err().contains("unknown unit"));
+        assert!(parse_size("k").is_err());
+        assert!(parse_size("").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+}
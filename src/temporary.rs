@@ -0,0 +1,94 @@
+//! The temporary names the atomic relink links the original under before renaming it over
+//! the duplicate: `PREFIX`, the pid of the run, `-` and 16 random hex digits.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::rng::Rng;
+
+/// What the temporary names begin with, unless `--tmp-prefix` says otherwise.
+pub const DEFAULT_TMP_PREFIX: &str = ".dedup-tmp-";
+
+/// Digits of the random part of a temporary name.
+const RANDOM_DIGITS: usize = 16;
+
+/// Parses `--tmp-prefix`, which must stay within the name of an entry.
+pub fn parse_tmp_prefix(s: &str) -> Result<String, String> {
+    if s.is_empty() {
+        return Err("the prefix may not be empty".to_string());
+    }
+    if s.contains(['/', '\0']) {
+        return Err(format!("the prefix may not contain / or NUL: {:?}", s));
+    }
+    Ok(s.to_string())
+}
+
+/// A temporary name of the run, of a fixed length, so that it fits wherever the name of
+/// the duplicate does unless the prefix is longer than the default.
+pub fn temporary_name(prefix: &str, rng: &mut Rng) -> String {
+    format!(
+        "{}{}-{:0width$x}",
+        prefix,
+        std::process::id(),
+        rng.next_u64(),
+        width = RANDOM_DIGITS
+    )
+}
+
+/// Whether `name` is exactly a temporary name with `prefix`, of this run or of another one
+/// that crashed before renaming it over a duplicate.
+pub fn is_temporary_name(name: &OsStr, prefix: &str) -> bool {
+    let Some(rest) = name.as_bytes().strip_prefix(prefix.as_bytes()) else {
+        return false;
+    };
+    let Some(dash) = rest.iter().position(|&b| b == b'-') else {
+        return false;
+    };
+    let (pid, random) = (&rest[..dash], &rest[dash + 1..]);
+    !pid.is_empty()
+        && pid.iter().all(u8::is_ascii_digit)
+        && random.len() == RANDOM_DIGITS
+        && random
+            .iter()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_recognized_exactly() {
+        let mut rng = Rng::new(1);
+        let name = temporary_name(".x-", &mut rng);
+        assert!(is_temporary_name(OsStr::new(&name), ".x-"));
+        assert!(!is_temporary_name(OsStr::new(&name), DEFAULT_TMP_PREFIX));
+        for name in [
+            ".dedup-tmp-12-0123456789abcdef",
+            ".dedup-tmp-1-ffffffffffffffff",
+        ] {
+            assert!(is_temporary_name(OsStr::new(name), DEFAULT_TMP_PREFIX));
+        }
+        for name in [
+            ".dedup-tmp-12-0123456789abcde",
+            ".dedup-tmp-12-0123456789abcdef0",
+            ".dedup-tmp--0123456789abcdef",
+            ".dedup-tmp-12-0123456789ABCDEF",
+            ".dedup-tmp-1x-0123456789abcdef",
+            "a.dedup-tmp-12-0123456789abcdef",
+            ".report.json.dedup-tmp-12",
+        ] {
+            assert!(
+                !is_temporary_name(OsStr::new(name), DEFAULT_TMP_PREFIX),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn prefix_stays_within_the_name() {
+        assert!(parse_tmp_prefix("tmp.").is_ok());
+        assert!(parse_tmp_prefix("").is_err());
+        assert!(parse_tmp_prefix("a/b").is_err());
+    }
+}
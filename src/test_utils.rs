@@ -0,0 +1,194 @@
+//! Helpers for tests of tools built on this crate: declarative fixture trees and
+//! assertions on inode sharing. Enabled by the `test-utils` feature, which is exempt from
+//! semver guarantees.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use filetime::FileTime;
+
+pub use crate::fixture::{FixtureSpec, FixtureSummary};
+
+static NEXT_TREE: AtomicU64 = AtomicU64::new(0);
+
+type Hook = Box<dyn Fn(&Path) -> io::Result<()> + Send + Sync>;
+
+/// Where the run calls the hooks of tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    /// Before a file is hashed.
+    Hash,
+    /// Before the temporary link to an original is renamed over a duplicate.
+    Rename,
+    /// Before a group is relinked, once the whole scan is done.
+    Relink,
+}
+
+static NEXT_HOOK: AtomicU64 = AtomicU64::new(0);
+static HOOKS: Mutex<Vec<(u64, Stage, Hook)>> = Mutex::new(Vec::new());
+
+/// A tree of files in a fresh directory under the temporary directory, removed on drop.
+/// Paths given to its methods are relative to the root.
+pub struct TreeBuilder {
+    root: PathBuf,
+}
+
+impl TreeBuilder {
+    pub fn new() -> io::Result<Self> {
+        let root = std::env::temp_dir().join(format!(
+            "dedup-test-{}-{}",
+            process::id(),
+            NEXT_TREE.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Creates a file, along with its parent directories.
+    pub fn file(
+        &mut self,
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> io::Result<&mut Self> {
+        let path = self.path(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+        Ok(self)
+    }
+
+    /// Creates a file of `size` bytes, all `byte`.
+    pub fn sized(
+        &mut self,
+        path: impl AsRef<Path>,
+        size: usize,
+        byte: u8,
+    ) -> io::Result<&mut Self> {
+        self.file(path, vec![byte; size])
+    }
+
+    /// Creates `path` as a hard link to the existing `existing`.
+    pub fn link(
+        &mut self,
+        existing: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+    ) -> io::Result<&mut Self> {
+        let path = self.path(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::hard_link(self.path(existing), path)?;
+        Ok(self)
+    }
+
+    /// Generates the tree of a `dedup gen-fixture` spec in the root, which must be empty.
+    pub fn generate(&mut self, spec: &str) -> anyhow::Result<FixtureSummary> {
+        crate::fixture::generate_fixture(&FixtureSpec::parse(spec)?, &self.root)
+    }
+
+    /// Sets the mtime of a file, in seconds since the epoch.
+    pub fn mtime(&mut self, path: impl AsRef<Path>, seconds: i64) -> io::Result<&mut Self> {
+        filetime::set_file_mtime(self.path(path), FileTime::from_unix_time(seconds, 0))?;
+        Ok(self)
+    }
+}
+
+impl Drop for TreeBuilder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn identity(path: &Path) -> (u64, u64) {
+    let metadata = fs::symlink_metadata(path)
+        .unwrap_or_else(|err| panic!("Failed to stat {}: {}", path.display(), err));
+    (metadata.dev(), metadata.ino())
+}
+
+/// Panics unless both paths are links to the same inode.
+pub fn assert_linked(a: impl AsRef<Path>, b: impl AsRef<Path>) {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    assert_eq!(
+        identity(a),
+        identity(b),
+        "{} and {} are not linked",
+        a.display(),
+        b.display()
+    );
+}
+
+/// Panics if both paths are links to the same inode.
+pub fn assert_not_linked(a: impl AsRef<Path>, b: impl AsRef<Path>) {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    assert_ne!(
+        identity(a),
+        identity(b),
+        "{} and {} are linked",
+        a.display(),
+        b.display()
+    );
+}
+
+/// Removes its hook from those run when dropped.
+pub struct HookGuard {
+    id: u64,
+}
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        let mut hooks = HOOKS.lock().unwrap_or_else(|err| err.into_inner());
+        hooks.retain(|(id, _, _)| *id != self.id);
+    }
+}
+
+fn add_hook(stage: Stage, hook: Hook) -> HookGuard {
+    let id = NEXT_HOOK.fetch_add(1, Ordering::Relaxed);
+    let mut hooks = HOOKS.lock().unwrap_or_else(|err| err.into_inner());
+    hooks.push((id, stage, hook));
+    HookGuard { id }
+}
+
+/// Runs `hook` on every file about to be hashed, by any run of the process, until the guard
+/// is dropped: for tests of files changing between the walk and their hashing. An error
+/// fails the hash with it, as if the file could not be read. The hooks of tests running
+/// alongside are all run, so each should only touch its own tree.
+pub fn before_hash(hook: impl Fn(&Path) -> io::Result<()> + Send + Sync + 'static) -> HookGuard {
+    add_hook(Stage::Hash, Box::new(hook))
+}
+
+/// Runs `hook` on every duplicate about to be replaced, once the temporary link to its
+/// original is made, until the guard is dropped. An error fails the rename with it, as if
+/// the filesystem returned it.
+pub fn before_rename(hook: impl Fn(&Path) -> io::Result<()> + Send + Sync + 'static) -> HookGuard {
+    add_hook(Stage::Rename, Box::new(hook))
+}
+
+/// Runs `hook` on the original of every group about to be relinked, until the guard is
+/// dropped: for tests of files changing between the scan and the relink. An error fails
+/// the group with it.
+pub fn before_relink(hook: impl Fn(&Path) -> io::Result<()> + Send + Sync + 'static) -> HookGuard {
+    add_hook(Stage::Relink, Box::new(hook))
+}
+
+/// Runs the hooks of `stage` on `path`, up to the first error.
+pub(crate) fn run_hooks(stage: Stage, path: &Path) -> io::Result<()> {
+    let hooks = HOOKS.lock().unwrap_or_else(|err| err.into_inner());
+    hooks
+        .iter()
+        .filter(|(_, at, _)| *at == stage)
+        .try_for_each(|(_, _, hook)| hook(path))
+}
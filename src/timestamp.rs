@@ -0,0 +1,90 @@
+use filetime::FileTime;
+
+const SECS_PER_DAY: i64 = 86400;
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Parses `YYYY-MM-DD` (UTC midnight), `@SECONDS` since the epoch, or an age such as `300d`
+/// (with `s`, `m`, `h` or `d`) counted back from now.
+pub fn parse_timestamp(s: &str) -> Result<FileTime, String> {
+    if let Some(seconds) = s.strip_prefix('@') {
+        let seconds = seconds
+            .parse()
+            .map_err(|_| format!("invalid number of seconds: {}", seconds))?;
+        return Ok(FileTime::from_unix_time(seconds, 0));
+    }
+    if let Some(unit) = s.chars().last().filter(char::is_ascii_alphabetic) {
+        let count: i64 = s[..s.len() - 1]
+            .parse()
+            .map_err(|_| format!("invalid age: {}", s))?;
+        let unit = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => SECS_PER_DAY,
+            _ => return Err(format!("unknown unit in {}, expected s, m, h or d", s)),
+        };
+        let now = FileTime::now();
+        return Ok(FileTime::from_unix_time(
+            now.unix_seconds() - count * unit,
+            0,
+        ));
+    }
+    let parts: Vec<_> = s.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return Err(format!(
+            "expected YYYY-MM-DD, @SECONDS or an age like 300d: {}",
+            s
+        ));
+    };
+    let parse = |part: &str| {
+        part.parse::<i64>()
+            .map_err(|_| format!("invalid date: {}", s))
+    };
+    let (year, month, day) = (parse(year)?, parse(month)?, parse(day)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(format!("invalid date: {}", s));
+    }
+    Ok(FileTime::from_unix_time(
+        days_from_civil(year, month, day) * SECS_PER_DAY,
+        0,
+    ))
+}
+
+/// Formats a time as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_timestamp(time: FileTime) -> String {
+    let seconds = time.unix_seconds();
+    let (year, month, day) = civil_from_days(seconds.div_euclid(SECS_PER_DAY));
+    let of_day = seconds.rem_euclid(SECS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60
+    )
+}
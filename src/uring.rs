@@ -0,0 +1,505 @@
+//! `--uring-stat`: the entries of each directory that the walk goes into are stat'ed in
+//! batches of statx operations submitted to an io_uring, a few syscalls per batch instead
+//! of one per entry. The walk takes the results as it reaches the entries, and stats
+//! itself whatever a batch did not cover, like entries added in between.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use filetime::FileTime;
+
+use crate::models::Stat;
+
+/// The number of statx operations in flight at once, which is also the batch size.
+const QUEUE_DEPTH: u32 = 256;
+
+const IORING_OP_STATX: u8 = 21;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x800_0000;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_REGISTER_PROBE: u32 = 8;
+const IO_URING_OP_SUPPORTED: u16 = 1;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// A submission queue entry, laid out for a statx: `off` holds the buffer and `len` the
+/// mask.
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    statx_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A region shared with the kernel, unmapped on drop.
+struct Mmap {
+    addr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Self> {
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { addr, len })
+    }
+
+    /// The field of the ring at `offset`, as the kernel updates it concurrently.
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*(self.addr.add(offset as usize) as *const AtomicU32) }
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.addr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.addr, self.len) };
+    }
+}
+
+/// An io_uring used only for batches of statx, one batch at a time.
+struct StatRing {
+    fd: OwnedFd,
+    params: Params,
+    sq: Mmap,
+    cq: Mmap,
+    sqes: Mmap,
+}
+
+impl StatRing {
+    /// Sets up the ring, if the kernel allows io_uring and supports statx through it.
+    fn new() -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                QUEUE_DEPTH,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        Self::probe_statx(fd.as_raw_fd())?;
+        let sq = Mmap::new(
+            fd.as_raw_fd(),
+            params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>(),
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq = Mmap::new(
+            fd.as_raw_fd(),
+            params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>(),
+            IORING_OFF_CQ_RING,
+        )?;
+        let sqes = Mmap::new(
+            fd.as_raw_fd(),
+            params.sq_entries as usize * mem::size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+        Ok(Self {
+            fd,
+            params,
+            sq,
+            cq,
+            sqes,
+        })
+    }
+
+    /// Fails unless the kernel supports statx operations, which came after io_uring itself.
+    fn probe_statx(fd: RawFd) -> io::Result<()> {
+        // a header of 16 bytes, then 256 operations of 8 bytes: op, resv, flags, resv2
+        let mut probe = [0u8; 16 + 256 * 8];
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                fd,
+                IORING_REGISTER_PROBE,
+                probe.as_mut_ptr(),
+                256,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let last_op = probe[0];
+        let op = 16 + IORING_OP_STATX as usize * 8;
+        let flags = u16::from_ne_bytes([probe[op + 2], probe[op + 3]]);
+        if last_op < IORING_OP_STATX || flags & IO_URING_OP_SUPPORTED == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no statx operation in io_uring",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Stats `names`, relative to `dir`, no more than [`QUEUE_DEPTH`] of them. An error of
+    /// the whole batch means that the ring is unusable.
+    fn statx(
+        &mut self,
+        dir: RawFd,
+        names: Vec<CString>,
+        flags: i32,
+    ) -> io::Result<Vec<io::Result<Stat>>> {
+        let count = names.len() as u32;
+        assert!(count <= self.params.sq_entries);
+        let mut buffers: Vec<libc::statx> = vec![unsafe { mem::zeroed() }; names.len()];
+
+        let sq_off = &self.params.sq_off;
+        let mask = unsafe { *self.sq.at::<u32>(sq_off.ring_mask) };
+        let tail = self.sq.atomic(sq_off.tail).load(Ordering::Relaxed);
+        for (index, (name, buffer)) in names.iter().zip(&mut buffers).enumerate() {
+            let slot = tail.wrapping_add(index as u32) & mask;
+            let sqe = Sqe {
+                opcode: IORING_OP_STATX,
+                fd: dir,
+                off: buffer as *mut libc::statx as u64,
+                addr: name.as_ptr() as u64,
+                len: libc::STATX_BASIC_STATS,
+                statx_flags: flags as u32,
+                user_data: index as u64,
+                ..Default::default()
+            };
+            unsafe {
+                ptr::write(self.sqes.at::<Sqe>(0).add(slot as usize), sqe);
+                ptr::write(self.sq.at::<u32>(sq_off.array).add(slot as usize), slot);
+            }
+        }
+        self.sq
+            .atomic(sq_off.tail)
+            .store(tail.wrapping_add(count), Ordering::Release);
+
+        let mut results: Vec<Option<io::Result<libc::statx>>> =
+            (0..names.len()).map(|_| None).collect();
+        let (mut submitted, mut completed) = (0, 0);
+        while completed < count {
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd(),
+                    count - submitted,
+                    count - completed,
+                    IORING_ENTER_GETEVENTS,
+                    ptr::null::<libc::sigset_t>(),
+                    0,
+                )
+            };
+            if ret < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                // operations may still be in flight: their buffers must outlive them
+                mem::forget(names);
+                mem::forget(buffers);
+                return Err(err);
+            }
+            submitted += ret as u32;
+
+            let cq_off = &self.params.cq_off;
+            let mask = unsafe { *self.cq.at::<u32>(cq_off.ring_mask) };
+            let mut head = self.cq.atomic(cq_off.head).load(Ordering::Relaxed);
+            let tail = self.cq.atomic(cq_off.tail).load(Ordering::Acquire);
+            while head != tail {
+                let cqe = unsafe { &*self.cq.at::<Cqe>(cq_off.cqes).add((head & mask) as usize) };
+                let index = cqe.user_data as usize;
+                results[index] = Some(if cqe.res < 0 {
+                    Err(io::Error::from_raw_os_error(-cqe.res))
+                } else {
+                    Ok(buffers[index])
+                });
+                head = head.wrapping_add(1);
+                completed += 1;
+            }
+            self.cq.atomic(cq_off.head).store(head, Ordering::Release);
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| result.unwrap().map(|buffer| stat_from_statx(&buffer)))
+            .collect())
+    }
+}
+
+fn stat_from_statx(buffer: &libc::statx) -> Stat {
+    Stat {
+        dev: libc::makedev(buffer.stx_dev_major, buffer.stx_dev_minor),
+        ino: buffer.stx_ino,
+        mode: buffer.stx_mode as u32,
+        nlink: buffer.stx_nlink as u64,
+        uid: buffer.stx_uid,
+        gid: buffer.stx_gid,
+        size: buffer.stx_size,
+        blocks: buffer.stx_blocks,
+        mtime: FileTime::from_unix_time(buffer.stx_mtime.tv_sec, buffer.stx_mtime.tv_nsec),
+        ctime: FileTime::from_unix_time(buffer.stx_ctime.tv_sec, buffer.stx_ctime.tv_nsec),
+    }
+}
+
+/// A directory that the walk went into, whose entries are stat'ed one batch ahead.
+struct PendingDir {
+    dir: fs::File,
+    entries: fs::ReadDir,
+    /// The results of the last batch, by name.
+    batch: HashMap<OsString, io::Result<Stat>>,
+}
+
+/// The stats of the entries of the directories under walk, taken by path.
+pub struct StatBatches {
+    /// Dropped once it fails, leaving every entry to the walk.
+    ring: Option<StatRing>,
+    follow_links: bool,
+    pending: HashMap<PathBuf, PendingDir>,
+}
+
+impl StatBatches {
+    /// Fails if io_uring or its statx is not available, e.g. on kernels older than 5.6.
+    pub fn new(follow_links: bool) -> io::Result<Self> {
+        Ok(Self {
+            ring: Some(StatRing::new()?),
+            follow_links,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Stats the entries of `dir` in batches from now on, as the walk goes into it. If the
+    /// directory cannot be read, its entries are left for the walk to stat, or fail on.
+    pub fn enter(&mut self, dir: &Path) {
+        if self.ring.is_none() {
+            return;
+        }
+        let (Ok(file), Ok(entries)) = (fs::File::open(dir), fs::read_dir(dir)) else {
+            return;
+        };
+        self.pending.insert(
+            dir.to_path_buf(),
+            PendingDir {
+                dir: file,
+                entries,
+                batch: HashMap::new(),
+            },
+        );
+    }
+
+    /// The stat of `path`, from the batch of its directory, which the next batch follows
+    /// once it is used up. `None` for anything not covered by the batches.
+    pub fn take(&mut self, path: &Path) -> Option<io::Result<Stat>> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return None;
+        };
+        let pending = self.pending.get_mut(dir)?;
+        if let Some(stat) = pending.batch.remove(name) {
+            return Some(stat);
+        }
+        // the walk is past the batch: whatever it did not take was left out
+        let names: Vec<OsString> = pending
+            .entries
+            .by_ref()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name())
+            .take(QUEUE_DEPTH as usize)
+            .collect();
+        if names.is_empty() {
+            self.pending.remove(dir);
+            return None;
+        }
+        let cnames = names
+            .iter()
+            .map(|name| CString::new(name.clone().into_vec()).unwrap())
+            .collect();
+        let flags = libc::AT_STATX_SYNC_AS_STAT
+            | if self.follow_links {
+                0
+            } else {
+                libc::AT_SYMLINK_NOFOLLOW
+            };
+        let ring = self.ring.as_mut()?;
+        let stats = match ring.statx(pending.dir.as_raw_fd(), cnames, flags) {
+            Ok(stats) => stats,
+            Err(err) => {
+                eprintln!(
+                    "Warning: --uring-stat: the ring failed ({}); each entry is stat'ed on its own from now on",
+                    err
+                );
+                self.ring = None;
+                self.pending.clear();
+                return None;
+            }
+        };
+        pending.batch = names.into_iter().zip(stats).collect();
+        pending.batch.remove(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    use crate::test_utils::TreeBuilder;
+
+    /// What the scan relies on, to compare with the stat of the walk.
+    fn key(stat: &Stat) -> (u64, u64, u32, u64, u32, u32, u64, u64, FileTime, FileTime) {
+        (
+            stat.dev,
+            stat.ino,
+            stat.mode,
+            stat.nlink,
+            stat.uid,
+            stat.gid,
+            stat.size,
+            stat.blocks,
+            stat.mtime,
+            stat.ctime,
+        )
+    }
+
+    fn batches(follow_links: bool) -> Option<StatBatches> {
+        match StatBatches::new(follow_links) {
+            Ok(batches) => Some(batches),
+            Err(err) => {
+                eprintln!("skipped: io_uring is not available: {}", err);
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn batches_give_the_stat_of_each_entry() {
+        let mut tree = TreeBuilder::new().unwrap();
+        // several batches
+        let count = QUEUE_DEPTH as usize * 2 + 10;
+        for i in 0..count {
+            tree.sized(format!("d/{i}"), i, b'x').unwrap();
+        }
+        tree.link("d/0", "d/linked").unwrap();
+        symlink("1", tree.path("d/symlink")).unwrap();
+        let Some(mut batches) = batches(false) else {
+            return;
+        };
+        let dir = tree.path("d");
+        batches.enter(&dir);
+        // in the order of the directory, as the walk takes them
+        let paths: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        for path in &paths {
+            let expected = Stat::from(&fs::symlink_metadata(path).unwrap());
+            let stat = batches.take(path).unwrap().unwrap();
+            assert_eq!(key(&stat), key(&expected), "{}", path.display());
+        }
+        assert_eq!(paths.len(), count + 2);
+    }
+
+    #[test]
+    fn an_error_goes_with_its_entry() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("d/a", "a").unwrap();
+        symlink("loop", tree.path("d/loop")).unwrap();
+        let Some(mut batches) = batches(true) else {
+            return;
+        };
+        batches.enter(&tree.path("d"));
+        let err = batches.take(&tree.path("d/loop")).unwrap().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+        let stat = batches.take(&tree.path("d/a")).unwrap().unwrap();
+        assert_eq!(stat.ino, fs::metadata(tree.path("d/a")).unwrap().ino());
+    }
+
+    #[test]
+    fn entries_of_other_directories_are_left_to_the_walk() {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("d/a", "a").unwrap().file("e/b", "b").unwrap();
+        let Some(mut batches) = batches(false) else {
+            return;
+        };
+        batches.enter(&tree.path("d"));
+        assert!(batches.take(&tree.path("e/b")).is_none());
+        assert!(batches.take(&tree.path("d/a")).is_some());
+    }
+}
@@ -0,0 +1,128 @@
+//! `--paranoid` with `--threads`: the byte comparisons are made on worker threads ahead of
+//! the relink, which goes through the groups in order as before and waits only for the
+//! comparisons it needs next.
+//!
+//! Each inode of a group is compared with one inode of the group, its representative.
+//! Two inodes equal to it are equal to each other, and one equal to it and one not are
+//! not; two inodes that both differ from it, or one that could not be read, are compared
+//! again by the relink, which reports the error if there is one.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use crate::digest::{same_content, BUFFER_SIZE};
+use crate::models::{Database, Dev, Ino};
+
+/// The memory of a comparison under way: a buffer for each file.
+const COMPARISON_MEMORY: u64 = 2 * BUFFER_SIZE as u64;
+
+type Key = (Dev, Ino);
+
+/// Compare the inode of `key`, through `path`, with the representative of its group.
+struct Job {
+    key: Key,
+    representative: PathBuf,
+    path: PathBuf,
+}
+
+/// The comparisons of every group, in the order the relink goes through them.
+fn jobs(database: &Database, groups: &[(Dev, Vec<Ino>)]) -> Vec<Job> {
+    let mut jobs = Vec::new();
+    for (dev, inos) in groups {
+        let device = &database.devices[dev];
+        let representative = &device.inodes.get(inos[0]).unwrap().files[0];
+        for &ino in &inos[1..] {
+            jobs.push(Job {
+                key: (*dev, ino),
+                representative: representative.clone(),
+                path: device.inodes.get(ino).unwrap().files[0].clone(),
+            });
+        }
+    }
+    jobs
+}
+
+/// The comparisons made ahead, as they come in from the workers.
+#[derive(Debug)]
+pub struct Verifications {
+    representatives: HashSet<Key>,
+    /// Inodes compared, or to be, with their representative.
+    expected: HashSet<Key>,
+    /// `None` for the comparisons that failed.
+    results: HashMap<Key, Option<bool>>,
+    /// Only in a mutex for the report holding it to be shared by the hashing workers.
+    receiver: Mutex<mpsc::Receiver<(Key, Option<bool>)>>,
+}
+
+impl Verifications {
+    /// Whether the inode has the contents of its representative, waiting for the
+    /// comparison if it is still under way. `None` if it is not compared ahead.
+    fn same_as_representative(&mut self, key: Key) -> Option<bool> {
+        if self.representatives.contains(&key) {
+            return Some(true);
+        }
+        if !self.expected.contains(&key) {
+            return None;
+        }
+        while !self.results.contains_key(&key) {
+            let receiver = self.receiver.get_mut().unwrap();
+            let (done, result) = receiver.recv().ok()?;
+            self.results.insert(done, result);
+        }
+        self.results[&key]
+    }
+
+    /// Whether `original` and `duplicate` have the same contents, as far as their
+    /// comparisons with their representative tell. `None` if they do not.
+    pub fn same(&mut self, dev: Dev, original: Ino, duplicate: Ino) -> Option<bool> {
+        let original = self.same_as_representative((dev, original))?;
+        let duplicate = self.same_as_representative((dev, duplicate))?;
+        (original || duplicate).then_some(original == duplicate)
+    }
+}
+
+/// Runs `relink` while the comparisons of `groups`, each a device and the inodes of a
+/// group of identical files, are made on up to `threads` workers, as many as
+/// `buffer_budget` bytes of buffers allow. The workers stop once `relink` returns.
+pub fn with_verifications<T>(
+    database: &Database,
+    groups: &[(Dev, Vec<Ino>)],
+    threads: u64,
+    buffer_budget: u64,
+    relink: impl FnOnce(Verifications) -> T,
+) -> T {
+    let jobs = jobs(database, groups);
+    let workers = threads.min(buffer_budget / COMPARISON_MEMORY).max(1);
+    let next = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (jobs, next, done) = (&jobs, &next, &done);
+            scope.spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let result = same_content(&job.representative, &job.path).ok();
+                    if sender.send((job.key, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        let verifications = Verifications {
+            representatives: groups.iter().map(|(dev, inos)| (*dev, inos[0])).collect(),
+            expected: jobs.iter().map(|job| job.key).collect(),
+            results: HashMap::new(),
+            receiver: Mutex::new(receiver),
+        };
+        let result = relink(verifications);
+        done.store(true, Ordering::Relaxed);
+        result
+    })
+}
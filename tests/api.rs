@@ -0,0 +1,138 @@
+mod common;
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use dedup::test_utils::TreeBuilder;
+use dedup::{apply, plan, scan, DedupPlan, OutsideRoots, PlanOptions, ScanOptions};
+
+use common::{dedup, link_groups};
+
+const SPEC: &str = "
+seed = 7
+files = 200
+duplicate_ratio = 0.4
+size_collisions = 0.2
+depth = 2
+fanout = 3
+hardlink_farms = 1
+farm_links = 4
+
+[sizes]
+\"0-1k\" = 70
+\"1k-64k\" = 30
+";
+
+#[test]
+fn plan_then_apply_ends_like_run() {
+    let mut staged = TreeBuilder::new().unwrap();
+    staged.generate(SPEC).unwrap();
+    let mut direct = TreeBuilder::new().unwrap();
+    direct.generate(SPEC).unwrap();
+    assert_eq!(link_groups(staged.root()), link_groups(direct.root()));
+
+    let targets = vec![staged.root().to_path_buf()];
+    let database = scan(&ScanOptions {
+        targets: targets.clone(),
+        ..Default::default()
+    })
+    .unwrap();
+    let plan = plan(&database, &PlanOptions::default()).unwrap();
+    assert!(!plan.groups.is_empty());
+    let report = apply(&plan, &targets).unwrap();
+    assert_eq!(report.skipped, 0);
+
+    dedup(["--quiet".as_ref(), direct.root().as_os_str()]);
+    assert_eq!(link_groups(staged.root()), link_groups(direct.root()));
+}
+
+#[test]
+fn apply_is_confined_to_the_roots_of_the_caller() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a/x", "same")
+        .unwrap()
+        .file("b/x", "same")
+        .unwrap();
+    let database = scan(&ScanOptions {
+        targets: vec![tree.root().to_path_buf()],
+        ..Default::default()
+    })
+    .unwrap();
+    let plan = plan(&database, &PlanOptions::default()).unwrap();
+    assert_eq!(plan.groups.len(), 1);
+
+    let err = apply(&plan, &[tree.path("a")]).unwrap_err();
+    assert!(err.is::<OutsideRoots>(), "{err:?}");
+    assert!(apply(&plan, &[]).is_err());
+}
+
+#[test]
+fn plans_keep_paths_that_are_not_utf8_through_json() {
+    let mut tree = TreeBuilder::new().unwrap();
+    let name = OsStr::from_bytes(b"caf\xe9");
+    tree.file("t/a", "same")
+        .unwrap()
+        .file(std::path::Path::new("t").join(name), "same")
+        .unwrap();
+    let database = scan(&ScanOptions {
+        targets: vec![tree.path("t")],
+        ..Default::default()
+    })
+    .unwrap();
+    let plan = plan(&database, &PlanOptions::default()).unwrap();
+    let json = serde_json::to_string(&plan).unwrap();
+    let back: DedupPlan = serde_json::from_str(&json).unwrap();
+    let paths = |plan: &DedupPlan| {
+        let group = &plan.groups[0];
+        (group.original.clone(), group.linked.clone())
+    };
+    assert_eq!(paths(&back), paths(&plan));
+    let (original, linked) = paths(&back);
+    assert!([original, linked[0].clone()].contains(&tree.path("t").join(name)));
+}
+
+#[test]
+fn options_act_as_their_flags_do() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("keep/a", "same")
+        .unwrap()
+        .file("other/a", "same")
+        .unwrap()
+        .file("other/a.tmp", "same")
+        .unwrap()
+        .file("small/x", "x")
+        .unwrap()
+        .file("small/y", "x")
+        .unwrap();
+    let database = scan(&ScanOptions {
+        targets: vec![tree.root().to_path_buf()],
+        exclude: vec!["*.tmp".to_string()],
+        min_size: 2,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let preferred = plan(
+        &database,
+        &PlanOptions {
+            prefer: vec![tree.path("other")],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(preferred.groups.len(), 1);
+    assert_eq!(preferred.groups[0].original, tree.path("other/a"));
+    assert_eq!(preferred.groups[0].linked, [tree.path("keep/a")]);
+
+    let referenced = plan(
+        &database,
+        &PlanOptions {
+            prefer: vec![tree.path("other")],
+            reference: vec![tree.path("keep")],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(referenced.groups[0].original, tree.path("keep/a"));
+    assert_eq!(referenced.groups[0].linked, [tree.path("other/a")]);
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, before_rename, TreeBuilder};
+use dedup::DEFAULT_TMP_PREFIX;
+
+use common::dedup;
+
+#[test]
+fn failure_after_the_temporary_link_leaves_the_duplicate_intact() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .file("t/c", "same")
+        .unwrap();
+    let b = tree.path("t/b");
+    let before = fs::metadata(&b).unwrap();
+    let _hook = {
+        let b = b.clone();
+        before_rename(move |path| {
+            if path == b {
+                // the temporary link to the original is there by now
+                let temporaries = fs::read_dir(b.parent().unwrap())
+                    .unwrap()
+                    .map(|entry| entry.unwrap().file_name())
+                    .filter(|name| name.to_string_lossy().starts_with(DEFAULT_TMP_PREFIX))
+                    .count();
+                assert_eq!(temporaries, 1);
+                return Err(io::Error::other("injected failure"));
+            }
+            Ok(())
+        })
+    };
+    let code = dedup([
+        "--keep-going".as_ref(),
+        "--quiet".as_ref(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_ne!(code, ExitCode::SUCCESS);
+
+    assert_eq!(fs::read(&b).unwrap(), b"same");
+    assert_eq!(fs::metadata(&b).unwrap().ino(), before.ino());
+    assert_not_linked(tree.path("t/a"), &b);
+    assert_linked(tree.path("t/a"), tree.path("t/c"));
+    let names: Vec<_> = fs::read_dir(tree.path("t"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names.len(), 3, "{names:?}");
+}
+
+#[test]
+fn original_swapped_for_a_symlink_is_never_linked_to() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .file("t/c", "same")
+        .unwrap()
+        .file("elsewhere", "same")
+        .unwrap();
+    let (a, elsewhere) = (tree.path("t/a"), tree.path("elsewhere"));
+    let swapped = AtomicBool::new(false);
+    // swapped once the first duplicate is about to be replaced, before the second is
+    let _hook = {
+        let (a, elsewhere) = (a.clone(), elsewhere.clone());
+        before_rename(move |path| {
+            if path.starts_with(a.parent().unwrap()) && !swapped.swap(true, Ordering::Relaxed) {
+                fs::remove_file(&a)?;
+                std::os::unix::fs::symlink(&elsewhere, &a)?;
+            }
+            Ok(())
+        })
+    };
+    dedup([
+        "--keep-going".as_ref(),
+        "--quiet".as_ref(),
+        tree.path("t").as_os_str(),
+    ]);
+
+    for duplicate in ["t/b", "t/c"] {
+        let duplicate = tree.path(duplicate);
+        assert!(fs::symlink_metadata(&duplicate).unwrap().is_file());
+        assert_eq!(fs::read(&duplicate).unwrap(), b"same");
+        assert_not_linked(&elsewhere, &duplicate);
+    }
+}
+
+#[test]
+fn exdev_restores_the_duplicate_and_skips_its_directory_pair() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for path in [
+        "t/orig/a",
+        "t/sub/b",
+        "t/sub/c",
+        "t/sub/deeper/d",
+        "t/other/e",
+    ] {
+        tree.file(path, "same").unwrap();
+    }
+    let sub = tree.path("t/sub");
+    let before = ["t/sub/b", "t/sub/c", "t/sub/deeper/d"].map(|path| tree.path(path));
+    let inodes = before.clone().map(|path| fs::metadata(path).unwrap().ino());
+    let attempts = Arc::new(AtomicUsize::new(0));
+    // as if t/sub were another subvolume behind a bind mount
+    let _hook = {
+        let (sub, attempts) = (sub.clone(), Arc::clone(&attempts));
+        before_rename(move |path| {
+            if path.starts_with(&sub) {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                return Err(io::Error::from_raw_os_error(libc::EXDEV));
+            }
+            Ok(())
+        })
+    };
+    let output = tree.path("output");
+    let code = dedup([
+        "--output".as_ref(),
+        output.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_eq!(code, ExitCode::SUCCESS);
+
+    // tried once, and neither the duplicate nor its temporary link is left changed
+    assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    for (path, ino) in before.iter().zip(inodes) {
+        assert_eq!(fs::read(path).unwrap(), b"same");
+        assert_eq!(fs::metadata(path).unwrap().ino(), ino);
+    }
+    let names: Vec<_> = fs::read_dir(&sub)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names.len(), 3, "{names:?}");
+    assert_linked(tree.path("t/orig/a"), tree.path("t/other/e"));
+
+    let output = fs::read_to_string(output).unwrap();
+    assert!(
+        output.contains(&format!(
+            "Warning: 3 files could not be linked across filesystems (EXDEV) between:\n  {} and {}\n",
+            tree.path("t/orig").display(),
+            sub.display()
+        )),
+        "{output}"
+    );
+}
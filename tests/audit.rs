@@ -0,0 +1,80 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Mutex;
+
+use dedup::test_utils::{before_relink, TreeBuilder};
+
+use common::dedup;
+
+#[test]
+fn sampled_links_are_audited_after_the_run() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for i in 0..5 {
+        tree.file(format!("t/{i}"), "same").unwrap();
+    }
+    let output = tree.path("output");
+    let code = dedup([
+        "--audit-samples".as_ref(),
+        "3".as_ref(),
+        "--seed".as_ref(),
+        "42".as_ref(),
+        "--output".as_ref(),
+        output.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_eq!(code, ExitCode::SUCCESS);
+    let output = fs::read_to_string(output).unwrap();
+    assert!(
+        output.contains("Audit: 3 of 3 sampled links passed (seed 42)\n"),
+        "{output}"
+    );
+}
+
+#[test]
+fn failed_samples_are_listed_and_fail_the_run() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a1", "one")
+        .unwrap()
+        .file("t/a2", "one")
+        .unwrap()
+        .file("t/b1", "two")
+        .unwrap()
+        .file("t/b2", "two")
+        .unwrap();
+    // the original relinked first is changed in place while the second group is relinked
+    let first = Mutex::new(None::<PathBuf>);
+    let root = tree.root().to_path_buf();
+    let _hook = before_relink(move |original| {
+        if !original.starts_with(&root) {
+            return Ok(());
+        }
+        let mut first = first.lock().unwrap();
+        match &*first {
+            None => *first = Some(original.to_path_buf()),
+            Some(changed) => fs::write(changed, "TWO")?,
+        }
+        Ok(())
+    });
+    let report = tree.path("report.json");
+    let code = dedup([
+        "--audit-samples".as_ref(),
+        "10".as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_eq!(code, ExitCode::from(5));
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    let audit = &report["audit"];
+    assert_eq!(audit["samples"], 2, "{report}");
+    assert_eq!(audit["passed"], 1, "{report}");
+    assert_eq!(
+        audit["failures"][0]["reason"], "the content of the original changed",
+        "{report}"
+    );
+}
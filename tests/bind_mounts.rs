@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use dedup::test_utils::{assert_linked, TreeBuilder};
+
+/// A bind mount, unmounted when dropped even if the test fails.
+struct BindMount(PathBuf);
+
+impl BindMount {
+    /// `None` unless run as root where mounts are allowed.
+    fn new(source: &Path, target: &Path) -> Option<Self> {
+        fs::create_dir(target).unwrap();
+        let status = Command::new("mount")
+            .arg("--bind")
+            .arg(source)
+            .arg(target)
+            .stderr(std::process::Stdio::null())
+            .status()
+            .ok()?;
+        status.success().then(|| Self(target.to_path_buf()))
+    }
+}
+
+impl Drop for BindMount {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.0).status();
+    }
+}
+
+fn run(tree: &TreeBuilder, options: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(options)
+        .arg("t")
+        .current_dir(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn directories_visited_through_a_bind_mount_are_reported() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/d/a", "same")
+        .unwrap()
+        .file("t/d/b", "same")
+        .unwrap();
+    let Some(_mount) = BindMount::new(&tree.path("t/d"), &tree.path("t/bind")) else {
+        eprintln!("skipped: needs root and mounts");
+        return;
+    };
+
+    let stdout = run(&tree, &["--dry-run", "-v"]);
+    // either is walked first
+    let examples = [
+        "Skipped 1 directories already visited via other paths:\n  t/d (visited as t/bind)\n",
+        "Skipped 1 directories already visited via other paths:\n  t/bind (visited as t/d)\n",
+    ];
+    assert!(
+        examples.iter().any(|example| stdout.contains(example)),
+        "{stdout}"
+    );
+    assert!(!run(&tree, &["--dry-run"]).contains("already visited"));
+
+    let report: serde_json::Value = serde_json::from_str(&run(&tree, &["--json"])).unwrap();
+    assert_eq!(report["skipped_dirs"]["count"], 1, "{report}");
+    assert_eq!(report["summary"]["files_scanned"], 2, "{report}");
+    assert_linked(tree.path("t/d/a"), tree.path("t/d/b"));
+}
@@ -0,0 +1,63 @@
+use std::process::Command;
+
+use dedup::test_utils::TreeBuilder;
+
+/// The output of a dry run over `tree` with `options`.
+fn run(tree: &TreeBuilder, options: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .arg("--dry-run")
+        .args(options)
+        .arg(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn duplicates_are_summed_up_by_extension() {
+    let mut tree = TreeBuilder::new().unwrap();
+    // a group per extension, the larger the later, each with a duplicate or two, and one
+    // without an extension
+    for i in 0..22 {
+        let contents = "x".repeat(i + 1);
+        for copy in ["a", "b"] {
+            tree.file(format!("{i}{copy}.E{i:02}"), &contents).unwrap();
+        }
+    }
+    for path in ["x.tar.gz", "y.tar.gz", "z.tar.gz", "README", "readme"] {
+        tree.file(
+            path,
+            if path.contains(".tar") {
+                "archive"
+            } else {
+                "text"
+            },
+        )
+        .unwrap();
+    }
+
+    let json: serde_json::Value =
+        serde_json::from_str(&run(&tree, &["--by-extension", "--json"])).unwrap();
+    let stats = json["by_extension"].as_array().unwrap();
+    // the 20 largest, then the rest
+    assert_eq!(stats.len(), 21, "{json}");
+    assert_eq!(stats[0]["extension"], "e21", "{json}");
+    assert_eq!(stats[0]["bytes"], 22, "{json}");
+    let by_name = |name: &str| stats.iter().find(|stats| stats["extension"] == name);
+    assert_eq!(by_name("gz").unwrap()["files"], 2, "{json}");
+    assert_eq!(by_name("gz").unwrap()["bytes"], 14, "{json}");
+    assert_eq!(by_name("(none)").unwrap()["bytes"], 4, "{json}");
+    let other = &stats[20];
+    assert_eq!(other["extension"], "other", "{json}");
+    // e00 to e03, the last of which ties with (none) and comes after it
+    assert_eq!(other["files"], 4, "{json}");
+    assert_eq!(other["bytes"], 1 + 2 + 3 + 4, "{json}");
+
+    let text = run(&tree, &["--by-extension", "--compound-ext"]);
+    assert!(text.contains("Duplicates by extension:\n"), "{text}");
+    assert!(
+        text.contains("  tar.gz                2 files                   14 bytes\n"),
+        "{text}"
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, before_hash, TreeBuilder};
+use filetime::FileTime;
+
+use common::dedup;
+
+/// Overwrites a file with `contents` of the same size, keeping its mtime, as a cache cannot
+/// tell.
+fn overwrite_keeping_mtime(path: &std::path::Path, contents: &[u8]) {
+    let mtime = FileTime::from_last_modification_time(&fs::metadata(path).unwrap());
+    fs::write(path, contents).unwrap();
+    filetime::set_file_mtime(path, mtime).unwrap();
+}
+
+#[test]
+fn stale_entries_are_purged_and_hashed_again() {
+    let small = |byte| vec![byte; 100];
+    // larger than the prefix, so that the prefix sieve tells them apart first
+    let large = |byte| vec![byte; 100 << 10];
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/small-a", small(b'a'))
+        .unwrap()
+        .file("t/small-b", small(b'b'))
+        .unwrap()
+        .file("t/large-a", large(b'a'))
+        .unwrap()
+        .file("t/large-b", large(b'b'))
+        .unwrap();
+    let cache = tree.path("cache.json");
+    let report = tree.path("report.json");
+    let run = |rate: &str| {
+        dedup([
+            "--cache".as_ref(),
+            cache.as_os_str(),
+            "--cache-verify-rate".as_ref(),
+            rate.as_ref(),
+            "--seed".as_ref(),
+            "1".as_ref(),
+            "--format".as_ref(),
+            "json".as_ref(),
+            "--output".as_ref(),
+            report.as_os_str(),
+            tree.path("t").as_os_str(),
+        ]);
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+        report
+    };
+
+    run("0");
+    overwrite_keeping_mtime(&tree.path("t/small-b"), &small(b'a'));
+    overwrite_keeping_mtime(&tree.path("t/large-b"), &large(b'a'));
+    run("0");
+    assert_not_linked(tree.path("t/small-a"), tree.path("t/small-b"));
+    assert_not_linked(tree.path("t/large-a"), tree.path("t/large-b"));
+
+    let report = run("1");
+    assert_linked(tree.path("t/small-a"), tree.path("t/small-b"));
+    assert_linked(tree.path("t/large-a"), tree.path("t/large-b"));
+    let verification = &report["cache_verification"];
+    assert_eq!(verification["stale"], 2, "{verification}");
+    assert_eq!(verification["verified"], 4, "{verification}");
+}
+
+#[test]
+fn second_run_over_an_unchanged_tree_hashes_nothing() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.generate("seed = 5\nfiles = 100\nduplicate_ratio = 0.5\nsize_collisions = 0.3\n")
+        .unwrap();
+    let root = tree.root().to_path_buf();
+    let hashes = Arc::new(AtomicU64::new(0));
+    let _hook = {
+        let (root, hashes) = (root.clone(), Arc::clone(&hashes));
+        before_hash(move |path| {
+            if path.starts_with(&root) {
+                hashes.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        })
+    };
+    let cache = tree.path("cache.json");
+    let report = tree.path("report.json");
+    let run = || {
+        dedup([
+            "--cache".as_ref(),
+            cache.as_os_str(),
+            "--format".as_ref(),
+            "json".as_ref(),
+            "--output".as_ref(),
+            report.as_os_str(),
+            root.as_os_str(),
+        ]);
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+        (hashes.swap(0, Ordering::Relaxed), report)
+    };
+
+    let (first, report) = run();
+    assert!(first > 0);
+    assert!(report["gain"].as_u64().unwrap() > 0, "{report}");
+    let (second, report) = run();
+    assert_eq!(second, 0);
+    assert_eq!(report["hashed"], 0, "{report}");
+}
@@ -0,0 +1,176 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use dedup::test_utils::{
+    assert_linked, assert_not_linked, before_hash, before_relink, TreeBuilder,
+};
+
+use common::dedup;
+
+/// The JSON report of a run over `root` with `threads`.
+fn run(root: &Path, threads: &str) -> serde_json::Value {
+    let report = root.with_extension("json");
+    dedup([
+        "--threads".as_ref(),
+        threads.as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        root.as_os_str(),
+    ]);
+    let value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    fs::remove_file(report).unwrap();
+    value
+}
+
+#[test]
+fn file_vanishing_before_its_hash_is_dropped() {
+    for threads in ["1", "4"] {
+        let mut tree = TreeBuilder::new().unwrap();
+        // a is walked first, and left as the only file of its size until b is found
+        tree.file("t/a", "same")
+            .unwrap()
+            .file("t/b", "same")
+            .unwrap()
+            .file("t/c", "same")
+            .unwrap();
+        let doomed = tree.path("t/a");
+        let _hook = before_hash(move |path| {
+            if path == doomed {
+                let _ = fs::remove_file(path);
+            }
+            Ok(())
+        });
+        let report = run(&tree.path("t"), threads);
+        assert!(!tree.path("t/a").exists());
+        assert_linked(tree.path("t/b"), tree.path("t/c"));
+        assert_eq!(report["vanished"], 1, "{report}");
+        assert_eq!(report["errors"].as_array().unwrap().len(), 0, "{report}");
+    }
+}
+
+#[test]
+fn file_truncated_before_its_hash_is_left_out() {
+    for threads in ["1", "4"] {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("t/a", "same")
+            .unwrap()
+            .file("t/b", "same")
+            .unwrap()
+            .file("t/c", "same")
+            .unwrap()
+            .file("t/empty", "")
+            .unwrap();
+        let truncated = tree.path("t/b");
+        let _hook = before_hash(move |path| {
+            if path == truncated {
+                fs::File::create(path).unwrap();
+            }
+            Ok(())
+        });
+        let report = run(&tree.path("t"), threads);
+        assert_eq!(fs::metadata(tree.path("t/b")).unwrap().len(), 0);
+        assert_linked(tree.path("t/a"), tree.path("t/c"));
+        assert_not_linked(tree.path("t/a"), tree.path("t/b"));
+        assert_not_linked(tree.path("t/b"), tree.path("t/empty"));
+        assert_eq!(report["changed"], 1, "{report}");
+    }
+}
+
+#[test]
+fn files_changed_after_the_scan_are_not_relinked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for (path, contents) in [
+        ("t/a", "same"),
+        ("t/b", "same"),
+        ("t/c", "same"),
+        ("t/d", "same"),
+        ("t/x1", "other"),
+        ("t/x2", "other"),
+    ] {
+        tree.file(path, contents).unwrap();
+        tree.mtime(path, 1_000_000_000).unwrap();
+    }
+    let (a, x1) = (tree.path("t/a"), tree.path("t/x1"));
+    let _hook = {
+        let (a, x1) = (a.clone(), x1.clone());
+        before_relink(move |original| {
+            let dir = original.parent().unwrap();
+            if original == a {
+                // modified in place, and replaced by another inode
+                fs::write(dir.join("c"), "SAME")?;
+                fs::write(dir.join("d.new"), "same")?;
+                fs::rename(dir.join("d.new"), dir.join("d"))?;
+            } else if original == x1 {
+                fs::write(original, "OTHER")?;
+            }
+            Ok(())
+        })
+    };
+    let report = run(&tree.path("t"), "1");
+    assert_linked(&a, tree.path("t/b"));
+    assert_not_linked(&a, tree.path("t/c"));
+    assert_not_linked(&a, tree.path("t/d"));
+    assert_eq!(fs::read(tree.path("t/c")).unwrap(), b"SAME");
+    // the group of a changed original is left alone
+    assert_not_linked(&x1, tree.path("t/x2"));
+    assert_eq!(fs::read(&x1).unwrap(), b"OTHER");
+    assert_eq!(fs::read(tree.path("t/x2")).unwrap(), b"other");
+    assert_eq!(report["changed_before_relink"], 3, "{report}");
+}
+
+#[test]
+fn duplicates_whose_ctime_advanced_are_skipped_as_asked() {
+    for policy in ["ignore", "skip"] {
+        let mut tree = TreeBuilder::new().unwrap();
+        for (path, mtime) in [
+            ("t/a", 1_000_000_000),
+            ("t/b", 1_100_000_000),
+            ("t/c", 1_200_000_000),
+        ] {
+            tree.file(path, "same").unwrap();
+            tree.mtime(path, mtime).unwrap();
+        }
+        // the most links make a the original, and unlinking its first path advances the
+        // ctime of c before the second is relinked
+        tree.link("t/a", "t/a2").unwrap();
+        tree.link("t/a", "t/a3").unwrap();
+        tree.link("t/c", "t/c2").unwrap();
+        let (a, b) = (tree.path("t/a"), tree.path("t/b"));
+        let _hook = {
+            let (a, b) = (a.clone(), b.clone());
+            before_relink(move |original| {
+                if original == a {
+                    // a chmod that changes nothing but the ctime
+                    let permissions = fs::metadata(&b)?.permissions();
+                    fs::set_permissions(&b, permissions)?;
+                }
+                Ok(())
+            })
+        };
+        let report = tree.path("report.json");
+        dedup([
+            "--abort-on-ctime-change".as_ref(),
+            policy.as_ref(),
+            "--format".as_ref(),
+            "json".as_ref(),
+            "--output".as_ref(),
+            report.as_os_str(),
+            tree.path("t").as_os_str(),
+        ]);
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+        assert_linked(&a, tree.path("t/c"));
+        assert_linked(&a, tree.path("t/c2"));
+        if policy == "skip" {
+            assert_not_linked(&a, &b);
+            assert_eq!(report["ctime_skipped"], 1, "{report}");
+        } else {
+            assert_linked(&a, &b);
+            assert_eq!(report["ctime_skipped"], 0, "{report}");
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::process::{Command, Output, Stdio};
+
+use dedup::test_utils::{assert_linked, TreeBuilder};
+
+/// A tree of enough groups that the listing fills the pipe.
+fn tree() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    for i in 0..2000 {
+        let contents = format!("contents {i}");
+        tree.file(format!("a/{i}"), &contents)
+            .unwrap()
+            .file(format!("b/{i}"), &contents)
+            .unwrap();
+    }
+    tree
+}
+
+/// Runs dedup with its output read by nobody: the reading end is closed right away.
+fn run_into_closed_pipe(tree: &TreeBuilder, options: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(options)
+        .arg(tree.root())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn closed_output_is_dropped_and_the_run_finishes() {
+    let tree = tree();
+    let output = run_into_closed_pipe(&tree, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    assert_linked(tree.path("a/0"), tree.path("b/0"));
+    assert_linked(tree.path("a/1999"), tree.path("b/1999"));
+}
+
+#[test]
+fn exit_on_epipe_stops_with_its_own_code() {
+    let tree = tree();
+    let output = run_into_closed_pipe(&tree, &["--exit-on-epipe"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(9), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
@@ -0,0 +1,38 @@
+// shared by the test crates, each using only some of it
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::Parser as _;
+use walkdir::WalkDir;
+
+/// Runs `dedup` with the arguments of a command line, without the program name.
+pub fn dedup<I, S>(args: I) -> ExitCode
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let argv = std::iter::once(std::ffi::OsString::from("dedup"))
+        .chain(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+    dedup::run(dedup::Args::parse_from(argv)).unwrap()
+}
+
+/// The regular files under `root`, relative to it, grouped by the inode they are links to.
+pub fn link_groups(root: &Path) -> BTreeSet<Vec<PathBuf>> {
+    let mut by_inode: BTreeMap<(u64, u64), Vec<PathBuf>> = BTreeMap::new();
+    for entry in WalkDir::new(root).sort_by_file_name() {
+        let entry = entry.unwrap();
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let metadata = entry.metadata().unwrap();
+        by_inode
+            .entry((metadata.dev(), metadata.ino()))
+            .or_default()
+            .push(entry.path().strip_prefix(root).unwrap().to_path_buf());
+    }
+    by_inode.into_values().collect()
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dedup::test_utils::{before_hash, TreeBuilder};
+
+use common::dedup;
+
+/// Sends `command` over the control socket and returns the answer.
+fn send(socket: &Path, command: &str) -> String {
+    let mut stream = UnixStream::connect(socket).unwrap();
+    writeln!(stream, "{}", command).unwrap();
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).unwrap();
+    reply.trim().to_string()
+}
+
+fn status(socket: &Path) -> serde_json::Value {
+    serde_json::from_str(&send(socket, "status")).unwrap()
+}
+
+fn wait_until(condition: impl Fn() -> bool) {
+    for _ in 0..500 {
+        if condition() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("timed out");
+}
+
+#[test]
+fn runs_pause_resume_and_stop_on_command() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for i in 0..100 {
+        let contents = format!("contents {i:03}");
+        tree.file(format!("t/a/{i}"), &contents)
+            .unwrap()
+            .file(format!("t/b/{i}"), &contents)
+            .unwrap();
+    }
+    let socket = tree.path("control.sock");
+    let report = tree.path("report.json");
+    let started = Arc::new(AtomicBool::new(false));
+    let released = Arc::new(AtomicBool::new(false));
+    let _hook = {
+        let (started, released) = (Arc::clone(&started), Arc::clone(&released));
+        before_hash(move |_| {
+            started.store(true, Ordering::Relaxed);
+            while !released.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread::sleep(Duration::from_millis(5));
+            Ok(())
+        })
+    };
+
+    let run = {
+        let (socket, report, target) = (socket.clone(), report.clone(), tree.path("t"));
+        thread::spawn(move || {
+            dedup([
+                "--threads".as_ref(),
+                "1".as_ref(),
+                "--control-socket".as_ref(),
+                socket.as_os_str(),
+                "--json".as_ref(),
+                "--output".as_ref(),
+                report.as_os_str(),
+                target.as_os_str(),
+            ])
+        })
+    };
+    wait_until(|| started.load(Ordering::Relaxed));
+    assert_eq!(send(&socket, "pause"), "paused");
+    released.store(true, Ordering::Relaxed);
+
+    // the file at hand is finished, and no other started
+    wait_until(|| status(&socket)["files_hashed"] == 1);
+    let paused = status(&socket);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(paused["paused"], true, "{paused}");
+    assert_eq!(status(&socket), paused);
+
+    assert_eq!(send(&socket, "resume"), "resumed");
+    wait_until(|| status(&socket)["files_hashed"].as_u64().unwrap() > 10);
+    assert_eq!(send(&socket, "stop"), "stopping");
+    run.join().unwrap();
+
+    assert!(!socket.exists());
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["stopped"], true);
+    assert!(report["summary"]["files_hashed"].as_u64().unwrap() < 200);
+}
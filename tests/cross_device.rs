@@ -0,0 +1,115 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+/// A directory on another device than the trees of the tests, removed when dropped.
+struct OtherDevice(PathBuf);
+
+impl OtherDevice {
+    /// In /dev/shm, if it is a device of its own.
+    fn new(tree: &TreeBuilder) -> Option<Self> {
+        let shm = Path::new("/dev/shm");
+        let dev = fs::metadata(shm).ok()?.dev();
+        if dev == fs::metadata(tree.root()).unwrap().dev() {
+            return None;
+        }
+        let dir = shm.join(tree.root().file_name().unwrap());
+        fs::create_dir(&dir).ok()?;
+        Some(Self(dir))
+    }
+}
+
+impl Drop for OtherDevice {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn content_on_several_devices_is_reported_not_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    let Some(other) = OtherDevice::new(&tree) else {
+        eprintln!("skipped: needs /dev/shm on a device of its own");
+        return;
+    };
+    tree.file("shared", "on both")
+        .unwrap()
+        .file("shared-too", "on both")
+        .unwrap()
+        .file("here-only", "only here")
+        .unwrap();
+    for (name, contents) in [("a", "on both"), ("b", "on both"), ("c", "elsewhere")] {
+        fs::write(other.0.join(name), contents).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--cross-device-report", "--json"])
+        .arg(tree.root())
+        .arg(&other.0)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let found = report["cross_device"].as_array().unwrap();
+    assert_eq!(found.len(), 1, "{report}");
+    assert_eq!(found[0]["size"], 7, "{report}");
+    let copies = found[0]["copies"].as_array().unwrap();
+    assert_eq!(copies.len(), 2, "{report}");
+    let paths: Vec<_> = copies
+        .iter()
+        .map(|copy| copy["path"].as_str().unwrap())
+        .collect();
+    assert!(
+        paths.contains(&tree.path("shared").to_str().unwrap()),
+        "{report}"
+    );
+    assert!(
+        paths.contains(&other.0.join("a").to_str().unwrap()),
+        "{report}"
+    );
+    for copy in copies {
+        assert_eq!(copy["files"], 2, "{report}");
+    }
+    // linked within each device only
+    assert_linked(tree.path("shared"), tree.path("shared-too"));
+    assert_linked(other.0.join("a"), other.0.join("b"));
+    assert_not_linked(tree.path("shared"), other.0.join("a"));
+}
+
+#[test]
+fn content_on_several_devices_is_only_reported_when_asked() {
+    let tree = TreeBuilder::new().unwrap();
+    let Some(other) = OtherDevice::new(&tree) else {
+        eprintln!("skipped: needs /dev/shm on a device of its own");
+        return;
+    };
+    fs::write(tree.path("x"), "same").unwrap();
+    fs::write(other.0.join("x"), "same").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .arg("--dry-run")
+        .arg(tree.root())
+        .arg(&other.0)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("several devices"), "{stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--dry-run", "--cross-device-report"])
+        .arg(tree.root())
+        .arg(&other.0)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Content on several devices, not linked:\n  4 bytes, "),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!("{} (1 files)\n", other.0.join("x").display())),
+        "{stdout}"
+    );
+}
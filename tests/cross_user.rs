@@ -0,0 +1,68 @@
+use std::os::unix::fs::chown;
+use std::process::{Command, Output};
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+const NOBODY: u32 = 65534;
+
+/// Only root can hand files to another user.
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Runs dedup with `options` over two identical files, the second owned by another user.
+fn run(options: &[&str]) -> (TreeBuilder, Output) {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("mine", "same")
+        .unwrap()
+        .file("theirs", "same")
+        .unwrap();
+    chown(tree.path("theirs"), Some(NOBODY), Some(NOBODY)).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(options)
+        .arg(tree.root())
+        .output()
+        .unwrap();
+    (tree, output)
+}
+
+#[test]
+fn groups_across_users_follow_the_policy() {
+    if !is_root() {
+        eprintln!("skipped: needs root");
+        return;
+    }
+    let warning = "Warning: linking files owned by different users: ";
+
+    // the owners must match unless ignored, whatever the policy
+    let (tree, output) = run(&["--cross-user", "allow"]);
+    assert!(output.status.success());
+    assert_not_linked(tree.path("mine"), tree.path("theirs"));
+
+    let (tree, output) = run(&["--ignore-ownership"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(warning));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Linked groups owned by different users: 1\n"),
+        "{stdout}"
+    );
+    assert_linked(tree.path("mine"), tree.path("theirs"));
+
+    let (tree, output) = run(&["--ignore-ownership", "--cross-user", "allow"]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8(output.stderr).unwrap().contains(warning));
+    assert!(!String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("different users"));
+    assert_linked(tree.path("mine"), tree.path("theirs"));
+
+    let (tree, output) = run(&["--ignore-ownership", "--cross-user", "skip"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Skipped groups owned by different users: 1\n"),
+        "{stdout}"
+    );
+    assert_not_linked(tree.path("mine"), tree.path("theirs"));
+}
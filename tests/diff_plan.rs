@@ -0,0 +1,95 @@
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::process::{Command, Output};
+
+use dedup::test_utils::{assert_not_linked, TreeBuilder};
+
+/// Runs dedup from the root of `tree` with `args`.
+fn run(tree: &TreeBuilder, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .current_dir(tree.root())
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn two_groups() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "one")
+        .unwrap()
+        .file("t/b", "one")
+        .unwrap()
+        .file("t/c", "two")
+        .unwrap()
+        .file("t/d", "two")
+        .unwrap();
+    tree
+}
+
+#[test]
+fn unchanged_plan_exits_zero_whatever_the_path_it_was_found_through() {
+    let tree = two_groups();
+    // saved through a relative path, compared through a symlink
+    symlink(tree.path("t"), tree.path("alias")).unwrap();
+    let output = run(
+        &tree,
+        &["--dry-run", "--format", "json", "--output", "old.json", "t"],
+    );
+    assert!(output.status.success());
+
+    let output = run(&tree, &["--diff-plan", "old.json", "alias/"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert_eq!(stdout, "Added: 0, removed: 0, unchanged: 2\n");
+    // only ever a dry run
+    assert_not_linked(tree.path("t/a"), tree.path("t/b"));
+}
+
+#[test]
+fn changed_plan_lists_the_operations_and_exits_one() {
+    let tree = two_groups();
+    let output = run(
+        &tree,
+        &["--dry-run", "--format", "json", "--output", "old.json", "t"],
+    );
+    assert!(output.status.success());
+    let old: serde_json::Value =
+        serde_json::from_slice(&fs::read(tree.path("old.json")).unwrap()).unwrap();
+    // saved relative to where it ran, compared canonical
+    let removed = old["groups"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|group| group["original"].as_str().unwrap().ends_with(['c', 'd']))
+        .unwrap();
+    let removed = [&removed["original"], &removed["linked"][0]]
+        .map(|path| tree.path(path.as_str().unwrap()).display().to_string());
+    // a month of churn: a group is gone and a file joins the other
+    fs::write(tree.path("t/d"), "changed").unwrap();
+    fs::write(tree.path("t/e"), "one").unwrap();
+
+    let output = run(&tree, &["--diff-plan", "old.json", "t"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.ends_with("Added: 1, removed: 1, unchanged: 1\n"),
+        "{stdout}"
+    );
+    let line = format!("- {} <- {}\n", removed[0], removed[1]);
+    assert!(stdout.contains(&line), "{stdout}");
+
+    let output = run(&tree, &["--diff-plan", "old.json", "--format", "json", "t"]);
+    assert_eq!(output.status.code(), Some(1));
+    let diff: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(diff["unchanged"], 1, "{diff}");
+    assert_eq!(diff["removed"][0]["original"], *removed[0], "{diff}");
+    assert_eq!(diff["removed"][0]["duplicate"], *removed[1], "{diff}");
+    // the new file is the newest, so never the original
+    let added = diff["added"].as_array().unwrap();
+    assert_eq!(added.len(), 1, "{diff}");
+    assert_eq!(
+        added[0]["duplicate"],
+        *tree.path("t/e").to_str().unwrap(),
+        "{diff}"
+    );
+}
@@ -0,0 +1,90 @@
+//! The cache impact of `--direct-io`: how much of the files hashed is left in the page
+//! cache with buffered reads and with O_DIRECT. Run with `--nocapture` for the figures.
+
+mod common;
+
+use std::fs;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use dedup::test_utils::TreeBuilder;
+
+use common::dedup;
+
+/// A multiple of the page and of the O_DIRECT alignment, so that no tail is read buffered.
+const SIZE: usize = 16 << 20;
+
+/// Drops the pages of the file from the page cache.
+fn evict(path: &Path) {
+    let file = fs::File::open(path).unwrap();
+    file.sync_all().unwrap();
+    let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    assert_eq!(ret, 0);
+}
+
+/// The pages of the file in the page cache, out of all its pages.
+fn cached_pages(path: &Path) -> (usize, usize) {
+    let file = fs::File::open(path).unwrap();
+    let len = file.metadata().unwrap().len() as usize;
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let pages = len.div_ceil(page);
+    let mut residency = vec![0_u8; pages];
+    unsafe {
+        let map = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+        assert_ne!(map, libc::MAP_FAILED);
+        assert_eq!(libc::mincore(map, len, residency.as_mut_ptr()), 0);
+        libc::munmap(map, len);
+    }
+    (
+        residency.iter().filter(|&&page| page & 1 != 0).count(),
+        pages,
+    )
+}
+
+/// The pages of both files cached by a dry run with `options`, from an empty cache.
+fn cached_by_run(tree: &TreeBuilder, options: &[&str]) -> Option<(usize, usize)> {
+    let (a, b) = (tree.path("t/a"), tree.path("t/b"));
+    evict(&a);
+    evict(&b);
+    if cached_pages(&a).0 + cached_pages(&b).0 > 0 {
+        // the cache would not let go of them, as on some overlay filesystems
+        return None;
+    }
+    let mut args = vec!["--dry-run", "--quiet", "--threads", "1"];
+    args.extend(options);
+    let root = tree.path("t");
+    dedup(args.iter().map(AsRef::as_ref).chain([root.as_os_str()]));
+    let ((cached_a, pages_a), (cached_b, pages_b)) = (cached_pages(&a), cached_pages(&b));
+    Some((cached_a + cached_b, pages_a + pages_b))
+}
+
+#[test]
+fn direct_io_leaves_the_page_cache_alone() {
+    let mut tree = TreeBuilder::new().unwrap();
+    let contents: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+    tree.file("t/a", &contents)
+        .unwrap()
+        .file("t/b", &contents)
+        .unwrap();
+
+    let Some((buffered, pages)) = cached_by_run(&tree, &[]) else {
+        eprintln!("skipped: the page cache cannot be emptied here");
+        return;
+    };
+    let (direct, _) = cached_by_run(&tree, &["--direct-io"]).unwrap();
+    eprintln!(
+        "pages left in the page cache by hashing {pages}: buffered {buffered}, direct {direct}"
+    );
+    assert!(buffered > pages / 2);
+    // unless the filesystem rejects O_DIRECT, and dedup falls back to buffered reads
+    if direct < pages / 2 {
+        assert_eq!(direct, 0);
+    }
+}
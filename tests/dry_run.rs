@@ -0,0 +1,89 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use dedup::test_utils::TreeBuilder;
+use filetime::FileTime;
+use walkdir::WalkDir;
+
+use common::dedup;
+
+const SPEC: &str = "
+seed = 3
+files = 100
+duplicate_ratio = 0.5
+hardlink_farms = 1
+farm_links = 3
+";
+
+/// The inode, link count and mtime of each file under `root`.
+fn inodes(root: &Path) -> BTreeMap<PathBuf, (u64, u64, i64)> {
+    WalkDir::new(root)
+        .into_iter()
+        .map(Result::unwrap)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            let metadata = entry.metadata().unwrap();
+            let stat = (metadata.ino(), metadata.nlink(), metadata.mtime());
+            (entry.into_path(), stat)
+        })
+        .collect()
+}
+
+/// The gain reported by a run over `root` with `options`.
+fn gain(root: &Path, options: &[&str]) -> serde_json::Value {
+    let report = root.with_extension("json");
+    let mut args: Vec<_> = options.iter().map(Into::into).collect();
+    args.extend(["--format".into(), "json".into(), "--output".into()]);
+    args.extend([report.clone().into_os_string(), root.as_os_str().to_owned()]);
+    dedup(args);
+    let value: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    fs::remove_file(report).unwrap();
+    value["gain"].clone()
+}
+
+#[test]
+fn dry_run_changes_no_inode() {
+    let mut dry = TreeBuilder::new().unwrap();
+    dry.generate(SPEC).unwrap();
+    let before = inodes(dry.root());
+    let projected = gain(dry.root(), &["--dry-run"]);
+    assert_eq!(inodes(dry.root()), before);
+
+    let mut real = TreeBuilder::new().unwrap();
+    real.generate(SPEC).unwrap();
+    let gained = gain(real.root(), &[]);
+    assert!(gained.as_u64().unwrap() > 0);
+    assert_eq!(projected, gained);
+}
+
+#[test]
+fn parallel_runs_plan_the_same_every_time() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.generate(SPEC).unwrap();
+    // equal mtimes, so that only the normalization breaks the ties between originals
+    for path in inodes(tree.root()).into_keys() {
+        filetime::set_file_mtime(path, FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+    }
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+            .args(["--dry-run", "--threads", "8"])
+            .arg(tree.root())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    let first = run();
+    assert!(first.windows(3).any(|line| line == b"<- "));
+    for _ in 1..20 {
+        assert_eq!(
+            String::from_utf8(run()).unwrap(),
+            String::from_utf8(first.clone()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Pins representative error messages, which name the operation and every path involved.
+
+mod common;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use dedup::test_utils::{before_hash, before_rename, TreeBuilder};
+
+use common::dedup;
+
+/// The standard error of a failed run with `--verbose-errors`.
+fn verbose_errors(args: &[&Path]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .arg("--verbose-errors")
+        .args(args)
+        .env_remove("RUST_BACKTRACE")
+        .env_remove("RUST_LIB_BACKTRACE")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn fatal_errors_print_their_chain_with_the_os_error() {
+    let tree = TreeBuilder::new().unwrap();
+    let missing = tree.path("missing");
+    assert_eq!(
+        verbose_errors(&[&missing]),
+        format!(
+            "Error: Failed to read a directory entry: {missing} (under target {missing})\n    \
+             caused by: IO error for operation on {missing}: No such file or directory (os error 2)\n    \
+             caused by: No such file or directory (os error 2) [errno 2, NotFound]\n",
+            missing = missing.display()
+        )
+    );
+    assert_eq!(
+        verbose_errors(&["--manifest".as_ref(), &missing, tree.root()]),
+        format!(
+            "Error: Failed to open the manifest: {}\n    \
+             caused by: No such file or directory (os error 2) [errno 2, NotFound]\n",
+            missing.display()
+        )
+    );
+}
+
+/// The messages of the errors a run over `tree` went on after.
+fn continued_errors(tree: &TreeBuilder) -> Vec<String> {
+    let report = tree.path("report.json");
+    dedup([
+        "--keep-going".as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["message"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn continued_errors_name_the_stage_and_the_paths() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .mtime("t/a", 1_000_000_000)
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    let (a, b) = (tree.path("t/a"), tree.path("t/b"));
+    let _hook = {
+        let b = b.clone();
+        before_rename(move |path| {
+            if path == b {
+                return Err(io::Error::from_raw_os_error(libc::EIO));
+            }
+            Ok(())
+        })
+    };
+    assert_eq!(
+        continued_errors(&tree),
+        [format!(
+            "Failed to fs::rename over the duplicate while relinking {} to {}: \
+             Input/output error (os error 5)",
+            b.display(),
+            a.display()
+        )]
+    );
+
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    let b = tree.path("t/b");
+    let _hook = {
+        let b = b.clone();
+        before_hash(move |path| {
+            if path == b {
+                return Err(io::Error::from_raw_os_error(libc::EACCES));
+            }
+            Ok(())
+        })
+    };
+    assert_eq!(
+        continued_errors(&tree),
+        [format!(
+            "Failed to calculate a hash: {}: Permission denied (os error 13)",
+            b.display()
+        )]
+    );
+}
@@ -0,0 +1,32 @@
+use clap::Parser as _;
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+/// Runs `dedup` with `args`, returning its error.
+fn run(args: &[&str]) -> anyhow::Result<()> {
+    let args = dedup::Args::parse_from(std::iter::once("dedup").chain(args.iter().copied()));
+    dedup::run(args).map(drop)
+}
+
+#[test]
+fn estimate_stops_the_relink_without_confirm() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a", "same").unwrap().file("b", "same").unwrap();
+    let root = tree.root().to_str().unwrap();
+
+    let err = run(&["--estimate-relink", root]).unwrap_err();
+    assert!(err.to_string().contains("--confirm"), "{err}");
+    assert_not_linked(tree.path("a"), tree.path("b"));
+
+    // the estimate alone
+    run(&["--estimate-relink", "--dry-run", "--quiet", root]).unwrap();
+    assert_not_linked(tree.path("a"), tree.path("b"));
+
+    run(&["--quiet", root]).unwrap();
+    assert_linked(tree.path("a"), tree.path("b"));
+}
+
+#[test]
+fn confirm_needs_the_estimate() {
+    let args = ["dedup", "--confirm", "."];
+    assert!(dedup::Args::try_parse_from(args).is_err());
+}
@@ -0,0 +1,76 @@
+use std::process::Command;
+
+use dedup::test_utils::TreeBuilder;
+
+/// The decisions printed for `path` by `--explain`.
+fn decisions<'a>(stdout: &'a str, path: &str) -> Vec<&'a str> {
+    let header = format!("Explain {}:\n", path);
+    let start = stdout.find(&header).unwrap_or_else(|| panic!("{stdout}")) + header.len();
+    stdout[start..]
+        .lines()
+        .map_while(|line| line.strip_prefix("  - "))
+        .collect()
+}
+
+#[test]
+fn each_watched_path_gets_its_decision_chain() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("original", "same")
+        .unwrap()
+        .mtime("original", 1_000_000_000)
+        .unwrap()
+        .file("duplicate", "same")
+        .unwrap()
+        .file("unique", "x")
+        .unwrap()
+        .file("excluded.tmp", "same")
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--exclude", "*.tmp"])
+        .args(["--explain", "original", "--explain", "duplicate"])
+        .args(["--explain", "unique", "--explain", "excluded.tmp"])
+        .args(["--explain", "missing", "."])
+        .current_dir(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    for path in ["original", "duplicate"] {
+        let decisions = decisions(&stdout, path);
+        assert!(
+            decisions[0].starts_with("scanned: 4 bytes, inode "),
+            "{stdout}"
+        );
+        assert!(
+            decisions.contains(&"grouped with 1 other inodes"),
+            "{stdout}"
+        );
+    }
+    assert_eq!(
+        decisions(&stdout, "original").last(),
+        Some(&"kept as the original")
+    );
+    assert_eq!(
+        decisions(&stdout, "duplicate").last(),
+        Some(&"linked to ./original")
+    );
+    let unique = decisions(&stdout, "unique");
+    assert_eq!(
+        unique[1..],
+        [
+            "unique size so far: not hashed",
+            "not part of any duplicate group"
+        ],
+        "{stdout}"
+    );
+    assert_eq!(
+        decisions(&stdout, "excluded.tmp"),
+        [
+            "excluded by --exclude: not scanned",
+            "not part of any duplicate group"
+        ]
+    );
+    // reported even though nothing is ever found at the path
+    assert_eq!(decisions(&stdout, "missing"), ["never found by the walk"]);
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::process::{Command, Output};
+
+use dedup::test_utils::TreeBuilder;
+
+fn run(tree: &TreeBuilder, options: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(options)
+        .arg(tree.path("t"))
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap()
+}
+
+fn duplicates() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .mtime("t", 1_000_000_000)
+        .unwrap();
+    tree
+}
+
+/// The status of `feature` in the JSON report of `output`.
+fn feature(output: &Output, feature: &str) -> Option<serde_json::Value> {
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    report["features"]
+        .as_array()?
+        .iter()
+        .find(|status| status["feature"] == feature)
+        .cloned()
+}
+
+#[test]
+fn requested_features_are_probed_and_reported() {
+    let tree = duplicates();
+    let output = run(&tree, &["--direct-io", "--dry-run", "--format", "json"]);
+    assert!(output.status.success(), "{output:?}");
+    let status = feature(&output, "direct-io").unwrap();
+    assert!(status["available"].is_boolean(), "{status}");
+    assert_eq!(
+        status["available"] == false,
+        status["degraded_to"] == "buffered reads",
+        "{status}"
+    );
+    // nothing else is relied on in a dry run
+    assert_eq!(feature(&output, "inode-flags"), None);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Degraded to"), "{stderr}");
+    assert!(stderr.contains("direct-io"), "{stderr}");
+}
+
+#[test]
+fn probes_leave_no_trace() {
+    let tree = duplicates();
+    let output = run(
+        &tree,
+        &["--direct-io", "--conflict-fallback", "reflink", "--quiet"],
+    );
+    assert!(output.status.success(), "{output:?}");
+    let mut names: Vec<_> = fs::read_dir(tree.path("t"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a", "b"]);
+    assert_eq!(fs::metadata(tree.path("t")).unwrap().mtime(), 1_000_000_000);
+}
+
+#[test]
+fn inode_flags_are_only_mentioned_when_missing() {
+    let tree = duplicates();
+    let output = run(&tree, &["--format", "json"]);
+    assert!(output.status.success(), "{output:?}");
+    let status = feature(&output, "inode-flags").unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr.contains("Degraded to"),
+        status["available"] == false,
+        "{status}: {stderr}"
+    );
+}
+
+#[test]
+fn strict_features_aborts_without_a_requested_feature() {
+    let options = ["--conflict-fallback", "reflink", "--format", "json"];
+    let output = run(&duplicates(), &options);
+    assert!(output.status.success(), "{output:?}");
+    let available = feature(&output, "reflink").unwrap()["available"] == true;
+
+    let tree = duplicates();
+    let output = run(&tree, &[&options[..], &["--strict-features"]].concat());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.success(), available, "{stderr}");
+    if !available {
+        assert!(
+            stderr.contains("--strict-features: not available in") && stderr.contains("reflink"),
+            "{stderr}"
+        );
+        // nothing was linked before the abort
+        let ino = |path| fs::metadata(tree.path(path)).unwrap().ino();
+        assert_ne!(ino("t/a"), ino("t/b"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Loads the C ABI from the cdylib, as a C program would.
+#![cfg(feature = "ffi")]
+
+use std::ffi::{c_char, CStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::slice;
+
+use dedup::test_utils::TreeBuilder;
+use libloading::{Library, Symbol};
+
+/// The cdylib built along with the test, next to it in the deps directory.
+fn library() -> Library {
+    let exe = std::env::current_exe().unwrap();
+    let path = exe.parent().unwrap().join("libdedup.so");
+    unsafe { Library::new(&path) }.unwrap_or_else(|err| panic!("{}: {err}", path.display()))
+}
+
+enum DedupOptions {}
+enum DedupScan {}
+
+#[test]
+fn scans_through_the_c_abi() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a", "same")
+        .unwrap()
+        .file("b", "same")
+        .unwrap()
+        .file("c", "other")
+        .unwrap();
+    let library = library();
+    unsafe {
+        let options_new: Symbol<extern "C" fn() -> *mut DedupOptions> =
+            library.get(b"dedup_options_new").unwrap();
+        let add_target: Symbol<unsafe extern "C" fn(*mut DedupOptions, *const u8, usize)> =
+            library.get(b"dedup_options_add_target").unwrap();
+        let options_free: Symbol<unsafe extern "C" fn(*mut DedupOptions)> =
+            library.get(b"dedup_options_free").unwrap();
+        let scan_run: Symbol<unsafe extern "C" fn(*const DedupOptions) -> *mut DedupScan> =
+            library.get(b"dedup_scan_run").unwrap();
+        let scan_error: Symbol<unsafe extern "C" fn(*const DedupScan) -> *const c_char> =
+            library.get(b"dedup_scan_error").unwrap();
+        let group_count: Symbol<unsafe extern "C" fn(*const DedupScan) -> usize> =
+            library.get(b"dedup_scan_group_count").unwrap();
+        let group_size: Symbol<unsafe extern "C" fn(*const DedupScan, usize) -> u64> =
+            library.get(b"dedup_scan_group_size").unwrap();
+        let group_hash: Symbol<unsafe extern "C" fn(*const DedupScan, usize) -> *const u8> =
+            library.get(b"dedup_scan_group_hash").unwrap();
+        let path_count: Symbol<unsafe extern "C" fn(*const DedupScan, usize) -> usize> =
+            library.get(b"dedup_scan_group_path_count").unwrap();
+        let group_path: Symbol<
+            unsafe extern "C" fn(*const DedupScan, usize, usize, *mut usize) -> *const u8,
+        > = library.get(b"dedup_scan_group_path").unwrap();
+        let scan_free: Symbol<unsafe extern "C" fn(*mut DedupScan)> =
+            library.get(b"dedup_scan_free").unwrap();
+
+        let options = options_new();
+        let root = tree.root().as_os_str().as_bytes();
+        add_target(options, root.as_ptr(), root.len());
+        let scan = scan_run(options);
+        options_free(options);
+
+        assert!(scan_error(scan).is_null());
+        assert_eq!(group_count(scan), 1);
+        assert_eq!(group_size(scan, 0), 4);
+        let hash = slice::from_raw_parts(group_hash(scan, 0), 32);
+        assert_eq!(
+            hex::encode(hash),
+            "0967115f2813a3541eaef77de9d9d5773f1c0c04314b0bbfe4ff3b3b1c55b5d5"
+        );
+        assert_eq!(path_count(scan, 0), 2);
+        let paths: Vec<_> = (0..2)
+            .map(|index| {
+                let mut len = 0;
+                let path = group_path(scan, 0, index, &mut len);
+                PathBuf::from(std::ffi::OsStr::from_bytes(slice::from_raw_parts(
+                    path, len,
+                )))
+            })
+            .collect();
+        assert_eq!(paths, [tree.path("a"), tree.path("b")]);
+        scan_free(scan);
+
+        // a missing target is reported through the scan, not a crash
+        let options = options_new();
+        let missing = tree.path("missing");
+        let missing = missing.as_os_str().as_bytes();
+        add_target(options, missing.as_ptr(), missing.len());
+        let scan = scan_run(options);
+        options_free(options);
+        let error = scan_error(scan);
+        assert!(!error.is_null());
+        assert!(!CStr::from_ptr(error).to_bytes().is_empty());
+        assert_eq!(group_count(scan), 0);
+        scan_free(scan);
+    }
+}
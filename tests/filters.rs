@@ -0,0 +1,204 @@
+mod common;
+
+use std::os::unix::fs::symlink;
+use std::process::{Command, Output};
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+#[test]
+fn sizes_outside_the_limits_are_left_alone() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for (name, size) in [("small", 1000), ("fits", 4096), ("large", 10000)] {
+        tree.sized(format!("{name}/a"), size, b'x')
+            .unwrap()
+            .sized(format!("{name}/b"), size, b'x')
+            .unwrap();
+    }
+    dedup([
+        "--quiet".as_ref(),
+        "--min-size".as_ref(),
+        "4k".as_ref(),
+        "--max-size".as_ref(),
+        "8K".as_ref(),
+        tree.root().as_os_str(),
+    ]);
+    assert_not_linked(tree.path("small/a"), tree.path("small/b"));
+    assert_linked(tree.path("fits/a"), tree.path("fits/b"));
+    assert_not_linked(tree.path("large/a"), tree.path("large/b"));
+}
+
+#[test]
+fn excluded_duplicates_stay_unlinked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for path in ["a", "b.tmp", "build/c", "src/build/d", "src/e"] {
+        tree.file(path, "same").unwrap();
+    }
+    dedup([
+        "--quiet".as_ref(),
+        "--exclude".as_ref(),
+        "*.tmp".as_ref(),
+        "--exclude".as_ref(),
+        "build".as_ref(),
+        tree.root().as_os_str(),
+    ]);
+    assert_linked(tree.path("a"), tree.path("src/e"));
+    for path in ["b.tmp", "build/c", "src/build/d"] {
+        assert_not_linked(tree.path("a"), tree.path(path));
+    }
+}
+
+#[test]
+fn empty_files_are_only_linked_with_dedup_empty() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for path in ["a/.keep", "b/.keep", "c/lock"] {
+        tree.file(path, "").unwrap();
+    }
+    dedup(["--quiet".as_ref(), tree.root().as_os_str()]);
+    assert_not_linked(tree.path("a/.keep"), tree.path("b/.keep"));
+    assert_not_linked(tree.path("a/.keep"), tree.path("c/lock"));
+    assert_not_linked(tree.path("b/.keep"), tree.path("c/lock"));
+
+    dedup([
+        "--quiet".as_ref(),
+        "--dedup-empty".as_ref(),
+        tree.root().as_os_str(),
+    ]);
+    assert_linked(tree.path("a/.keep"), tree.path("b/.keep"));
+    assert_linked(tree.path("a/.keep"), tree.path("c/lock"));
+}
+
+/// Runs `dedup filters` with `args` in the root of `tree`.
+fn filters(tree: &TreeBuilder, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .arg("filters")
+        .args(args)
+        .current_dir(tree.root())
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{args:?}: {output:?}");
+    output
+}
+
+fn filtered_tree() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    for path in ["t/a", "t/b", "t/x.tmp", "t/build/c"] {
+        tree.file(path, "same").unwrap();
+    }
+    tree.file("t/empty", "")
+        .unwrap()
+        .file("t/small", "x")
+        .unwrap();
+    symlink("a", tree.path("t/link")).unwrap();
+    tree
+}
+
+const RULES: [&str; 6] = [
+    "--exclude",
+    "*.tmp",
+    "--exclude",
+    "build",
+    "--min-size",
+    "2",
+];
+
+#[test]
+fn filters_test_gives_each_entry_its_verdict_and_rule() {
+    let tree = filtered_tree();
+    let output = filters(&tree, &[&["--test"][..], &RULES, &["t"]].concat());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<_> = stdout.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "filtered t/empty  (empty, without --dedup-empty)",
+            "filtered t/link  (not a regular file)",
+            "filtered t/small  (--min-size)",
+            "filtered t/x.tmp  (--exclude *.tmp)",
+            "pruned   t/build  (--exclude build)",
+            "scanned  t",
+            "scanned  t/a",
+            "scanned  t/b",
+        ]
+    );
+    // nothing is hashed or linked
+    assert_not_linked(tree.path("t/a"), tree.path("t/b"));
+}
+
+#[test]
+fn filters_test_agrees_with_the_scan() {
+    let tree = filtered_tree();
+    let output = filters(
+        &tree,
+        &[&["--test", "--format", "json"][..], &RULES, &["t"]].concat(),
+    );
+    let tests: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(tests["entries"], 8, "{tests}");
+    let tests = tests["tests"].as_array().unwrap();
+    let scanned = tests
+        .iter()
+        .filter(|test| {
+            test["verdict"] == "scanned" && tree.path(test["path"].as_str().unwrap()).is_file()
+        })
+        .count();
+    let left_out = tests
+        .iter()
+        .filter(|test| test["verdict"] != "scanned")
+        .count();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--dry-run", "--format", "json"])
+        .args(RULES)
+        .arg("t")
+        .current_dir(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // the scan counts the files it filters by size among those scanned
+    let files_scanned = report["summary"]["files_scanned"].as_u64().unwrap();
+    let size_filtered = report["size_filtered"].as_u64().unwrap();
+    assert_eq!(files_scanned - size_filtered, scanned as u64, "{report}");
+    let skipped: u64 = report["skips"]["counts"]
+        .as_object()
+        .unwrap()
+        .values()
+        .map(|count| count.as_u64().unwrap())
+        .sum();
+    assert_eq!(skipped, left_out as u64, "{report}");
+}
+
+#[test]
+fn filters_test_samples_the_same_entries_for_a_seed() {
+    let tree = filtered_tree();
+    let args = ["--test", "--sample", "3", "--seed", "7", "t"];
+    let first = String::from_utf8(filters(&tree, &args).stdout).unwrap();
+    let lines: Vec<_> = first.lines().collect();
+    assert_eq!(lines.len(), 4, "{first}");
+    assert_eq!(lines[3], "Sampled 3 of 9 entries (seed 7)");
+    assert_eq!(
+        String::from_utf8(filters(&tree, &args).stdout).unwrap(),
+        first
+    );
+}
+
+#[test]
+fn filters_without_test_lists_the_rules() {
+    let tree = TreeBuilder::new().unwrap();
+    let output = filters(&tree, &RULES);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            "--exclude *.tmp: prunes matching directories, filters matching files",
+            "--exclude build: prunes matching directories, filters matching files",
+            "Directories already walked, e.g. through a bind mount: pruned",
+            "Anything but regular files: filtered",
+            "Empty files, without --dedup-empty: filtered",
+            "--min-size 2: filters smaller files",
+        ]
+    );
+}
@@ -0,0 +1,79 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::process::Command;
+
+use dedup::test_utils::{assert_not_linked, TreeBuilder};
+
+/// The output of a run over the target of `tree` in `format`, from its root.
+fn export(tree: &TreeBuilder, format: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .current_dir(tree.root())
+        .args(["--dry-run", "--format", format, "t"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn groups() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "one")
+        .unwrap()
+        .file("t/with, comma", "one")
+        .unwrap()
+        .file("t/c", "two")
+        .unwrap()
+        .file(OsStr::from_bytes(b"t/caf\xe9"), "two")
+        .unwrap()
+        .file("t/unique", "three")
+        .unwrap();
+    tree
+}
+
+#[test]
+fn flat_csv_has_a_row_per_file_of_every_group() {
+    let tree = groups();
+    let csv = export(&tree, "flat-csv");
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("group_id,role,path,size,hash,inode,nlink")
+    );
+    let rows: Vec<&str> = lines.collect();
+    assert_eq!(rows.len(), 4, "{csv}");
+    // the group id of the row with `fields` after it
+    let group_of = |fields: &str| {
+        let row = rows.iter().find(|row| row.contains(fields)).unwrap();
+        row.split_once(',').unwrap().0
+    };
+    let group = group_of(",original,t/a,3,");
+    assert_eq!(group_of(",duplicate,\"t/with, comma\",3,"), group);
+    let other = group_of(",original,t/c,3,");
+    assert_eq!(group_of(",duplicate,t/caf\u{fffd},3,"), other);
+    assert_ne!(group, other);
+    // report-only
+    assert_not_linked(tree.path("t/a"), tree.path("t/with, comma"));
+}
+
+#[test]
+fn group_ids_are_stable_across_exports() {
+    let tree = groups();
+    let csv = export(&tree, "flat-csv");
+    assert_eq!(export(&tree, "flat-csv"), csv);
+
+    // and joinable with the JSON rows
+    let json: serde_json::Value = serde_json::from_str(&export(&tree, "flat-json")).unwrap();
+    let rows = json.as_array().unwrap();
+    assert_eq!(rows.len(), 4, "{json}");
+    for row in rows {
+        let line = csv
+            .lines()
+            .find(|line| line.contains(&format!("{},", row["hash"].as_str().unwrap())))
+            .unwrap();
+        assert!(
+            line.starts_with(&format!("{},", row["group_id"].as_str().unwrap())),
+            "{line}: {row}"
+        );
+        assert_eq!(row["size"], 3, "{row}");
+    }
+}
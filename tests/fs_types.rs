@@ -0,0 +1,78 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+/// Runs `dedup` over `tree` with `args`, and returns its JSON report.
+fn json_run(tree: &TreeBuilder, args: &[&str]) -> serde_json::Value {
+    let report = tree.path("report.json");
+    let mut argv: Vec<&std::ffi::OsStr> = args.iter().map(|arg| arg.as_ref()).collect();
+    argv.extend([
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+    ]);
+    let target = tree.path("t");
+    argv.push(target.as_os_str());
+    dedup(argv);
+    serde_json::from_slice(&fs::read(report).unwrap()).unwrap()
+}
+
+fn duplicates() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    tree
+}
+
+#[test]
+fn devices_outside_the_allowlist_are_report_only() {
+    // the type of the filesystem the tests run on, whatever it is
+    let tree = duplicates();
+    let report = json_run(&tree, &["--dry-run"]);
+    let fs_type = report["devices"][0]["fs_type"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(report["devices"][0]["report_only"], false, "{report}");
+
+    for args in [
+        ["--fs-allow", "no-such-type"],
+        ["--fs-deny", &format!("vfat,{fs_type}")],
+    ] {
+        let tree = duplicates();
+        let report = json_run(&tree, &args);
+        assert_not_linked(tree.path("t/a"), tree.path("t/b"));
+        assert_eq!(
+            report["devices"][0]["report_only"], true,
+            "{args:?}: {report}"
+        );
+        // still scanned and reported
+        assert_eq!(report["groups_found"], 1, "{args:?}: {report}");
+    }
+
+    let tree = duplicates();
+    let report = json_run(&tree, &["--fs-allow", &format!("xfs,{fs_type}")]);
+    assert_linked(tree.path("t/a"), tree.path("t/b"));
+    assert_eq!(report["devices"][0]["report_only"], false, "{report}");
+}
+
+#[test]
+fn report_only_devices_are_annotated_in_the_summary() {
+    let tree = duplicates();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--fs-allow", "no-such-type"])
+        .arg(tree.path("t"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(": report-only"), "{stdout}");
+    assert_not_linked(tree.path("t/a"), tree.path("t/b"));
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use dedup::test_utils::{assert_linked, before_hash, TreeBuilder};
+
+use common::dedup;
+
+#[test]
+fn each_inode_is_hashed_once_whatever_its_links() {
+    for threads in ["1", "4"] {
+        let mut tree = TreeBuilder::new().unwrap();
+        // the size first seen through a link, each inode found through several paths
+        tree.file("t/a1", "xxxx")
+            .unwrap()
+            .link("t/a1", "t/a2")
+            .unwrap()
+            .link("t/a1", "t/d/a3")
+            .unwrap()
+            .file("t/b1", "xxxx")
+            .unwrap()
+            .link("t/b1", "t/d/b2")
+            .unwrap()
+            .file("t/c1", "yyyy")
+            .unwrap()
+            .link("t/c1", "t/c2")
+            .unwrap();
+        let root = tree.path("t");
+        let hashes = Arc::new(Mutex::new(BTreeMap::new()));
+        let _hook = {
+            let (root, hashes) = (root.clone(), Arc::clone(&hashes));
+            before_hash(move |path| {
+                if path.starts_with(&root) {
+                    let ino = fs::metadata(path).unwrap().ino();
+                    *hashes.lock().unwrap().entry(ino).or_insert(0) += 1;
+                }
+                Ok(())
+            })
+        };
+        let report = tree.path("report.json");
+        dedup([
+            "--threads".as_ref(),
+            threads.as_ref(),
+            "--format".as_ref(),
+            "json".as_ref(),
+            "--output".as_ref(),
+            report.as_os_str(),
+            root.as_os_str(),
+        ]);
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+        let hashes = hashes.lock().unwrap();
+        assert_eq!(hashes.len(), 3, "{hashes:?}");
+        assert!(hashes.values().all(|&n| n == 1), "{hashes:?}");
+        assert_eq!(report["hashed"], 3, "{report}");
+        assert_linked(tree.path("t/a1"), tree.path("t/d/b2"));
+    }
+}
+
+#[test]
+fn a_second_run_prints_only_the_summary() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/d/b", "same")
+        .unwrap()
+        .file("t/c", "other")
+        .unwrap()
+        .link("t/c", "t/d/e")
+        .unwrap()
+        .mtime("t/a", 1_000_000_000)
+        .unwrap();
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+            .arg(tree.path("t"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // the links made beforehand are already deduplicated, and need no group line
+    let first = run();
+    assert!(first.contains("<- "), "{first}");
+    assert!(!first.contains("t/d/e"), "{first}");
+    assert!(first.contains("Already deduplicated: 1 files\n"), "{first}");
+    assert_linked(tree.path("t/a"), tree.path("t/d/b"));
+    let mtime = fs::metadata(tree.path("t/a")).unwrap().mtime();
+
+    let second = run();
+    assert_eq!(
+        second,
+        "No duplicates found\nAlready deduplicated: 2 files\nGain: 0 bytes\nHashed: 0 files\n"
+    );
+    // nor is the original touched again
+    assert_eq!(fs::metadata(tree.path("t/a")).unwrap().mtime(), mtime);
+}
+
+#[test]
+fn paths_beyond_the_cap_are_counted_not_stored() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("preferred/original", "same")
+        .unwrap()
+        .file("farm/0", "same")
+        .unwrap();
+    for i in 1..200 {
+        tree.link("farm/0", format!("farm/{i}")).unwrap();
+    }
+    let report = tree.path("report.json");
+    dedup([
+        "--max-paths-per-inode".as_ref(),
+        "3".as_ref(),
+        "--prefer".as_ref(),
+        tree.path("preferred").as_os_str(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.root().as_os_str(),
+    ]);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+
+    // only the stored paths are relinked, and the inode stays in use through the others
+    let linked = report["groups"][0]["linked"].as_array().unwrap();
+    assert_eq!(linked.len(), 3, "{report}");
+    for path in linked {
+        assert_linked(tree.path("preferred/original"), path.as_str().unwrap());
+    }
+    assert_eq!(report["capped_inodes"], 1, "{report}");
+    assert_eq!(report["gain"], 0, "{report}");
+    let farm = fs::metadata(tree.path("farm/0")).unwrap();
+    let original = fs::metadata(tree.path("preferred/original")).unwrap();
+    assert_eq!(farm.nlink() + original.nlink(), 201);
+    assert_eq!(report["summary"]["files_scanned"], 201, "{report}");
+}
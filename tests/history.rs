@@ -0,0 +1,113 @@
+use std::fs;
+use std::io::Write as _;
+use std::process::{Command, Output};
+
+use dedup::test_utils::TreeBuilder;
+
+fn run(tree: &TreeBuilder, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .current_dir(tree.root())
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    output
+}
+
+/// The records of the history of `tree` that parse.
+fn records(tree: &TreeBuilder) -> Vec<serde_json::Value> {
+    fs::read_to_string(tree.path("history.jsonl"))
+        .unwrap()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// `n` with its thousands separated, as the history prints it.
+fn with_commas(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+#[test]
+fn every_run_appends_a_record_per_device() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    run(&tree, &["--history", "history.jsonl", "--dry-run", "t"]);
+    run(&tree, &["--history", "history.jsonl", "t"]);
+
+    let records = records(&tree);
+    assert_eq!(records.len(), 2);
+    let [dry_run, real] = [&records[0], &records[1]];
+    assert_eq!(dry_run["version"], 1);
+    assert_eq!(dry_run["dry_run"], true);
+    assert_eq!(real["dry_run"], false);
+    assert_eq!(real["groups"], 1);
+    assert_eq!(real["errors"], 0);
+    assert_eq!(real["gain"], dry_run["gain"]);
+    assert_ne!(real["run"], dry_run["run"]);
+    // the same options but for --dry-run
+    assert_ne!(real["settings_hash"], dry_run["settings_hash"]);
+    for field in ["timestamp", "dev", "fs_id", "fs_type"] {
+        assert!(!real[field].is_null(), "{field}: {real}");
+    }
+}
+
+#[test]
+fn corrupt_records_are_skipped_with_a_warning() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    run(&tree, &["--history", "history.jsonl", "--dry-run", "t"]);
+    // a run that crashed while appending
+    fs::OpenOptions::new()
+        .append(true)
+        .open(tree.path("history.jsonl"))
+        .unwrap()
+        .write_all(br#"{"version":1,"run":12"#)
+        .unwrap();
+    run(&tree, &["--history", "history.jsonl", "t"]);
+    let records = records(&tree);
+    assert_eq!(records.len(), 2);
+    let gain = with_commas(records[1]["gain"].as_u64().unwrap());
+
+    let output = run(&tree, &["history", "history.jsonl", "--summary"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("Warning: skipped a corrupt record at line 2 of history.jsonl: "),
+        "{stderr}"
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("Runs: 1 (1 dry runs not counted)\nFrom "),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with(&format!(
+            "Total gain: {gain} bytes\nAverage per run: {gain} bytes, 1 groups\n"
+        )),
+        "{stdout}"
+    );
+
+    let output = run(&tree, &["history", "history.jsonl"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{stdout}");
+    assert!(lines[0].contains(" (dry run)  gain: "), "{stdout}");
+    assert!(
+        lines[1].ends_with(&format!("  gain: {gain} bytes, groups: 1, errors: 0")),
+        "{stdout}"
+    );
+}
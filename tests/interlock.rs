@@ -0,0 +1,83 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::process::{Command, Output};
+
+use dedup::test_utils::TreeBuilder;
+
+/// Runs dedup with `options` over a pair of duplicates, and tells whether anything changed.
+/// The quarantine directory is given whatever the mode, which only quarantine uses.
+fn run(options: &[&str]) -> (bool, Output) {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(options)
+        .arg("--quarantine-dir")
+        .arg(tree.path("quarantine"))
+        .arg(tree.path("t"))
+        .output()
+        .unwrap();
+    let inodes: Vec<_> = ["t/a", "t/b"]
+        .iter()
+        .map(|path| {
+            fs::metadata(tree.path(path))
+                .ok()
+                .map(|metadata| metadata.ino())
+        })
+        .collect();
+    let unchanged = inodes[0].is_some() && inodes[1].is_some() && inodes[0] != inodes[1];
+    (!unchanged, output)
+}
+
+#[test]
+fn every_mode_acts_with_execute_and_never_with_a_dry_run() {
+    for mode in ["hardlink", "delete", "quarantine"] {
+        let (changed, output) = run(&["--mode", mode, "--execute"]);
+        assert!(output.status.success(), "{mode}: {output:?}");
+        assert!(changed, "{mode}");
+
+        for dry_run in ["--dry-run", "--no-act", "-n"] {
+            let (changed, output) = run(&["--mode", mode, dry_run]);
+            assert!(output.status.success(), "{mode} {dry_run}: {output:?}");
+            assert!(!changed, "{mode} {dry_run}");
+            // asked for, so nothing to note
+            assert!(output.stderr.is_empty(), "{mode} {dry_run}: {output:?}");
+        }
+    }
+}
+
+#[test]
+fn destructive_modes_without_execute_are_dry_runs() {
+    let (changed, output) = run(&["--mode", "hardlink"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(changed);
+
+    for mode in ["delete", "quarantine"] {
+        let (changed, output) = run(&["--mode", mode]);
+        assert!(output.status.success(), "{mode}: {output:?}");
+        assert!(!changed, "{mode}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            stderr,
+            format!("Note: --mode {mode} without --execute is a dry run; nothing is changed\n")
+        );
+    }
+}
+
+#[test]
+fn execute_conflicts_with_a_dry_run() {
+    for mode in ["hardlink", "delete", "quarantine"] {
+        for dry_run in ["--dry-run", "--no-act"] {
+            let (changed, output) = run(&["--mode", mode, "--execute", dry_run]);
+            assert_eq!(output.status.code(), Some(2), "{mode} {dry_run}");
+            assert!(!changed, "{mode} {dry_run}");
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(
+                stderr.contains("the argument '--execute' cannot be used with '--dry-run'"),
+                "{mode} {dry_run}: {stderr}"
+            );
+        }
+    }
+}
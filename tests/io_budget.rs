@@ -0,0 +1,47 @@
+mod common;
+
+use std::fs;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+#[test]
+fn hashing_stops_at_the_budget_and_the_rest_is_reported() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.sized("first/a1", 1000, b'a')
+        .unwrap()
+        .sized("first/a2", 1000, b'a')
+        .unwrap()
+        .sized("second/b1", 2000, b'b')
+        .unwrap()
+        .sized("second/b2", 2000, b'b')
+        .unwrap()
+        .sized("second/c", 3000, b'c')
+        .unwrap();
+    let report = tree.path("report.json");
+    // the targets are walked in order, and hashed as they are walked on a single thread:
+    // the budget is spent on the first group
+    dedup([
+        "--threads".as_ref(),
+        "1".as_ref(),
+        "--io-budget".as_ref(),
+        "2000".as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("first").as_os_str(),
+        tree.path("second").as_os_str(),
+    ]);
+
+    assert_linked(tree.path("first/a1"), tree.path("first/a2"));
+    assert_not_linked(tree.path("second/b1"), tree.path("second/b2"));
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["hashed"], 2, "{report}");
+    assert_eq!(report["gain"], report["groups"][0]["gain"], "{report}");
+    // both files of the second group, but not the file of a size seen once
+    assert_eq!(report["not_evaluated"], 2, "{report}");
+    assert_eq!(report["not_evaluated_bytes"], 4000, "{report}");
+    assert_eq!(report["skips"]["counts"]["io-budget-spent"], 2, "{report}");
+}
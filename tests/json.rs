@@ -0,0 +1,48 @@
+use std::os::unix::fs::MetadataExt;
+use std::process::Command;
+
+use dedup::test_utils::TreeBuilder;
+
+#[test]
+fn json_output_is_pure_and_has_the_documented_fields() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a", "same")
+        .unwrap()
+        .file("d/b", "same")
+        .unwrap()
+        .file("d/c", "same")
+        .unwrap()
+        .file("e", "other")
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .arg("--json")
+        .arg(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    // nothing but the report on stdout
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let summary = &report["summary"];
+    assert_eq!(summary["files_scanned"], 4, "{summary}");
+    assert_eq!(summary["files_hashed"], 3, "{summary}");
+    assert_eq!(summary["errors"], 0, "{summary}");
+    assert!(summary["gain"].as_u64().unwrap() > 0, "{summary}");
+
+    let groups = report["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 1, "{report}");
+    let group = &groups[0];
+    let dev = std::fs::metadata(tree.root()).unwrap().dev();
+    assert_eq!(group["dev"], dev, "{group}");
+    assert_eq!(group["original"], tree.path("a").to_str().unwrap());
+    assert_eq!(
+        group["linked"],
+        serde_json::json!([
+            tree.path("d/b").to_str().unwrap(),
+            tree.path("d/c").to_str().unwrap()
+        ])
+    );
+    assert_eq!(group["gain"], summary["gain"]);
+    assert_eq!(group["size"], 4);
+    assert_eq!(group["hash"].as_str().unwrap().len(), 64);
+}
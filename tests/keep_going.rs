@@ -0,0 +1,113 @@
+mod common;
+
+use std::fs;
+use std::io;
+
+use dedup::test_utils::{
+    assert_linked, assert_not_linked, before_hash, before_rename, TreeBuilder,
+};
+
+use common::dedup;
+
+#[test]
+fn unreadable_file_leaves_the_other_duplicates_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/m/unreadable", "same")
+        .unwrap()
+        .file("t/z", "same")
+        .unwrap()
+        .file("t/x1", "other")
+        .unwrap()
+        .file("t/x2", "other")
+        .unwrap();
+    // as EACCES would, which root is never denied
+    let unreadable = tree.path("t/m/unreadable");
+    let _hook = {
+        let unreadable = unreadable.clone();
+        before_hash(move |path| {
+            if path == unreadable {
+                return Err(io::Error::from_raw_os_error(libc::EACCES));
+            }
+            Ok(())
+        })
+    };
+    let report = tree.path("report.json");
+    let code = dedup([
+        "--keep-going".as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_eq!(code, 8.into());
+
+    assert_linked(tree.path("t/a"), tree.path("t/z"));
+    assert_linked(tree.path("t/x1"), tree.path("t/x2"));
+    assert_not_linked(tree.path("t/a"), &unreadable);
+    assert_eq!(fs::read(&unreadable).unwrap(), b"same");
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    // exactly one structured entry, for the file alone, and counted once
+    let errors = report["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1, "{report}");
+    let error = &errors[0];
+    assert_eq!(error["kind"], "hash", "{error}");
+    assert_eq!(
+        error["paths"],
+        serde_json::json!([unreadable.to_str().unwrap()])
+    );
+    assert_eq!(error["errno"], libc::EACCES, "{error}");
+    let message = error["message"].as_str().unwrap();
+    assert!(message.contains("denied"), "{message}");
+    assert_eq!(report["summary"]["errors"], 1, "{report}");
+}
+
+#[test]
+fn failed_relink_is_one_structured_entry() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .file("t/c", "same")
+        .unwrap();
+    let b = tree.path("t/b");
+    let _hook = {
+        let b = b.clone();
+        before_rename(move |path| {
+            if path == b {
+                return Err(io::Error::from_raw_os_error(libc::EIO));
+            }
+            Ok(())
+        })
+    };
+    let report = tree.path("report.json");
+    let code = dedup([
+        "--keep-going".as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_eq!(code, 8.into());
+
+    assert_linked(tree.path("t/a"), tree.path("t/c"));
+    assert_not_linked(tree.path("t/a"), &b);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    let errors = report["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1, "{report}");
+    let error = &errors[0];
+    assert_eq!(error["kind"], "relink", "{error}");
+    assert!(
+        error["paths"]
+            .as_array()
+            .unwrap()
+            .contains(&b.to_str().unwrap().into()),
+        "{error}"
+    );
+    assert_eq!(error["errno"], libc::EIO, "{error}");
+    assert_eq!(report["summary"]["errors"], 1, "{report}");
+}
@@ -0,0 +1,22 @@
+mod common;
+
+use dedup::test_utils::TreeBuilder;
+
+use common::{dedup, link_groups};
+
+#[test]
+fn group_is_split_between_originals_at_max_links() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for i in 0..7 {
+        tree.file(format!("f{i}"), "same").unwrap();
+    }
+    dedup([
+        "--quiet".as_ref(),
+        "--max-links".as_ref(),
+        "3".as_ref(),
+        tree.root().as_os_str(),
+    ]);
+    let mut sizes: Vec<_> = link_groups(tree.root()).iter().map(Vec::len).collect();
+    sizes.sort();
+    assert_eq!(sizes, [1, 3, 3]);
+}
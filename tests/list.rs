@@ -0,0 +1,65 @@
+mod common;
+
+use std::process::Command;
+
+use dedup::test_utils::TreeBuilder;
+
+use common::link_groups;
+
+/// Two groups, one with a file linked twice, and a file of a size of its own.
+fn fixture() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a", "same")
+        .unwrap()
+        .file("d/b", "same")
+        .unwrap()
+        .link("d/b", "d/c")
+        .unwrap()
+        .file("e", "other")
+        .unwrap()
+        .file("d/f", "other")
+        .unwrap()
+        .file("unique", "unique")
+        .unwrap();
+    tree
+}
+
+/// The listing of the fixture with `options`, listed from its root.
+fn list(options: &[&str]) -> String {
+    let tree = fixture();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .arg("--list")
+        .args(options)
+        .arg(".")
+        .current_dir(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn listing_matches_fdupes() {
+    assert_eq!(list(&[]), "./a\n./d/b\n./d/c\n\n./d/f\n./e\n\n");
+    assert_eq!(
+        list(&["--size"]),
+        "4 bytes each:\n./a\n./d/b\n./d/c\n\n5 bytes each:\n./d/f\n./e\n\n"
+    );
+    assert_eq!(
+        list(&["--size", "--hardlinks-as-one"]),
+        "4 bytes each:\n./a\n./d/b\n\n5 bytes each:\n./d/f\n./e\n\n"
+    );
+}
+
+#[test]
+fn listing_changes_nothing() {
+    let tree = fixture();
+    let before = link_groups(tree.root());
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .arg("--list")
+        .arg(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(link_groups(tree.root()), before);
+}
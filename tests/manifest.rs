@@ -0,0 +1,64 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+/// The line of `path` in a manifest, as it is now.
+fn manifest_line(path: &Path) -> String {
+    let metadata = fs::metadata(path).unwrap();
+    format!(
+        "{}\t{}.{:09}\t{}\t{}\t{}\n",
+        metadata.len(),
+        metadata.mtime(),
+        metadata.mtime_nsec(),
+        metadata.ino(),
+        metadata.dev(),
+        path.display()
+    )
+}
+
+/// Writes the manifest of the files `paths` of `tree`, and returns its path.
+fn write_manifest(tree: &TreeBuilder, paths: &[&str]) -> std::path::PathBuf {
+    let manifest: String = paths
+        .iter()
+        .map(|path| manifest_line(&tree.path(path)))
+        .collect();
+    let path = tree.path("manifest.tsv");
+    fs::write(&path, manifest).unwrap();
+    path
+}
+
+#[test]
+fn manifest_without_targets_changes_nothing() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .file("elsewhere/c", "same")
+        .unwrap();
+    let manifest = write_manifest(&tree, &["t/a", "t/b", "elsewhere/c"]);
+
+    dedup([
+        "--quiet".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+    ]);
+    assert_not_linked(tree.path("t/a"), tree.path("t/b"));
+    assert_not_linked(tree.path("t/a"), tree.path("elsewhere/c"));
+
+    // the targets say what may be modified, whatever else the manifest lists
+    dedup([
+        "--quiet".as_ref(),
+        "--manifest".as_ref(),
+        manifest.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_linked(tree.path("t/a"), tree.path("t/b"));
+    assert_not_linked(tree.path("t/a"), tree.path("elsewhere/c"));
+}
@@ -0,0 +1,77 @@
+use std::process::{Command, Output};
+
+use dedup::test_utils::{assert_not_linked, TreeBuilder};
+
+fn expect_mirrored(tree: &TreeBuilder, options: &[&str], source: &str, mirror: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(options)
+        .args(["--expect-mirrored", source, mirror])
+        .current_dir(tree.root())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn content_missing_from_the_mirror_fails_the_check() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("source/x", "one")
+        .unwrap()
+        .file("source/d/y", "two")
+        .unwrap()
+        .file("source/z", "three")
+        .unwrap()
+        .file("source/w", "four")
+        .unwrap()
+        .file("mirror/x", "one")
+        .unwrap()
+        .file("mirror/elsewhere", "two")
+        .unwrap()
+        // of the same size, but other content
+        .file("mirror/z", "thr3e")
+        .unwrap();
+
+    let output = expect_mirrored(&tree, &[], "source", "mirror");
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "source/w\nsource/z\nUnmatched: 2 of 4 files\n"
+    );
+    assert_not_linked(tree.path("source/x"), tree.path("mirror/x"));
+
+    let output = expect_mirrored(&tree, &["--unmatched-limit", "1"], "source", "mirror");
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "source/w\n... and 1 more\nUnmatched: 2 of 4 files\n"
+    );
+
+    let output = expect_mirrored(&tree, &["--json"], "source", "mirror");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "scanned": 4,
+            "unmatched_count": 2,
+            "unmatched": ["source/w", "source/z"],
+        })
+    );
+}
+
+#[test]
+fn the_check_is_directional() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("source/x", "one")
+        .unwrap()
+        .file("mirror/x", "one")
+        .unwrap()
+        .file("mirror/extra", "more")
+        .unwrap();
+    let output = expect_mirrored(&tree, &[], "source", "mirror");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Unmatched: 0 of 1 files\n"
+    );
+    let output = expect_mirrored(&tree, &[], "mirror", "source");
+    assert_eq!(output.status.code(), Some(4));
+}
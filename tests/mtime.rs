@@ -0,0 +1,221 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dedup::test_utils::{assert_linked, TreeBuilder};
+
+use common::dedup;
+
+/// The mtime the original ends up with under `policy`, for a group with mtimes 1, 2 and 3
+/// times a billion seconds where the original, preferred, is the one in between.
+fn original_mtime(policy: &str) -> i64 {
+    let mut tree = TreeBuilder::new().unwrap();
+    for (path, mtime) in [
+        ("old", 1_000_000_000),
+        ("preferred/middle", 2_000_000_000),
+        ("new", 3_000_000_000),
+    ] {
+        tree.file(path, "same").unwrap().mtime(path, mtime).unwrap();
+    }
+    dedup([
+        "--quiet".as_ref(),
+        "--mtime-policy".as_ref(),
+        policy.as_ref(),
+        "--prefer".as_ref(),
+        tree.path("preferred").as_os_str(),
+        tree.root().as_os_str(),
+    ]);
+    assert_linked(tree.path("preferred/middle"), tree.path("old"));
+    assert_linked(tree.path("preferred/middle"), tree.path("new"));
+    fs::metadata(tree.path("preferred/middle")).unwrap().mtime()
+}
+
+#[test]
+fn mtime_policies_pick_the_mtime_of_the_original() {
+    assert_eq!(original_mtime("oldest"), 1_000_000_000);
+    assert_eq!(original_mtime("newest"), 3_000_000_000);
+    assert_eq!(original_mtime("keep"), 2_000_000_000);
+}
+
+/// The seconds since the epoch.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[test]
+fn future_mtimes_are_warned_about_and_kept_from_the_original_as_asked() {
+    const YEAR: i64 = 365 * 24 * 3600;
+    const DAY: i64 = 24 * 3600;
+    for policy in ["ignore", "clamp", "skip"] {
+        // the oldest mtime of the group is a year ahead, the original's a day later still
+        let mut tree = TreeBuilder::new().unwrap();
+        let start = now();
+        for (path, mtime) in [
+            ("preferred/original", start + YEAR + DAY),
+            ("future", start + YEAR),
+        ] {
+            tree.file(path, "same").unwrap().mtime(path, mtime).unwrap();
+        }
+        let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+            .args(["--future-mtime", policy, "--prefer"])
+            .arg(tree.path("preferred"))
+            .arg(tree.root())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let end = now();
+        let (stdout, stderr) = (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        );
+        for path in ["preferred/original", "future"] {
+            let warning = format!(
+                "Warning: {} has an mtime in the future\n",
+                tree.path(path).display()
+            );
+            assert!(stderr.contains(&warning), "{policy}: {stderr}");
+        }
+        assert!(
+            stdout.contains("Mtimes in the future: 2 files\n"),
+            "{policy}: {stdout}"
+        );
+
+        assert_linked(tree.path("preferred/original"), tree.path("future"));
+        let mtime = fs::metadata(tree.path("preferred/original"))
+            .unwrap()
+            .mtime();
+        match policy {
+            "ignore" => assert_eq!(mtime, start + YEAR),
+            "clamp" => assert!((start..=end).contains(&mtime), "{mtime}"),
+            // no member may give its mtime: the original keeps its own
+            _ => assert_eq!(mtime, start + YEAR + DAY),
+        }
+    }
+}
+
+#[test]
+fn skipped_future_mtimes_leave_the_newest_mtime_to_the_others() {
+    let mut tree = TreeBuilder::new().unwrap();
+    let start = now();
+    for (path, mtime) in [
+        ("preferred/original", 1_000_000_000),
+        ("past", 1_500_000_000),
+        ("future", start + 365 * 24 * 3600),
+    ] {
+        tree.file(path, "same").unwrap().mtime(path, mtime).unwrap();
+    }
+    dedup([
+        "--quiet".as_ref(),
+        "--mtime-policy".as_ref(),
+        "newest".as_ref(),
+        "--future-mtime".as_ref(),
+        "skip".as_ref(),
+        "--prefer".as_ref(),
+        tree.path("preferred").as_os_str(),
+        tree.root().as_os_str(),
+    ]);
+    assert_linked(tree.path("preferred/original"), tree.path("future"));
+    assert_eq!(
+        fs::metadata(tree.path("preferred/original"))
+            .unwrap()
+            .mtime(),
+        1_500_000_000
+    );
+}
+
+/// The mtime the original ends up with under `--mtime-floor @1500000000` and `policy`,
+/// for a group where the original has 2 billion seconds and the other file `other`.
+fn floored_mtime(policy: &str, other: i64) -> i64 {
+    let mut tree = TreeBuilder::new().unwrap();
+    for (path, mtime) in [("preferred/original", 2_000_000_000), ("other", other)] {
+        tree.file(path, "same").unwrap().mtime(path, mtime).unwrap();
+    }
+    dedup([
+        "--quiet".as_ref(),
+        "--mtime-floor".as_ref(),
+        "@1500000000".as_ref(),
+        "--mtime-floor-policy".as_ref(),
+        policy.as_ref(),
+        "--prefer".as_ref(),
+        tree.path("preferred").as_os_str(),
+        tree.root().as_os_str(),
+    ]);
+    assert_linked(tree.path("preferred/original"), tree.path("other"));
+    fs::metadata(tree.path("preferred/original"))
+        .unwrap()
+        .mtime()
+}
+
+#[test]
+fn mtime_floor_policies_apply_only_to_groups_reaching_below_the_floor() {
+    // straddling the floor
+    assert_eq!(floored_mtime("clamp", 1_000_000_000), 1_500_000_000);
+    assert_eq!(floored_mtime("skip", 1_000_000_000), 2_000_000_000);
+    // on and above it
+    for policy in ["clamp", "skip"] {
+        assert_eq!(floored_mtime(policy, 1_500_000_000), 1_500_000_000);
+        assert_eq!(floored_mtime(policy, 1_600_000_000), 1_600_000_000);
+    }
+}
+
+#[test]
+fn mtime_floor_is_reported_per_group_when_verbose() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for (path, mtime) in [
+        ("a", 1_000_000_000),
+        ("b", 2_000_000_000),
+        ("c", 1_600_000_000),
+        ("d", 2_000_000_000),
+    ] {
+        let content = if path < "c" { "floored" } else { "above" };
+        tree.file(path, content)
+            .unwrap()
+            .mtime(path, mtime)
+            .unwrap();
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["-v", "--mtime-floor", "@1500000000"])
+        .arg(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let notes: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.contains("older than the floor"))
+        .collect();
+    assert_eq!(notes.len(), 1, "{stdout}");
+    assert!(notes[0].ends_with(", using the floor"), "{stdout}");
+}
+
+#[test]
+fn mtime_granularity_is_reported_per_device_when_verbose() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a", "same")
+        .unwrap()
+        .mtime("a", 1_000_000_000)
+        .unwrap()
+        .file("b", "same")
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .arg("-v")
+        .arg(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let device = stdout
+        .lines()
+        .find(|line| line.starts_with("Device "))
+        .unwrap();
+    assert!(device.contains(", mtime granularity "), "{stdout}");
+    assert_linked(tree.path("a"), tree.path("b"));
+    // the probe leaves nothing behind
+    assert_eq!(fs::read_dir(tree.root()).unwrap().count(), 2);
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::os::unix::fs::{chown, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output};
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+const NOBODY: u32 = 65534;
+
+/// Only root can hand files to another user and run dedup as that user.
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Runs dedup as another user with `options`, where the original is owned by root, so
+/// its mtime cannot be set, though writable by all, so it can be linked to, and the duplicate sits in a directory of root's writable by
+/// all, so its mtime cannot be restored either.
+fn run(options: &[&str]) -> (TreeBuilder, Output) {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("preferred/original", "same")
+        .unwrap()
+        .mtime("preferred/original", 1_500_000_000)
+        .unwrap()
+        .file("shared/duplicate", "same")
+        .unwrap()
+        .mtime("shared/duplicate", 1_000_000_000)
+        .unwrap();
+    chown(tree.path("shared/duplicate"), Some(NOBODY), Some(NOBODY)).unwrap();
+    for (path, mode) in [
+        ("", 0o755),
+        ("preferred", 0o755),
+        ("preferred/original", 0o666),
+        ("shared", 0o777),
+        ("shared/duplicate", 0o666),
+    ] {
+        fs::set_permissions(tree.path(path), fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    // where the other user can run it from
+    let program = tree.path("dedup");
+    fs::copy(env!("CARGO_BIN_EXE_dedup"), &program).unwrap();
+    let output = Command::new(&program)
+        .uid(NOBODY)
+        .gid(NOBODY)
+        .args(["--ignore-ownership", "--cross-user", "allow", "--prefer"])
+        .arg(tree.path("preferred"))
+        .args(options)
+        .arg(tree.path("preferred"))
+        .arg(tree.path("shared"))
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap();
+    (tree, output)
+}
+
+#[test]
+fn mtimes_that_cannot_be_set_are_warned_about() {
+    if !is_root() {
+        eprintln!("skipped: needs root");
+        return;
+    }
+    let (tree, output) = run(&[]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_linked(
+        tree.path("preferred/original"),
+        tree.path("shared/duplicate"),
+    );
+    // the mtime of the original, and of the directory of the duplicate
+    for warning in [
+        "while updating the mtime of the original: ",
+        "to restore a directory mtime: ",
+    ] {
+        assert!(stderr.contains(warning), "{stderr}");
+    }
+    assert!(stdout.contains("Failed to set mtimes: 2\n"), "{stdout}");
+    assert!(output.status.success(), "{stdout}{stderr}");
+}
+
+#[test]
+fn strict_mtime_makes_a_failure_to_set_one_fatal() {
+    if !is_root() {
+        eprintln!("skipped: needs root");
+        return;
+    }
+    let (tree, output) = run(&["--strict-mtime"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Operation not permitted"), "{stderr}");
+    assert_not_linked(
+        tree.path("preferred/original"),
+        tree.path("shared/duplicate"),
+    );
+}
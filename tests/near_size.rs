@@ -0,0 +1,57 @@
+use std::process::Command;
+
+use dedup::test_utils::{assert_not_linked, TreeBuilder};
+
+/// A file of 100 bytes, the same padded within and beyond a window of 10 bytes, and a file
+/// of a size within the window but of other content.
+fn fixture() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    let base = vec![b'a'; 100];
+    let padded = |padding: usize| [&base[..], &vec![0; padding]].concat();
+    tree.file("base", &base)
+        .unwrap()
+        .file("padded", padded(4))
+        .unwrap()
+        .file("far", padded(50))
+        .unwrap()
+        .sized("other", 102, b'b')
+        .unwrap();
+    tree
+}
+
+fn run(tree: &TreeBuilder, options: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--near-size-report", "10"])
+        .args(options)
+        .arg(".")
+        .current_dir(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn files_padded_within_the_window_are_reported_not_linked() {
+    let tree = fixture();
+    let stdout = run(&tree, &[]);
+    assert!(
+        stdout
+            .contains("Near-duplicates:\n              100 ./base\n              104 ./padded\n\n"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("./far"), "{stdout}");
+    assert!(!stdout.contains("./other"), "{stdout}");
+    assert_not_linked(tree.path("base"), tree.path("padded"));
+
+    let report: serde_json::Value = serde_json::from_str(&run(&tree, &["--json"])).unwrap();
+    assert_eq!(
+        report["near_duplicates"],
+        serde_json::json!([{
+            "files": [
+                { "path": "./base", "size": 100 },
+                { "path": "./padded", "size": 104 },
+            ]
+        }])
+    );
+}
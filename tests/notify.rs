@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read as _;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use dedup::test_utils::TreeBuilder;
+
+/// Writes the outcome the hook is given to `notified` in the root of the tree.
+const NOTIFY: &str = "env | grep ^DEDUP_ > notified";
+
+fn duplicates() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    tree
+}
+
+fn run(tree: &TreeBuilder, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .current_dir(tree.root())
+        .args(args)
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap()
+}
+
+/// The variables the hook was run with.
+fn notified(tree: &TreeBuilder) -> BTreeMap<String, String> {
+    fs::read_to_string(tree.path("notified"))
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (name, value) = line.split_once('=').unwrap();
+            (name.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn hook_is_told_how_the_run_went() {
+    let tree = duplicates();
+    let output = run(&tree, &["--quiet", "--notify-cmd", NOTIFY, "t"]);
+    assert!(output.status.success(), "{output:?}");
+    let env = notified(&tree);
+    assert_eq!(env["DEDUP_STATUS"], "success");
+    assert_eq!(env["DEDUP_GROUPS"], "1");
+    assert_eq!(env["DEDUP_ERRORS"], "0");
+    assert_ne!(env["DEDUP_GAIN_BYTES"], "0");
+    assert_eq!(env["DEDUP_DURATION_SECS"], "0");
+    assert_eq!(env.len(), 5, "{env:?}");
+
+    // and runs however the run ends
+    let tree = duplicates();
+    let output = run(&tree, &["--notify-cmd", NOTIFY, "t/missing"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(notified(&tree)["DEDUP_STATUS"], "failure");
+}
+
+#[test]
+fn hook_runs_when_the_run_is_interrupted() {
+    let tree = duplicates();
+    // stopped where it asks to go on
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .current_dir(tree.root())
+        .args([
+            "--estimate-relink",
+            "--confirm",
+            "--notify-cmd",
+            NOTIFY,
+            "t",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // open until the end, or the run would go on as a dry run
+    let _stdin = child.stdin.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let mut prompt = Vec::new();
+    let mut byte = [0];
+    while !prompt.ends_with(b"[y/N] ") {
+        assert_eq!(stderr.read(&mut byte).unwrap(), 1, "{prompt:?}");
+        prompt.push(byte[0]);
+    }
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), Some(128 + libc::SIGTERM));
+    assert_eq!(notified(&tree)["DEDUP_STATUS"], "cancelled");
+}
+
+#[test]
+fn hook_failures_are_only_warnings() {
+    let tree = duplicates();
+    let output = run(&tree, &["--quiet", "--notify-cmd", "exit 3", "t"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "Warning: --notify-cmd exited with exit status: 3\n");
+
+    let started = Instant::now();
+    let output = run(
+        &tree,
+        &[
+            "--quiet",
+            "--notify-cmd",
+            "sleep 30",
+            "--notify-timeout",
+            "1",
+            "t",
+        ],
+    );
+    assert!(output.status.success());
+    assert!(started.elapsed() < Duration::from_secs(10));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr,
+        "Warning: --notify-cmd still running after 1s, killed\n"
+    );
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+const FILES: [(&str, &str); 7] = [
+    ("d1/f", "one"),
+    ("d2/f", "one"),
+    ("d3/f", "one"),
+    ("e", "one"),
+    ("g/x", "two"),
+    ("g/y", "two"),
+    ("h", "two"),
+];
+
+/// The originals picked over the files created in `order`, relative to the root.
+fn originals(order: impl Iterator<Item = (&'static str, &'static str)>) -> BTreeSet<PathBuf> {
+    let mut tree = TreeBuilder::new().unwrap();
+    for (path, contents) in order {
+        tree.file(path, contents).unwrap();
+        tree.mtime(path, 1_000_000_000).unwrap();
+    }
+    let report = tree.path("report.json");
+    dedup([
+        "--dry-run".as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.root().as_os_str(),
+    ]);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    report["groups"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|group| {
+            let original = PathBuf::from(group["original"].as_str().unwrap());
+            original.strip_prefix(tree.root()).unwrap().to_path_buf()
+        })
+        .collect()
+}
+
+#[test]
+fn original_does_not_depend_on_the_order_of_creation() {
+    let forward = originals(FILES.into_iter());
+    let backward = originals(FILES.into_iter().rev());
+    // every other file first
+    let interleaved = originals(
+        FILES
+            .into_iter()
+            .step_by(2)
+            .chain(FILES.into_iter().skip(1).step_by(2)),
+    );
+    assert_eq!(forward, backward);
+    assert_eq!(forward, interleaved);
+    // with equal link counts and mtimes, the first path wins
+    let expected: BTreeSet<_> = ["d1/f", "g/x"].into_iter().map(PathBuf::from).collect();
+    assert_eq!(forward, expected);
+}
+
+fn ino(path: impl AsRef<Path>) -> u64 {
+    fs::metadata(path).unwrap().ino()
+}
+
+/// A tree where the inode with the most links is in `work`, along with copies in
+/// `masters` and `other`, and a symlink `alias` to the root.
+fn tree_with_masters() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/work/a", "same")
+        .unwrap()
+        .link("t/work/a", "t/work/b")
+        .unwrap()
+        .link("t/work/a", "t/work/c")
+        .unwrap()
+        .file("t/masters/f", "same")
+        .unwrap()
+        .file("t/other/f", "same")
+        .unwrap();
+    symlink(tree.path("t"), tree.path("alias")).unwrap();
+    tree
+}
+
+#[test]
+fn preferred_directories_win_over_link_counts() {
+    let tree = tree_with_masters();
+    let masters = ino(tree.path("t/masters/f"));
+    dedup([
+        "--quiet".as_ref(),
+        "--prefer".as_ref(),
+        tree.path("t/masters").as_os_str(),
+        "--prefer".as_ref(),
+        tree.path("t/other").as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    for path in ["t/work/a", "t/work/c", "t/other/f"] {
+        assert_eq!(ino(tree.path(path)), masters, "{path}");
+    }
+
+    // the earlier --prefer wins
+    let tree = tree_with_masters();
+    let other = ino(tree.path("t/other/f"));
+    dedup([
+        "--quiet".as_ref(),
+        "--prefer".as_ref(),
+        tree.path("t/other").as_os_str(),
+        "--prefer".as_ref(),
+        tree.path("t/masters").as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_eq!(ino(tree.path("t/masters/f")), other);
+}
+
+#[test]
+fn preferred_directories_match_however_they_are_spelled() {
+    let tree = tree_with_masters();
+    let masters = ino(tree.path("t/masters/f"));
+    dedup([
+        "--quiet".as_ref(),
+        "--prefer".as_ref(),
+        tree.path("alias/masters").as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_eq!(ino(tree.path("t/work/a")), masters);
+
+    // and the other way around
+    let tree = tree_with_masters();
+    let masters = ino(tree.path("t/masters/f"));
+    dedup([
+        "--quiet".as_ref(),
+        "--prefer".as_ref(),
+        tree.path("t/masters").as_os_str(),
+        tree.path("alias").as_os_str(),
+    ]);
+    assert_eq!(ino(tree.path("t/work/a")), masters);
+}
+
+#[test]
+fn reference_trees_are_only_linked_to() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for path in ["t/ref/a", "t/ref/b", "t/backup/a", "t/backup/b"] {
+        tree.file(path, "same").unwrap();
+        tree.mtime(path, 1_000_000_000).unwrap();
+    }
+    tree.mtime("t/ref/a", 1_500_000_000).unwrap();
+    symlink(tree.path("t"), tree.path("alias")).unwrap();
+    let before = ["t/ref/a", "t/ref/b"].map(|path| {
+        let metadata = fs::metadata(tree.path(path)).unwrap();
+        (metadata.ino(), metadata.mtime())
+    });
+
+    // named through the symlink, the reference is still told apart from the backup
+    dedup([
+        "--quiet".as_ref(),
+        "--reference".as_ref(),
+        tree.path("alias/ref").as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_not_linked(tree.path("t/ref/a"), tree.path("t/ref/b"));
+    assert_linked(tree.path("t/backup/a"), tree.path("t/backup/b"));
+    let backup = ino(tree.path("t/backup/a"));
+    assert!(before.iter().any(|&(ino, _)| ino == backup));
+    let after = ["t/ref/a", "t/ref/b"].map(|path| {
+        let metadata = fs::metadata(tree.path(path)).unwrap();
+        (metadata.ino(), metadata.mtime())
+    });
+    assert_eq!(after, before);
+}
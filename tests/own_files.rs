@@ -0,0 +1,81 @@
+mod common;
+
+use std::fs;
+
+use dedup::test_utils::{assert_linked, TreeBuilder};
+
+use common::dedup;
+
+/// The paths of the files in the groups of a JSON report.
+fn grouped(report: &std::path::Path) -> Vec<String> {
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(report).unwrap()).unwrap();
+    report["groups"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|group| {
+            let linked = group["linked"].as_array().unwrap().iter();
+            std::iter::once(&group["original"]).chain(linked)
+        })
+        .map(|path| path.as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn cache_inside_the_target_is_neither_hashed_nor_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/x", "same")
+        .unwrap()
+        .file("t/y", "same")
+        .unwrap();
+    let cache = tree.path("t/cache.json");
+    let report = tree.path("report.json");
+    let run = || {
+        dedup([
+            "--format".as_ref(),
+            "json".as_ref(),
+            "--output".as_ref(),
+            report.as_os_str(),
+            "--cache".as_ref(),
+            cache.as_os_str(),
+            tree.path("t").as_os_str(),
+        ])
+    };
+
+    run();
+    assert_linked(tree.path("t/x"), tree.path("t/y"));
+    // an identical copy would be linked to the cache, were the cache scanned
+    fs::copy(&cache, tree.path("t/copy.json")).unwrap();
+    fs::copy(&cache, tree.path("t/cache.json.tmp")).unwrap();
+    run();
+    let grouped = grouped(&report);
+    assert!(
+        grouped.iter().all(|path| !path.contains("cache.json")),
+        "{grouped:?}"
+    );
+    assert!(!grouped.iter().any(|path| path.ends_with("copy.json")));
+}
+
+#[test]
+fn leftover_report_temporaries_are_not_scanned() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/report.json", "same")
+        .unwrap()
+        .file("t/.report.json.dedup-tmp-12345", "same")
+        .unwrap()
+        .file("t/x", "other")
+        .unwrap()
+        .file("t/y", "other")
+        .unwrap();
+    let report = tree.path("report.json");
+    dedup([
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    let grouped = grouped(&report);
+    assert_eq!(grouped.len(), 2, "{grouped:?}");
+    assert!(grouped.iter().all(|path| !path.contains("report.json")));
+}
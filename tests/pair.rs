@@ -0,0 +1,94 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::ExitCode;
+
+use clap::Parser as _;
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+/// Runs `dedup pair a b`, returning the error instead of unwrapping it.
+fn pair(a: &Path, b: &Path) -> anyhow::Result<ExitCode> {
+    dedup::run(dedup::Args::parse_from([
+        "dedup".as_ref(),
+        "pair".as_ref(),
+        a.as_os_str(),
+        b.as_os_str(),
+    ]))
+}
+
+#[test]
+fn identical_files_are_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a", "same")
+        .unwrap()
+        .mtime("a", 2_000_000_000)
+        .unwrap()
+        .file("b", "same")
+        .unwrap()
+        .mtime("b", 1_000_000_000)
+        .unwrap();
+    let code = dedup([
+        "pair".as_ref(),
+        tree.path("a").as_os_str(),
+        tree.path("b").as_os_str(),
+    ]);
+    assert_eq!(code, ExitCode::SUCCESS);
+    assert_linked(tree.path("a"), tree.path("b"));
+    assert_eq!(fs::metadata(tree.path("a")).unwrap().mtime(), 1_000_000_000);
+
+    // a second time, they are already one
+    let code = dedup([
+        "pair".as_ref(),
+        tree.path("a").as_os_str(),
+        tree.path("b").as_os_str(),
+    ]);
+    assert_eq!(code, ExitCode::from(6));
+}
+
+#[test]
+fn different_files_are_refused() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a", "same")
+        .unwrap()
+        .file("b", "diff")
+        .unwrap()
+        .file("c", "longer")
+        .unwrap();
+    for other in ["b", "c"] {
+        let code = dedup([
+            "pair".as_ref(),
+            tree.path("a").as_os_str(),
+            tree.path(other).as_os_str(),
+        ]);
+        assert_eq!(code, ExitCode::from(7), "{other}");
+        assert_not_linked(tree.path("a"), tree.path(other));
+    }
+}
+
+#[test]
+fn files_on_other_devices_are_refused() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a", "same").unwrap();
+    let other = Path::new("/dev/shm");
+    let dev = |path: &Path| fs::metadata(path).unwrap().dev();
+    if !other.is_dir() || dev(other) == dev(tree.root()) {
+        eprintln!("skipped: needs /dev/shm on another device");
+        return;
+    }
+    let b = other.join(format!("dedup-pair-{}", std::process::id()));
+    fs::write(&b, "same").unwrap();
+    let result = pair(&tree.path("a"), &b);
+    let b_ino = fs::metadata(&b).unwrap().ino();
+    fs::remove_file(&b).unwrap();
+
+    let err = result.unwrap_err();
+    assert!(
+        err.to_string().contains("Not on the same device"),
+        "{err:#}"
+    );
+    assert_ne!(b_ino, fs::metadata(tree.path("a")).unwrap().ino());
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+use filetime::FileTime;
+
+use common::{dedup, link_groups};
+
+/// Runs twice over groups of three identical files, first to fill a cache, then with
+/// `--paranoid` and `threads` after some files were overwritten behind the back of the
+/// cache. Returns the links made and the report of the second run.
+fn run(threads: &str) -> (BTreeSet<Vec<PathBuf>>, serde_json::Value) {
+    let mut tree = TreeBuilder::new().unwrap();
+    for group in ["x", "y", "z"] {
+        for i in 1..=3 {
+            let path = format!("t/{group}{i}");
+            tree.file(&path, group.repeat(100)).unwrap();
+            tree.mtime(&path, 1_000_000_000).unwrap();
+        }
+    }
+    let cache = tree.path("cache.json");
+    let report = tree.path("report.json");
+    dedup([
+        "--dry-run".as_ref(),
+        "--quiet".as_ref(),
+        "--cache".as_ref(),
+        cache.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    for path in ["t/x2", "t/y1", "t/z3"] {
+        let path = tree.path(path);
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&path).unwrap());
+        fs::write(&path, "o".repeat(100)).unwrap();
+        filetime::set_file_mtime(&path, mtime).unwrap();
+    }
+    dedup([
+        "--paranoid".as_ref(),
+        "--threads".as_ref(),
+        threads.as_ref(),
+        "--cache".as_ref(),
+        cache.as_os_str(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    for odd in ["x2", "y1", "z3"] {
+        for group in ["x", "y", "z"] {
+            for i in 1..=3 {
+                let other = format!("{group}{i}");
+                if other != odd {
+                    assert_not_linked(
+                        tree.path(format!("t/{odd}")),
+                        tree.path(format!("t/{other}")),
+                    );
+                }
+            }
+        }
+    }
+    assert_linked(tree.path("t/x1"), tree.path("t/x3"));
+    assert_linked(tree.path("t/z1"), tree.path("t/z2"));
+    let report = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    (link_groups(&tree.path("t")), report)
+}
+
+#[test]
+fn mismatching_duplicates_are_skipped_whatever_the_threads() {
+    let (one, report_one) = run("1");
+    let (four, report_four) = run("4");
+    assert_eq!(one, four);
+    assert_eq!(
+        report_one["content_mismatches"],
+        report_four["content_mismatches"]
+    );
+    // y1, the original of its group, differs from both its duplicates
+    assert_eq!(report_four["content_mismatches"], 4, "{report_four}");
+}
+
+#[test]
+fn same_size_different_contents_are_never_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "aaaa")
+        .unwrap()
+        .file("t/b", "aaaa")
+        .unwrap();
+    let cache = tree.path("cache.json");
+    let root = tree.path("t");
+    let run = |option: &str| {
+        dedup([
+            option.as_ref(),
+            "--quiet".as_ref(),
+            "--cache".as_ref(),
+            cache.as_os_str(),
+            root.as_os_str(),
+        ])
+    };
+    run("--dry-run");
+    // the same size and mtime, so that the cache still holds the hash of the contents before
+    let b = tree.path("t/b");
+    let mtime = FileTime::from_last_modification_time(&fs::metadata(&b).unwrap());
+    fs::write(&b, "bbbb").unwrap();
+    filetime::set_file_mtime(&b, mtime).unwrap();
+
+    run("--paranoid");
+    assert_not_linked(tree.path("t/a"), &b);
+    assert_eq!(fs::read(&b).unwrap(), b"bbbb");
+}
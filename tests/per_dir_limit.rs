@@ -0,0 +1,71 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use dedup::test_utils::{assert_linked, TreeBuilder};
+
+use common::dedup;
+
+fn run(tree: &TreeBuilder) -> serde_json::Value {
+    let report = tree.path("report.json");
+    dedup([
+        "--per-dir-limit".as_ref(),
+        "2".as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    serde_json::from_slice(&fs::read(&report).unwrap()).unwrap()
+}
+
+#[test]
+fn duplicates_beyond_the_limit_are_deferred_to_later_runs() {
+    let mut tree = TreeBuilder::new().unwrap();
+    // five duplicates in one directory, of their originals in another
+    for i in 1..=5 {
+        let original = format!("t/originals/{i}");
+        tree.sized(&original, i * 10_000, b'a' + i as u8)
+            .unwrap()
+            .mtime(&original, 1_000_000_000)
+            .unwrap()
+            .sized(format!("t/copies/{i}"), i * 10_000, b'a' + i as u8)
+            .unwrap();
+    }
+    let linked = |tree: &TreeBuilder| -> Vec<usize> {
+        (1..=5)
+            .filter(|i| {
+                fs::metadata(tree.path(format!("t/copies/{i}")))
+                    .unwrap()
+                    .nlink()
+                    == 2
+            })
+            .collect()
+    };
+
+    // the largest first, two a run, until none is left
+    let mut deferred_gains = Vec::new();
+    for (expected, deferred) in [
+        (vec![4, 5], 3),
+        (vec![2, 3, 4, 5], 1),
+        (vec![1, 2, 3, 4, 5], 0),
+    ] {
+        let report = run(&tree);
+        assert_eq!(linked(&tree), expected, "{report}");
+        assert_eq!(report["deferred"], deferred, "{report}");
+        deferred_gains.push(report["deferred_gain"].as_u64().unwrap());
+    }
+    assert!(
+        deferred_gains[0] > deferred_gains[1] && deferred_gains[1] > deferred_gains[2],
+        "{deferred_gains:?}"
+    );
+    assert_eq!(deferred_gains[2], 0);
+    for i in 1..=5 {
+        assert_linked(
+            tree.path(format!("t/originals/{i}")),
+            tree.path(format!("t/copies/{i}")),
+        );
+    }
+}
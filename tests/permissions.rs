@@ -0,0 +1,80 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+const NOBODY: u32 = 65534;
+
+/// Three identical files, the last of which differs from the others as `differ` makes it.
+fn tree(differ: impl Fn(&std::path::Path)) -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    for path in ["t/a", "t/b", "t/c"] {
+        tree.file(path, "same").unwrap();
+        fs::set_permissions(tree.path(path), fs::Permissions::from_mode(0o644)).unwrap();
+    }
+    differ(&tree.path("t/c"));
+    tree
+}
+
+/// Runs dedup with `options` over the tree, and returns its JSON report.
+fn run(tree: &TreeBuilder, options: &[&str]) -> serde_json::Value {
+    let report = tree.path("report.json");
+    let mut args: Vec<&std::ffi::OsStr> = options.iter().map(|option| option.as_ref()).collect();
+    let target = tree.path("t");
+    args.extend([
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        target.as_os_str(),
+    ]);
+    dedup(args);
+    serde_json::from_slice(&fs::read(&report).unwrap()).unwrap()
+}
+
+#[test]
+fn files_with_other_permissions_are_not_merged_unless_ignored() {
+    let private = |path: &std::path::Path| {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap()
+    };
+    let tree = tree(private);
+    let report = run(&tree, &[]);
+    assert_linked(tree.path("t/a"), tree.path("t/b"));
+    assert_not_linked(tree.path("t/a"), tree.path("t/c"));
+    assert_eq!(
+        fs::metadata(tree.path("t/c")).unwrap().mode() & 0o777,
+        0o600
+    );
+    assert_eq!(report["split_groups"], 1, "{report}");
+
+    let tree = self::tree(private);
+    let report = run(&tree, &["--ignore-permissions"]);
+    assert_linked(tree.path("t/a"), tree.path("t/c"));
+    assert_eq!(report["split_groups"], 0, "{report}");
+}
+
+#[test]
+fn files_of_other_owners_are_not_merged_unless_ignored() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipped: needs root");
+        return;
+    }
+    let theirs = |path: &std::path::Path| chown(path, Some(NOBODY), Some(NOBODY)).unwrap();
+    let tree = tree(theirs);
+    let report = run(&tree, &[]);
+    assert_linked(tree.path("t/a"), tree.path("t/b"));
+    assert_not_linked(tree.path("t/a"), tree.path("t/c"));
+    assert_eq!(report["split_groups"], 1, "{report}");
+    // the ownership is checked apart from the permissions
+    let tree = self::tree(theirs);
+    run(&tree, &["--ignore-permissions"]);
+    assert_not_linked(tree.path("t/a"), tree.path("t/c"));
+
+    let tree = self::tree(theirs);
+    run(&tree, &["--ignore-ownership", "--cross-user", "allow"]);
+    assert_linked(tree.path("t/a"), tree.path("t/c"));
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use std::fs;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+/// Files of 256 KiB sharing their first 192 KiB, as media files of the same encoder do,
+/// and a copy of the first.
+fn common_headers(tree: &mut TreeBuilder) {
+    for (name, tail) in [("a", 1), ("b", 2), ("c", 3), ("d", 4), ("copy", 1)] {
+        let mut contents = vec![0_u8; 192 << 10];
+        contents.extend(vec![tail; 64 << 10]);
+        tree.file(format!("t/{name}"), contents).unwrap();
+    }
+}
+
+/// Runs a dry run with `options`, returning the files hashed in full.
+fn hashed(tree: &TreeBuilder, options: &[&str]) -> u64 {
+    let report = tree.path("report.json");
+    let mut args: Vec<_> = ["--dry-run", "--format", "json", "--output"]
+        .iter()
+        .map(Into::into)
+        .collect();
+    args.push(report.clone().into_os_string());
+    args.extend(options.iter().map(Into::into));
+    args.push(tree.path("t").into_os_string());
+    dedup(args);
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(report).unwrap()).unwrap();
+    report["hashed"].as_u64().unwrap()
+}
+
+#[test]
+fn tail_prefilter_avoids_full_hashes_of_common_headers() {
+    let mut tree = TreeBuilder::new().unwrap();
+    common_headers(&mut tree);
+    for threads in ["1", "2"] {
+        let options = |region| ["--threads", threads, "--prefilter-region", region];
+        assert_eq!(hashed(&tree, &options("head")), 5);
+        // only the copy shares its tail
+        assert_eq!(hashed(&tree, &options("tail")), 2);
+        assert_eq!(hashed(&tree, &options("head+tail")), 2);
+    }
+}
+
+#[test]
+fn groups_are_made_on_full_hashes() {
+    let mut tree = TreeBuilder::new().unwrap();
+    common_headers(&mut tree);
+    // same tail as a, but another header
+    let mut contents = vec![9_u8; 192 << 10];
+    contents.extend(vec![1_u8; 64 << 10]);
+    tree.file("t/e", contents).unwrap();
+    dedup([
+        "--quiet".as_ref(),
+        "--prefilter-region".as_ref(),
+        "tail".as_ref(),
+        "--prefilter-size".as_ref(),
+        "4k".as_ref(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_linked(tree.path("t/a"), tree.path("t/copy"));
+    assert_not_linked(tree.path("t/a"), tree.path("t/e"));
+}
@@ -0,0 +1,42 @@
+use std::process::{Command, Output};
+
+use dedup::test_utils::TreeBuilder;
+
+fn run(options: &[&str]) -> Output {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(options)
+        .arg("--progress")
+        .arg(tree.path("t"))
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap()
+}
+
+fn has_progress(output: &[u8]) -> bool {
+    let output = String::from_utf8_lossy(output);
+    ["Walking:", "Hashing:", "Relinking:"]
+        .iter()
+        .any(|phase| output.contains(phase))
+}
+
+#[test]
+fn progress_never_goes_to_stdout() {
+    let output = run(&[]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(!has_progress(&output.stdout), "{output:?}");
+}
+
+#[test]
+fn progress_is_suppressed_with_a_json_report() {
+    for options in [&["--json"][..], &["--format", "json"]] {
+        let output = run(options);
+        assert!(output.status.success(), "{options:?}: {output:?}");
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+        assert!(!has_progress(&output.stderr), "{options:?}: {output:?}");
+    }
+}
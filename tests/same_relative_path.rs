@@ -0,0 +1,63 @@
+use std::process::Command;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+#[test]
+fn only_files_at_the_same_relative_path_are_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("monday/x", "one")
+        .unwrap()
+        .file("tuesday/x", "one")
+        .unwrap()
+        .file("monday/d/y", "two")
+        .unwrap()
+        .file("tuesday/renamed", "two")
+        .unwrap()
+        .file("monday/z", "three")
+        .unwrap()
+        .file("tuesday/z", "three")
+        .unwrap()
+        .file("tuesday/d/copy", "three")
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--same-relative-path", "monday", "tuesday"])
+        .current_dir(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_linked(tree.path("monday/x"), tree.path("tuesday/x"));
+    assert_linked(tree.path("monday/z"), tree.path("tuesday/z"));
+    assert_not_linked(tree.path("monday/d/y"), tree.path("tuesday/renamed"));
+    assert_not_linked(tree.path("monday/z"), tree.path("tuesday/d/copy"));
+    // the content found at other paths is reported instead, a group at a time in the order
+    // of the relative paths
+    assert!(
+        stdout.contains(
+            "Moved or renamed content:\n  \
+             monday/d/y\n  tuesday/renamed\n\n  \
+             tuesday/d/copy\n  monday/z\n\n"
+        ),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn without_the_option_content_is_linked_wherever_it_is() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("monday/d/y", "two")
+        .unwrap()
+        .file("tuesday/renamed", "two")
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["monday", "tuesday"])
+        .current_dir(tree.root())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("Moved or renamed content"));
+    assert_linked(tree.path("monday/d/y"), tree.path("tuesday/renamed"));
+}
@@ -0,0 +1,80 @@
+use std::process::Command;
+
+use dedup::test_utils::TreeBuilder;
+
+/// A tree with files skipped for three reasons, more of one than are listed as examples.
+fn skipped() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    for i in 0..12 {
+        tree.file(format!("t/{i:02}.tmp"), "same").unwrap();
+    }
+    tree.file("t/small", "x")
+        .unwrap()
+        .file("t/empty", "")
+        .unwrap()
+        .file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    tree
+}
+
+fn run(tree: &TreeBuilder, options: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .current_dir(tree.root())
+        .args(["--dry-run", "--exclude", "*.tmp", "--min-size", "2"])
+        .args(options)
+        .arg("t")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn skips_are_counted_by_stable_reason() {
+    let tree = skipped();
+    let report: serde_json::Value = serde_json::from_str(&run(&tree, &["--json"])).unwrap();
+    let skips = &report["skips"];
+    assert_eq!(
+        skips["counts"],
+        serde_json::json!({"below-min-size": 1, "empty": 1, "excluded-by-pattern": 12}),
+        "{skips}"
+    );
+    // only listed when asked for
+    assert!(skips.get("examples").is_none(), "{skips}");
+}
+
+#[test]
+fn list_skipped_keeps_a_few_examples_of_each_reason() {
+    let tree = skipped();
+    let report: serde_json::Value =
+        serde_json::from_str(&run(&tree, &["--json", "--list-skipped"])).unwrap();
+    let examples = &report["skips"]["examples"];
+    assert_eq!(
+        examples["empty"],
+        serde_json::json!(["t/empty"]),
+        "{examples}"
+    );
+    assert_eq!(
+        examples["below-min-size"],
+        serde_json::json!(["t/small"]),
+        "{examples}"
+    );
+    assert_eq!(
+        examples["excluded-by-pattern"].as_array().unwrap().len(),
+        10,
+        "{examples}"
+    );
+
+    // in the order of the reasons, not by name
+    let text = run(&tree, &["--list-skipped"]);
+    assert!(
+        text.contains("Skipped files by reason:\n  excluded-by-pattern: 12\n"),
+        "{text}"
+    );
+    assert!(
+        text.contains("\n  below-min-size: 1\n    t/small\n  empty: 1\n    t/empty\n"),
+        "{text}"
+    );
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::os::unix::fs::{chown, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+const NOBODY: u32 = 65534;
+
+/// Only root can hand files to another user and run dedup as that user.
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[test]
+fn files_of_others_in_a_sticky_directory_are_skipped() {
+    if !is_root() {
+        eprintln!("skipped: needs root");
+        return;
+    }
+    let mut tree = TreeBuilder::new().unwrap();
+    // the oldest is the original, owned by the user running dedup
+    tree.file("sticky/mine", "same")
+        .unwrap()
+        .mtime("sticky/mine", 1_000_000_000)
+        .unwrap()
+        .file("sticky/also-mine", "same")
+        .unwrap()
+        .mtime("sticky/also-mine", 1_100_000_000)
+        .unwrap()
+        .file("sticky/theirs", "same")
+        .unwrap()
+        .mtime("sticky/theirs", 1_200_000_000)
+        .unwrap();
+    for path in ["sticky/mine", "sticky/also-mine"] {
+        chown(tree.path(path), Some(NOBODY), Some(NOBODY)).unwrap();
+    }
+    for (path, mode) in [("", 0o755), ("sticky", 0o1777), ("sticky/theirs", 0o644)] {
+        fs::set_permissions(tree.path(path), fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    // where the other user can run it from
+    let program = tree.path("dedup");
+    fs::copy(env!("CARGO_BIN_EXE_dedup"), &program).unwrap();
+    let report = tree.path("report.json");
+    let status = Command::new(&program)
+        .uid(NOBODY)
+        .gid(NOBODY)
+        .args(["--ignore-ownership", "--cross-user", "allow", "--json"])
+        .arg(tree.path("sticky"))
+        .stdout(fs::File::create(&report).unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    assert_linked(tree.path("sticky/mine"), tree.path("sticky/also-mine"));
+    assert_not_linked(tree.path("sticky/mine"), tree.path("sticky/theirs"));
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["sticky_skipped"], 1, "{report}");
+}
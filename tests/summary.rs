@@ -0,0 +1,62 @@
+use std::fs;
+use std::process::{Command, Output};
+
+use dedup::test_utils::{assert_not_linked, TreeBuilder};
+
+fn run(tree: &TreeBuilder, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(args)
+        .arg(tree.path("t"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn no_duplicates_and_nothing_linkable_are_told_apart() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "one").unwrap().file("t/b", "two").unwrap();
+    let output = run(&tree, &[]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert!(stdout.contains("No duplicates found\n"), "{stdout}");
+    assert!(stdout.contains("Gain: 0 bytes\n"), "{stdout}");
+
+    // a group whose files are both anchors is found, but left alone
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    let anchors = tree.path("anchors");
+    fs::write(
+        &anchors,
+        format!(
+            "{}\n{}\n",
+            tree.path("t/a").display(),
+            tree.path("t/b").display()
+        ),
+    )
+    .unwrap();
+    let output = run(&tree, &["--anchors", anchors.to_str().unwrap()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(3), "{stdout}");
+    assert!(
+        stdout.contains("Found 1 duplicate groups, but none could be linked\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Gain: 0 bytes\n"), "{stdout}");
+    assert_not_linked(tree.path("t/a"), tree.path("t/b"));
+
+    // and once one of two groups is linked, the run succeeds
+    tree.file("t/c", "more")
+        .unwrap()
+        .file("t/d", "more")
+        .unwrap();
+    let output = run(&tree, &["--anchors", anchors.to_str().unwrap()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert!(
+        stdout.contains("Deduplicated 1 of 2 duplicate groups\n"),
+        "{stdout}"
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::Path;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+/// The inode and mtime of `path`, which a modification would change.
+fn state(path: &Path) -> (u64, i64, i64) {
+    let metadata = fs::symlink_metadata(path).unwrap();
+    (metadata.ino(), metadata.mtime(), metadata.mtime_nsec())
+}
+
+#[test]
+fn followed_destinations_outside_the_targets_are_never_modified() {
+    let mut tree = TreeBuilder::new().unwrap();
+    for path in ["t/a", "outside/x", "outside/y"] {
+        tree.file(path, "same").unwrap();
+        tree.mtime(path, 1_000_000_000).unwrap();
+    }
+    tree.file("t/b", "other")
+        .unwrap()
+        .file("outside/c", "other")
+        .unwrap();
+    symlink(tree.path("outside"), tree.path("t/dir")).unwrap();
+    symlink(tree.path("outside/c"), tree.path("t/file")).unwrap();
+    let outside = ["outside", "outside/x", "outside/y", "outside/c"].map(|path| tree.path(path));
+    let before = outside.clone().map(|path| state(&path));
+
+    dedup([
+        "--follow-symlinks".as_ref(),
+        "--quiet".as_ref(),
+        tree.path("t").as_os_str(),
+    ]);
+
+    // the duplicates in the target are linked to what the symlinks lead to, which is left
+    // as it was, even where it holds duplicates of its own
+    assert_linked(tree.path("t/a"), tree.path("outside/x"));
+    assert_linked(tree.path("t/b"), tree.path("outside/c"));
+    assert_not_linked(tree.path("outside/x"), tree.path("outside/y"));
+    assert_eq!(outside.map(|path| state(&path)), before);
+    assert!(fs::symlink_metadata(tree.path("t/dir"))
+        .unwrap()
+        .is_symlink());
+    assert!(fs::symlink_metadata(tree.path("t/file"))
+        .unwrap()
+        .is_symlink());
+}
+
+#[test]
+fn symlink_loops_are_walked_once() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/d/a", "same")
+        .unwrap()
+        .file("t/d/e/b", "same")
+        .unwrap();
+    // back up to the target, and to a directory that leads back to it
+    symlink(tree.path("t"), tree.path("t/d/e/up")).unwrap();
+    symlink(tree.path("t/d"), tree.path("t/d/e/again")).unwrap();
+    let report = tree.path("report.json");
+
+    dedup([
+        "--follow-symlinks".as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_linked(tree.path("t/d/a"), tree.path("t/d/e/b"));
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["summary"]["files_scanned"], 2, "{report}");
+    assert!(fs::symlink_metadata(tree.path("t/d/e/up"))
+        .unwrap()
+        .is_symlink());
+}
+
+#[test]
+fn symlinks_to_duplicates_are_never_replaced() {
+    for follow in [false, true] {
+        let mut tree = TreeBuilder::new().unwrap();
+        tree.file("t/a", "same")
+            .unwrap()
+            .file("t/b", "same")
+            .unwrap();
+        symlink(tree.path("t/b"), tree.path("t/link")).unwrap();
+        symlink("a", tree.path("t/relative")).unwrap();
+        let target = tree.path("t");
+        let mut args = vec!["--quiet".as_ref(), target.as_os_str()];
+        if follow {
+            args.push("--follow-symlinks".as_ref());
+        }
+
+        dedup(args);
+        assert_linked(tree.path("t/a"), tree.path("t/b"));
+        for link in ["t/link", "t/relative"] {
+            let metadata = fs::symlink_metadata(tree.path(link)).unwrap();
+            assert!(metadata.is_symlink(), "{link}, following: {follow}");
+        }
+        assert_eq!(
+            fs::read_link(tree.path("t/link")).unwrap(),
+            tree.path("t/b")
+        );
+        assert_eq!(fs::read(tree.path("t/link")).unwrap(), b"same");
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::symlink;
+
+use dedup::test_utils::{assert_linked, TreeBuilder};
+
+use common::dedup;
+
+#[test]
+fn the_same_directory_given_twice_is_scanned_once() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .file("t/c", "other")
+        .unwrap();
+    symlink(tree.path("t"), tree.path("alias")).unwrap();
+    let report = tree.path("report.json");
+    dedup([
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+        tree.path("alias").as_os_str(),
+        tree.path("t/").as_os_str(),
+    ]);
+    assert_linked(tree.path("t/a"), tree.path("t/b"));
+
+    let report: serde_json::Value = serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+    let groups = report["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 1, "{report}");
+    // under the first spelling only, never linked to itself through another
+    assert_eq!(groups[0]["original"], tree.path("t/a").to_str().unwrap());
+    assert_eq!(
+        groups[0]["linked"],
+        serde_json::json!([tree.path("t/b").to_str().unwrap()])
+    );
+}
+
+#[test]
+fn overlapping_symlinked_targets_count_each_inode_once() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/c", "same")
+        .unwrap()
+        .file("t/sub/a", "same")
+        .unwrap()
+        .file("t/sub/b", "same")
+        .unwrap()
+        .link("t/sub/b", "t/sub/b2")
+        .unwrap();
+    // a target inside another, reached through a symlink
+    symlink(tree.path("t/sub"), tree.path("sub")).unwrap();
+    let report = tree.path("report.json");
+    let run = |targets: &[&str], dry_run: bool| {
+        let mut args = vec![
+            "--follow-symlinks".as_ref(),
+            "--format".as_ref(),
+            "json".as_ref(),
+            "--output".as_ref(),
+            report.as_os_str(),
+        ];
+        if dry_run {
+            args.push("--dry-run".as_ref());
+        }
+        let targets: Vec<_> = targets.iter().map(|target| tree.path(target)).collect();
+        args.extend(targets.iter().map(|target| target.as_os_str()));
+        dedup(args);
+        let report: serde_json::Value =
+            serde_json::from_slice(&fs::read(&report).unwrap()).unwrap();
+        report
+    };
+
+    let alone = run(&["t"], true);
+    assert!(alone["gain"].as_u64().unwrap() > 0, "{alone}");
+    for targets in [["t", "sub"], ["sub", "t"]] {
+        let report = run(&targets, true);
+        assert_eq!(report["gain"], alone["gain"], "{targets:?}: {report}");
+        assert_eq!(
+            report["summary"]["files_scanned"], 4,
+            "{targets:?}: {report}"
+        );
+        let groups = report["groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 1, "{targets:?}: {report}");
+        assert_eq!(groups[0]["gain"], report["gain"], "{targets:?}: {report}");
+    }
+
+    let report = run(&["sub", "t"], false);
+    assert_eq!(report["gain"], alone["gain"], "{report}");
+    assert_linked(tree.path("t/c"), tree.path("t/sub/a"));
+    assert_linked(tree.path("t/c"), tree.path("t/sub/b2"));
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+
+use common::dedup;
+
+const LEFTOVER: &str = ".dedup-tmp-123-0123456789abcdef";
+
+#[test]
+fn leftover_link_to_a_scanned_file_is_removed() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .link("t/a", format!("t/{LEFTOVER}"))
+        .unwrap();
+    dedup(["--quiet".as_ref(), tree.path("t").as_os_str()]);
+    assert!(!tree.path(format!("t/{LEFTOVER}")).exists());
+    assert_linked(tree.path("t/a"), tree.path("t/b"));
+}
+
+#[test]
+fn leftover_holding_the_only_link_is_kept() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file(format!("t/{LEFTOVER}"), "same")
+        .unwrap()
+        .file("t/c", "same")
+        .unwrap();
+    dedup(["--quiet".as_ref(), tree.path("t").as_os_str()]);
+    assert_not_linked(tree.path(format!("t/{LEFTOVER}")), tree.path("t/c"));
+}
+
+#[test]
+fn leftovers_are_recognized_by_the_given_prefix() {
+    let mut tree = TreeBuilder::new().unwrap();
+    let leftover = "t/tmp~42-fedcba9876543210";
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .link("t/a", leftover)
+        .unwrap();
+    dedup([
+        "--quiet".as_ref(),
+        "--tmp-prefix".as_ref(),
+        "tmp~".as_ref(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert!(!tree.path(leftover).exists());
+    assert_linked(tree.path("t/a"), tree.path("t/b"));
+    let names: Vec<_> = std::fs::read_dir(tree.path("t"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names.len(), 2, "{names:?}");
+}
+
+#[test]
+fn duplicates_named_at_name_max_are_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    let name = format!("t/{}", "x".repeat(255));
+    tree.file("t/a", "same")
+        .unwrap()
+        .file(&name, "same")
+        .unwrap();
+    dedup(["--quiet".as_ref(), tree.path("t").as_os_str()]);
+    assert_linked(tree.path("t/a"), tree.path(&name));
+    let names: Vec<_> = std::fs::read_dir(tree.path("t"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names.len(), 2, "{names:?}");
+}
+
+#[test]
+fn temporary_names_too_long_skip_the_duplicate() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap();
+    let report = tree.path("report.json");
+    // the prefix alone leaves no room for the random part within NAME_MAX
+    let prefix = "p".repeat(250);
+    let code = dedup([
+        "--tmp-prefix".as_ref(),
+        prefix.as_ref(),
+        "--format".as_ref(),
+        "json".as_ref(),
+        "--output".as_ref(),
+        report.as_os_str(),
+        tree.path("t").as_os_str(),
+    ]);
+    assert_eq!(code, 3.into());
+    assert_not_linked(tree.path("t/a"), tree.path("t/b"));
+    assert_eq!(std::fs::read(tree.path("t/b")).unwrap(), b"same");
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["skips"]["counts"]["name-too-long"], 1, "{report}");
+    assert_eq!(report["failures"], 0, "{report}");
+}
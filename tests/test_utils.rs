@@ -0,0 +1,59 @@
+use std::fs;
+
+use dedup::test_utils::{assert_linked, assert_not_linked, TreeBuilder};
+use filetime::FileTime;
+
+#[test]
+fn tree_is_built_as_declared() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a/x", "contents")
+        .unwrap()
+        .sized("a/y", 3000, b'y')
+        .unwrap()
+        .link("a/x", "b/c/x")
+        .unwrap()
+        .mtime("a/y", 1_000_000_000)
+        .unwrap();
+    assert_eq!(fs::read(tree.path("a/x")).unwrap(), b"contents");
+    assert_eq!(fs::read(tree.path("a/y")).unwrap(), vec![b'y'; 3000]);
+    assert_linked(tree.path("a/x"), tree.path("b/c/x"));
+    assert_not_linked(tree.path("a/x"), tree.path("a/y"));
+    let mtime = FileTime::from_last_modification_time(&fs::metadata(tree.path("a/y")).unwrap());
+    assert_eq!(mtime.unix_seconds(), 1_000_000_000);
+}
+
+#[test]
+fn tree_is_removed_on_drop() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("a/x", "contents").unwrap();
+    let root = tree.root().to_path_buf();
+    drop(tree);
+    assert!(!root.exists());
+}
+
+#[test]
+fn generated_tree_has_the_duplicates_of_its_spec() {
+    let mut tree = TreeBuilder::new().unwrap();
+    let summary = tree
+        .generate("seed = 1\nfiles = 50\nduplicate_ratio = 0.5\nhardlink_farms = 1\n")
+        .unwrap();
+    assert!(summary.files >= 50);
+    assert!(summary.duplicates > 0);
+    assert!(summary.hard_links > 0);
+}
+
+#[test]
+#[should_panic(expected = "are not linked")]
+fn copies_are_not_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("x", "same").unwrap().file("y", "same").unwrap();
+    assert_linked(tree.path("x"), tree.path("y"));
+}
+
+#[test]
+#[should_panic(expected = "are linked")]
+fn links_are_linked() {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("x", "same").unwrap().link("x", "y").unwrap();
+    assert_not_linked(tree.path("x"), tree.path("y"));
+}
@@ -0,0 +1,110 @@
+//! `--uring-stat` against the stat of the walk, and the benchmark of the walk with and
+//! without it, which is ignored by default:
+//!
+//!     cargo test --release --test uring_stat -- --ignored --nocapture
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use dedup::test_utils::TreeBuilder;
+
+use common::link_groups;
+
+/// The number of files of the benchmark.
+const FILES: usize = 1_000_000;
+/// The files of the benchmark per directory.
+const FILES_PER_DIR: usize = 1_000;
+
+/// A tree with directories of several batches, hard links, symlinks and duplicates of
+/// several sizes.
+fn tree() -> TreeBuilder {
+    let mut tree = TreeBuilder::new().unwrap();
+    for i in 0..600 {
+        tree.sized(format!("t/many/{i}"), i % 50 + 1, b'x').unwrap();
+    }
+    for i in 0..20 {
+        tree.sized(format!("t/d{}/e/{i}", i % 3), i % 5 + 1, b'y')
+            .unwrap();
+    }
+    tree.link("t/many/0", "t/d0/linked").unwrap();
+    symlink("0", tree.path("t/many/symlink")).unwrap();
+    tree
+}
+
+fn json_run(tree: &TreeBuilder, options: &[&str]) -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--format", "json", "--threads", "1"])
+        .args(options)
+        .arg("t")
+        .current_dir(tree.root())
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{options:?}: {output:?}");
+    // the note about io_uring being unavailable, where it is
+    assert!(
+        output.stderr.is_empty() || options.contains(&"--uring-stat"),
+        "{output:?}"
+    );
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn uring_stat_scans_as_the_walk_does() {
+    let tree = tree();
+    let walked = json_run(&tree, &["--dry-run"]);
+    let batched = json_run(&tree, &["--dry-run", "--uring-stat"]);
+    for field in ["groups", "summary", "sieve", "skips", "already_linked"] {
+        assert_eq!(batched[field], walked[field], "{field}");
+    }
+    assert_eq!(walked["summary"]["files_scanned"], 621);
+
+    let other = self::tree();
+    json_run(&tree, &[]);
+    json_run(&other, &["--uring-stat"]);
+    assert_eq!(link_groups(&tree.path("t")), link_groups(&other.path("t")));
+}
+
+/// The time of a dry run over `tree` with `options`, nothing being hashed.
+fn walk_time(tree: &TreeBuilder, options: &[&str]) -> Duration {
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["--dry-run", "--quiet", "--min-size", "1G"])
+        .args(options)
+        .arg("t")
+        .current_dir(tree.root())
+        .output()
+        .unwrap();
+    let elapsed = start.elapsed();
+    assert!(output.status.success(), "{output:?}");
+    elapsed
+}
+
+#[test]
+#[ignore = "creates a million files; run with --ignored --nocapture for the figures"]
+fn walk_throughput_with_and_without_uring_stat() {
+    let tree = TreeBuilder::new().unwrap();
+    for dir in 0..FILES / FILES_PER_DIR {
+        let dir = tree.path(format!("t/{dir}"));
+        fs::create_dir_all(&dir).unwrap();
+        for file in 0..FILES_PER_DIR {
+            fs::File::create(dir.join(file.to_string())).unwrap();
+        }
+    }
+    // the first runs fill the dentry and inode caches, for the next ones to compare
+    walk_time(&tree, &[]);
+    walk_time(&tree, &["--uring-stat"]);
+    for options in [&[][..], &["--uring-stat"]] {
+        let elapsed = walk_time(&tree, options);
+        eprintln!(
+            "{:<14} {FILES} files in {:.2}s: {:.0} files/s",
+            options.first().unwrap_or(&"stat per entry"),
+            elapsed.as_secs_f64(),
+            FILES as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
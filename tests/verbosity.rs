@@ -0,0 +1,91 @@
+use std::process::Command;
+
+use dedup::test_utils::TreeBuilder;
+
+/// The stdout and stderr of a run with `options` over a pair of duplicates, a file of a
+/// size of its own and an empty file, which is skipped.
+fn run(options: &[&str]) -> (String, String) {
+    let mut tree = TreeBuilder::new().unwrap();
+    tree.file("t/a", "same")
+        .unwrap()
+        .file("t/b", "same")
+        .unwrap()
+        .file("t/c", "unique")
+        .unwrap()
+        .file("t/e", "")
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(options)
+        .arg("t")
+        .current_dir(tree.root())
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{options:?}: {output:?}");
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn quiet_prints_only_the_gain() {
+    for quiet in ["-q", "--quiet"] {
+        let (stdout, stderr) = run(&[quiet]);
+        assert_eq!(stdout, "Gain: 4,096 bytes\n");
+        assert_eq!(stderr, "");
+    }
+}
+
+#[test]
+fn normal_lists_groups_and_the_summary() {
+    let (stdout, stderr) = run(&[]);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert!(
+        lines.contains(&"<- t/a") || lines.contains(&"<- t/b"),
+        "{stdout}"
+    );
+    assert!(
+        lines.contains(&"Deduplicated 1 of 1 duplicate groups"),
+        "{stdout}"
+    );
+    assert!(lines.contains(&"Gain: 4,096 bytes"), "{stdout}");
+    assert!(!stdout.contains("   gain:"), "{stdout}");
+    assert!(!stdout.contains("Size sieve:"), "{stdout}");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn verbose_adds_skips_sieve_decisions_and_group_gains() {
+    for verbose in ["-v", "--verbose"] {
+        let (stdout, stderr) = run(&[verbose]);
+        let lines: Vec<_> = stdout.lines().collect();
+        assert!(lines.contains(&"   gain: 4,096 bytes"), "{stdout}");
+        assert!(lines.contains(&"Gain: 4,096 bytes"), "{stdout}");
+        assert!(
+            stdout.contains("Size sieve: 2 unique sizes set"),
+            "{stdout}"
+        );
+
+        let lines: Vec<_> = stderr.lines().collect();
+        assert!(lines.contains(&"Skipped (empty): t/e"), "{stderr}");
+        assert!(
+            lines.contains(&"Sieve: unique size 6 so far: t/c"),
+            "{stderr}"
+        );
+        assert!(
+            lines.contains(&"Sieve: size 4 seen again, hashing: t/a")
+                || lines.contains(&"Sieve: size 4 seen again, hashing: t/b"),
+            "{stderr}"
+        );
+    }
+}
+
+#[test]
+fn quiet_and_verbose_conflict() {
+    let output = Command::new(env!("CARGO_BIN_EXE_dedup"))
+        .args(["-q", "-v", "."])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+}